@@ -808,6 +808,7 @@ pub async fn ingest_docs_cli(args: IngestDocsArgs) -> anyhow::Result<()> {
             ingest_source,
             Some(&update_progress_bar),
             args.commit_type,
+            None,
         )
         .await?;
     progress_bar.finish();
@@ -835,7 +836,7 @@ pub async fn search_index(args: SearchIndexArgs) -> anyhow::Result<SearchRespons
         .transpose()?;
     let sort_by_field = args.sort_by_score.then_some(SortByField {
         field_name: "_score".to_string(),
-        order: SortOrder::Desc,
+        order: Some(SortOrder::Desc),
     });
     let search_request = SearchRequestQueryString {
         query: args.query,
@@ -103,6 +103,24 @@ pub struct SearcherConfig {
     pub split_footer_cache_capacity: Byte,
     pub max_num_concurrent_split_searches: usize,
     pub max_num_concurrent_split_streams: usize,
+    /// Maximum number of top hits a search request may collect, its `start_offset` included,
+    /// regardless of its `max_hits`.
+    pub max_hits_ceiling: Option<u64>,
+    /// What happens to requests asking for more hits than `max_hits_ceiling`.
+    pub max_hits_ceiling_mode: MaxHitsCeilingMode,
+}
+
+/// Handling of the search requests whose `max_hits` exceeds the `max_hits_ceiling` of the
+/// searcher.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaxHitsCeilingMode {
+    /// The request is served with `max_hits` reduced to the ceiling, and the response reports
+    /// the reduced value.
+    #[default]
+    Clamp,
+    /// The request is rejected.
+    Reject,
 }
 
 impl Default for SearcherConfig {
@@ -114,6 +132,8 @@ impl Default for SearcherConfig {
             max_num_concurrent_split_searches: 100,
             aggregation_memory_limit: Byte::from_bytes(500_000_000), // 500M
             aggregation_bucket_limit: 65000,
+            max_hits_ceiling: None,
+            max_hits_ceiling_mode: MaxHitsCeilingMode::Clamp,
         }
     }
 }
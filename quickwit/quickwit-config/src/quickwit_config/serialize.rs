@@ -375,6 +375,7 @@ mod tests {
     use itertools::Itertools;
 
     use super::*;
+    use crate::MaxHitsCeilingMode;
 
     fn get_config_filepath(config_filename: &str) -> String {
         format!(
@@ -450,6 +451,8 @@ mod tests {
                 split_footer_cache_capacity: Byte::from_str("1G").unwrap(),
                 max_num_concurrent_split_searches: 150,
                 max_num_concurrent_split_streams: 120,
+                max_hits_ceiling: Some(10_000),
+                max_hits_ceiling_mode: MaxHitsCeilingMode::Reject,
             }
         );
         assert_eq!(
@@ -34,7 +34,7 @@ use crate::default_doc_mapper::mapping_tree::{build_mapping_tree, MappingNode};
 use crate::default_doc_mapper::FieldMappingType;
 pub use crate::default_doc_mapper::QuickwitJsonOptions;
 use crate::doc_mapper::{JsonObject, Partition};
-use crate::query_builder::build_query;
+use crate::query_builder::{build_query, validate_sort_by_fields};
 use crate::routing_expression::RoutingExpr;
 use crate::{
     Cardinality, DocMapper, DocParsingError, ModeType, QueryParserError, WarmupInfo,
@@ -78,6 +78,8 @@ pub struct DefaultDocMapper {
     dynamic_field: Option<Field>,
     /// Default list of field names used for search.
     default_search_field_names: Vec<String>,
+    /// Sort of the requests that do not specify one.
+    default_sort_by_field: Option<String>,
     /// Timestamp field name.
     timestamp_field_name: Option<String>,
     /// Root node of the field mapping tree.
@@ -209,6 +211,11 @@ impl TryFrom<DefaultDocMapperBuilder> for DefaultDocMapper {
             default_search_field_names.push(field_name.clone());
         }
 
+        if let Some(default_sort_by_field) = &builder.default_sort_by_field {
+            validate_sort_by_fields(default_sort_by_field, &schema, None)
+                .context("Invalid default sort by field.")?;
+        }
+
         // Resolve tag fields
         let mut tag_field_names: BTreeSet<String> = Default::default();
         for tag_field_name in &builder.tag_fields {
@@ -229,6 +236,7 @@ impl TryFrom<DefaultDocMapperBuilder> for DefaultDocMapper {
             source_field,
             dynamic_field,
             default_search_field_names,
+            default_sort_by_field: builder.default_sort_by_field,
             timestamp_field_name: builder.timestamp_field,
             field_mappings,
             tag_field_names,
@@ -261,6 +269,7 @@ impl From<DefaultDocMapper> for DefaultDocMapperBuilder {
             field_mappings: default_doc_mapper.field_mappings.into(),
             tag_fields: default_doc_mapper.tag_field_names.into_iter().collect(),
             default_search_fields: default_doc_mapper.default_search_field_names,
+            default_sort_by_field: default_doc_mapper.default_sort_by_field,
             mode,
             dynamic_mapping,
             partition_key: partition_key_opt,
@@ -384,6 +393,10 @@ impl DocMapper for DefaultDocMapper {
         self.timestamp_field_name.as_deref()
     }
 
+    fn default_sort_by_field(&self) -> Option<&str> {
+        self.default_sort_by_field.as_deref()
+    }
+
     fn tag_field_names(&self) -> BTreeSet<String> {
         self.tag_field_names.clone()
     }
@@ -566,6 +579,22 @@ mod tests {
         assert_eq!(&builder.try_build().unwrap_err().to_string(), &expected_msg);
     }
 
+    #[test]
+    fn test_fail_to_build_doc_mapper_with_unknown_default_sort_by_field() {
+        let doc_mapper = r#"{
+            "default_sort_by_field": "-timestamp",
+            "field_mappings": [
+                {
+                    "name": "body",
+                    "type": "text"
+                }
+            ]
+        }"#;
+        let builder = serde_json::from_str::<DefaultDocMapperBuilder>(doc_mapper).unwrap();
+        let expected_msg = "Invalid default sort by field.";
+        assert_eq!(&builder.try_build().unwrap_err().to_string(), &expected_msg);
+    }
+
     #[test]
     fn test_fail_to_build_doc_mapper_with_non_fast_timestamp_field() {
         let doc_mapper = r#"{
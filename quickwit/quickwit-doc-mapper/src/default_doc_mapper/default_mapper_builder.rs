@@ -42,6 +42,10 @@ pub struct DefaultDocMapperBuilder {
     /// Name of the fields that are searched by default, unless overridden.
     #[serde(default)]
     pub default_search_fields: Vec<String>,
+    /// Sort of the requests that do not specify one, in the syntax of `sort_by_field`.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_sort_by_field: Option<String>,
     /// Name of the field storing the timestamp of the event for time series data.
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -111,6 +111,12 @@ pub trait DocMapper: Send + Sync + Debug + DynClone + 'static {
         None
     }
 
+    /// Returns the sort of the requests that do not specify one, in the syntax of
+    /// `SearchRequest::sort_by_field`.
+    fn default_sort_by_field(&self) -> Option<&str> {
+        None
+    }
+
     /// Returns the tag field names
     fn tag_field_names(&self) -> BTreeSet<String> {
         Default::default()
@@ -202,8 +208,8 @@ mod tests {
         FastFieldOptions, FieldMappingType, QuickwitJsonOptions, QuickwitTextOptions,
     };
     use crate::{
-        Cardinality, DefaultDocMapperBuilder, DocMapper, DocParsingError, FieldMappingEntry,
-        WarmupInfo, DYNAMIC_FIELD_NAME,
+        Cardinality, DefaultDocMapper, DefaultDocMapperBuilder, DocMapper, DocParsingError,
+        FieldMappingEntry, WarmupInfo, DYNAMIC_FIELD_NAME,
     };
 
     const JSON_DEFAULT_DOC_MAPPER: &str = r#"
@@ -323,6 +329,7 @@ mod tests {
             sort_order: None,
             sort_by_field: None,
             aggregation_request: None,
+            ..Default::default()
         };
         let (query, _) = doc_mapper.query(schema, &search_request).unwrap();
         assert_eq!(
@@ -360,6 +367,7 @@ mod tests {
             sort_order: None,
             sort_by_field: Some("text_field".to_string()),
             aggregation_request: None,
+            ..Default::default()
         };
         let query = doc_mapper.query(schema, &search_request).unwrap_err();
         assert_eq!(
@@ -368,6 +376,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_doc_mapper_query_with_sort_field_aggregate() {
+        let doc_mapper: DefaultDocMapper = serde_json::from_value(serde_json::json!({
+            "field_mappings": [
+                {"name": "scores", "type": "array<i64>", "fast": true},
+                {"name": "timestamps", "type": "array<datetime>", "fast": true},
+            ]
+        }))
+        .unwrap();
+        let schema = doc_mapper.schema();
+        let query = |sort_by_field: &str| {
+            let search_request = SearchRequest {
+                index_id: "quickwit-index".to_string(),
+                query: "*".to_string(),
+                sort_by_field: Some(sort_by_field.to_string()),
+                ..Default::default()
+            };
+            doc_mapper.query(schema.clone(), &search_request)
+        };
+        assert!(query("-scores:sum,timestamps:max").is_ok());
+        assert_eq!(
+            format!("{:?}", query("timestamps:sum").unwrap_err()),
+            "QueryParserError(Sort by the sum of the values of a field requires a numeric field \
+             `timestamps`.)"
+        );
+        assert!(query("unknown_field:max").is_err());
+    }
+
     #[test]
     fn test_doc_mapper_query_with_json_field_default_search_fields() {
         let mut doc_mapper_builder = DefaultDocMapperBuilder::default();
@@ -395,6 +431,7 @@ mod tests {
             sort_order: None,
             sort_by_field: None,
             aggregation_request: None,
+            ..Default::default()
         };
         let (query, _) = doc_mapper.query(schema, &search_request).unwrap();
         assert_eq!(
@@ -430,6 +467,7 @@ mod tests {
             sort_order: None,
             sort_by_field: None,
             aggregation_request: None,
+            ..Default::default()
         };
         let (query, _) = doc_mapper.query(schema, &search_request).unwrap();
         assert_eq!(
@@ -31,6 +31,24 @@ impl From<tantivy::query::QueryParserError> for QueryParserError {
     }
 }
 
+impl QueryParserError {
+    /// Returns the name of the field that the query requires to be a fast field, if the query
+    /// failed because the field is not a fast field in the schema.
+    pub fn missing_fast_field(&self) -> Option<&str> {
+        self.0
+            .downcast_ref::<MissingFastFieldError>()
+            .map(|missing_fast_field_error| missing_fast_field_error.field_name.as_str())
+    }
+}
+
+/// The query requires a field to be a fast field, but it is not declared as such in the schema.
+#[derive(Error, Debug)]
+#[error("{message}")]
+pub(crate) struct MissingFastFieldError {
+    pub field_name: String,
+    pub message: String,
+}
+
 /// Error that may happen when parsing
 /// a document from JSON.
 #[derive(Debug, Error, Eq, PartialEq)]
@@ -25,6 +25,7 @@ use tantivy::query::{Query, QueryParser, QueryParserError as TantivyQueryParserE
 use tantivy::query_grammar::{UserInputAst, UserInputLeaf, UserInputLiteral};
 use tantivy::schema::{Field, FieldEntry, FieldType, Schema};
 
+use crate::error::MissingFastFieldError;
 use crate::{QueryParserError, WarmupInfo, DYNAMIC_FIELD_NAME, QUICKWIT_TOKENIZER_MANAGER};
 
 /// Build a `Query` with field resolution & forbidding range clauses.
@@ -55,13 +56,52 @@ pub(crate) fn build_query(
         resolve_fields(&schema, &request.search_fields)?
     };
 
-    if let Some(sort_by_field) = &request.sort_by_field {
-        validate_sort_by_field(sort_by_field, &schema, Some(&search_fields))?;
+    let rank_fusion_sort_by_field_opt = request
+        .rank_fusion
+        .as_ref()
+        .map(|rank_fusion| &rank_fusion.sort_by_field);
+    for sort_by_field in request
+        .sort_by_field
+        .iter()
+        .chain(rank_fusion_sort_by_field_opt)
+    {
+        validate_sort_by_fields(sort_by_field, &schema, Some(&search_fields))?;
+    }
+
+    if let Some(geo_distance) = &request.sort_by_geo_distance {
+        for field_name in [&geo_distance.lat_field, &geo_distance.lon_field] {
+            validate_sort_by_field(field_name, &schema, None)?;
+        }
     }
 
+    if let Some(score_boost) = &request.score_boost {
+        validate_sort_by_field(&score_boost.field_name, &schema, None)?;
+    }
+
+    if let Some(collapse_field) = &request.collapse_field {
+        validate_collapse_field(collapse_field, &schema)?;
+    }
+
+    if let Some(diversify_by) = &request.diversify_by {
+        validate_diversify_field(&diversify_by.field_name, &schema)?;
+    }
+
+    if let Some(hash_partition) = &request.hash_partition {
+        validate_hash_partition_field(&hash_partition.field_name, &schema)?;
+    }
+
+    if let Some(dedup_field) = &request.dedup_field {
+        validate_dedup_field(dedup_field, &schema)?;
+    }
+
+    let field_boosts = resolve_field_boosts(&schema, request)?;
+
     let mut query_parser =
         QueryParser::new(schema, search_fields, QUICKWIT_TOKENIZER_MANAGER.clone());
     query_parser.set_conjunction_by_default();
+    for (field, boost) in field_boosts {
+        query_parser.set_field_boost(field, boost);
+    }
     let query = query_parser.parse_query(&request.query)?;
 
     let mut term_set_query_fields = HashSet::new();
@@ -98,6 +138,28 @@ fn resolve_fields(schema: &Schema, field_names: &[String]) -> anyhow::Result<Vec
     Ok(fields)
 }
 
+/// Resolves the fields of the request's `field_boosts`, which must be indexed, and checks their
+/// boosts are positive.
+fn resolve_field_boosts(
+    schema: &Schema,
+    request: &SearchRequest,
+) -> anyhow::Result<Vec<(Field, f32)>> {
+    let mut field_boosts = Vec::with_capacity(request.field_boosts.len());
+    for (field_name, &boost) in &request.field_boosts {
+        let field = schema
+            .get_field(field_name)
+            .with_context(|| format!("Unknown boosted field: `{field_name}`"))?;
+        if !schema.get_field_entry(field).is_indexed() {
+            bail!("Boosted field `{field_name}` must be indexed.");
+        }
+        if !boost.is_finite() || boost <= 0.0 {
+            bail!("The boost of field `{field_name}` must be a positive number, got `{boost}`.");
+        }
+        field_boosts.push((field, boost));
+    }
+    Ok(field_boosts)
+}
+
 // Extract leaves from query ast.
 fn collect_leaves(user_input_ast: &UserInputAst) -> Vec<&UserInputLeaf> {
     match user_input_ast {
@@ -131,11 +193,15 @@ fn is_valid_field_for_range(field_entry: &FieldEntry) -> anyhow::Result<()> {
         | FieldType::I64(_)
         | FieldType::U64(_) => {
             if !field_entry.is_fast() {
-                bail!(
-                    "Range queries require having a fast field (field `{}` is not declared as a \
-                     fast field.)",
-                    field_entry.name()
-                );
+                return Err(MissingFastFieldError {
+                    field_name: field_entry.name().to_string(),
+                    message: format!(
+                        "Range queries require having a fast field (field `{}` is not declared as \
+                         a fast field.)",
+                        field_entry.name()
+                    ),
+                }
+                .into());
             }
         }
         other_type => {
@@ -252,6 +318,48 @@ fn validate_requested_snippet_fields(
     Ok(())
 }
 
+/// Validates a comma separated list of sort fields, in the syntax of `sort_by_field`.
+pub(crate) fn validate_sort_by_fields(
+    sort_by_field: &str,
+    schema: &Schema,
+    search_fields_opt: Option<&Vec<Field>>,
+) -> anyhow::Result<()> {
+    // Several fields can be given, each optionally prefixed by `-` or `+`.
+    for sort_field in sort_by_field.split(',') {
+        let sort_field = sort_field.trim();
+        let field_name = sort_field
+            .strip_prefix(['-', '+'])
+            .unwrap_or(sort_field)
+            .trim();
+        if let Some(field_name) = field_name.strip_prefix("_exists:") {
+            validate_field_exists_sort_field(field_name.trim(), schema)?;
+        } else if let Some((field_name, aggregate)) = field_name.rsplit_once(':') {
+            // The values of multivalued fields are reduced with `:first`, `:min`, `:max` or
+            // `:sum`, whose syntax is checked by the searcher.
+            let field_name = field_name.trim();
+            validate_sort_by_field(field_name, schema, search_fields_opt)?;
+            if aggregate.trim() == "sum" {
+                validate_numeric_sort_field(field_name, schema)?;
+            }
+        } else {
+            validate_sort_by_field(field_name, schema, search_fields_opt)?;
+        }
+    }
+    Ok(())
+}
+
+/// Summing the values of a field only makes sense for numbers.
+fn validate_numeric_sort_field(field_name: &str, schema: &Schema) -> anyhow::Result<()> {
+    let field = schema.get_field(field_name)?;
+    if !matches!(
+        schema.get_field_entry(field).field_type(),
+        FieldType::U64(_) | FieldType::I64(_) | FieldType::F64(_)
+    ) {
+        bail!("Sort by the sum of the values of a field requires a numeric field `{field_name}`.")
+    }
+    Ok(())
+}
+
 pub(crate) fn validate_sort_by_field(
     field_name: &str,
     schema: &Schema,
@@ -272,15 +380,108 @@ pub(crate) fn validate_sort_by_field(
         )
     }
     if !sort_by_field_entry.is_fast() {
-        bail!(
-            "Sort by field must be a fast field, please add the fast property to your field `{}`.",
-            field_name
-        )
+        return Err(MissingFastFieldError {
+            field_name: field_name.to_string(),
+            message: format!(
+                "Sort by field must be a fast field, please add the fast property to your field \
+                 `{field_name}`."
+            ),
+        }
+        .into());
     }
 
     Ok(())
 }
 
+fn validate_collapse_field(field_name: &str, schema: &Schema) -> anyhow::Result<()> {
+    let collapse_field = schema
+        .get_field(field_name)
+        .with_context(|| format!("Unknown collapse field: `{field_name}`"))?;
+    if !schema.get_field_entry(collapse_field).is_fast() {
+        return Err(MissingFastFieldError {
+            field_name: field_name.to_string(),
+            message: format!(
+                "Collapse field must be a fast field, please add the fast property to your field \
+                 `{field_name}`."
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn validate_diversify_field(field_name: &str, schema: &Schema) -> anyhow::Result<()> {
+    let diversify_field = schema
+        .get_field(field_name)
+        .with_context(|| format!("Unknown diversify field: `{field_name}`"))?;
+    if !schema.get_field_entry(diversify_field).is_fast() {
+        return Err(MissingFastFieldError {
+            field_name: field_name.to_string(),
+            message: format!(
+                "Diversify field must be a fast field, please add the fast property to your field \
+                 `{field_name}`."
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Like the fields sorted by presence, the hashed fields may be text fields, as only the hash of
+/// a value matters.
+fn validate_hash_partition_field(field_name: &str, schema: &Schema) -> anyhow::Result<()> {
+    let hash_partition_field = schema
+        .get_field(field_name)
+        .with_context(|| format!("Unknown hash partition field: `{field_name}`"))?;
+    if !schema.get_field_entry(hash_partition_field).is_fast() {
+        return Err(MissingFastFieldError {
+            field_name: field_name.to_string(),
+            message: format!(
+                "Hash partition field must be a fast field, please add the fast property to your \
+                 field `{field_name}`."
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Unlike the other sort fields, fields sorted by presence may be text fields, as only the
+/// existence of a value matters.
+fn validate_field_exists_sort_field(field_name: &str, schema: &Schema) -> anyhow::Result<()> {
+    let sort_by_field = schema
+        .get_field(field_name)
+        .with_context(|| format!("Unknown sort by field: `{field_name}`"))?;
+    if !schema.get_field_entry(sort_by_field).is_fast() {
+        return Err(MissingFastFieldError {
+            field_name: field_name.to_string(),
+            message: format!(
+                "Sort by field must be a fast field, please add the fast property to your field \
+                 `{field_name}`."
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn validate_dedup_field(field_name: &str, schema: &Schema) -> anyhow::Result<()> {
+    let dedup_field = schema
+        .get_field(field_name)
+        .with_context(|| format!("Unknown dedup field: `{field_name}`"))?;
+    if !schema.get_field_entry(dedup_field).is_fast() {
+        return Err(MissingFastFieldError {
+            field_name: field_name.to_string(),
+            message: format!(
+                "Dedup field must be a fast field, please add the fast property to your field \
+                 `{field_name}`."
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
 fn validate_sort_by_score(
     schema: &Schema,
     search_fields_opt: Option<&Vec<Field>>,
@@ -351,6 +552,7 @@ mod test {
             start_offset: 0,
             sort_order: None,
             sort_by_field: None,
+            ..Default::default()
         };
 
         let default_field_names =
@@ -599,6 +801,65 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_build_query_missing_fast_field() {
+        let range_request = SearchRequest {
+            query: "ip_notff:[127.0.0.1 TO 127.1.1.1]".to_string(),
+            ..Default::default()
+        };
+        let query_err = build_query(make_schema(), &range_request, &[]).unwrap_err();
+        assert_eq!(query_err.missing_fast_field(), Some("ip_notff"));
+
+        let sort_request = SearchRequest {
+            query: "title:foo".to_string(),
+            sort_by_field: Some("-ip_notff".to_string()),
+            ..Default::default()
+        };
+        let query_err = build_query(make_schema(), &sort_request, &[]).unwrap_err();
+        assert_eq!(query_err.missing_fast_field(), Some("ip_notff"));
+
+        let text_range_request = SearchRequest {
+            query: "title:[a TO b]".to_string(),
+            ..Default::default()
+        };
+        let query_err = build_query(make_schema(), &text_range_request, &[]).unwrap_err();
+        assert_eq!(query_err.missing_fast_field(), None);
+    }
+
+    #[test]
+    fn test_build_query_field_boosts() {
+        let build_query_with_boosts = |field_boosts: &[(&str, f32)]| {
+            let request = SearchRequest {
+                query: "foo".to_string(),
+                field_boosts: field_boosts
+                    .iter()
+                    .map(|(field_name, boost)| (field_name.to_string(), *boost))
+                    .collect(),
+                ..Default::default()
+            };
+            build_query(
+                make_schema(),
+                &request,
+                &["title".to_string(), "desc".to_string()],
+            )
+            .map(|(query, _warmup_info)| format!("{query:?}"))
+            .map_err(|error| error.to_string())
+        };
+        let query = build_query_with_boosts(&[("title", 2.0)]).unwrap();
+        assert!(query.contains("Boost"), "{query}");
+        let query = build_query_with_boosts(&[]).unwrap();
+        assert!(!query.contains("Boost"), "{query}");
+
+        let error = build_query_with_boosts(&[("foo", 2.0)]).unwrap_err();
+        assert!(error.contains("Unknown boosted field: `foo`"), "{error}");
+        let error = build_query_with_boosts(&[("ip_notff", 2.0)]).unwrap_err();
+        assert!(error.contains("must be indexed"), "{error}");
+        for invalid_boost in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            let error = build_query_with_boosts(&[("title", invalid_boost)]).unwrap_err();
+            assert!(error.contains("must be a positive number"), "{error}");
+        }
+    }
+
     #[track_caller]
     fn check_snippet_fields_validation(
         query_str: &str,
@@ -619,6 +880,7 @@ mod test {
             start_offset: 0,
             sort_order: None,
             sort_by_field: None,
+            ..Default::default()
         };
         let user_input_ast = tantivy::query_grammar::parse_query(&request.query)
             .map_err(|_| QueryParserError::SyntaxError(request.query.clone()))
@@ -731,6 +993,7 @@ mod test {
             start_offset: 0,
             sort_order: None,
             sort_by_field: None,
+            ..Default::default()
         };
         let request_without_set = SearchRequest {
             aggregation_request: None,
@@ -744,6 +1007,7 @@ mod test {
             start_offset: 0,
             sort_order: None,
             sort_by_field: None,
+            ..Default::default()
         };
 
         let default_field_names = vec!["title".to_string(), "desc".to_string()];
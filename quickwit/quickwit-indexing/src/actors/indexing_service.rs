@@ -17,7 +17,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
 
@@ -103,6 +103,9 @@ pub struct IndexingServiceCounters {
     pub num_running_merge_pipelines: usize,
     pub num_deleted_queues: usize,
     pub num_delete_queue_failures: usize,
+    /// Number of valid documents processed by the running indexing pipelines of each index,
+    /// refreshed on every supervision round of the indexing service.
+    pub num_indexed_docs_per_index: BTreeMap<String, u64>,
 }
 
 type SourceId = String;
@@ -360,6 +363,16 @@ impl IndexingService {
                     }
                 },
             );
+        let mut num_indexed_docs_per_index: BTreeMap<String, u64> = BTreeMap::new();
+        for (pipeline_id, pipeline_handle) in &self.indexing_pipeline_handles {
+            let pipeline_statistics = pipeline_handle.observe().await;
+            *num_indexed_docs_per_index
+                .entry(pipeline_id.index_config_id.index_id.clone())
+                .or_default() += pipeline_statistics
+                .num_docs
+                .saturating_sub(pipeline_statistics.num_invalid_docs);
+        }
+        self.counters.num_indexed_docs_per_index = num_indexed_docs_per_index;
         // Evict and kill merge pipelines that are not needed.
         let needed_merge_pipeline_ids: HashSet<MergePipelineId> = self
             .indexing_pipeline_handles
@@ -19,4 +19,7 @@
 
 mod cluster_sandbox;
 
-pub use cluster_sandbox::{build_node_configs, ClusterSandbox};
+pub use cluster_sandbox::{
+    build_node_configs, default_wait_timeout, ClusterSandbox, MetastoreBackend, NodeShutdownReport,
+    SplitSummary,
+};
@@ -28,7 +28,7 @@ use quickwit_rest_client::models::IngestSource;
 use quickwit_rest_client::rest_client::CommitType;
 use quickwit_serve::SearchRequestQueryString;
 
-use crate::test_utils::ClusterSandbox;
+use crate::test_utils::{default_wait_timeout, ClusterSandbox};
 
 fn get_ndjson_filepath(ndjson_dataset_filename: &str) -> String {
     format!(
@@ -140,6 +140,19 @@ async fn test_standalone_server() {
     sandbox.shutdown().await.unwrap();
 }
 
+#[tokio::test]
+async fn test_standalone_server_graceful_and_forced_shutdown() {
+    quickwit_common::setup_logging_for_tests();
+    let sandbox = ClusterSandbox::start_standalone_node().await.unwrap();
+    let shutdown_reports = sandbox.shutdown().await.unwrap();
+    assert_eq!(shutdown_reports.len(), 1);
+    assert!(!shutdown_reports[0].actor_exit_statuses.is_empty());
+    assert!(shutdown_reports[0].drain_duration < default_wait_timeout());
+
+    let sandbox = ClusterSandbox::start_standalone_node().await.unwrap();
+    sandbox.shutdown_forcefully().await.unwrap();
+}
+
 #[tokio::test]
 async fn test_multi_nodes_cluster() {
     quickwit_common::setup_logging_for_tests();
@@ -150,10 +163,13 @@ async fn test_multi_nodes_cluster() {
         HashSet::from_iter([QuickwitService::ControlPlane]),
         HashSet::from_iter([QuickwitService::Janitor]),
     ];
-    let sandbox = ClusterSandbox::start_cluster_nodes(&nodes_services)
+    let mut sandbox = ClusterSandbox::start_cluster_nodes(&nodes_services)
+        .await
+        .unwrap();
+    sandbox
+        .wait_for_cluster_num_ready_nodes(4, default_wait_timeout())
         .await
         .unwrap();
-    sandbox.wait_for_cluster_num_ready_nodes(4).await.unwrap();
 
     {
         // Wait for indexer to fully start.
@@ -230,6 +246,7 @@ async fn test_multi_nodes_cluster() {
             ingest_source,
             None,
             CommitType::Auto,
+            None,
         )
         .await
         .unwrap();
@@ -247,5 +264,54 @@ async fn test_multi_nodes_cluster() {
         .await
         .unwrap();
     assert_eq!(search_response_one_hit.num_hits, 1);
+
+    // Check that every searcher node serves the search, including a searcher node added to the
+    // running cluster.
+    let added_searcher_idx = sandbox
+        .add_node(HashSet::from_iter([QuickwitService::Searcher]))
+        .await
+        .unwrap();
+    let searcher_rest_clients = sandbox.rest_clients_for_service(QuickwitService::Searcher);
+    assert_eq!(searcher_rest_clients.len(), 2);
+    for searcher_rest_client in searcher_rest_clients {
+        let search_response = searcher_rest_client
+            .search(
+                "my-new-multi-node-index",
+                SearchRequestQueryString {
+                    query: "body:bar".to_string(),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(search_response.num_hits, 1);
+    }
+
+    // Check that a paused node is no longer reported ready until it is resumed.
+    sandbox.pause_node(added_searcher_idx).await.unwrap();
+    sandbox
+        .wait_for_cluster_num_ready_nodes(4, default_wait_timeout())
+        .await
+        .unwrap();
+    sandbox.resume_node(added_searcher_idx).await.unwrap();
+    sandbox
+        .wait_for_cluster_num_ready_nodes(5, default_wait_timeout())
+        .await
+        .unwrap();
+
+    // Check that search is working again once the searcher node has restarted.
+    sandbox.restart_node(0).await.unwrap();
+    let search_response_after_restart = sandbox
+        .searcher_rest_client
+        .search(
+            "my-new-multi-node-index",
+            SearchRequestQueryString {
+                query: "body:bar".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(search_response_after_restart.num_hits, 1);
     sandbox.shutdown().await.unwrap();
 }
@@ -17,14 +17,16 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
+use std::time::{Duration, Instant};
+
 use bytes::Bytes;
+use itertools::Itertools;
 use quickwit_metastore::SplitState;
 use quickwit_rest_client::models::IngestSource;
 use quickwit_rest_client::rest_client::CommitType;
-use quickwit_serve::SearchRequestQueryString;
 use serde_json::json;
 
-use crate::test_utils::ClusterSandbox;
+use crate::test_utils::{default_wait_timeout, ClusterSandbox, MetastoreBackend, SplitSummary};
 
 #[tokio::test]
 async fn test_restarting_standalone_server() {
@@ -63,7 +65,10 @@ async fn test_restarting_standalone_server() {
 
     // Wait fo the pipeline to start.
     // TODO: there should be a better way to do this.
-    sandbox.wait_for_indexing_pipelines(1).await.unwrap();
+    sandbox
+        .wait_for_indexing_pipelines(1, default_wait_timeout())
+        .await
+        .unwrap();
 
     let old_incarnation_id = sandbox
         .indexer_rest_client
@@ -80,10 +85,15 @@ async fn test_restarting_standalone_server() {
             index_id,
             IngestSource::Bytes(json!({"body": "first record"}).to_string().into()),
             None,
-            CommitType::Force,
+            CommitType::Auto,
+            None,
         )
         .await
         .unwrap();
+    sandbox
+        .wait_for_searchable_doc_count(index_id, "body:record", 1)
+        .await
+        .unwrap();
 
     // Delete the indexq
     sandbox
@@ -101,7 +111,10 @@ async fn test_restarting_standalone_server() {
         .await
         .unwrap();
 
-    sandbox.wait_for_indexing_pipelines(1).await.unwrap();
+    sandbox
+        .wait_for_indexing_pipelines(1, default_wait_timeout())
+        .await
+        .unwrap();
 
     let new_incarnation_id = sandbox
         .indexer_rest_client
@@ -112,64 +125,379 @@ async fn test_restarting_standalone_server() {
         .incarnation_id;
     assert_ne!(old_incarnation_id, new_incarnation_id);
 
-    // Index a couple of records to create 2 additional splits.
+    // The previous incarnation of the index is gone with its record.
+    assert_eq!(
+        sandbox.search_count(index_id, "body:record").await.unwrap(),
+        0
+    );
+
+    // Index three records, one at a time so that each one lands in its own split.
+    for (num_records, body) in [
+        (1, "second record"),
+        (2, "third record"),
+        (3, "fourth record"),
+    ] {
+        sandbox
+            .indexer_rest_client
+            .ingest(
+                index_id,
+                IngestSource::Bytes(json!({ "body": body }).to_string().into()),
+                None,
+                CommitType::Auto,
+                None,
+            )
+            .await
+            .unwrap();
+        sandbox
+            .wait_for_searchable_doc_count(index_id, "body:record", num_records)
+            .await
+            .unwrap();
+    }
+
     sandbox
-        .indexer_rest_client
-        .ingest(
+        .assert_search_hits(
             index_id,
-            IngestSource::Bytes(json!({"body": "second record"}).to_string().into()),
-            None,
-            CommitType::Force,
+            "body:record",
+            &[
+                json!({"body": "second record"}),
+                json!({"body": "third record"}),
+                json!({"body": "fourth record"}),
+            ],
         )
+        .await;
+
+    // Wait for splits to merge, since we created 3 splits and merge factor is 3,
+    // we should get 1 published split with no staged splits eventually.
+    let num_published_splits = sandbox
+        .wait_for_merge_quiescence(index_id, Duration::from_secs(30))
+        .await
+        .unwrap();
+    assert_eq!(num_published_splits, 1);
+    let published_splits: Vec<SplitSummary> = sandbox
+        .split_summary(index_id)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|split| split.split_state == SplitState::Published)
+        .collect();
+    assert_eq!(published_splits.len(), 1);
+    assert_eq!(published_splits[0].num_docs, 3);
+    // The index has no timestamp field.
+    assert!(published_splits[0].time_range.is_none());
+
+    sandbox.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_indexes_survive_restart_with_file_backed_metastore() {
+    quickwit_common::setup_logging_for_tests();
+    let mut sandbox = ClusterSandbox::start_standalone_node_with_metastore(MetastoreBackend::File)
+        .await
+        .unwrap();
+    let index_id = "test-index-surviving-restart";
+    let index_config = Bytes::from(format!(
+        r#"
+            version: 0.5
+            index_id: {}
+            doc_mapping:
+                field_mappings:
+                - name: body
+                  type: text
+            "#,
+        index_id
+    ));
+    let incarnation_id = sandbox
+        .indexer_rest_client
+        .indexes()
+        .create(index_config, quickwit_config::ConfigFormat::Yaml, false)
+        .await
+        .unwrap()
+        .incarnation_id;
+
+    sandbox.restart_node(0).await.unwrap();
+
+    let index_metadata = sandbox
+        .indexer_rest_client
+        .indexes()
+        .get(index_id)
         .await
         .unwrap();
+    assert_eq!(index_metadata.incarnation_id, incarnation_id);
+
+    sandbox.shutdown().await.unwrap();
+}
 
+#[tokio::test]
+async fn test_delete_by_query() {
+    quickwit_common::setup_logging_for_tests();
+    let sandbox = ClusterSandbox::start_standalone_node().await.unwrap();
+    let index_id = "test-delete-by-query";
+    let index_config = Bytes::from(format!(
+        r#"
+            version: 0.5
+            index_id: {}
+            doc_mapping:
+                field_mappings:
+                - name: body
+                  type: text
+            indexing_settings:
+                commit_timeout_secs: 1
+            "#,
+        index_id
+    ));
     sandbox
         .indexer_rest_client
-        .ingest(
-            index_id,
-            IngestSource::Bytes(json!({"body": "third record"}).to_string().into()),
-            None,
-            CommitType::Force,
-        )
+        .indexes()
+        .create(index_config, quickwit_config::ConfigFormat::Yaml, false)
+        .await
+        .unwrap();
+    sandbox
+        .wait_for_indexing_pipelines(1, default_wait_timeout())
+        .await
+        .unwrap();
+
+    let docs = [
+        json!({"body": "acme record"}),
+        json!({"body": "globex record"}),
+        json!({"body": "initech record"}),
+    ];
+    sandbox
+        .ingest_and_commit(index_id, docs.to_vec())
         .await
         .unwrap();
+    sandbox
+        .assert_search_hits(index_id, "body:record", &docs)
+        .await;
 
     sandbox
         .indexer_rest_client
-        .ingest(
-            index_id,
-            IngestSource::Bytes(json!({"body": "fourth record"}).to_string().into()),
-            None,
-            CommitType::Force,
-        )
+        .delete_by_query(index_id, "body:acme", None, None)
+        .await
+        .unwrap();
+
+    // The delete task is applied by the janitor on its next planning round.
+    let timeout = Duration::from_secs(120);
+    let start = Instant::now();
+    loop {
+        let num_hits = sandbox.search_count(index_id, "body:acme").await.unwrap();
+        if num_hits == 0 {
+            break;
+        }
+        assert!(
+            start.elapsed() < timeout,
+            "The delete task was not applied after waiting for {timeout:?}."
+        );
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    sandbox
+        .assert_search_hits(index_id, "body:record", &docs[1..])
+        .await;
+
+    sandbox.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_search_stream_export() {
+    quickwit_common::setup_logging_for_tests();
+    let sandbox = ClusterSandbox::start_standalone_node().await.unwrap();
+    let index_id = "test-search-stream-export";
+    let index_config = Bytes::from(format!(
+        r#"
+            version: 0.5
+            index_id: {}
+            doc_mapping:
+                field_mappings:
+                - name: id
+                  type: u64
+                  fast: true
+            "#,
+        index_id
+    ));
+    sandbox
+        .indexer_rest_client
+        .indexes()
+        .create(index_config, quickwit_config::ConfigFormat::Yaml, false)
+        .await
+        .unwrap();
+    sandbox
+        .wait_for_indexing_pipelines(1, default_wait_timeout())
         .await
         .unwrap();
 
-    let search_response_empty = sandbox
+    let num_docs = 5_000;
+    let docs = (0..num_docs).map(|id| json!({ "id": id })).collect();
+    sandbox.ingest_and_commit(index_id, docs).await.unwrap();
+
+    let mut search_stream = sandbox
         .searcher_rest_client
-        .search(
-            index_id,
-            SearchRequestQueryString {
-                query: "body:record".to_string(),
-                ..Default::default()
-            },
-        )
+        .search_stream(index_id, "*", "id")
         .await
         .unwrap();
+    let mut ids = Vec::new();
+    while let Some(row) = search_stream.next_row().await.unwrap() {
+        let id: u64 = std::str::from_utf8(&row).unwrap().parse().unwrap();
+        ids.push(id);
+    }
+    ids.sort_unstable();
+    assert_eq!(ids, (0..num_docs).collect::<Vec<u64>>());
 
-    assert_eq!(search_response_empty.num_hits, 3);
+    sandbox.shutdown().await.unwrap();
+}
 
-    // Wait for splits to merge, since we created 3 splits and merge factor is 3,
-    // we should get 1 published split with no staged splits eventually.
+#[tokio::test]
+async fn test_ingest_and_commit_reports_rejected_docs() {
+    quickwit_common::setup_logging_for_tests();
+    let sandbox = ClusterSandbox::start_standalone_node().await.unwrap();
+    let index_id = "test-ingest-and-commit-rejected-docs";
+    let index_config = Bytes::from(format!(
+        r#"
+            version: 0.5
+            index_id: {}
+            doc_mapping:
+                field_mappings:
+                - name: id
+                  type: u64
+            "#,
+        index_id
+    ));
     sandbox
-        .wait_for_published_splits(
-            index_id,
-            Some(vec![SplitState::Published, SplitState::Staged]),
-            1,
-        )
+        .indexer_rest_client
+        .indexes()
+        .create(index_config, quickwit_config::ConfigFormat::Yaml, false)
+        .await
+        .unwrap();
+    sandbox
+        .wait_for_indexing_pipelines(1, default_wait_timeout())
+        .await
+        .unwrap();
+
+    sandbox
+        .ingest_and_commit(index_id, vec![json!({"id": 1}), json!({"id": 2})])
         .await
         .unwrap();
+    assert_eq!(sandbox.search_count(index_id, "*").await.unwrap(), 2);
+
+    let error = sandbox
+        .ingest_and_commit(index_id, vec![json!({"id": 3}), json!({"id": "three"})])
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("1 of the 2 ingested documents"));
+    assert_eq!(sandbox.search_count(index_id, "*").await.unwrap(), 3);
+
+    let error = sandbox
+        .ingest_and_commit(index_id, vec![json!([4])])
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("is not a JSON object"));
+
+    sandbox.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_indexing_rate() {
+    quickwit_common::setup_logging_for_tests();
+    let sandbox = ClusterSandbox::start_standalone_node().await.unwrap();
+    let index_id = "test-indexing-rate";
+    let index_config = Bytes::from(format!(
+        r#"
+            version: 0.5
+            index_id: {}
+            doc_mapping:
+                field_mappings:
+                - name: body
+                  type: text
+            indexing_settings:
+                commit_timeout_secs: 1
+            "#,
+        index_id
+    ));
+    sandbox
+        .indexer_rest_client
+        .indexes()
+        .create(index_config, quickwit_config::ConfigFormat::Yaml, false)
+        .await
+        .unwrap();
+    sandbox
+        .wait_for_indexing_pipelines(1, default_wait_timeout())
+        .await
+        .unwrap();
+
+    let num_docs = 20_000;
+    let docs_ndjson = (0..num_docs)
+        .map(|doc_idx| json!({ "body": format!("record {doc_idx}") }).to_string())
+        .join("\n");
+    let ingest_future = sandbox.indexer_rest_client.ingest(
+        index_id,
+        IngestSource::Bytes(docs_ndjson.into()),
+        None,
+        CommitType::Auto,
+        None,
+    );
+    let measure_future = sandbox.measure_indexing_rate(index_id, Duration::from_secs(5));
+    let (ingest_result, indexing_rate_result) = tokio::join!(ingest_future, measure_future);
+    ingest_result.unwrap();
+    // The floor is far below the actual rate so that the test does not flake on loaded
+    // machines.
+    let indexing_rate = indexing_rate_result.unwrap();
+    assert!(
+        indexing_rate > 100.0,
+        "Indexed {indexing_rate:.0} docs/s, expected more than 100 docs/s."
+    );
+
+    sandbox.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_create_and_cleanup_indexes() {
+    quickwit_common::setup_logging_for_tests();
+    let sandbox = ClusterSandbox::start_standalone_node().await.unwrap();
+    let index_configs: Vec<Bytes> = (0..3)
+        .map(|idx| {
+            Bytes::from(format!(
+                r#"
+                version: 0.5
+                index_id: test-bulk-index-{idx}
+                doc_mapping:
+                    field_mappings:
+                    - name: body
+                      type: text
+                "#
+            ))
+        })
+        .collect();
+    let incarnation_ids = sandbox.create_indexes(&index_configs).await.unwrap();
+    assert_eq!(incarnation_ids.len(), 3);
+    assert_eq!(
+        sandbox
+            .indexer_rest_client
+            .indexes()
+            .list()
+            .await
+            .unwrap()
+            .len(),
+        3
+    );
+
+    // Creating an index that already exists fails, but the other indexes are still created.
+    let error = sandbox
+        .create_indexes(&[
+            index_configs[0].clone(),
+            Bytes::from_static(b"version: 0.5\nindex_id: test-bulk-index-3"),
+        ])
+        .await
+        .unwrap_err();
+    let error_message = error.to_string();
+    assert!(error_message.contains("Failed to create 1 out of 2 indexes"));
+    assert!(error_message.contains("index config #0"));
+
+    sandbox.cleanup_indexes().await.unwrap();
+    assert!(sandbox
+        .indexer_rest_client
+        .indexes()
+        .list()
+        .await
+        .unwrap()
+        .is_empty());
 
     sandbox.shutdown().await.unwrap();
 }
@@ -260,6 +260,7 @@ impl JaegerService {
             sort_order: None,
             sort_by_field: None,
             snippet_fields: Vec::new(),
+            ..Default::default()
         };
         let search_response = self.search_service.root_search(search_request).await?;
 
@@ -309,6 +310,7 @@ impl JaegerService {
             sort_by_field: None,
             aggregation_request: None,
             snippet_fields: Vec::new(),
+            ..Default::default()
         };
         let search_response = match self.search_service.root_search(search_request).await {
             Ok(search_response) => search_response,
@@ -656,6 +658,8 @@ fn build_aggregations_query(num_traces: usize) -> String {
         num_traces,
         trace_id_field_name: "trace_id".to_string(),
         span_timestamp_field_name: "span_start_timestamp_secs".to_string(),
+        service_name_field: None,
+        service_name: None,
     })
     .expect("The collector should be JSON serializable.");
     debug!(query=%query, "Aggregations query");
@@ -195,6 +195,7 @@ impl From<SearchStreamRequest> for SearchRequest {
             sort_by_field: None,
             sort_order: None,
             aggregation_request: None,
+            ..Default::default()
         }
     }
 }
@@ -218,6 +219,19 @@ impl fmt::Display for SplitSearchError {
     }
 }
 
+impl fmt::Display for SplitSearchErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            SplitSearchErrorCode::Internal => "internal error",
+            SplitSearchErrorCode::Timeout => "storage timeout",
+            SplitSearchErrorCode::Storage => "storage error",
+            SplitSearchErrorCode::MissingFastField => "missing fast field",
+            SplitSearchErrorCode::Aggregation => "undecodable aggregation result",
+        };
+        f.write_str(description)
+    }
+}
+
 /// `MutMetadataMap` used to extract [`tonic::metadata::MetadataMap`] from a request.
 pub struct MutMetadataMap<'a>(&'a mut tonic::metadata::MetadataMap);
 
@@ -28,10 +28,14 @@ pub struct SearchRequest {
     /// The results with rank [start_offset..start_offset + max_hits) are returned.
     #[prost(uint64, tag = "7")]
     pub start_offset: u64,
-    /// Sort order
+    /// Sort order of the sort fields without an order prefix. When unset, `_score` and
+    /// datetime fields sort in descending order, other fields in ascending order.
     #[prost(enumeration = "SortOrder", optional, tag = "9")]
     pub sort_order: ::core::option::Option<i32>,
     /// Sort by fast field. If unset sort by docid
+    /// Several comma separated fields can be given, e.g. `timestamp,-severity`,
+    /// in which case ties on a field are broken by the next one. A `-` prefix
+    /// sorts the field in descending order regardless of the sort_order.
     #[prost(string, optional, tag = "10")]
     pub sort_by_field: ::core::option::Option<::prost::alloc::string::String>,
     /// json serialized aggregation_request
@@ -40,6 +44,336 @@ pub struct SearchRequest {
     /// Fields to extract snippet on
     #[prost(string, repeated, tag = "12")]
     pub snippet_fields: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// If set, hits are picked in turn from the top-K of each split,
+    /// instead of taking the global top-K. This guarantees every split
+    /// is represented in the returned hits.
+    #[prost(bool, tag = "13")]
+    pub round_robin_splits: bool,
+    /// If set, only hits sorting strictly after this partial hit are returned.
+    /// The whole (sorting_field_value, split_id, segment_ord, doc_id) key is compared,
+    /// so that documents sharing the same sorting value are neither skipped nor duplicated.
+    #[prost(message, optional, tag = "14")]
+    pub search_after: ::core::option::Option<PartialHit>,
+    /// If set, ties on the sorting value are broken by a hash of this fast field's value
+    /// rather than by the document address, which changes when splits are merged.
+    /// The field must hold a unique value per document (e.g. a document id) for the
+    /// resulting order to be fully deterministic.
+    #[prost(string, optional, tag = "15")]
+    pub tie_breaker_field: ::core::option::Option<::prost::alloc::string::String>,
+    /// json serialized metric aggregations computed over the returned hits only,
+    /// rather than over all the matching documents.
+    /// (e.g. {"avg_price": {"avg": {"field": "price"}}})
+    #[prost(string, optional, tag = "16")]
+    pub top_hits_aggregation_request: ::core::option::Option<
+        ::prost::alloc::string::String,
+    >,
+    /// If set, only documents whose fast field holds the given value are collected.
+    /// This is checked while collecting, before any other work, which is cheaper than
+    /// a term query when it is combined with a broad query.
+    #[prost(message, optional, tag = "17")]
+    pub fast_field_equals: ::core::option::Option<FastFieldEquals>,
+    /// If set, each returned hit lists the indexed fields holding at least
+    /// one of the query terms.
+    #[prost(bool, tag = "18")]
+    pub return_matched_fields: bool,
+    /// Placement of the documents lacking a value for a sort fast field:
+    /// `_last` (the default), `_first`, or a value used in place of the missing one.
+    #[prost(string, optional, tag = "19")]
+    pub sort_missing: ::core::option::Option<::prost::alloc::string::String>,
+    /// If set, the response carries an estimate of the number of matching documents,
+    /// computed from term statistics or by sampling documents rather than by counting them.
+    #[prost(bool, tag = "20")]
+    pub estimate_total_hits: bool,
+    /// If set, the hits are ranked by fusing the ranking defined by `sort_by_field`
+    /// with a second ranking. Cannot be combined with `search_after`.
+    #[prost(message, optional, tag = "21")]
+    pub rank_fusion: ::core::option::Option<RankFusion>,
+    /// JSON serialized request of document samples per bucket of a fast field, e.g.
+    /// `{"field": "severity", "size": 3}` or `{"field": "latency", "interval": 100, "size": 3}`.
+    /// The samples are the best documents of each bucket according to the sort order.
+    #[prost(string, optional, tag = "22")]
+    pub bucket_samples_request: ::core::option::Option<::prost::alloc::string::String>,
+    /// If set, only the best hit of each group of documents sharing a value of this
+    /// fast field is returned. `num_hits` still counts all the matching documents.
+    #[prost(string, optional, tag = "23")]
+    pub collapse_field: ::core::option::Option<::prost::alloc::string::String>,
+    /// How exactly `num_hits` is counted. Counting all the matching documents can be
+    /// costly when only the top hits are needed.
+    #[prost(enumeration = "CountHitsMode", tag = "24")]
+    pub count_hits: i32,
+    /// Number of hits after which counting stops with the `THRESHOLD` mode.
+    #[prost(uint64, tag = "25")]
+    pub count_hits_threshold: u64,
+    /// If set, the hits are first sorted by their distance to a point, according to
+    /// `sort_order`, then by the fields of `sort_by_field`.
+    #[prost(message, optional, tag = "26")]
+    pub sort_by_geo_distance: ::core::option::Option<GeoDistanceSort>,
+    /// If set, the hits carry the decoded values of their sort criteria in `sort_values`.
+    #[prost(bool, tag = "27")]
+    pub return_sort_values: bool,
+    /// If set, the hits are a uniform random sample of the matching documents instead of
+    /// the top hits of a sort order. The same seed draws the same sample, as long as the
+    /// splits do not change.
+    #[prost(uint64, optional, tag = "28")]
+    pub random_sample_seed: ::core::option::Option<u64>,
+    /// If set, the documents scoring below this threshold are discarded: they are neither
+    /// returned nor counted. Only valid when sorting by `_score`.
+    #[prost(float, optional, tag = "29")]
+    pub min_score: ::core::option::Option<f32>,
+    /// Overrides the aggregation memory limit of the searchers, in bytes, up to a hard ceiling.
+    #[prost(uint64, optional, tag = "30")]
+    pub aggregation_memory_limit: ::core::option::Option<u64>,
+    /// Overrides the aggregation bucket limit of the searchers, up to a hard ceiling.
+    #[prost(uint32, optional, tag = "31")]
+    pub aggregation_bucket_limit: ::core::option::Option<u32>,
+    /// Bounds of the time range, as RFC3339 datetimes such as `2023-01-10T15:13:35.123Z`. They are
+    /// mutually exclusive with `start_timestamp` and `end_timestamp` respectively, and define the
+    /// same semi-open interval.
+    #[prost(string, optional, tag = "32")]
+    pub start_timestamp_rfc3339: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(string, optional, tag = "33")]
+    pub end_timestamp_rfc3339: ::core::option::Option<::prost::alloc::string::String>,
+    /// If set, only documents whose numerical or datetime fast field lies within the range are
+    /// collected. Unlike the time range of the request, it applies to any fast field, such as a
+    /// second datetime field of the index.
+    #[prost(message, optional, tag = "34")]
+    pub fast_field_range: ::core::option::Option<FastFieldRange>,
+    /// If set, each searcher stops collecting documents once this many milliseconds have elapsed
+    /// since it received the request, and returns the hits collected so far instead of an error.
+    #[prost(uint64, optional, tag = "35")]
+    pub soft_deadline_millis: ::core::option::Option<u64>,
+    /// If set, sorting by `_score` ranks the documents by their BM25 score plus a boost derived
+    /// from the value of a fast field, for instance to favor recent documents.
+    #[prost(message, optional, tag = "36")]
+    pub score_boost: ::core::option::Option<ScoreBoost>,
+    /// Order of the hits tying on all the sort criteria, by tie breaker value and then by
+    /// document address: split ID, segment ordinal and document ID. Ascending if unset.
+    #[prost(enumeration = "SortOrder", optional, tag = "37")]
+    pub tie_break_order: ::core::option::Option<i32>,
+    /// If set, the hits sharing a value of this fast field with a better hit are dropped, e.g. the
+    /// copies of a document ingested twice. The field is expected to hold a stable document ID.
+    /// `num_hits` still counts the duplicates.
+    #[prost(string, optional, tag = "38")]
+    pub dedup_field: ::core::option::Option<::prost::alloc::string::String>,
+    /// If set, each returned hit carries the byte offsets of the query terms within its
+    /// text fields indexed with positions (`record: position`), e.g. to underline them.
+    /// They are only computed for the returned hits.
+    #[prost(bool, tag = "39")]
+    pub return_highlights: bool,
+    /// If set, the documents are not fetched: each hit only carries the address of its document
+    /// (split ID, segment ordinal and document ID), without score nor sort values. Cannot be
+    /// combined with snippets, matched fields, highlights, sort values or bucket samples.
+    #[prost(bool, tag = "40")]
+    pub hits_only_addresses: bool,
+    /// JSON object mapping the names of top-level `terms` aggregations to the key of the last
+    /// bucket of the previous page, e.g. `{"services": "nginx"}`. The buckets of these
+    /// aggregations then start right after that key, in the order of the aggregation, and their
+    /// `size` is the size of the page.
+    #[prost(string, optional, tag = "41")]
+    pub terms_aggregation_after: ::core::option::Option<::prost::alloc::string::String>,
+    /// If set, at most `max_hits_per_group` hits of each group of documents sharing a value of a
+    /// fast field are returned, so that no group dominates the hits. `num_hits` still counts all
+    /// the matching documents. Cannot be combined with `collapse_field`.
+    #[prost(message, optional, tag = "42")]
+    pub diversify_by: ::core::option::Option<DiversifyBy>,
+    /// If set, the leaves return the complete set of the doc ids matching the query in each segment,
+    /// see `LeafSearchResponse::doc_id_sets`, instead of the top hits. `max_hits` is then ignored.
+    /// Meant for set operations, e.g. joins, rather than for displaying hits.
+    #[prost(bool, tag = "43")]
+    pub collect_doc_id_sets: bool,
+    /// Boosts of the scores of the matches on some fields, keyed by field name, e.g. to favor the
+    /// matches on `title` over those on `body`. The boosts must be positive.
+    #[prost(map = "string, float", tag = "44")]
+    pub field_boosts: ::std::collections::HashMap<::prost::alloc::string::String, f32>,
+    /// If set, at most `max_hits` hits of each source are returned, e.g. when the splits of an index
+    /// come from several federated sources. Unlike `diversify_by`, which groups the hits by the value
+    /// of a field, the quota groups them by the source prefix of their split id.
+    #[prost(message, optional, tag = "45")]
+    pub max_hits_per_source: ::core::option::Option<MaxHitsPerSource>,
+    /// If set, only the documents of a bucket of a hash partition of the values of a fast field are
+    /// collected, e.g. to compare rankings on the same stable slice of the corpus.
+    #[prost(message, optional, tag = "46")]
+    pub hash_partition: ::core::option::Option<HashPartition>,
+}
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GeoDistanceSort {
+    /// Fast fields holding the latitude and the longitude of the documents, in degrees.
+    #[prost(string, tag = "1")]
+    pub lat_field: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub lon_field: ::prost::alloc::string::String,
+    /// Point the distances are computed from, in degrees.
+    #[prost(double, tag = "3")]
+    pub lat: f64,
+    #[prost(double, tag = "4")]
+    pub lon: f64,
+}
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DiversifyBy {
+    /// Fast field whose values define the groups. Documents without a value form a group.
+    #[prost(string, tag = "1")]
+    pub field_name: ::prost::alloc::string::String,
+    /// Maximum number of hits per group, at least 1.
+    #[prost(uint32, tag = "2")]
+    pub max_hits_per_group: u32,
+}
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MaxHitsPerSource {
+    /// Character ending the source prefix of the split ids, e.g. `/` for the split id
+    /// `source-a/01H...`. The split ids without it all belong to one unnamed source.
+    #[prost(string, tag = "1")]
+    pub split_id_separator: ::prost::alloc::string::String,
+    /// Maximum number of hits per source, at least 1.
+    #[prost(uint32, tag = "2")]
+    pub max_hits: u32,
+}
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ScoreBoost {
+    /// Numerical or datetime fast field. A document with the value `v` gets a boost of
+    /// `factor * ln(1 + v)`, datetimes being taken in seconds. Documents without a value, or
+    /// with a negative one, get no boost.
+    #[prost(string, tag = "1")]
+    pub field_name: ::prost::alloc::string::String,
+    #[prost(double, tag = "2")]
+    pub factor: f64,
+}
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HashPartition {
+    /// Fast field whose values are hashed. Documents without a value belong to no bucket.
+    #[prost(string, tag = "1")]
+    pub field_name: ::prost::alloc::string::String,
+    /// Number of buckets, at least 1.
+    #[prost(uint64, tag = "2")]
+    pub modulus: u64,
+    /// Bucket of the collected documents, lower than `modulus`.
+    #[prost(uint64, tag = "3")]
+    pub bucket: u64,
+}
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RankFusion {
+    /// Sort fields of the second ranking, with the same syntax as `sort_by_field`.
+    #[prost(string, tag = "1")]
+    pub sort_by_field: ::prost::alloc::string::String,
+    /// Sort order of the second ranking fields without a `-` prefix.
+    #[prost(enumeration = "SortOrder", optional, tag = "2")]
+    pub sort_order: ::core::option::Option<i32>,
+    /// Constant `k` of the reciprocal rank fusion score, the sum over the rankings
+    /// of `1 / (k + rank)`. Defaults to 60.
+    #[prost(uint32, optional, tag = "3")]
+    pub rank_constant: ::core::option::Option<u32>,
+}
+/// A list of partial hits, from the best to the worst.
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Ranking {
+    #[prost(message, repeated, tag = "1")]
+    pub partial_hits: ::prost::alloc::vec::Vec<PartialHit>,
+}
+/// The best partial hits of a bucket, from the best to the worst.
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LeafBucketSamples {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "2")]
+    pub partial_hits: ::prost::alloc::vec::Vec<PartialHit>,
+}
+/// The best hits of a bucket, from the best to the worst.
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BucketSamples {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "2")]
+    pub hits: ::prost::alloc::vec::Vec<Hit>,
+}
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FastFieldEquals {
+    /// Name of the fast field.
+    #[prost(string, tag = "1")]
+    pub field_name: ::prost::alloc::string::String,
+    /// Value of the field, parsed according to the type of the fast field.
+    #[prost(string, tag = "2")]
+    pub value: ::prost::alloc::string::String,
+}
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FastFieldRange {
+    /// Name of the fast field.
+    #[prost(string, tag = "1")]
+    pub field_name: ::prost::alloc::string::String,
+    /// Inclusive lower bound. Datetime fields are bounded by timestamps in seconds.
+    #[prost(int64, optional, tag = "2")]
+    pub lower_bound: ::core::option::Option<i64>,
+    /// Exclusive upper bound. Datetime fields are bounded by timestamps in seconds.
+    #[prost(int64, optional, tag = "3")]
+    pub upper_bound: ::core::option::Option<i64>,
+}
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct EstimatedTotalHits {
+    /// Estimated number of documents matching the query.
+    #[prost(uint64, tag = "1")]
+    pub value: u64,
+    /// Bounds of the 95% confidence interval of the estimate.
+    #[prost(uint64, tag = "2")]
+    pub lower_bound: u64,
+    #[prost(uint64, tag = "3")]
+    pub upper_bound: u64,
+}
+/// Counters of the collection of the documents matching the query, telling the selectivity of
+/// the filters of the collector and the churn of the top-K.
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CollectionStats {
+    /// Number of documents matching the query visited by the collector.
+    /// num_docs_visited = num_docs_rejected + num_docs_accepted.
+    #[prost(uint64, tag = "1")]
+    pub num_docs_visited: u64,
+    /// Number of visited documents rejected by the filters of the collector.
+    #[prost(uint64, tag = "2")]
+    pub num_docs_rejected: u64,
+    /// Number of rejected documents outside of the time range of the request.
+    #[prost(uint64, tag = "3")]
+    pub num_docs_rejected_by_timestamp_filter: u64,
+    /// Number of visited documents accepted by the filters of the collector.
+    #[prost(uint64, tag = "4")]
+    pub num_docs_accepted: u64,
+    /// Number of documents that entered the top-K, including those evicted later on.
+    #[prost(uint64, tag = "5")]
+    pub num_docs_entered_top_k: u64,
+}
+/// Counters of the searched splits by where their data came from. A split is warm when its footer
+/// was found in the split footer cache of the searcher, and cold when it was fetched from the
+/// storage.
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CacheStatus {
+    #[prost(uint64, tag = "1")]
+    pub num_warm_splits: u64,
+    #[prost(uint64, tag = "2")]
+    pub num_cold_splits: u64,
 }
 #[derive(Serialize, Deserialize, utoipa::ToSchema)]
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -61,6 +395,35 @@ pub struct SearchResponse {
     /// Serialized aggregation response
     #[prost(string, optional, tag = "5")]
     pub aggregation: ::core::option::Option<::prost::alloc::string::String>,
+    /// Serialized response of the aggregations computed over the returned hits.
+    #[prost(string, optional, tag = "6")]
+    pub top_hits_aggregation: ::core::option::Option<::prost::alloc::string::String>,
+    /// Estimate of the number of hits matching the query, if requested.
+    #[prost(message, optional, tag = "7")]
+    pub estimated_total_hits: ::core::option::Option<EstimatedTotalHits>,
+    /// Samples of the hits of each bucket, if requested.
+    #[prost(message, repeated, tag = "8")]
+    pub bucket_samples: ::prost::alloc::vec::Vec<BucketSamples>,
+    /// If set, counting stopped early and `num_hits` is a lower bound of the number
+    /// of matching documents.
+    #[prost(bool, tag = "9")]
+    pub num_hits_is_lower_bound: bool,
+    /// If set, the soft deadline of the request passed before all the documents were collected:
+    /// the hits and the aggregations only cover part of the matching documents.
+    #[prost(bool, tag = "10")]
+    pub timed_out: bool,
+    /// Types of the values of the sort criteria, in the order of the criteria, when the
+    /// request's `return_sort_values` is set. They tell clients how to format the sort values.
+    #[prost(enumeration = "SortValueType", repeated, tag = "11")]
+    pub sort_value_types: ::prost::alloc::vec::Vec<i32>,
+    /// Set when the request's `max_hits` exceeded the `max_hits_ceiling` of the searchers:
+    /// the number of hits the request was served with.
+    #[prost(uint64, optional, tag = "12")]
+    pub clamped_max_hits: ::core::option::Option<u64>,
+    /// If set, the intermediate aggregation results of some splits could not be decoded, and the
+    /// aggregation only covers the other splits. The failing splits are listed in `errors`.
+    #[prost(bool, tag = "13")]
+    pub aggregation_is_partial: bool,
 }
 #[derive(Serialize, Deserialize, utoipa::ToSchema)]
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -75,6 +438,9 @@ pub struct SplitSearchError {
     /// Flag to indicate if the error can be considered a retryable error
     #[prost(bool, tag = "3")]
     pub retryable_error: bool,
+    /// Machine-readable cause of the failure.
+    #[prost(enumeration = "SplitSearchErrorCode", tag = "4")]
+    pub code: i32,
 }
 #[derive(Serialize, Deserialize, utoipa::ToSchema)]
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -137,6 +503,12 @@ pub struct LeafHit {
     /// A snippet of the matching content
     #[prost(string, optional, tag = "3")]
     pub leaf_snippet_json: ::core::option::Option<::prost::alloc::string::String>,
+    /// The fields that matched the query, if requested.
+    #[prost(string, repeated, tag = "4")]
+    pub matched_fields: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// The offsets of the query terms in the document, if requested.
+    #[prost(message, repeated, tag = "5")]
+    pub match_offsets: ::prost::alloc::vec::Vec<MatchOffset>,
 }
 #[derive(Serialize, Deserialize, utoipa::ToSchema)]
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -151,6 +523,34 @@ pub struct Hit {
     /// A snippet of the matching content
     #[prost(string, optional, tag = "3")]
     pub snippet: ::core::option::Option<::prost::alloc::string::String>,
+    /// The fields that matched the query, if requested.
+    /// Fields of JSON objects are identified by their full path, e.g. `attributes.user`.
+    #[prost(string, repeated, tag = "4")]
+    pub matched_fields: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// The offsets of the query terms in the document, if requested.
+    #[prost(message, repeated, tag = "5")]
+    pub match_offsets: ::prost::alloc::vec::Vec<MatchOffset>,
+}
+/// Location of a query term within a text value of a hit.
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MatchOffset {
+    /// Name of the text field holding the term.
+    #[prost(string, tag = "1")]
+    pub field_name: ::prost::alloc::string::String,
+    /// Ordinal of the value holding the term, among the values of the field.
+    #[prost(uint32, tag = "2")]
+    pub value_ord: u32,
+    /// Position of the term in the field, as indexed.
+    #[prost(uint32, tag = "3")]
+    pub position: u32,
+    /// Byte offsets of the term in the value, as the semi-open interval
+    /// [start_offset, end_offset).
+    #[prost(uint32, tag = "4")]
+    pub start_offset: u32,
+    #[prost(uint32, tag = "5")]
+    pub end_offset: u32,
 }
 /// A partial hit, is a hit for which we have not fetch the content yet.
 /// Instead, it holds a document_uri which is enough information to
@@ -166,7 +566,10 @@ pub struct PartialHit {
     /// If the user requested for a bottom-K of a given fast field, then quickwit simply
     /// emits an decreasing mapping of this fast field.
     ///
-    /// In case of a tie, quickwit uses the increasing order of
+    /// In case of a tie, quickwit uses the decreasing order of
+    /// - the secondary_sorting_field_values,
+    /// and then the increasing order of
+    /// - the tie_breaker_value,
     /// - the split_id,
     /// - the segment_ord,
     /// - the doc id.
@@ -181,6 +584,38 @@ pub struct PartialHit {
     /// The DocId identifies a unique document at the scale of a tantivy segment.
     #[prost(uint32, tag = "4")]
     pub doc_id: u32,
+    /// Hash of the value of the request's `tie_breaker_field`, 0 if unset.
+    /// Ties on the sorting_field_value are broken by increasing tie_breaker_value
+    /// before falling back to the document address.
+    #[prost(uint64, tag = "5")]
+    pub tie_breaker_value: u64,
+    /// Values of the fast fields required by the request's `top_hits_aggregation_request`,
+    /// keyed by field name. Documents without a value for a field have no entry.
+    #[prost(map = "string, double", tag = "6")]
+    pub docvalue_fields: ::std::collections::HashMap<::prost::alloc::string::String, f64>,
+    /// Sorting values of the secondary sort fields, when sorting by several fields.
+    /// They are compared in order, with the same encoding as the sorting_field_value,
+    /// when the sorting_field_value is a tie.
+    #[prost(uint64, repeated, tag = "7")]
+    pub secondary_sorting_field_values: ::prost::alloc::vec::Vec<u64>,
+    /// Score of the document, only set when sorting by `_score`.
+    /// The sorting values hold an order-preserving mapping of the score.
+    #[prost(float, optional, tag = "8")]
+    pub score: ::core::option::Option<f32>,
+    /// Value of the collapse field of the document, only set when collapsing the hits, or of the
+    /// `diversify_by` field when diversifying them. Documents without a value have no key and are
+    /// grouped together.
+    #[prost(string, optional, tag = "9")]
+    pub collapse_key: ::core::option::Option<::prost::alloc::string::String>,
+    /// JSON serialized values of the sort criteria of the document, in order, only set
+    /// when the request's `return_sort_values` is set. Unlike the sorting values, they
+    /// hold the actual values: a number, a boolean or `null` when the document has no value.
+    #[prost(string, repeated, tag = "10")]
+    pub sort_values: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// Value of the dedup field of the document, only set when deduplicating the hits.
+    /// Documents without a value are never considered duplicates.
+    #[prost(string, optional, tag = "11")]
+    pub dedup_key: ::core::option::Option<::prost::alloc::string::String>,
 }
 #[derive(Serialize, Deserialize, utoipa::ToSchema)]
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -199,11 +634,78 @@ pub struct LeafSearchResponse {
     /// num_attempted_splits = num_successful_splits + num_failed_splits.
     #[prost(uint64, tag = "4")]
     pub num_attempted_splits: u64,
-    /// postcard serialized intermediate aggregation_result.
+    /// postcard serialized intermediate aggregation_result, preceded by the kind of the aggregations
+    /// that produced it.
     #[prost(bytes = "vec", optional, tag = "5")]
     pub intermediate_aggregation_result: ::core::option::Option<
         ::prost::alloc::vec::Vec<u8>,
     >,
+    /// json serialized result of the aggregations computed over `partial_hits`.
+    #[prost(string, optional, tag = "7")]
+    pub top_hits_aggregation: ::core::option::Option<::prost::alloc::string::String>,
+    /// Estimate of the number of documents matched by the query, if requested.
+    #[prost(message, optional, tag = "8")]
+    pub estimated_total_hits: ::core::option::Option<EstimatedTotalHits>,
+    /// The rankings fused into `partial_hits` when rank fusion is requested.
+    /// They are kept to fuse the rankings of several responses.
+    #[prost(message, repeated, tag = "9")]
+    pub rankings: ::prost::alloc::vec::Vec<Ranking>,
+    /// Samples of the partial hits of each bucket, if requested.
+    #[prost(message, repeated, tag = "10")]
+    pub bucket_samples: ::prost::alloc::vec::Vec<LeafBucketSamples>,
+    /// If set, counting stopped early and `num_hits` is a lower bound of the number
+    /// of matching documents.
+    #[prost(bool, tag = "11")]
+    pub num_hits_is_lower_bound: bool,
+    /// If set, the soft deadline of the request passed before all the documents were collected.
+    #[prost(bool, tag = "12")]
+    pub timed_out: bool,
+    /// Number of splits searched successfully, to be compared with `num_attempted_splits`.
+    /// The splits skipped once the soft deadline passed are neither successful nor failed.
+    #[prost(uint64, tag = "13")]
+    pub num_successful_splits: u64,
+    /// Types of the values of the sort criteria when the request's `return_sort_values` is set.
+    /// The leaves searching a split without value for a criterion report `NO_VALUE` for it.
+    #[prost(enumeration = "SortValueType", repeated, tag = "14")]
+    pub sort_value_types: ::prost::alloc::vec::Vec<i32>,
+    /// Statistics of the collection of the matching documents, summed over the segments.
+    #[prost(message, optional, tag = "15")]
+    pub collection_stats: ::core::option::Option<CollectionStats>,
+    /// Set when the request's `max_hits` exceeded the `max_hits_ceiling` of the searcher:
+    /// the number of hits the leaf was allowed to return.
+    #[prost(uint64, optional, tag = "16")]
+    pub clamped_max_hits: ::core::option::Option<u64>,
+    /// Doc ids matching the query in each segment, when the request's `collect_doc_id_sets` is set.
+    #[prost(message, repeated, tag = "17")]
+    pub doc_id_sets: ::prost::alloc::vec::Vec<SegmentDocIdSet>,
+    /// Number of searched splits whose data was served from the caches of the searcher.
+    #[prost(message, optional, tag = "18")]
+    pub cache_status: ::core::option::Option<CacheStatus>,
+    /// Smallest `sorting_field_value` of `partial_hits`, unset without hits. Once the top-K is full,
+    /// documents sorting lower cannot make it into the hits.
+    #[prost(uint64, optional, tag = "19")]
+    pub min_sorting_field_value: ::core::option::Option<u64>,
+    /// Id of the split, set on the response of a single split. Unset once the responses of several
+    /// splits are merged.
+    #[prost(string, optional, tag = "20")]
+    pub split_id: ::core::option::Option<::prost::alloc::string::String>,
+    /// If set, the intermediate aggregation results of some splits could not be decoded, and
+    /// `intermediate_aggregation_result` only merges the other ones. The failing splits are listed in
+    /// `failed_splits` with the `AGGREGATION` code.
+    #[prost(bool, tag = "21")]
+    pub aggregation_is_partial: bool,
+}
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SegmentDocIdSet {
+    #[prost(string, tag = "1")]
+    pub split_id: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "2")]
+    pub segment_ord: u32,
+    /// postcard serialized `DocIdSet` of the doc ids of the segment.
+    #[prost(bytes = "vec", tag = "3")]
+    pub doc_ids: ::prost::alloc::vec::Vec<u8>,
 }
 #[derive(Serialize, Deserialize, utoipa::ToSchema)]
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -409,6 +911,126 @@ impl SortOrder {
         }
     }
 }
+/// Type of the values of a sort criterion.
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum SortValueType {
+    /// The criterion has no value, like `_doc`, or its field has no value in the split.
+    NoValue = 0,
+    U64 = 1,
+    I64 = 2,
+    F64 = 3,
+    Bool = 4,
+    Datetime = 5,
+    /// The score of the documents, possibly boosted.
+    Score = 6,
+}
+impl SortValueType {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            SortValueType::NoValue => "NO_VALUE",
+            SortValueType::U64 => "U64",
+            SortValueType::I64 => "I64",
+            SortValueType::F64 => "F64",
+            SortValueType::Bool => "BOOL",
+            SortValueType::Datetime => "DATETIME",
+            SortValueType::Score => "SCORE",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "NO_VALUE" => Some(Self::NoValue),
+            "U64" => Some(Self::U64),
+            "I64" => Some(Self::I64),
+            "F64" => Some(Self::F64),
+            "BOOL" => Some(Self::Bool),
+            "DATETIME" => Some(Self::Datetime),
+            "SCORE" => Some(Self::Score),
+            _ => None,
+        }
+    }
+}
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum CountHitsMode {
+    /// Counts all the matching documents.
+    CountAll = 0,
+    /// Counts the matching documents until the requested hits are found.
+    Underestimate = 1,
+    /// Counts the matching documents until `count_hits_threshold` is reached.
+    Threshold = 2,
+}
+impl CountHitsMode {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            CountHitsMode::CountAll => "COUNT_ALL",
+            CountHitsMode::Underestimate => "UNDERESTIMATE",
+            CountHitsMode::Threshold => "THRESHOLD",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "COUNT_ALL" => Some(Self::CountAll),
+            "UNDERESTIMATE" => Some(Self::Underestimate),
+            "THRESHOLD" => Some(Self::Threshold),
+            _ => None,
+        }
+    }
+}
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum SplitSearchErrorCode {
+    /// Any other failure, or a failure reported by a searcher unaware of the error codes.
+    Internal = 0,
+    /// Fetching the split from the storage timed out.
+    Timeout = 1,
+    /// The split could not be fetched from the storage.
+    Storage = 2,
+    /// The request requires a fast field that the split does not have.
+    MissingFastField = 3,
+    /// The intermediate aggregation result of the split could not be decoded. The hits of the split
+    /// are still merged.
+    Aggregation = 4,
+}
+impl SplitSearchErrorCode {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            SplitSearchErrorCode::Internal => "INTERNAL",
+            SplitSearchErrorCode::Timeout => "TIMEOUT",
+            SplitSearchErrorCode::Storage => "STORAGE",
+            SplitSearchErrorCode::MissingFastField => "MISSING_FAST_FIELD",
+            SplitSearchErrorCode::Aggregation => "AGGREGATION",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "INTERNAL" => Some(Self::Internal),
+            "TIMEOUT" => Some(Self::Timeout),
+            "STORAGE" => Some(Self::Storage),
+            "MISSING_FAST_FIELD" => Some(Self::MissingFastField),
+            "AGGREGATION" => Some(Self::Aggregation),
+            _ => None,
+        }
+    }
+}
 #[derive(Serialize, Deserialize, utoipa::ToSchema)]
 #[serde(rename_all = "snake_case")]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
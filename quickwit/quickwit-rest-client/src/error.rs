@@ -17,6 +17,8 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
+use std::time::Duration;
+
 use reqwest::StatusCode;
 use serde::Deserialize;
 use thiserror::Error;
@@ -26,6 +28,9 @@ pub static DEFAULT_CONTENT_TYPE: &str = "application/json";
 
 #[derive(Error, Debug)]
 pub enum Error {
+    // The ingest request did not complete, typically waiting for a commit, within the timeout.
+    #[error("Commit timed out after {0:?}.")]
+    CommitTimeout(Duration),
     // Error returned by Quickwit server.
     #[error("Api error: {0}")]
     Api(#[from] ApiError),
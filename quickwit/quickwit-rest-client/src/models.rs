@@ -61,6 +61,14 @@ impl ApiResponse {
         }
     }
 
+    /// Checks status and returns the underlying response, e.g. to read its body incrementally.
+    pub(crate) async fn into_checked_inner(self) -> Result<reqwest::Response, Error> {
+        if self.inner.status().is_client_error() || self.inner.status().is_server_error() {
+            return Err(self.api_error().await);
+        }
+        Ok(self.inner)
+    }
+
     pub async fn deserialize<T: DeserializeOwned>(self) -> Result<T, Error> {
         if self.inner.status().is_client_error() || self.inner.status().is_server_error() {
             Err(self.api_error().await)
@@ -76,3 +84,12 @@ pub enum IngestSource {
     File(PathBuf),
     Stdin,
 }
+
+/// Outcome of the ingestion of a batch of documents read from an NDJSON file.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct IngestBatchReport {
+    /// Number of documents sent to the ingest API.
+    pub num_docs: usize,
+    /// Number of lines rejected because they are not valid JSON objects.
+    pub num_parse_errors: usize,
+}
@@ -17,29 +17,32 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
+use std::path::Path;
 use std::time::Duration;
 
-use bytes::Bytes;
+use bytes::{Buf, Bytes, BytesMut};
 use quickwit_cluster::ClusterSnapshot;
 use quickwit_common::FileEntry;
 use quickwit_config::{ConfigFormat, SourceConfig};
 use quickwit_indexing::actors::IndexingServiceCounters;
 pub use quickwit_ingest::CommitType;
 use quickwit_metastore::{IndexMetadata, Split};
+use quickwit_proto::metastore_api::DeleteTask;
 use quickwit_search::SearchResponseRest;
-use quickwit_serve::{ListSplitsQueryParams, SearchRequestQueryString};
+use quickwit_serve::{DeleteQueryRequest, ListSplitsQueryParams, SearchRequestQueryString};
 use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
 use reqwest::{Client, Method, StatusCode, Url};
 use serde::Serialize;
-use serde_json::json;
+use serde_json::{json, Map as JsonMap, Value as JsonValue};
 
 use crate::error::Error;
-use crate::models::{ApiResponse, IngestSource};
+use crate::models::{ApiResponse, IngestBatchReport, IngestSource};
 use crate::BatchLineReader;
 
 pub const DEFAULT_BASE_URL: &str = "http://127.0.0.1:7280";
 pub const DEFAULT_CONTENT_TYPE: &str = "application/json";
 pub const INGEST_CONTENT_LENGTH_LIMIT: usize = 10 * 1024 * 1024; // 10MiB
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct Transport {
     base_url: Url,
@@ -83,6 +86,28 @@ impl Transport {
         header_map: Option<HeaderMap>,
         query_string: Option<&Q>,
         body: Option<Bytes>,
+    ) -> Result<ApiResponse, Error> {
+        self.send_with_timeout(
+            method,
+            path,
+            header_map,
+            query_string,
+            body,
+            Some(DEFAULT_REQUEST_TIMEOUT),
+        )
+        .await
+    }
+
+    /// Creates an asynchronous request that can be awaited. Without timeout, the response body
+    /// can be read for as long as needed.
+    async fn send_with_timeout<Q: Serialize + ?Sized>(
+        &self,
+        method: Method,
+        path: &str,
+        header_map: Option<HeaderMap>,
+        query_string: Option<&Q>,
+        body: Option<Bytes>,
+        timeout_opt: Option<Duration>,
     ) -> Result<ApiResponse, Error> {
         let url = if path.starts_with('/') {
             self.base_url.join(path)
@@ -91,7 +116,9 @@ impl Transport {
         }
         .map_err(|error| Error::UrlParse(error.to_string()))?;
         let mut request_builder = self.client.request(method, url);
-        request_builder = request_builder.timeout(Duration::from_secs(10));
+        if let Some(timeout) = timeout_opt {
+            request_builder = request_builder.timeout(timeout);
+        }
         let mut request_headers = HeaderMap::new();
         request_headers.insert(CONTENT_TYPE, HeaderValue::from_static(DEFAULT_CONTENT_TYPE));
         if let Some(header_map_val) = header_map {
@@ -139,6 +166,57 @@ impl QuickwitClient {
         Ok(search_response)
     }
 
+    /// Creates a delete task deleting the documents of the index that match the query, restricted
+    /// to the documents with a timestamp in `[start_timestamp, end_timestamp)` if set. Returns
+    /// the opstamp identifying the delete task.
+    pub async fn delete_by_query(
+        &self,
+        index_id: &str,
+        query: &str,
+        start_timestamp: Option<i64>,
+        end_timestamp: Option<i64>,
+    ) -> Result<u64, Error> {
+        let path = format!("{index_id}/delete-tasks");
+        let delete_query = DeleteQueryRequest {
+            query: query.to_string(),
+            search_fields: Vec::new(),
+            start_timestamp,
+            end_timestamp,
+        };
+        let body = Bytes::from(serde_json::to_vec(&delete_query).unwrap());
+        let response = self
+            .transport
+            .send::<()>(Method::POST, &path, None, None, Some(body))
+            .await?;
+        let delete_task: DeleteTask = response.deserialize().await?;
+        Ok(delete_task.opstamp)
+    }
+
+    /// Streams the values of a fast field for all the documents of the index matching the query,
+    /// one CSV row per document.
+    ///
+    /// The rows are read from the response as [`SearchStream::next_row`] is called, so a slow
+    /// consumer slows down the transfer instead of buffering the whole result set.
+    pub async fn search_stream(
+        &self,
+        index_id: &str,
+        query: &str,
+        fast_field: &str,
+    ) -> Result<SearchStream, Error> {
+        let path = format!("{index_id}/search/stream");
+        let query_params = [
+            ("query", query),
+            ("fast_field", fast_field),
+            ("output_format", "csv"),
+        ];
+        let response = self
+            .transport
+            .send_with_timeout(Method::GET, &path, None, Some(&query_params), None, None)
+            .await?;
+        let response = response.into_checked_inner().await?;
+        Ok(SearchStream::new(response))
+    }
+
     pub fn indexes(&self) -> IndexClient {
         IndexClient::new(&self.transport)
     }
@@ -163,12 +241,19 @@ impl QuickwitClient {
         NodeHealthClient::new(&self.transport)
     }
 
+    /// Ingests the documents of `ingest_source` in batches, requesting the `last_block_commit`
+    /// commit behavior with the last batch.
+    ///
+    /// `commit_timeout_opt` bounds the request sending the last batch, which may have to wait for
+    /// a commit. When it elapses, [`Error::CommitTimeout`] is returned and the ingestion can be
+    /// retried.
     pub async fn ingest(
         &self,
         index_id: &str,
         ingest_source: IngestSource,
         on_ingest_event: Option<&dyn Fn(IngestEvent)>,
         last_block_commit: CommitType,
+        commit_timeout_opt: Option<Duration>,
     ) -> Result<(), Error> {
         let ingest_path = format!("{index_id}/ingest");
         let mut batch_reader = match ingest_source {
@@ -181,41 +266,174 @@ impl QuickwitClient {
             }
         };
         while let Some(batch) = batch_reader.next_batch().await? {
-            loop {
-                let query_params = if !batch_reader.has_next() {
-                    last_block_commit.to_query_parameter()
-                } else {
-                    None
-                };
-
-                let response = self
-                    .transport
-                    .send(
-                        Method::POST,
-                        &ingest_path,
-                        None,
-                        query_params,
-                        Some(batch.clone()),
-                    )
-                    .await?;
-
-                if response.status_code() == StatusCode::TOO_MANY_REQUESTS {
-                    if let Some(event_fn) = &on_ingest_event {
-                        event_fn(IngestEvent::Sleep)
-                    }
-                    tokio::time::sleep(Duration::from_secs(1)).await;
-                } else {
-                    response.check().await?;
-                    break;
-                }
-            }
+            let (query_params, commit_timeout_opt) = if !batch_reader.has_next() {
+                (last_block_commit.to_query_parameter(), commit_timeout_opt)
+            } else {
+                (None, None)
+            };
+            let batch_len = batch.len();
+            self.ingest_batch(
+                &ingest_path,
+                batch,
+                query_params,
+                commit_timeout_opt,
+                on_ingest_event,
+            )
+            .await?;
             if let Some(event_fn) = on_ingest_event.as_ref() {
-                event_fn(IngestEvent::IngestedDocBatch(batch.len()))
+                event_fn(IngestEvent::IngestedDocBatch(batch_len))
             }
         }
 
         Ok(())
     }
+
+    /// Streams a newline-delimited JSON file to the ingest API in batches of at most
+    /// [`INGEST_CONTENT_LENGTH_LIMIT`] bytes.
+    ///
+    /// The lines that are not valid JSON objects are not sent: they are counted in the report of
+    /// their batch instead. `commit_timeout_opt` bounds the request sending the last batch, see
+    /// [`QuickwitClient::ingest`].
+    pub async fn ingest_ndjson_file(
+        &self,
+        index_id: &str,
+        filepath: &Path,
+        last_block_commit: CommitType,
+        commit_timeout_opt: Option<Duration>,
+    ) -> Result<Vec<IngestBatchReport>, Error> {
+        let ingest_path = format!("{index_id}/ingest");
+        let mut batch_reader =
+            BatchLineReader::from_file(filepath, INGEST_CONTENT_LENGTH_LIMIT).await?;
+        let mut batch_reports = Vec::new();
+
+        while let Some(batch) = batch_reader.next_batch().await? {
+            let (query_params, commit_timeout_opt) = if !batch_reader.has_next() {
+                (last_block_commit.to_query_parameter(), commit_timeout_opt)
+            } else {
+                (None, None)
+            };
+            let (valid_docs, batch_report) = filter_ndjson_batch(&batch);
+            self.ingest_batch(
+                &ingest_path,
+                valid_docs,
+                query_params,
+                commit_timeout_opt,
+                None,
+            )
+            .await?;
+            batch_reports.push(batch_report);
+        }
+        Ok(batch_reports)
+    }
+
+    /// Sends a batch of documents to the ingest API, retrying as long as the ingest API is rate
+    /// limited. A request exceeding `commit_timeout_opt` fails with [`Error::CommitTimeout`].
+    async fn ingest_batch(
+        &self,
+        ingest_path: &str,
+        batch: Bytes,
+        query_params: Option<&[(&str, &str)]>,
+        commit_timeout_opt: Option<Duration>,
+        on_ingest_event: Option<&dyn Fn(IngestEvent)>,
+    ) -> Result<(), Error> {
+        let timeout = commit_timeout_opt.unwrap_or(DEFAULT_REQUEST_TIMEOUT);
+        loop {
+            let response = self
+                .transport
+                .send_with_timeout(
+                    Method::POST,
+                    ingest_path,
+                    None,
+                    query_params,
+                    Some(batch.clone()),
+                    Some(timeout),
+                )
+                .await
+                .map_err(|error| match (error, commit_timeout_opt) {
+                    (Error::Client(client_error), Some(commit_timeout))
+                        if client_error.is_timeout() =>
+                    {
+                        Error::CommitTimeout(commit_timeout)
+                    }
+                    (error, _) => error,
+                })?;
+
+            if response.status_code() == StatusCode::TOO_MANY_REQUESTS {
+                if let Some(event_fn) = &on_ingest_event {
+                    event_fn(IngestEvent::Sleep)
+                }
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            } else {
+                response.check().await?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Drops the lines of an NDJSON batch that are not valid JSON objects and counts them.
+fn filter_ndjson_batch(batch: &[u8]) -> (Bytes, IngestBatchReport) {
+    let mut valid_docs = Vec::with_capacity(batch.len());
+    let mut batch_report = IngestBatchReport::default();
+
+    for line in batch.split(|byte| *byte == b'\n') {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        if serde_json::from_slice::<JsonMap<String, JsonValue>>(line).is_ok() {
+            valid_docs.extend_from_slice(line);
+            valid_docs.push(b'\n');
+            batch_report.num_docs += 1;
+        } else {
+            batch_report.num_parse_errors += 1;
+        }
+    }
+    (Bytes::from(valid_docs), batch_report)
+}
+
+/// Rows of a search stream, read from the response on demand.
+pub struct SearchStream {
+    response: reqwest::Response,
+    buffer: BytesMut,
+    // Number of bytes at the start of the buffer known not to contain a row delimiter.
+    num_scanned_bytes: usize,
+}
+
+impl SearchStream {
+    fn new(response: reqwest::Response) -> Self {
+        Self {
+            response,
+            buffer: BytesMut::new(),
+            num_scanned_bytes: 0,
+        }
+    }
+
+    /// Returns the next row without its trailing newline, or `None` once the stream is over.
+    pub async fn next_row(&mut self) -> Result<Option<Bytes>, Error> {
+        loop {
+            if let Some(position) = self.buffer[self.num_scanned_bytes..]
+                .iter()
+                .position(|byte| *byte == b'\n')
+            {
+                let row_len = self.num_scanned_bytes + position;
+                let row = self.buffer.split_to(row_len).freeze();
+                // Skips the delimiter.
+                self.buffer.advance(1);
+                self.num_scanned_bytes = 0;
+                return Ok(Some(row));
+            }
+            self.num_scanned_bytes = self.buffer.len();
+
+            let Some(chunk) = self.response.chunk().await? else {
+                if self.buffer.is_empty() {
+                    return Ok(None);
+                }
+                self.num_scanned_bytes = 0;
+                return Ok(Some(self.buffer.split().freeze()));
+            };
+            self.buffer.extend_from_slice(&chunk);
+        }
+    }
 }
 
 pub enum IngestEvent {
@@ -530,6 +748,7 @@ fn header_from_config_format(config_format: ConfigFormat) -> HeaderMap {
 mod test {
     use std::path::PathBuf;
     use std::str::FromStr;
+    use std::time::Duration;
 
     use bytes::Bytes;
     use quickwit_config::{ConfigFormat, SourceConfig};
@@ -550,7 +769,7 @@ mod test {
 
     use super::{QuickwitClient, Transport};
     use crate::error::Error;
-    use crate::models::IngestSource;
+    use crate::models::{IngestBatchReport, IngestSource};
 
     #[test]
     fn test_transport_urls() {
@@ -587,11 +806,15 @@ mod test {
         };
         let expected_search_response = SearchResponseRest {
             num_hits: 0,
+            estimated_total_hits: None,
             hits: Vec::new(),
             snippets: None,
             aggregations: None,
             elapsed_time_micros: 100,
             errors: Vec::new(),
+            sort_values: None,
+            sort_value_types: Vec::new(),
+            timed_out: false,
         };
         Mock::given(method("POST"))
             .and(path("/api/v1/my-index/search"))
@@ -610,6 +833,63 @@ mod test {
         );
     }
 
+    #[tokio::test]
+    async fn test_search_stream_endpoint() {
+        let mock_server = MockServer::start().await;
+        let server_url = Url::parse(&mock_server.uri()).unwrap();
+        let qw_client = QuickwitClient::new(Transport::new(server_url));
+        Mock::given(method("GET"))
+            .and(path("/api/v1/my-index/search/stream"))
+            .and(query_param("query", "body:foo"))
+            .and(query_param("fast_field", "id"))
+            .and(query_param("output_format", "csv"))
+            .respond_with(ResponseTemplate::new(StatusCode::OK).set_body_string("1\n22\n333"))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        let mut search_stream = qw_client
+            .search_stream("my-index", "body:foo", "id")
+            .await
+            .unwrap();
+        let mut rows = Vec::new();
+        while let Some(row) = search_stream.next_row().await.unwrap() {
+            rows.push(row);
+        }
+        assert_eq!(rows, vec!["1", "22", "333"]);
+    }
+
+    #[tokio::test]
+    async fn test_delete_by_query_endpoint() {
+        let mock_server = MockServer::start().await;
+        let server_url = Url::parse(&mock_server.uri()).unwrap();
+        let qw_client = QuickwitClient::new(Transport::new(server_url));
+        Mock::given(method("POST"))
+            .and(path("/api/v1/my-index/delete-tasks"))
+            .and(body_json(json!({
+                "query": "tenant:acme",
+                "search_fields": [],
+                "start_timestamp": 10,
+                "end_timestamp": null,
+            })))
+            .respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!({
+                "create_timestamp": 1,
+                "opstamp": 3,
+                "delete_query": {
+                    "index_id": "my-index",
+                    "query": "tenant:acme",
+                    "start_timestamp": 10,
+                },
+            })))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        let opstamp = qw_client
+            .delete_by_query("my-index", "tenant:acme", Some(10), None)
+            .await
+            .unwrap();
+        assert_eq!(opstamp, 3);
+    }
+
     fn get_ndjson_filepath(ndjson_dataset_filename: &str) -> String {
         format!(
             "{}/resources/tests/{}",
@@ -650,7 +930,7 @@ mod test {
             .await;
         let ingest_source = IngestSource::File(PathBuf::from_str(&ndjson_filepath).unwrap());
         qw_client
-            .ingest("my-index", ingest_source, None, CommitType::Auto)
+            .ingest("my-index", ingest_source, None, CommitType::Auto, None)
             .await
             .unwrap();
     }
@@ -678,7 +958,7 @@ mod test {
             .await;
         let ingest_source = IngestSource::File(PathBuf::from_str(&ndjson_filepath).unwrap());
         qw_client
-            .ingest("my-index", ingest_source, None, CommitType::Force)
+            .ingest("my-index", ingest_source, None, CommitType::Force, None)
             .await
             .unwrap();
     }
@@ -706,9 +986,74 @@ mod test {
             .await;
         let ingest_source = IngestSource::File(PathBuf::from_str(&ndjson_filepath).unwrap());
         qw_client
-            .ingest("my-index", ingest_source, None, CommitType::WaitFor)
+            .ingest("my-index", ingest_source, None, CommitType::WaitFor, None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ingest_endpoint_should_return_commit_timeout_error() {
+        let mock_server = MockServer::start().await;
+        let server_url = Url::parse(&mock_server.uri()).unwrap();
+        let qw_client = QuickwitClient::new(Transport::new(server_url));
+        let ndjson_filepath = get_ndjson_filepath("documents_to_ingest.json");
+        Mock::given(method("POST"))
+            .and(path("/api/v1/my-index/ingest"))
+            .and(query_param("commit", "wait_for"))
+            .respond_with(ResponseTemplate::new(StatusCode::OK).set_delay(Duration::from_secs(5)))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        let ingest_source = IngestSource::File(PathBuf::from_str(&ndjson_filepath).unwrap());
+        let error = qw_client
+            .ingest(
+                "my-index",
+                ingest_source,
+                None,
+                CommitType::WaitFor,
+                Some(Duration::from_millis(100)),
+            )
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, Error::CommitTimeout(timeout) if timeout == Duration::from_millis(100))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ingest_ndjson_file() {
+        let mock_server = MockServer::start().await;
+        let server_url = Url::parse(&mock_server.uri()).unwrap();
+        let qw_client = QuickwitClient::new(Transport::new(server_url));
+        let ndjson_filepath = get_ndjson_filepath("documents_to_ingest_with_parse_errors.json");
+        Mock::given(method("POST"))
+            .and(path("/api/v1/my-index/ingest"))
+            .and(query_param("commit", "force"))
+            .and(body_bytes(
+                b"{\"user\":\"8\",\"tags\":[\"rust\"]}\n{\"user\":\"6\",\"tags\":[\"go\"]}\n"
+                    .to_vec(),
+            ))
+            .respond_with(ResponseTemplate::new(StatusCode::OK))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let batch_reports = qw_client
+            .ingest_ndjson_file(
+                "my-index",
+                &PathBuf::from_str(&ndjson_filepath).unwrap(),
+                CommitType::Force,
+                None,
+            )
             .await
             .unwrap();
+        assert_eq!(
+            batch_reports,
+            vec![IngestBatchReport {
+                num_docs: 2,
+                num_parse_errors: 2,
+            }]
+        );
     }
 
     #[tokio::test]
@@ -735,7 +1080,7 @@ mod test {
             .await;
         let ingest_source = IngestSource::File(PathBuf::from_str(&ndjson_filepath).unwrap());
         let error = qw_client
-            .ingest("my-index", ingest_source, None, CommitType::Auto)
+            .ingest("my-index", ingest_source, None, CommitType::Auto, None)
             .await
             .unwrap_err();
         assert!(matches!(error, Error::Api(_)));
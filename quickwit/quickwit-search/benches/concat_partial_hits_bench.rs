@@ -0,0 +1,67 @@
+// Copyright (C) 2023 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use quickwit_proto::PartialHit;
+use quickwit_search::concat_partial_hits;
+
+const NUM_LEAVES: usize = 500;
+const NUM_HITS_PER_LEAF: usize = 100;
+
+fn make_partial_hits_per_leaf() -> Vec<Vec<PartialHit>> {
+    (0..NUM_LEAVES)
+        .map(|leaf_ord| {
+            (0..NUM_HITS_PER_LEAF)
+                .map(|doc_ord| PartialHit {
+                    split_id: format!("split_{leaf_ord:03}"),
+                    doc_id: doc_ord as u32,
+                    ..Default::default()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The former concatenation, which grows the output as the hits come.
+fn flatten_partial_hits(partial_hits_per_leaf: Vec<Vec<PartialHit>>) -> Vec<PartialHit> {
+    partial_hits_per_leaf.into_iter().flatten().collect()
+}
+
+pub fn concat_partial_hits_benchmark(c: &mut Criterion) {
+    let partial_hits_per_leaf = make_partial_hits_per_leaf();
+    let mut group = c.benchmark_group("concat-partial-hits");
+    group.bench_function("flatten", |b| {
+        b.iter_batched(
+            || partial_hits_per_leaf.clone(),
+            flatten_partial_hits,
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("with-capacity", |b| {
+        b.iter_batched(
+            || partial_hits_per_leaf.clone(),
+            concat_partial_hits,
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, concat_partial_hits_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,105 @@
+// Copyright (C) 2023 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use quickwit_indexing::TestSandbox;
+use quickwit_proto::{FastFieldEquals, SearchRequest};
+use quickwit_search::single_node_search;
+use serde_json::json;
+
+const INDEX_ID: &str = "fast-field-equals-filter-bench";
+
+const DOC_MAPPING_YAML: &str = r#"
+    field_mappings:
+      - name: body
+        type: text
+      - name: request_id
+        type: u64
+        fast: true
+"#;
+
+const NUM_SPLITS: u64 = 4;
+const NUM_DOCS_PER_SPLIT: u64 = 50_000;
+
+/// Every document matches the broad text query, and a single one has the requested id.
+fn make_search_request(
+    query: &str,
+    fast_field_equals_opt: Option<FastFieldEquals>,
+) -> SearchRequest {
+    SearchRequest {
+        index_id: INDEX_ID.to_string(),
+        query: query.to_string(),
+        max_hits: 10,
+        fast_field_equals: fast_field_equals_opt,
+        ..Default::default()
+    }
+}
+
+pub fn fast_field_equals_filter_benchmark(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let test_sandbox = runtime.block_on(async {
+        let test_sandbox = TestSandbox::create(INDEX_ID, DOC_MAPPING_YAML, "{}", &["body"])
+            .await
+            .unwrap();
+        for split_ord in 0..NUM_SPLITS {
+            let docs = (0..NUM_DOCS_PER_SPLIT).map(move |doc_ord| {
+                json!({
+                    "body": "info request served",
+                    "request_id": split_ord * NUM_DOCS_PER_SPLIT + doc_ord,
+                })
+            });
+            test_sandbox.add_documents(docs).await.unwrap();
+        }
+        test_sandbox
+    });
+    let requested_id = NUM_SPLITS * NUM_DOCS_PER_SPLIT / 2;
+    let filter_request = make_search_request(
+        "request",
+        Some(FastFieldEquals {
+            field_name: "request_id".to_string(),
+            value: requested_id.to_string(),
+        }),
+    );
+    let boolean_query_request =
+        make_search_request(&format!("request AND request_id:{requested_id}"), None);
+
+    let mut group = c.benchmark_group("fast-field-equals-filter");
+    for (bench_name, search_request) in [
+        ("collection-time-filter", &filter_request),
+        ("boolean-query", &boolean_query_request),
+    ] {
+        group.bench_function(bench_name, |b| {
+            b.to_async(&runtime).iter(|| async {
+                let search_response = single_node_search(
+                    search_request,
+                    &*test_sandbox.metastore(),
+                    test_sandbox.storage_uri_resolver(),
+                )
+                .await
+                .unwrap();
+                assert_eq!(search_response.num_hits, 1);
+            })
+        });
+    }
+    group.finish();
+    runtime.block_on(test_sandbox.assert_quit());
+}
+
+criterion_group!(benches, fast_field_equals_filter_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,116 @@
+// Copyright (C) 2023 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use std::cmp::Reverse;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use quickwit_proto::{PartialHit, SortOrder};
+use quickwit_search::merge_partial_hits;
+
+const NUM_SPLITS: usize = 100;
+const NUM_HITS_PER_SPLIT: usize = 1_000;
+
+/// Returns the hits of each split, sorted like the hits of leaf responses.
+fn make_partial_hits_per_split() -> Vec<Vec<PartialHit>> {
+    (0..NUM_SPLITS)
+        .map(|split_ord| {
+            let mut partial_hits: Vec<PartialHit> = (0..NUM_HITS_PER_SPLIT)
+                .map(|doc_ord| {
+                    let seed = (split_ord * NUM_HITS_PER_SPLIT + doc_ord) as u64;
+                    PartialHit {
+                        // Pseudo-random values, with a few ties.
+                        sorting_field_value: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) % 100_000,
+                        split_id: format!("split_{split_ord:03}"),
+                        segment_ord: 0,
+                        doc_id: doc_ord as u32,
+                        ..Default::default()
+                    }
+                })
+                .collect();
+            partial_hits.sort_unstable_by_key(|partial_hit| {
+                (Reverse(partial_hit.sorting_field_value), partial_hit.doc_id)
+            });
+            partial_hits
+        })
+        .collect()
+}
+
+/// The former merge: concatenates the hits of all the splits and sorts them.
+fn concat_and_sort_partial_hits(
+    partial_hits_per_split: Vec<Vec<PartialHit>>,
+    num_hits: usize,
+) -> Vec<PartialHit> {
+    let mut partial_hits: Vec<PartialHit> = partial_hits_per_split.into_iter().flatten().collect();
+    partial_hits.sort_unstable_by(|left, right| {
+        let left_key = (
+            Reverse(left.sorting_field_value),
+            left.split_id.as_str(),
+            left.doc_id,
+        );
+        let right_key = (
+            Reverse(right.sorting_field_value),
+            right.split_id.as_str(),
+            right.doc_id,
+        );
+        left_key.cmp(&right_key)
+    });
+    partial_hits.truncate(num_hits);
+    partial_hits
+}
+
+pub fn merge_partial_hits_benchmark(c: &mut Criterion) {
+    let partial_hits_per_split = make_partial_hits_per_split();
+    let mut group = c.benchmark_group("merge-partial-hits");
+    for num_hits in [10, NUM_HITS_PER_SPLIT] {
+        assert_eq!(
+            merge_partial_hits(partial_hits_per_split.clone(), num_hits, SortOrder::Asc),
+            concat_and_sort_partial_hits(partial_hits_per_split.clone(), num_hits)
+        );
+        group.bench_with_input(
+            BenchmarkId::new("k-way-merge", num_hits),
+            &num_hits,
+            |b, &num_hits| {
+                b.iter_batched(
+                    || partial_hits_per_split.clone(),
+                    |partial_hits_per_split| {
+                        merge_partial_hits(partial_hits_per_split, num_hits, SortOrder::Asc)
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("concat-and-sort", num_hits),
+            &num_hits,
+            |b, &num_hits| {
+                b.iter_batched(
+                    || partial_hits_per_split.clone(),
+                    |partial_hits_per_split| {
+                        concat_and_sort_partial_hits(partial_hits_per_split, num_hits)
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, merge_partial_hits_benchmark);
+criterion_main!(benches);
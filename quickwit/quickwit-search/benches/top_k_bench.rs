@@ -0,0 +1,80 @@
+// Copyright (C) 2023 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::BinaryHeap;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use quickwit_search::{offer_to_top_k, SortedTopK, TopK};
+
+const NUM_DOCS: u32 = 1_000_000;
+
+/// The hits of a synthetic segment, as `(sorting key, doc id)` pairs visited in doc id order.
+fn make_segment_hits() -> Vec<(u64, u32)> {
+    (0..NUM_DOCS)
+        .map(|doc_id| {
+            // Pseudo-random values, with a few ties.
+            let sorting_key = (doc_id as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) % 100_000;
+            (sorting_key, doc_id)
+        })
+        .collect()
+}
+
+fn collect_top_k(
+    mut top_k: impl TopK<(u64, u32)>,
+    segment_hits: &[(u64, u32)],
+    max_hits: usize,
+) -> Vec<(u64, u32)> {
+    for hit in segment_hits {
+        offer_to_top_k(&mut top_k, *hit, max_hits);
+    }
+    top_k.into_sorted_vec()
+}
+
+pub fn top_k_benchmark(c: &mut Criterion) {
+    let segment_hits = make_segment_hits();
+    let mut group = c.benchmark_group("top-k");
+    for max_hits in [5, 50, 1_000] {
+        assert_eq!(
+            collect_top_k(SortedTopK::with_capacity(max_hits), &segment_hits, max_hits),
+            collect_top_k(BinaryHeap::with_capacity(max_hits), &segment_hits, max_hits)
+        );
+        group.bench_with_input(
+            BenchmarkId::new("sorted-vec", max_hits),
+            &max_hits,
+            |b, &max_hits| {
+                b.iter(|| {
+                    collect_top_k(SortedTopK::with_capacity(max_hits), &segment_hits, max_hits)
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("binary-heap", max_hits),
+            &max_hits,
+            |b, &max_hits| {
+                b.iter(|| {
+                    collect_top_k(BinaryHeap::with_capacity(max_hits), &segment_hits, max_hits)
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, top_k_benchmark);
+criterion_main!(benches);
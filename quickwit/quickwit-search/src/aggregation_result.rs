@@ -0,0 +1,421 @@
+// Copyright (C) 2023 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tantivy::aggregation::intermediate_agg_result::IntermediateAggregationResults;
+use tantivy::TantivyError;
+
+use crate::collector::serialize_fruit;
+use crate::distinct_count_collector::HyperLogLog;
+use crate::find_trace_ids_collector::Span;
+use crate::timestamp_range_collector::TimestampRange;
+
+/// Kind of the aggregations producing an intermediate aggregation result.
+///
+/// The serialized intermediate aggregation results of the leaf responses start with their kind,
+/// so that the result of a kind of aggregations cannot be decoded as the result of another kind.
+/// Leaves predating the kind serialize their results without it, which are still accepted, see
+/// [`deserialize_tagged_fruit`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum AggregationKind {
+    // The kinds are serialized as their index: new kinds go last.
+    FindTraceIds,
+    DistinctCount,
+    Tantivy,
+    TimestampRange,
+}
+
+/// Intermediate aggregation result decoded along with its kind.
+///
+/// The variants follow the order of the kinds of [`AggregationKind`]: postcard serializes a
+/// variant as its index followed by its content, which is the layout written by
+/// [`serialize_tagged_fruit`]. The two orders are checked to match by the tests.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) enum IntermediateAggregationResult {
+    FindTraceIds(Vec<Span>),
+    DistinctCount(HyperLogLog),
+    Tantivy(IntermediateAggregationResults),
+    /// `None` if no matching document has a timestamp.
+    TimestampRange(Option<TimestampRange>),
+}
+
+impl IntermediateAggregationResult {
+    pub fn kind(&self) -> AggregationKind {
+        match self {
+            IntermediateAggregationResult::FindTraceIds(_) => AggregationKind::FindTraceIds,
+            IntermediateAggregationResult::DistinctCount(_) => AggregationKind::DistinctCount,
+            IntermediateAggregationResult::Tantivy(_) => AggregationKind::Tantivy,
+            IntermediateAggregationResult::TimestampRange(_) => AggregationKind::TimestampRange,
+        }
+    }
+
+    /// Deserializes the result of aggregations of the expected kind, see
+    /// [`deserialize_tagged_fruit`].
+    pub fn from_bytes(serialized: &[u8], expected_kind: AggregationKind) -> tantivy::Result<Self> {
+        let result = match expected_kind {
+            AggregationKind::FindTraceIds => IntermediateAggregationResult::FindTraceIds(
+                deserialize_tagged_fruit(serialized, expected_kind)?,
+            ),
+            AggregationKind::DistinctCount => IntermediateAggregationResult::DistinctCount(
+                deserialize_tagged_fruit(serialized, expected_kind)?,
+            ),
+            AggregationKind::Tantivy => IntermediateAggregationResult::Tantivy(
+                deserialize_tagged_fruit(serialized, expected_kind)?,
+            ),
+            AggregationKind::TimestampRange => IntermediateAggregationResult::TimestampRange(
+                deserialize_tagged_fruit(serialized, expected_kind)?,
+            ),
+        };
+        Ok(result)
+    }
+
+    pub fn to_bytes(&self) -> tantivy::Result<Vec<u8>> {
+        serialize_fruit(self).map_err(map_error)
+    }
+
+    /// Merges two results of the same kind, without the request. The spans of trace ids are
+    /// concatenated, so that the merge of the `FindTraceIdsCollector` can later truncate them
+    /// to the requested number of traces.
+    pub fn merge(self, other: Self) -> tantivy::Result<Self> {
+        let merged = match (self, other) {
+            (
+                IntermediateAggregationResult::FindTraceIds(mut spans),
+                IntermediateAggregationResult::FindTraceIds(other_spans),
+            ) => {
+                spans.extend(other_spans);
+                IntermediateAggregationResult::FindTraceIds(spans)
+            }
+            (
+                IntermediateAggregationResult::DistinctCount(mut sketch),
+                IntermediateAggregationResult::DistinctCount(other_sketch),
+            ) => {
+                sketch.merge(&other_sketch);
+                IntermediateAggregationResult::DistinctCount(sketch)
+            }
+            (
+                IntermediateAggregationResult::TimestampRange(timestamp_range_opt),
+                IntermediateAggregationResult::TimestampRange(other_timestamp_range_opt),
+            ) => IntermediateAggregationResult::TimestampRange(
+                match (timestamp_range_opt, other_timestamp_range_opt) {
+                    (Some(timestamp_range), Some(other_timestamp_range)) => {
+                        Some(timestamp_range.merge(other_timestamp_range))
+                    }
+                    (timestamp_range_opt, other_timestamp_range_opt) => {
+                        timestamp_range_opt.or(other_timestamp_range_opt)
+                    }
+                },
+            ),
+            (
+                IntermediateAggregationResult::Tantivy(mut results),
+                IntermediateAggregationResult::Tantivy(other_results),
+            ) => {
+                results.merge_fruits(other_results)?;
+                IntermediateAggregationResult::Tantivy(results)
+            }
+            (left, right) => return Err(kind_mismatch_error(left.kind(), right.kind())),
+        };
+        Ok(merged)
+    }
+}
+
+/// Serializes the fruit of aggregations of the given kind, preceded by the kind.
+pub(crate) fn serialize_tagged_fruit<T: Serialize>(
+    kind: AggregationKind,
+    fruit: &T,
+) -> tantivy::Result<Vec<u8>> {
+    serialize_fruit(&(kind, fruit)).map_err(map_error)
+}
+
+/// Deserializes a fruit serialized with [`serialize_tagged_fruit`], after checking that it was
+/// produced by aggregations of the expected kind.
+///
+/// Leaves predating the kind serialize their fruits without it: a fruit that cannot be decoded
+/// along with the expected kind is decoded as such a fruit instead, before giving up.
+pub(crate) fn deserialize_tagged_fruit<T: DeserializeOwned>(
+    serialized: &[u8],
+    expected_kind: AggregationKind,
+) -> tantivy::Result<T> {
+    let tagged_error = match check_tagged_fruit_kind(serialized, expected_kind)
+        .and_then(deserialize_whole_fruit)
+    {
+        Ok(fruit) => return Ok(fruit),
+        Err(tagged_error) => tagged_error,
+    };
+    deserialize_whole_fruit(serialized).map_err(|_| tagged_error)
+}
+
+/// Returns a fruit serialized with [`serialize_tagged_fruit`] as is, without decoding it, after
+/// checking that it was produced by aggregations of the expected kind. A fruit serialized without
+/// its kind by a leaf predating it is decoded and serialized again along with its kind.
+pub(crate) fn ensure_tagged_fruit(
+    serialized: Vec<u8>,
+    expected_kind: AggregationKind,
+) -> tantivy::Result<Vec<u8>> {
+    if check_tagged_fruit_kind(&serialized, expected_kind).is_ok() {
+        return Ok(serialized);
+    }
+    IntermediateAggregationResult::from_bytes(&serialized, expected_kind)?.to_bytes()
+}
+
+/// Deserializes a fruit spanning all the given bytes. Rejecting trailing bytes keeps a fruit
+/// serialized along with its kind from being mistaken for a fruit serialized without it, and
+/// conversely.
+fn deserialize_whole_fruit<T: DeserializeOwned>(serialized: &[u8]) -> tantivy::Result<T> {
+    let (fruit, remaining) = postcard::take_from_bytes(serialized).map_err(map_error)?;
+    if !remaining.is_empty() {
+        return Err(TantivyError::InternalError(format!(
+            "Aggregation Result Postcard Error: {} trailing bytes",
+            remaining.len()
+        )));
+    }
+    Ok(fruit)
+}
+
+/// Checks that a fruit serialized with [`serialize_tagged_fruit`] was produced by aggregations of
+/// the expected kind, by only decoding its kind. Returns the serialized fruit without its kind.
+fn check_tagged_fruit_kind(
+    serialized: &[u8],
+    expected_kind: AggregationKind,
+) -> tantivy::Result<&[u8]> {
+    let (kind, serialized_fruit): (AggregationKind, &[u8]) =
+        postcard::take_from_bytes(serialized).map_err(map_error)?;
+    if kind != expected_kind {
+        return Err(kind_mismatch_error(expected_kind, kind));
+    }
+    Ok(serialized_fruit)
+}
+
+fn kind_mismatch_error(expected_kind: AggregationKind, kind: AggregationKind) -> TantivyError {
+    TantivyError::InternalError(format!(
+        "expected an intermediate aggregation result of kind {expected_kind:?}, got one of kind \
+         {kind:?}"
+    ))
+}
+
+fn map_error(error: postcard::Error) -> TantivyError {
+    TantivyError::InternalError(format!("Aggregation Result Postcard Error: {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use quickwit_opentelemetry::otlp::TraceId;
+    use tantivy::collector::Collector;
+    use tantivy::DateTime;
+
+    use super::*;
+    use crate::DistinctCountCollector;
+
+    fn assert_round_trip<T: Serialize + DeserializeOwned>(kind: AggregationKind, fruit: T) {
+        let serialized = serialize_tagged_fruit(kind, &fruit).unwrap();
+        let result = IntermediateAggregationResult::from_bytes(&serialized, kind).unwrap();
+        assert_eq!(result.kind(), kind);
+        assert_eq!(result.to_bytes().unwrap(), serialized);
+
+        let decoded_fruit: T = deserialize_tagged_fruit(&serialized, kind).unwrap();
+        assert_eq!(
+            serialize_tagged_fruit(kind, &decoded_fruit).unwrap(),
+            serialized
+        );
+        for other_kind in [
+            AggregationKind::FindTraceIds,
+            AggregationKind::DistinctCount,
+            AggregationKind::Tantivy,
+            AggregationKind::TimestampRange,
+        ] {
+            if other_kind != kind {
+                let error = deserialize_tagged_fruit::<T>(&serialized, other_kind)
+                    .err()
+                    .unwrap();
+                assert!(matches!(error, TantivyError::InternalError(_)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_trace_ids_result_round_trip() {
+        let spans = vec![
+            Span {
+                trace_id: TraceId::new([1u8; 16]),
+                span_timestamp: DateTime::from_timestamp_secs(2),
+            },
+            Span {
+                trace_id: TraceId::new([2u8; 16]),
+                span_timestamp: DateTime::from_timestamp_secs(1),
+            },
+        ];
+        assert_round_trip(AggregationKind::FindTraceIds, spans.clone());
+
+        let serialized = serialize_tagged_fruit(AggregationKind::FindTraceIds, &spans).unwrap();
+        let IntermediateAggregationResult::FindTraceIds(decoded_spans) =
+            IntermediateAggregationResult::from_bytes(&serialized, AggregationKind::FindTraceIds)
+                .unwrap()
+        else {
+            panic!("expected a FindTraceIds result");
+        };
+        assert_eq!(decoded_spans, spans);
+    }
+
+    #[test]
+    fn test_distinct_count_result_round_trip() {
+        let collector = DistinctCountCollector {
+            distinct_count_field: "color".to_string(),
+            precision: 4,
+        };
+        let sketch = collector.merge_fruits(Vec::new()).unwrap();
+        assert_round_trip(AggregationKind::DistinctCount, sketch.clone());
+
+        let serialized = serialize_tagged_fruit(AggregationKind::DistinctCount, &sketch).unwrap();
+        let IntermediateAggregationResult::DistinctCount(decoded_sketch) =
+            IntermediateAggregationResult::from_bytes(&serialized, AggregationKind::DistinctCount)
+                .unwrap()
+        else {
+            panic!("expected a DistinctCount result");
+        };
+        assert_eq!(decoded_sketch, sketch);
+    }
+
+    #[test]
+    fn test_timestamp_range_result_round_trip() {
+        let timestamp_range = TimestampRange {
+            min_timestamp: DateTime::from_timestamp_secs(1),
+            max_timestamp: DateTime::from_timestamp_secs(2),
+        };
+        assert_round_trip(AggregationKind::TimestampRange, Some(timestamp_range));
+        assert_round_trip(AggregationKind::TimestampRange, None::<TimestampRange>);
+
+        let other_timestamp_range = TimestampRange {
+            min_timestamp: DateTime::from_timestamp_secs(3),
+            max_timestamp: DateTime::from_timestamp_secs(4),
+        };
+        let merged_result = IntermediateAggregationResult::TimestampRange(Some(timestamp_range))
+            .merge(IntermediateAggregationResult::TimestampRange(None))
+            .unwrap()
+            .merge(IntermediateAggregationResult::TimestampRange(Some(
+                other_timestamp_range,
+            )))
+            .unwrap();
+        let IntermediateAggregationResult::TimestampRange(Some(merged_timestamp_range)) =
+            merged_result
+        else {
+            panic!("expected a TimestampRange result");
+        };
+        assert_eq!(
+            merged_timestamp_range,
+            TimestampRange {
+                min_timestamp: DateTime::from_timestamp_secs(1),
+                max_timestamp: DateTime::from_timestamp_secs(4),
+            }
+        );
+    }
+
+    #[test]
+    fn test_tantivy_result_round_trip() {
+        assert_round_trip(
+            AggregationKind::Tantivy,
+            IntermediateAggregationResults::default(),
+        );
+    }
+
+    #[test]
+    fn test_serialized_kinds() {
+        // Leaves and roots of different versions exchange the kinds during an upgrade.
+        for (kind, expected_serialized_kind) in [
+            (AggregationKind::FindTraceIds, 0u8),
+            (AggregationKind::DistinctCount, 1u8),
+            (AggregationKind::Tantivy, 2u8),
+            (AggregationKind::TimestampRange, 3u8),
+        ] {
+            assert_eq!(
+                postcard::to_allocvec(&kind).unwrap(),
+                [expected_serialized_kind]
+            );
+        }
+    }
+
+    #[test]
+    fn test_result_variants_follow_kinds() {
+        let results = [
+            IntermediateAggregationResult::FindTraceIds(Vec::new()),
+            IntermediateAggregationResult::DistinctCount(
+                DistinctCountCollector {
+                    distinct_count_field: "color".to_string(),
+                    precision: 4,
+                }
+                .merge_fruits(Vec::new())
+                .unwrap(),
+            ),
+            IntermediateAggregationResult::Tantivy(IntermediateAggregationResults::default()),
+            IntermediateAggregationResult::TimestampRange(None),
+        ];
+        for result in results {
+            let serialized_kind = postcard::to_allocvec(&result.kind()).unwrap();
+            assert!(result.to_bytes().unwrap().starts_with(&serialized_kind));
+        }
+    }
+
+    #[test]
+    fn test_deserialize_untagged_fruit() {
+        // Leaves predating the kind serialize their fruits without it.
+        let spans = vec![
+            Span {
+                trace_id: TraceId::new([1u8; 16]),
+                span_timestamp: DateTime::from_timestamp_secs(2),
+            },
+            Span {
+                trace_id: TraceId::new([2u8; 16]),
+                span_timestamp: DateTime::from_timestamp_secs(1),
+            },
+        ];
+        let untagged_spans = serialize_fruit(&spans).unwrap();
+        let tagged_spans = serialize_tagged_fruit(AggregationKind::FindTraceIds, &spans).unwrap();
+        let decoded_spans: Vec<Span> =
+            deserialize_tagged_fruit(&untagged_spans, AggregationKind::FindTraceIds).unwrap();
+        assert_eq!(decoded_spans, spans);
+        assert_eq!(
+            ensure_tagged_fruit(untagged_spans, AggregationKind::FindTraceIds).unwrap(),
+            tagged_spans
+        );
+        assert_eq!(
+            ensure_tagged_fruit(tagged_spans.clone(), AggregationKind::FindTraceIds).unwrap(),
+            tagged_spans
+        );
+
+        let untagged_results = serialize_fruit(&IntermediateAggregationResults::default()).unwrap();
+        let result =
+            IntermediateAggregationResult::from_bytes(&untagged_results, AggregationKind::Tantivy)
+                .unwrap();
+        assert_eq!(result.kind(), AggregationKind::Tantivy);
+
+        // A fruit decoding neither along with its kind nor without it is rejected.
+        let error = deserialize_tagged_fruit::<Vec<Span>>(&[0xff], AggregationKind::FindTraceIds)
+            .err()
+            .unwrap();
+        assert!(matches!(error, TantivyError::InternalError(_)));
+    }
+
+    #[test]
+    fn test_merge_rejects_results_of_different_kinds() {
+        let tantivy_result =
+            IntermediateAggregationResult::Tantivy(IntermediateAggregationResults::default());
+        let spans_result = IntermediateAggregationResult::FindTraceIds(Vec::new());
+        let error = tantivy_result.merge(spans_result).err().unwrap();
+        assert!(matches!(error, TantivyError::InternalError(_)));
+    }
+}
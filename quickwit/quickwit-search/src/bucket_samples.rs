@@ -0,0 +1,277 @@
+// Copyright (C) 2023 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use quickwit_proto::{BucketSamples, Hit, LeafBucketSamples, PartialHit};
+use serde::Deserialize;
+use tantivy::columnar::{ColumnType, MonotonicallyMappableToU64, StrColumn};
+use tantivy::fastfield::Column;
+use tantivy::{DocId, SegmentReader};
+
+use crate::GlobalDocAddress;
+
+/// Buckets documents by the value of a fast field, like a `terms` aggregation, or by ranges of
+/// values when an `interval` is given, like a `histogram` aggregation, and samples the `size`
+/// best documents of each bucket according to the sort order of the request.
+///
+/// Documents are bucketed by the first value of the field, documents without a value are not
+/// sampled.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct BucketSamplesRequest {
+    pub field: String,
+    pub interval: Option<f64>,
+    pub size: usize,
+}
+
+impl BucketSamplesRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(interval) = self.interval {
+            if !interval.is_finite() || interval <= 0.0 {
+                return Err(format!("interval must be positive, but got {interval}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+enum BucketColumn {
+    Str(StrColumn),
+    Numerical {
+        column: Column<u64>,
+        column_type: ColumnType,
+    },
+    Missing,
+}
+
+/// Samples the best hits of each bucket of a segment.
+///
+/// The hits are generic so that the sampler can keep the collector's own heap items, whose
+/// order is the inverse of their rank.
+pub(crate) struct BucketSampler<T: Ord> {
+    column: BucketColumn,
+    interval_opt: Option<f64>,
+    size: usize,
+    /// Bucket key, see `bucket_key`, to the worst-first heap of its best hits.
+    hits_per_bucket: HashMap<u64, BinaryHeap<T>>,
+}
+
+impl<T: Ord> BucketSampler<T> {
+    pub fn open(
+        request: &BucketSamplesRequest,
+        segment_reader: &SegmentReader,
+    ) -> tantivy::Result<Self> {
+        let fast_fields = segment_reader.fast_fields();
+        let column = if let Some(str_column) = fast_fields.str(&request.field)? {
+            BucketColumn::Str(str_column)
+        } else if let Some((column, column_type)) = fast_fields.u64_lenient(&request.field)? {
+            BucketColumn::Numerical {
+                column,
+                column_type,
+            }
+        } else {
+            BucketColumn::Missing
+        };
+        Ok(BucketSampler {
+            column,
+            interval_opt: request.interval,
+            size: request.size,
+            hits_per_bucket: HashMap::new(),
+        })
+    }
+
+    /// Returns the bucket of the document: the term ordinal of a string value, the value of a
+    /// numerical field or, with an interval, the index of the range holding it.
+    #[inline]
+    pub fn bucket_key(&self, doc_id: DocId) -> Option<u64> {
+        if self.size == 0 {
+            return None;
+        }
+        match &self.column {
+            BucketColumn::Str(str_column) => str_column.term_ords(doc_id).next(),
+            BucketColumn::Numerical {
+                column,
+                column_type,
+            } => {
+                let value = column.first(doc_id)?;
+                match self.interval_opt {
+                    Some(interval) => {
+                        let value_f64 = numerical_value_to_f64(value, *column_type)?;
+                        let bucket_index = (value_f64 / interval).floor() as i64;
+                        Some(bucket_index.to_u64())
+                    }
+                    None => Some(value),
+                }
+            }
+            BucketColumn::Missing => None,
+        }
+    }
+
+    #[inline]
+    pub fn collect(&mut self, bucket_key: u64, hit: T) {
+        let hits = self.hits_per_bucket.entry(bucket_key).or_default();
+        if hits.len() < self.size {
+            hits.push(hit);
+        } else if let Some(mut worst_hit) = hits.peek_mut() {
+            if hit < *worst_hit {
+                *worst_hit = hit;
+            }
+        }
+    }
+
+    /// Returns the keys of the buckets along with their hits, from the best to the worst.
+    pub fn harvest(self) -> tantivy::Result<Vec<(String, Vec<T>)>> {
+        let mut buffer = String::new();
+        let mut buckets = Vec::with_capacity(self.hits_per_bucket.len());
+        for (bucket_key, hits) in self.hits_per_bucket {
+            let key = match &self.column {
+                BucketColumn::Str(str_column) => {
+                    buffer.clear();
+                    str_column.ord_to_str(bucket_key, &mut buffer)?;
+                    buffer.clone()
+                }
+                BucketColumn::Numerical { column_type, .. } => match self.interval_opt {
+                    Some(interval) => (i64::from_u64(bucket_key) as f64 * interval).to_string(),
+                    None => numerical_value_to_f64(bucket_key, *column_type)
+                        .unwrap_or_default()
+                        .to_string(),
+                },
+                BucketColumn::Missing => continue,
+            };
+            buckets.push((key, hits.into_sorted_vec()));
+        }
+        Ok(buckets)
+    }
+}
+
+fn numerical_value_to_f64(value: u64, column_type: ColumnType) -> Option<f64> {
+    match column_type {
+        ColumnType::U64 | ColumnType::Bool => Some(value as f64),
+        ColumnType::I64 | ColumnType::DateTime => Some(i64::from_u64(value) as f64),
+        ColumnType::F64 => Some(f64::from_u64(value)),
+        _ => None,
+    }
+}
+
+/// Orders bucket keys numerically when they are both numbers, which is the case for the
+/// buckets of numerical fields, and lexicographically otherwise.
+pub(crate) fn cmp_bucket_keys(left: &str, right: &str) -> Ordering {
+    match (left.parse::<f64>(), right.parse::<f64>()) {
+        (Ok(left_f64), Ok(right_f64)) => left_f64.total_cmp(&right_f64),
+        _ => left.cmp(right),
+    }
+}
+
+/// Returns the partial hits of the samples that are not already among the given hits, so that
+/// they can be fetched along with them.
+pub(crate) fn bucket_sample_partial_hits_to_fetch(
+    partial_hits: &[PartialHit],
+    leaf_bucket_samples: &[LeafBucketSamples],
+) -> Vec<PartialHit> {
+    let mut seen_doc_addrs: HashSet<GlobalDocAddress> = partial_hits
+        .iter()
+        .map(GlobalDocAddress::from_partial_hit)
+        .collect();
+    leaf_bucket_samples
+        .iter()
+        .flat_map(|bucket_samples| bucket_samples.partial_hits.iter())
+        .filter(|partial_hit| {
+            seen_doc_addrs.insert(GlobalDocAddress::from_partial_hit(partial_hit))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Moves the fetched hits of the samples from `hits` to their buckets.
+///
+/// `hits` holds the fetched hits of `partial_hits` and of the samples, see
+/// `bucket_sample_partial_hits_to_fetch`.
+pub(crate) fn assemble_bucket_samples(
+    hits: &mut Vec<Hit>,
+    partial_hits: &[PartialHit],
+    leaf_bucket_samples: Vec<LeafBucketSamples>,
+) -> Vec<BucketSamples> {
+    if leaf_bucket_samples.is_empty() {
+        return Vec::new();
+    }
+    let hit_per_doc_addr: HashMap<GlobalDocAddress, Hit> = hits
+        .iter()
+        .flat_map(|hit| {
+            let partial_hit = hit.partial_hit.as_ref()?;
+            Some((GlobalDocAddress::from_partial_hit(partial_hit), hit.clone()))
+        })
+        .collect();
+    let bucket_samples = leaf_bucket_samples
+        .into_iter()
+        .map(|leaf_bucket_samples| BucketSamples {
+            key: leaf_bucket_samples.key,
+            hits: leaf_bucket_samples
+                .partial_hits
+                .iter()
+                .flat_map(|partial_hit| {
+                    hit_per_doc_addr
+                        .get(&GlobalDocAddress::from_partial_hit(partial_hit))
+                        .cloned()
+                })
+                .collect(),
+        })
+        .collect();
+    let doc_addrs: HashSet<GlobalDocAddress> = partial_hits
+        .iter()
+        .map(GlobalDocAddress::from_partial_hit)
+        .collect();
+    hits.retain(|hit| {
+        hit.partial_hit
+            .as_ref()
+            .map(|partial_hit| doc_addrs.contains(&GlobalDocAddress::from_partial_hit(partial_hit)))
+            .unwrap_or(true)
+    });
+    bucket_samples
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::{cmp_bucket_keys, BucketSamplesRequest};
+
+    #[test]
+    fn test_cmp_bucket_keys() {
+        assert_eq!(cmp_bucket_keys("20", "100"), Ordering::Less);
+        assert_eq!(cmp_bucket_keys("-1.5", "-1"), Ordering::Less);
+        assert_eq!(cmp_bucket_keys("error", "info"), Ordering::Less);
+        assert_eq!(cmp_bucket_keys("100", "error"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_bucket_samples_request_validate() {
+        let request: BucketSamplesRequest =
+            serde_json::from_str(r#"{"field": "latency", "interval": 0, "size": 3}"#).unwrap();
+        assert!(request.validate().is_err());
+        let request: BucketSamplesRequest =
+            serde_json::from_str(r#"{"field": "latency", "interval": 10, "size": 3}"#).unwrap();
+        assert!(request.validate().is_ok());
+        assert!(
+            serde_json::from_str::<BucketSamplesRequest>(r#"{"field": "latency", "top": 3}"#)
+                .is_err()
+        );
+    }
+}
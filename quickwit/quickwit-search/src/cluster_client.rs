@@ -22,15 +22,20 @@ use quickwit_proto::{
     FetchDocsRequest, FetchDocsResponse, LeafListTermsRequest, LeafListTermsResponse,
     LeafSearchRequest, LeafSearchResponse, LeafSearchStreamRequest, LeafSearchStreamResponse,
 };
-use tantivy::aggregation::intermediate_agg_result::IntermediateAggregationResults;
 use tokio::sync::mpsc::error::SendError;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use tracing::debug;
 
+use crate::aggregation_result::{AggregationKind, IntermediateAggregationResult};
+use crate::collector::{
+    merge_cache_status, merge_clamped_max_hits, merge_collection_stats, merge_sort_value_types,
+    parse_aggregation_request,
+};
 use crate::retry::search::LeafSearchRetryPolicy;
 use crate::retry::search_stream::{LeafSearchStreamRetryPolicy, SuccessfulSplitIds};
 use crate::retry::{retry_client, DefaultRetryPolicy, RetryPolicy};
+use crate::total_hits_estimate::merge_estimated_total_hits;
 use crate::{SearchError, SearchJobPlacer, SearchServiceClient};
 
 /// Client that executes placed requests (Request, `SearchServiceClient`) and provides
@@ -90,8 +95,10 @@ impl ClusterClient {
                 "Leaf search response error: `{:?}`. Retry once to execute {:?} with {:?}",
                 response_res, retry_request, client
             );
+            let aggregation_kind_opt = aggregation_kind(&retry_request)?;
             let retry_result = client.leaf_search(retry_request).await;
-            response_res = merge_leaf_search_results(response_res, retry_result);
+            response_res =
+                merge_leaf_search_results(response_res, retry_result, aggregation_kind_opt);
         }
         response_res
     }
@@ -156,27 +163,51 @@ impl ClusterClient {
     }
 }
 
+/// Returns the kind of the aggregations requested by a leaf search request, if any.
+fn aggregation_kind(request: &LeafSearchRequest) -> crate::Result<Option<AggregationKind>> {
+    let Some(search_request) = &request.search_request else {
+        return Ok(None);
+    };
+    let (aggregations_opt, _) = parse_aggregation_request(search_request)?;
+    Ok(aggregations_opt.map(|aggregations| aggregations.kind()))
+}
+
 // Merge initial leaf search results with results obtained from a retry.
 fn merge_leaf_search_results(
     initial_response_result: crate::Result<LeafSearchResponse>,
     retry_response_result: crate::Result<LeafSearchResponse>,
+    aggregation_kind_opt: Option<AggregationKind>,
 ) -> crate::Result<LeafSearchResponse> {
     match (initial_response_result, retry_response_result) {
         (Ok(mut initial_response), Ok(mut retry_response)) => {
             initial_response
                 .partial_hits
                 .append(&mut retry_response.partial_hits);
+            initial_response
+                .bucket_samples
+                .append(&mut retry_response.bucket_samples);
+            initial_response
+                .doc_id_sets
+                .append(&mut retry_response.doc_id_sets);
             let intermediate_aggregation_result = initial_response
                 .intermediate_aggregation_result
                 .map::<crate::Result<_>, _>(|res1_bytes| {
                     if let Some(res2_str) = retry_response.intermediate_aggregation_result.as_ref()
                     {
-                        let mut res1: IntermediateAggregationResults =
-                            postcard::from_bytes(res1_bytes.as_slice())?;
-                        let res2: IntermediateAggregationResults =
-                            postcard::from_bytes(res2_str.as_slice())?;
-                        res1.merge_fruits(res2)?;
-                        let serialized = postcard::to_allocvec(&res1)?;
+                        let aggregation_kind = aggregation_kind_opt.ok_or_else(|| {
+                            SearchError::InternalError(
+                                "intermediate aggregation results returned for a request without \
+                                 aggregations"
+                                    .to_string(),
+                            )
+                        })?;
+                        let res1 = IntermediateAggregationResult::from_bytes(
+                            &res1_bytes,
+                            aggregation_kind,
+                        )?;
+                        let res2 =
+                            IntermediateAggregationResult::from_bytes(res2_str, aggregation_kind)?;
+                        let serialized = res1.merge(res2)?.to_bytes()?;
                         Ok(serialized)
                     } else {
                         Ok(res1_bytes)
@@ -188,8 +219,41 @@ fn merge_leaf_search_results(
                 num_hits: initial_response.num_hits + retry_response.num_hits,
                 num_attempted_splits: initial_response.num_attempted_splits
                     + retry_response.num_attempted_splits,
+                num_successful_splits: initial_response.num_successful_splits
+                    + retry_response.num_successful_splits,
                 failed_splits: retry_response.failed_splits,
                 partial_hits: initial_response.partial_hits,
+                top_hits_aggregation: None,
+                estimated_total_hits: merge_estimated_total_hits([
+                    initial_response.estimated_total_hits.as_ref(),
+                    retry_response.estimated_total_hits.as_ref(),
+                ]),
+                rankings: initial_response.rankings,
+                bucket_samples: initial_response.bucket_samples,
+                num_hits_is_lower_bound: initial_response.num_hits_is_lower_bound
+                    || retry_response.num_hits_is_lower_bound,
+                timed_out: initial_response.timed_out || retry_response.timed_out,
+                sort_value_types: merge_sort_value_types([
+                    initial_response.sort_value_types.as_slice(),
+                    retry_response.sort_value_types.as_slice(),
+                ])?,
+                collection_stats: merge_collection_stats([
+                    initial_response.collection_stats.as_ref(),
+                    retry_response.collection_stats.as_ref(),
+                ]),
+                clamped_max_hits: merge_clamped_max_hits([
+                    initial_response.clamped_max_hits,
+                    retry_response.clamped_max_hits,
+                ]),
+                doc_id_sets: initial_response.doc_id_sets,
+                min_sorting_field_value: initial_response.min_sorting_field_value,
+                cache_status: merge_cache_status([
+                    initial_response.cache_status.as_ref(),
+                    retry_response.cache_status.as_ref(),
+                ]),
+                split_id: initial_response.split_id,
+                aggregation_is_partial: initial_response.aggregation_is_partial
+                    || retry_response.aggregation_is_partial,
             };
             Ok(merged_response)
         }
@@ -234,7 +298,9 @@ mod tests {
     use quickwit_grpc_clients::service_client_pool::ServiceClientPool;
     use quickwit_proto::{
         PartialHit, SearchRequest, SearchStreamRequest, SplitIdAndFooterOffsets, SplitSearchError,
+        SplitSearchErrorCode,
     };
+    use tantivy::aggregation::intermediate_agg_result::IntermediateAggregationResults;
 
     use super::*;
     use crate::root::SearchJob;
@@ -246,6 +312,13 @@ mod tests {
             split_id: split_id.to_string(),
             segment_ord: 1,
             doc_id,
+            tie_breaker_value: 0,
+            docvalue_fields: Default::default(),
+            secondary_sorting_field_values: Vec::new(),
+            score: None,
+            collapse_key: None,
+            sort_values: Vec::new(),
+            dedup_key: None,
         }
     }
 
@@ -451,6 +524,7 @@ mod tests {
                         error: "mock_error".to_string(),
                         split_id: "split_2".to_string(),
                         retryable_error: true,
+                        code: SplitSearchErrorCode::Internal as i32,
                     }],
                     num_attempted_splits: 1,
                     ..Default::default()
@@ -467,6 +541,7 @@ mod tests {
                         error: "mock_error".to_string(),
                         split_id: "split_3".to_string(),
                         retryable_error: true,
+                        code: SplitSearchErrorCode::Internal as i32,
                     }],
                     num_attempted_splits: 1,
                     ..Default::default()
@@ -494,6 +569,7 @@ mod tests {
             error: "error".to_string(),
             split_id: "split_2".to_string(),
             retryable_error: true,
+            code: SplitSearchErrorCode::Internal as i32,
         };
         let leaf_response = LeafSearchResponse {
             num_hits: 1,
@@ -510,7 +586,7 @@ mod tests {
             ..Default::default()
         };
         let merged_leaf_search_response =
-            merge_leaf_search_results(Ok(leaf_response), Ok(leaf_response_retry)).unwrap();
+            merge_leaf_search_results(Ok(leaf_response), Ok(leaf_response_retry), None).unwrap();
         assert_eq!(merged_leaf_search_response.num_attempted_splits, 2);
         assert_eq!(merged_leaf_search_response.num_hits, 2);
         assert_eq!(merged_leaf_search_response.partial_hits.len(), 2);
@@ -518,12 +594,43 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_merge_leaf_search_retry_with_untagged_aggregation_results() -> anyhow::Result<()> {
+        // Leaves predating the aggregation kind serialize their results without it.
+        let serialized = postcard::to_allocvec(&IntermediateAggregationResults::default()).unwrap();
+        let leaf_response = LeafSearchResponse {
+            intermediate_aggregation_result: Some(serialized.clone()),
+            num_attempted_splits: 1,
+            ..Default::default()
+        };
+        let leaf_response_retry = LeafSearchResponse {
+            intermediate_aggregation_result: Some(serialized),
+            num_attempted_splits: 1,
+            ..Default::default()
+        };
+        let merged_leaf_search_response = merge_leaf_search_results(
+            Ok(leaf_response),
+            Ok(leaf_response_retry),
+            Some(AggregationKind::Tantivy),
+        )
+        .unwrap();
+        let merged_result = IntermediateAggregationResult::from_bytes(
+            &merged_leaf_search_response
+                .intermediate_aggregation_result
+                .unwrap(),
+            AggregationKind::Tantivy,
+        )?;
+        assert_eq!(merged_result.kind(), AggregationKind::Tantivy);
+        Ok(())
+    }
+
     #[test]
     fn test_merge_leaf_search_retry_on_error() -> anyhow::Result<()> {
         let split_error = SplitSearchError {
             error: "error".to_string(),
             split_id: "split_2".to_string(),
             retryable_error: true,
+            code: SplitSearchErrorCode::Internal as i32,
         };
         let leaf_response = LeafSearchResponse {
             num_hits: 1,
@@ -535,6 +642,7 @@ mod tests {
         let merged_result = merge_leaf_search_results(
             Err(SearchError::InternalError("error".to_string())),
             Ok(leaf_response),
+            None,
         )
         .unwrap();
         assert_eq!(merged_result.num_attempted_splits, 1);
@@ -549,6 +657,7 @@ mod tests {
         let merge_error = merge_leaf_search_results(
             Err(SearchError::InternalError("error".to_string())),
             Err(SearchError::InternalError("retry error".to_string())),
+            None,
         )
         .unwrap_err();
         assert_eq!(merge_error.to_string(), "Internal error: `error`.");
@@ -17,37 +17,310 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use std::cmp::Ordering;
-use std::collections::{BinaryHeap, HashSet};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::fmt::Debug;
+use std::hash::Hasher;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Instant;
 
+use fnv::FnvHasher;
 use itertools::Itertools;
-use quickwit_doc_mapper::{DocMapper, WarmupInfo};
-use quickwit_proto::{LeafSearchResponse, PartialHit, SearchRequest, SortOrder};
-use serde::Deserialize;
-use tantivy::aggregation::agg_req::{get_fast_field_names, Aggregations};
+use quickwit_config::{MaxHitsCeilingMode, SearcherConfig};
+use quickwit_doc_mapper::{DocMapper, WarmupInfo, DYNAMIC_FIELD_NAME};
+use quickwit_proto::{
+    CacheStatus, CollectionStats, CountHitsMode, LeafBucketSamples, LeafSearchResponse, PartialHit,
+    Ranking, SearchRequest, SegmentDocIdSet, SortOrder, SortValueType, SplitSearchError,
+    SplitSearchErrorCode,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use tantivy::aggregation::agg_req::{
+    get_fast_field_names, Aggregation, AggregationVariants, Aggregations,
+};
 use tantivy::aggregation::intermediate_agg_result::IntermediateAggregationResults;
 use tantivy::aggregation::{AggregationLimits, AggregationSegmentCollector};
 use tantivy::collector::{Collector, SegmentCollector};
-use tantivy::columnar::ColumnType;
+use tantivy::columnar::{Cardinality, ColumnType, MonotonicallyMappableToU64, StrColumn};
 use tantivy::fastfield::Column;
-use tantivy::{DocId, Score, SegmentOrdinal, SegmentReader, TantivyError};
+use tantivy::query::{Scorer, Weight};
+use tantivy::schema::{FieldType, Schema};
+use tantivy::{
+    DateTime, DocId, DocSet, IndexSortByField, Order, Score, SegmentOrdinal, SegmentReader,
+    TantivyError, TERMINATED,
+};
 
-use crate::filters::{create_timestamp_filter_builder, TimestampFilter, TimestampFilterBuilder};
+use crate::aggregation_result::{
+    deserialize_tagged_fruit, ensure_tagged_fruit, serialize_tagged_fruit, AggregationKind,
+};
+use crate::bucket_samples::{cmp_bucket_keys, BucketSampler, BucketSamplesRequest};
+use crate::distinct_count_collector::{DistinctCountCollector, DistinctCountSegmentCollector};
+use crate::doc_id_set::DocIdSet;
+use crate::filters::{
+    create_timestamp_filter_builder, request_time_range, timestamp_field_precision,
+    FastFieldEqualsFilter, FastFieldEqualsFilterBuilder, FastFieldRangeFilter,
+    FastFieldRangeFilterBuilder, TimestampFilter, TimestampFilterBuilder,
+};
 use crate::find_trace_ids_collector::{FindTraceIdsCollector, FindTraceIdsSegmentCollector};
-use crate::partial_hit_sorting_key;
+use crate::ordering::{f32_to_u64, f64_to_u64, i64_to_u64};
 use crate::service::SearcherContext;
+use crate::terms_pagination::TermsAggregationPages;
+use crate::timestamp_range_collector::{TimestampRangeCollector, TimestampRangeSegmentCollector};
+use crate::top_hits_aggregation::{DocValueFieldsReader, TopHitsAggregations};
+use crate::top_k::{offer_to_top_k, AdaptiveTopK, TopK};
+use crate::total_hits_estimate::merge_estimated_total_hits;
+use crate::{partial_hit_sorting_key, tie_break_order, SearchError, TieBreak};
 
+/// A criterion by which the hits are sorted.
 #[derive(Clone, Debug)]
 pub(crate) enum SortBy {
+    /// Sorts by the address of the document within the segment.
     DocId,
+    /// Sorts by the value of the document for a fast field, the values of multivalued fields
+    /// being reduced to one by `aggregate`.
     FastField {
+        /// Name of the fast field.
         field_name: String,
+        /// Order of the hits.
         order: SortOrder,
+        /// Placement of the documents without a value.
+        missing: MissingValue,
+        /// Reduction of the values of the document.
+        aggregate: SortValueAggregate,
     },
+    /// Sorts by the BM25 score.
     Score {
+        /// Order of the hits.
         order: SortOrder,
     },
+    /// Sorts by the BM25 score plus `factor * ln(1 + value)`, where `value` is the value of the
+    /// document for the boost fast field.
+    ScoreWithBoost {
+        /// Name of the fast field holding the boost value.
+        boost_field: String,
+        /// Weight of the boost relative to the score.
+        factor: f64,
+        /// Order of the hits.
+        order: SortOrder,
+    },
+    /// Sorts by the distance between the document and a point.
+    GeoDistance {
+        /// Fast field holding the latitude of the document, in degrees.
+        lat_field: String,
+        /// Fast field holding the longitude of the document, in degrees.
+        lon_field: String,
+        /// Point from which the distances are measured.
+        center: GeoPoint,
+        /// Order of the hits.
+        order: SortOrder,
+        /// Placement of the documents without a value, a value being a distance in meters.
+        missing: MissingValue,
+    },
+    /// Sorts by a pseudo-random key derived from the seed and the address of the document.
+    ///
+    /// The top hits of each segment are then a uniform sample of its matching documents, and
+    /// merging the top hits of several segments or leaves yields a uniform sample of all their
+    /// matching documents: each of them contributes in proportion to its number of matching
+    /// documents.
+    Random {
+        /// Seed of the pseudo-random keys, the same seed yielding the same sample.
+        seed: u64,
+    },
+    /// Sorts by whether the document has a value for the fast field, whatever the value: in
+    /// descending order, the documents with a value rank first.
+    FieldExists {
+        /// Name of the fast field.
+        field_name: String,
+        /// Order of the hits.
+        order: SortOrder,
+    },
+    /// Does not sort the hits, which are sorted by the other criteria, but only collects the
+    /// documents whose hash of the value of the fast field is `bucket` modulo `modulus`.
+    ///
+    /// The hash must be stable across process runs for a document to always fall in the same
+    /// bucket: it is computed with FNV, which, unlike the default hasher of the standard
+    /// library, is not randomly seeded. A bucket is then the same slice of the corpus across
+    /// requests and splits, e.g. to compare rankings on an identical subset. Documents without a
+    /// value for the field belong to no bucket.
+    HashPartition {
+        /// Name of the fast field.
+        field_name: String,
+        /// Number of buckets.
+        modulus: u64,
+        /// Bucket of the collected documents, lower than `modulus`.
+        bucket: u64,
+    },
+}
+
+/// A point on Earth, in degrees.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct GeoPoint {
+    /// Latitude, in degrees.
+    pub lat: f64,
+    /// Longitude, in degrees.
+    pub lon: f64,
+}
+
+/// Mean radius of the Earth, in meters.
+const EARTH_RADIUS_METERS: f64 = 6_371_008.8;
+
+impl GeoPoint {
+    /// Returns the great-circle distance to the other point in meters, using the haversine
+    /// formula.
+    fn haversine_distance(&self, other: &GeoPoint) -> f64 {
+        let lat_rad = self.lat.to_radians();
+        let other_lat_rad = other.lat.to_radians();
+        let half_delta_lat = (other_lat_rad - lat_rad) / 2.0;
+        let half_delta_lon = (other.lon - self.lon).to_radians() / 2.0;
+        let haversine = half_delta_lat.sin().powi(2)
+            + lat_rad.cos() * other_lat_rad.cos() * half_delta_lon.sin().powi(2);
+        2.0 * EARTH_RADIUS_METERS * haversine.sqrt().min(1.0).asin()
+    }
+}
+
+/// Placement of the documents lacking a value for the sort fast field, which does not depend
+/// on the sort order.
+///
+/// The value given by the user is a `MissingNumber`, converted for each segment to the sorting
+/// field of the type of the sort column.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum MissingValue<T = MissingNumber> {
+    /// Documents without a value rank before all the others.
+    First,
+    /// Documents without a value rank after all the others.
+    #[default]
+    Last,
+    /// Documents without a value are sorted as if they held this value.
+    Value(T),
+}
+
+impl<T> MissingValue<T> {
+    fn map_value<U>(self, map_fn: impl FnOnce(T) -> U) -> MissingValue<U> {
+        match self {
+            MissingValue::First => MissingValue::First,
+            MissingValue::Last => MissingValue::Last,
+            MissingValue::Value(value) => MissingValue::Value(map_fn(value)),
+        }
+    }
+}
+
+impl FromStr for MissingValue {
+    type Err = String;
+
+    fn from_str(missing: &str) -> Result<Self, Self::Err> {
+        match missing {
+            "_first" => Ok(MissingValue::First),
+            "_last" => Ok(MissingValue::Last),
+            _ => missing.parse().map(MissingValue::Value),
+        }
+    }
+}
+
+/// A value given by the user for the documents lacking one, kept as parsed until the type of the
+/// sort column is known.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum MissingNumber {
+    U64(u64),
+    I64(i64),
+    F64(f64),
+}
+
+impl MissingNumber {
+    /// Returns the number as an unsigned integer, saturating negative numbers to 0.
+    fn to_u64(self) -> u64 {
+        match self {
+            MissingNumber::U64(value) => value,
+            MissingNumber::I64(value) => value.max(0) as u64,
+            MissingNumber::F64(value) => value as u64,
+        }
+    }
+
+    /// Returns the number as a signed integer, saturating out of range numbers.
+    fn to_i64(self) -> i64 {
+        match self {
+            MissingNumber::U64(value) => value.min(i64::MAX as u64) as i64,
+            MissingNumber::I64(value) => value,
+            MissingNumber::F64(value) => value as i64,
+        }
+    }
+
+    fn to_f64(self) -> f64 {
+        match self {
+            MissingNumber::U64(value) => value as f64,
+            MissingNumber::I64(value) => value as f64,
+            MissingNumber::F64(value) => value,
+        }
+    }
+}
+
+impl FromStr for MissingNumber {
+    type Err = String;
+
+    fn from_str(missing: &str) -> Result<Self, Self::Err> {
+        missing
+            .parse()
+            .map(MissingNumber::U64)
+            .or_else(|_| missing.parse().map(MissingNumber::I64))
+            .or_else(|_| missing.parse().map(MissingNumber::F64))
+            .map_err(|_| format!("expected `_first`, `_last` or a number, but got `{missing}`"))
+    }
+}
+
+/// Reduction of the values of a document for a multivalued fast field into the value it is
+/// sorted by. Documents without any value are placed according to the `MissingValue`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) enum SortValueAggregate {
+    /// The first value, in the order the values were indexed.
+    #[default]
+    First,
+    /// The lowest value.
+    Min,
+    /// The highest value.
+    Max,
+    /// The sum of the values, saturating for integers. Only meaningful for numeric fields.
+    Sum,
+}
+
+impl FromStr for SortValueAggregate {
+    type Err = String;
+
+    fn from_str(aggregate: &str) -> Result<Self, Self::Err> {
+        match aggregate {
+            "first" => Ok(SortValueAggregate::First),
+            "min" => Ok(SortValueAggregate::Min),
+            "max" => Ok(SortValueAggregate::Max),
+            "sum" => Ok(SortValueAggregate::Sum),
+            _ => Err(format!(
+                "expected `first`, `min`, `max` or `sum`, but got `{aggregate}`"
+            )),
+        }
+    }
+}
+
+/// How exactly the matching documents are counted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum CountHits {
+    /// Counts all the matching documents.
+    #[default]
+    CountAll,
+    /// Counts the matching documents of each segment until the requested hits are found.
+    Underestimate,
+    /// Counts the matching documents of each segment until the threshold is reached.
+    Threshold(u64),
+}
+
+impl CountHits {
+    fn from_search_request(search_request: &SearchRequest) -> Self {
+        match CountHitsMode::from_i32(search_request.count_hits) {
+            Some(CountHitsMode::Underestimate) => CountHits::Underestimate,
+            Some(CountHitsMode::Threshold) => {
+                CountHits::Threshold(search_request.count_hits_threshold)
+            }
+            Some(CountHitsMode::CountAll) | None => CountHits::CountAll,
+        }
+    }
 }
 
 /// The `SortingFieldComputer` can be seen as the specialization of `SortBy` applied to a specific
@@ -56,12 +329,59 @@ enum SortingFieldComputer {
     /// If undefined, we simply sort by DocIds.
     DocId,
     FastField {
-        sort_column: Column<u64>,
+        sort_column: SortColumn,
         order: SortOrder,
+        missing: MissingValue<u64>,
+        aggregate: SortValueAggregate,
     },
     Score {
         order: SortOrder,
     },
+    ScoreWithBoost {
+        boost_column: SortColumn,
+        factor: f64,
+        order: SortOrder,
+    },
+    GeoDistance {
+        lat_column: Column<f64>,
+        lon_column: Column<f64>,
+        center: GeoPoint,
+        order: SortOrder,
+        missing: MissingValue<u64>,
+    },
+    Random {
+        /// Seed of the request mixed with the id of the segment, so that the documents of
+        /// different segments get unrelated keys.
+        segment_seed: u64,
+    },
+    FieldExists {
+        /// Column of the term ordinals for string fields, of the values otherwise.
+        column: Column<u64>,
+        order: SortOrder,
+    },
+}
+
+/// Maps a sorting value to the sorting field, applying the sort order and the missing value
+/// policy.
+#[inline]
+fn sorting_field_with_missing(
+    value_opt: Option<u64>,
+    order: SortOrder,
+    missing: MissingValue<u64>,
+) -> u64 {
+    let field_val = match (value_opt, missing) {
+        (Some(field_val), _) | (None, MissingValue::Value(field_val)) => field_val,
+        // The highest sorting field ranks first whatever the order.
+        (None, MissingValue::First) => return u64::MAX,
+        (None, MissingValue::Last) => return 0u64,
+    };
+    match order {
+        // Descending is our most common case.
+        SortOrder::Desc => field_val,
+        // We get Ascending order by using a decreasing mapping over u64 as the
+        // sorting_field.
+        SortOrder::Asc => u64::MAX - field_val,
+    }
 }
 
 impl SortingFieldComputer {
@@ -69,24 +389,45 @@ impl SortingFieldComputer {
     fn compute_sorting_field(&self, doc_id: DocId, score: Score) -> u64 {
         match self {
             SortingFieldComputer::FastField {
-                sort_column: fast_field_reader,
+                sort_column,
+                order,
+                missing,
+                aggregate,
+            } => sorting_field_with_missing(
+                sort_column.aggregate(doc_id, *aggregate),
+                *order,
+                *missing,
+            ),
+            SortingFieldComputer::GeoDistance {
+                lat_column,
+                lon_column,
+                center,
                 order,
+                missing,
             } => {
-                if let Some(field_val) = fast_field_reader.first(doc_id) {
-                    match order {
-                        // Descending is our most common case.
-                        SortOrder::Desc => field_val,
-                        // We get Ascending order by using a decreasing mapping over u64 as the
-                        // sorting_field.
-                        SortOrder::Asc => u64::MAX - field_val,
-                    }
-                } else {
-                    0u64
-                }
+                let distance_opt =
+                    lat_column
+                        .first(doc_id)
+                        .zip(lon_column.first(doc_id))
+                        .map(|(lat, lon)| {
+                            let distance = center.haversine_distance(&GeoPoint { lat, lon });
+                            f64_to_u64(distance)
+                        });
+                sorting_field_with_missing(distance_opt, *order, *missing)
             }
             SortingFieldComputer::DocId => doc_id as u64,
-            SortingFieldComputer::Score { order } => {
-                let u64_score = f32_to_u64(score);
+            SortingFieldComputer::Random { segment_seed } => mix_u64(segment_seed ^ doc_id as u64),
+            SortingFieldComputer::Score { order } => score_sorting_field(score, *order),
+            SortingFieldComputer::FieldExists { column, order } => {
+                let has_value = column.first(doc_id).is_some();
+                sorting_field_with_missing(Some(has_value as u64), *order, MissingValue::Last)
+            }
+            SortingFieldComputer::ScoreWithBoost {
+                boost_column,
+                factor,
+                order,
+            } => {
+                let u64_score = f64_to_u64(boosted_score(boost_column, *factor, doc_id, score));
                 match order {
                     SortOrder::Desc => u64_score,
                     SortOrder::Asc => u64::MAX - u64_score,
@@ -94,48 +435,451 @@ impl SortingFieldComputer {
             }
         }
     }
+
+    /// Returns the actual value the document is sorted by, as opposed to its ranking key,
+    /// or `null` if the document has no value.
+    fn compute_sort_value(&self, doc_id: DocId, score: Score) -> JsonValue {
+        match self {
+            SortingFieldComputer::FastField {
+                sort_column,
+                aggregate,
+                ..
+            } => sort_column.aggregate_json(doc_id, *aggregate),
+            SortingFieldComputer::GeoDistance {
+                lat_column,
+                lon_column,
+                center,
+                ..
+            } => lat_column
+                .first(doc_id)
+                .zip(lon_column.first(doc_id))
+                .map(|(lat, lon)| {
+                    JsonValue::from(center.haversine_distance(&GeoPoint { lat, lon }))
+                })
+                .unwrap_or(JsonValue::Null),
+            SortingFieldComputer::DocId | SortingFieldComputer::Random { .. } => JsonValue::Null,
+            SortingFieldComputer::Score { .. } => JsonValue::from(score),
+            SortingFieldComputer::FieldExists { column, .. } => {
+                JsonValue::from(column.first(doc_id).is_some())
+            }
+            SortingFieldComputer::ScoreWithBoost {
+                boost_column,
+                factor,
+                ..
+            } => JsonValue::from(boosted_score(boost_column, *factor, doc_id, score)),
+        }
+    }
+}
+
+/// Adds to the score the boost of the document, `factor * ln(1 + value)`. Documents without a
+/// value, or with a negative one, get no boost.
+#[inline]
+fn boosted_score(boost_column: &SortColumn, factor: f64, doc_id: DocId, score: Score) -> f64 {
+    let boost = boost_column
+        .first_f64(doc_id)
+        .map(|value| factor * value.max(0.0).ln_1p())
+        .unwrap_or(0.0);
+    score as f64 + boost
+}
+
+/// Fast field column of a sort criterion, read according to the type of the field.
+enum SortColumn {
+    /// Column of unsigned integers, booleans, datetimes or of any other type read leniently.
+    U64(Column<u64>, ColumnType),
+    I64(Column<i64>),
+    F64(Column<f64>),
+}
+
+impl SortColumn {
+    /// Returns the values of the document reduced by `aggregate`, mapped to a `u64` preserving
+    /// order.
+    #[inline]
+    fn aggregate(&self, doc_id: DocId, aggregate: SortValueAggregate) -> Option<u64> {
+        match self {
+            SortColumn::U64(column, _) => {
+                aggregate_values(column, doc_id, aggregate, u64::saturating_add)
+            }
+            SortColumn::I64(column) => {
+                aggregate_values(column, doc_id, aggregate, i64::saturating_add).map(i64_to_u64)
+            }
+            SortColumn::F64(column) => {
+                aggregate_values(column, doc_id, aggregate, |left, right| left + right)
+                    .map(f64_to_u64)
+            }
+        }
+    }
+
+    /// Returns the sorting value of a document holding the number given by the user for the
+    /// documents lacking a value, as `aggregate` would for a value of the column.
+    fn missing_sorting_value(&self, number: MissingNumber) -> u64 {
+        match self {
+            SortColumn::U64(..) => number.to_u64(),
+            SortColumn::I64(_) => i64_to_u64(number.to_i64()),
+            SortColumn::F64(_) => f64_to_u64(number.to_f64()),
+        }
+    }
+
+    /// Returns the first value of the document as a float, datetimes being taken in seconds.
+    #[inline]
+    fn first_f64(&self, doc_id: DocId) -> Option<f64> {
+        match self {
+            SortColumn::U64(column, ColumnType::DateTime) => column
+                .first(doc_id)
+                .map(|value| DateTime::from_u64(value).into_timestamp_secs() as f64),
+            SortColumn::U64(column, _) => column.first(doc_id).map(|value| value as f64),
+            SortColumn::I64(column) => column.first(doc_id).map(|value| value as f64),
+            SortColumn::F64(column) => column.first(doc_id),
+        }
+    }
+
+    /// Returns true if every document of the segment has a value.
+    fn is_full(&self) -> bool {
+        let cardinality = match self {
+            SortColumn::U64(column, _) => column.get_cardinality(),
+            SortColumn::I64(column) => column.get_cardinality(),
+            SortColumn::F64(column) => column.get_cardinality(),
+        };
+        cardinality == Cardinality::Full
+    }
+
+    /// Returns the values of the document reduced by `aggregate`, decoded according to the type
+    /// of the column. Datetimes are timestamps in microseconds. The values of the other types,
+    /// like term ordinals, have no meaning outside of the segment and are returned as `null`.
+    fn aggregate_json(&self, doc_id: DocId, aggregate: SortValueAggregate) -> JsonValue {
+        let value_opt: Option<JsonValue> = match self {
+            SortColumn::U64(column, column_type) => {
+                aggregate_values(column, doc_id, aggregate, u64::saturating_add).and_then(|value| {
+                    match column_type {
+                        ColumnType::U64 => Some(JsonValue::from(value)),
+                        ColumnType::Bool => Some(JsonValue::from(bool::from_u64(value))),
+                        ColumnType::DateTime => Some(JsonValue::from(
+                            DateTime::from_u64(value).into_timestamp_micros(),
+                        )),
+                        _ => None,
+                    }
+                })
+            }
+            SortColumn::I64(column) => {
+                aggregate_values(column, doc_id, aggregate, i64::saturating_add)
+                    .map(JsonValue::from)
+            }
+            SortColumn::F64(column) => {
+                aggregate_values(column, doc_id, aggregate, |left, right| left + right)
+                    .map(JsonValue::from)
+            }
+        };
+        value_opt.unwrap_or(JsonValue::Null)
+    }
+}
+
+/// Reduces the values of the document for the column according to `aggregate`, summing them
+/// with `add`.
+#[inline]
+fn aggregate_values<T: PartialOrd + Copy + Debug + Send + Sync + 'static>(
+    column: &Column<T>,
+    doc_id: DocId,
+    aggregate: SortValueAggregate,
+    add: impl Fn(T, T) -> T,
+) -> Option<T> {
+    match aggregate {
+        SortValueAggregate::First => column.first(doc_id),
+        SortValueAggregate::Min => {
+            column
+                .values_for_doc(doc_id)
+                .reduce(|min, value| if value < min { value } else { min })
+        }
+        SortValueAggregate::Max => {
+            column
+                .values_for_doc(doc_id)
+                .reduce(|max, value| if value > max { value } else { max })
+        }
+        SortValueAggregate::Sum => column.values_for_doc(doc_id).reduce(add),
+    }
+}
+
+/// Returns the ranking key of a score.
+///
+/// Scorers are expected to yield finite scores, but pathological ones can yield non-finite
+/// values. Infinities are clamped to the finite score of largest magnitude of the same sign, and
+/// NaN ranks below every other score whatever the order, so that it never surfaces as a top hit.
+fn score_sorting_field(score: Score, order: SortOrder) -> u64 {
+    if score.is_nan() {
+        return 0;
+    }
+    // The ranking keys of the clamped scores are never 0 in either order.
+    let u64_score = f32_to_u64(score.clamp(f32::MIN, f32::MAX));
+    match order {
+        SortOrder::Desc => u64_score,
+        SortOrder::Asc => u64::MAX - u64_score,
+    }
 }
 
-/// Converts a float to an unsigned integer while preserving order.
-/// See `<https://lemire.me/blog/2020/12/14/converting-floating-point-numbers-to-integers-while-preserving-order/>`
-fn f32_to_u64(value: f32) -> u64 {
-    let value_u32 = u32::from_le_bytes(value.to_le_bytes());
-    let mut mask = (value_u32 as i32 >> 31) as u32;
-    mask |= 0x80000000;
-    (value_u32 ^ mask) as u64
+/// Scrambles the bits of the value with the finalizer of SplitMix64, so that close values,
+/// like consecutive doc ids, get unrelated hashes.
+pub(crate) fn mix_u64(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
 }
 
-/// Takes a user-defined sorting criteria and resolves it to a
-/// segment specific `SortFieldComputer`.
+/// Resolves the sort criteria for the segment, along with the types of their values.
+///
+/// Hash partitions filter the documents rather than sort them, and are left out. Without any
+/// other criterion, the documents are sorted by doc id.
 fn resolve_sort_by(
+    sort_by: &[SortBy],
+    segment_reader: &SegmentReader,
+) -> tantivy::Result<Vec<(SortingFieldComputer, SortValueType)>> {
+    let sort_criteria: Vec<&SortBy> = sort_by
+        .iter()
+        .filter(|sort_criterion| !matches!(sort_criterion, SortBy::HashPartition { .. }))
+        .collect();
+    if sort_criteria.is_empty() {
+        return Ok(vec![(SortingFieldComputer::DocId, SortValueType::NoValue)]);
+    }
+    sort_criteria
+        .into_iter()
+        .map(|sort_criterion| resolve_sort_criterion(sort_criterion, segment_reader))
+        .collect()
+}
+
+/// Opens the fast field column of a sort criterion, along with its type if the column exists. A
+/// missing column is read as an empty one.
+fn open_sort_column(
+    field_name: &str,
+    segment_reader: &SegmentReader,
+) -> tantivy::Result<(SortColumn, Option<ColumnType>)> {
+    let fast_fields = segment_reader.fast_fields();
+    let sort_column_opt: Option<(Column<u64>, ColumnType)> = fast_fields.u64_lenient(field_name)?;
+    let column_type_opt = sort_column_opt
+        .as_ref()
+        .map(|(_, column_type)| *column_type);
+    // The raw `u64` values of signed and float columns do not necessarily follow the
+    // order of the values, so these columns are read with their own type.
+    let sort_column = match sort_column_opt {
+        Some((_, ColumnType::I64)) => fast_fields
+            .column_opt::<i64>(field_name)?
+            .map(SortColumn::I64),
+        Some((_, ColumnType::F64)) => fast_fields
+            .column_opt::<f64>(field_name)?
+            .map(SortColumn::F64),
+        Some((sort_column, column_type)) => Some(SortColumn::U64(sort_column, column_type)),
+        None => None,
+    }
+    .unwrap_or_else(|| {
+        SortColumn::U64(
+            Column::build_empty_column(segment_reader.max_doc()),
+            ColumnType::U64,
+        )
+    });
+    Ok((sort_column, column_type_opt))
+}
+
+/// Returns the type of the values of a fast field column, as reported to clients.
+fn sort_value_type(column_type_opt: Option<ColumnType>) -> SortValueType {
+    match column_type_opt {
+        Some(ColumnType::U64) => SortValueType::U64,
+        Some(ColumnType::I64) => SortValueType::I64,
+        Some(ColumnType::F64) => SortValueType::F64,
+        Some(ColumnType::Bool) => SortValueType::Bool,
+        Some(ColumnType::DateTime) => SortValueType::Datetime,
+        // `u64_lenient` does not open the other column types.
+        Some(_) | None => SortValueType::NoValue,
+    }
+}
+
+fn resolve_sort_criterion(
     sort_by: &SortBy,
     segment_reader: &SegmentReader,
-) -> tantivy::Result<SortingFieldComputer> {
+) -> tantivy::Result<(SortingFieldComputer, SortValueType)> {
     match sort_by {
-        SortBy::DocId => Ok(SortingFieldComputer::DocId),
-        SortBy::FastField { field_name, order } => {
-            let sort_column_opt: Option<(Column<u64>, ColumnType)> =
-                segment_reader.fast_fields().u64_lenient(field_name)?;
-            let sort_column = if let Some((sort_column, _column_type)) = sort_column_opt {
-                sort_column
-            } else {
-                Column::build_empty_column(segment_reader.max_doc())
-            };
-            Ok(SortingFieldComputer::FastField {
+        SortBy::DocId => Ok((SortingFieldComputer::DocId, SortValueType::NoValue)),
+        SortBy::HashPartition { .. } => {
+            unreachable!("hash partitions are left out of the sort criteria")
+        }
+        SortBy::FastField {
+            field_name,
+            order,
+            missing,
+            aggregate,
+        } => {
+            let (sort_column, column_type_opt) = open_sort_column(field_name, segment_reader)?;
+            let missing = missing.map_value(|number| sort_column.missing_sorting_value(number));
+            let sorting_field_computer = SortingFieldComputer::FastField {
                 sort_column,
                 order: *order,
-            })
+                missing,
+                aggregate: *aggregate,
+            };
+            Ok((sorting_field_computer, sort_value_type(column_type_opt)))
+        }
+        SortBy::Score { order } => Ok((
+            SortingFieldComputer::Score { order: *order },
+            SortValueType::Score,
+        )),
+        SortBy::ScoreWithBoost {
+            boost_field,
+            factor,
+            order,
+        } => {
+            let (boost_column, _) = open_sort_column(boost_field, segment_reader)?;
+            let sorting_field_computer = SortingFieldComputer::ScoreWithBoost {
+                boost_column,
+                factor: *factor,
+                order: *order,
+            };
+            Ok((sorting_field_computer, SortValueType::Score))
         }
-        SortBy::Score { order } => Ok(SortingFieldComputer::Score { order: *order }),
+        SortBy::Random { seed } => {
+            // Segment ids are stable, unlike segment ordinals which depend on the searcher.
+            let mut hasher = FnvHasher::default();
+            hasher.write_u64(*seed);
+            hasher.write(segment_reader.segment_id().uuid_string().as_bytes());
+            let sorting_field_computer = SortingFieldComputer::Random {
+                segment_seed: hasher.finish(),
+            };
+            Ok((sorting_field_computer, SortValueType::NoValue))
+        }
+        SortBy::FieldExists { field_name, order } => {
+            let (column, _) = open_key_column(field_name, segment_reader)?;
+            let sorting_field_computer = SortingFieldComputer::FieldExists {
+                column,
+                order: *order,
+            };
+            Ok((sorting_field_computer, SortValueType::Bool))
+        }
+        SortBy::GeoDistance {
+            lat_field,
+            lon_field,
+            center,
+            order,
+            missing,
+        } => {
+            let fast_fields = segment_reader.fast_fields();
+            let open_f64_column = |field_name: &str| -> tantivy::Result<Column<f64>> {
+                Ok(fast_fields
+                    .column_opt::<f64>(field_name)?
+                    .unwrap_or_else(|| Column::build_empty_column(segment_reader.max_doc())))
+            };
+            let sorting_field_computer = SortingFieldComputer::GeoDistance {
+                lat_column: open_f64_column(lat_field)?,
+                lon_column: open_f64_column(lon_field)?,
+                center: *center,
+                order: *order,
+                // A missing value given by the user is a distance in meters.
+                missing: missing.map_value(|distance| f64_to_u64(distance.to_f64())),
+            };
+            // The distances are in meters.
+            Ok((sorting_field_computer, SortValueType::F64))
+        }
+    }
+}
+
+/// Computes a tie-breaking key from the value of a designated fast field.
+///
+/// The key only depends on the document content, so that documents sharing a sorting value
+/// are ordered identically regardless of the physical layout of the index, which changes
+/// whenever splits are merged. It requires the field to hold a unique value per document.
+/// Documents without a value get the key 0.
+enum TieBreakerComputer {
+    Str {
+        str_column: StrColumn,
+        buffer: String,
+    },
+    U64(Column<u64>),
+}
+
+impl TieBreakerComputer {
+    fn open(field_name: &str, segment_reader: &SegmentReader) -> tantivy::Result<Self> {
+        if let Some(str_column) = segment_reader.fast_fields().str(field_name)? {
+            return Ok(TieBreakerComputer::Str {
+                str_column,
+                buffer: String::new(),
+            });
+        }
+        let column = if let Some((column, _column_type)) =
+            segment_reader.fast_fields().u64_lenient(field_name)?
+        {
+            column
+        } else {
+            Column::build_empty_column(segment_reader.max_doc())
+        };
+        Ok(TieBreakerComputer::U64(column))
+    }
+
+    /// Returns a hash of the field value, which is stable across processes and index versions.
+    fn compute_tie_breaker(&mut self, doc_id: DocId) -> u64 {
+        self.hash_value(doc_id).unwrap_or(0)
+    }
+
+    /// Returns a hash of the field value, or `None` if the document has no value.
+    fn hash_value(&mut self, doc_id: DocId) -> Option<u64> {
+        let mut hasher = FnvHasher::default();
+        match self {
+            TieBreakerComputer::Str { str_column, buffer } => {
+                let term_ord = str_column.term_ords(doc_id).next()?;
+                if !str_column.ord_to_str(term_ord, buffer).unwrap_or(false) {
+                    return None;
+                }
+                hasher.write(buffer.as_bytes());
+            }
+            TieBreakerComputer::U64(column) => {
+                hasher.write_u64(column.first(doc_id)?);
+            }
+        }
+        Some(hasher.finish())
+    }
+}
+
+/// Only accepts the documents of a bucket of a hash partition, see `SortBy::HashPartition`.
+struct HashPartitionFilter {
+    /// Hashes the values of the field like the tie breaker, which is stable across processes.
+    hasher: TieBreakerComputer,
+    modulus: u64,
+    bucket: u64,
+}
+
+impl HashPartitionFilter {
+    fn open(
+        field_name: &str,
+        modulus: u64,
+        bucket: u64,
+        segment_reader: &SegmentReader,
+    ) -> tantivy::Result<Self> {
+        Ok(HashPartitionFilter {
+            hasher: TieBreakerComputer::open(field_name, segment_reader)?,
+            modulus,
+            bucket,
+        })
+    }
+
+    fn is_match(&mut self, doc_id: DocId) -> bool {
+        self.hasher
+            .hash_value(doc_id)
+            .map(|hash| hash % self.modulus == self.bucket)
+            .unwrap_or(false)
     }
 }
 
 /// PartialHitHeapItem order is the inverse of the natural order
 /// so that we actually have a min-heap.
-#[derive(Clone, Copy)]
+///
+/// The natural order is the order of `partial_hit_sorting_key` restricted to a segment, whose
+/// hits share their `split_id` and `segment_ord`, so that hits are ranked the same way within
+/// segments and when merging: ties are always broken by document address, in the same
+/// `tie_break_order`. Otherwise, paginating with `search_after` would skip or repeat hits.
+#[derive(Clone)]
 struct PartialHitHeapItem {
     sorting_field_value: u64,
+    /// Values of the secondary sort fields, empty when sorting by a single field.
+    secondary_sorting_field_values: Vec<u64>,
+    tie_breaker_value: u64,
     doc_id: DocId,
+    /// The score of the document, only kept when sorting by score. It does not take part
+    /// in the comparison, the sorting field values already accounting for it.
+    score_opt: Option<Score>,
+    /// Shared by all the hits of the collector.
+    tie_break_order: SortOrder,
 }
 
 impl PartialOrd for PartialHitHeapItem {
@@ -144,22 +888,28 @@ impl PartialOrd for PartialHitHeapItem {
     }
 }
 
+impl PartialHitHeapItem {
+    /// Returns the key of the hit within its segment, see `partial_hit_sorting_key`.
+    #[inline]
+    fn sorting_key(&self) -> (Reverse<u64>, Reverse<&[u64]>, TieBreak<(u64, DocId)>) {
+        (
+            Reverse(self.sorting_field_value),
+            Reverse(self.secondary_sorting_field_values.as_slice()),
+            TieBreak {
+                key: (self.tie_breaker_value, self.doc_id),
+                order: self.tie_break_order,
+            },
+        )
+    }
+}
+
 impl Ord for PartialHitHeapItem {
     #[inline]
     fn cmp(&self, other: &Self) -> Ordering {
-        let by_sorting_field = other
-            .sorting_field_value
-            .partial_cmp(&self.sorting_field_value)
-            .unwrap_or(Ordering::Equal);
-
-        let lazy_order_by_doc_id = || {
-            self.doc_id
-                .partial_cmp(&other.doc_id)
-                .unwrap_or(Ordering::Equal)
-        };
-
-        // In case of a tie on the feature, we sort by ascending `DocId`.
-        by_sorting_field.then_with(lazy_order_by_doc_id)
+        // In case of a tie on the feature, we compare the secondary sort fields in order,
+        // and then sort by tie breaker value and by `DocId`, in the tie break order.
+        // The best hits being the lowest, the worst hit sits at the top of the heap.
+        self.sorting_key().cmp(&other.sorting_key())
     }
 }
 
@@ -171,78 +921,441 @@ impl PartialEq for PartialHitHeapItem {
 
 impl Eq for PartialHitHeapItem {}
 
-enum AggregationSegmentCollectors {
-    FindTraceIdsSegmentCollector(Box<FindTraceIdsSegmentCollector>),
-    TantivyAggregationSegmentCollector(AggregationSegmentCollector),
+/// The `search_after` cursor specialized for a given segment.
+///
+/// A hit is after the cursor if its `(Reverse(sorting_field_value),
+/// Reverse(secondary_sorting_field_values), tie_breaker_value, split_id, segment_ord, doc_id)` key
+/// is strictly greater than the cursor's key, the last four components being compared in the tie
+/// break order. This is the order used to merge partial hits (see `partial_hit_sorting_key`), so
+/// paging with the last hit of a page as the cursor visits each document exactly once, even when
+/// many documents share a sorting value: the cursor's own document is excluded and the next tied
+/// document is included.
+struct SegmentSearchAfter {
+    sorting_field_value: u64,
+    secondary_sorting_field_values: Vec<u64>,
+    tie_breaker_value: u64,
+    /// Position of the segment relative to the cursor's `(split_id, segment_ord)`.
+    segment_cmp_cursor: Ordering,
+    doc_id: DocId,
+    tie_break_order: SortOrder,
 }
 
-/// Quickwit collector working at the scale of the segment.
-pub struct QuickwitSegmentCollector {
-    num_hits: u64,
-    split_id: String,
-    sort_by: SortingFieldComputer,
-    hits: BinaryHeap<PartialHitHeapItem>,
-    max_hits: usize,
-    segment_ord: u32,
-    timestamp_filter_opt: Option<TimestampFilter>,
-    aggregation: Option<AggregationSegmentCollectors>,
-}
+impl SegmentSearchAfter {
+    fn new(
+        cursor: &PartialHit,
+        split_id: &str,
+        segment_ord: SegmentOrdinal,
+        tie_break_order: SortOrder,
+    ) -> Self {
+        let segment_cmp_cursor =
+            (split_id, segment_ord).cmp(&(cursor.split_id.as_str(), cursor.segment_ord));
+        SegmentSearchAfter {
+            sorting_field_value: cursor.sorting_field_value,
+            secondary_sorting_field_values: cursor.secondary_sorting_field_values.clone(),
+            tie_breaker_value: cursor.tie_breaker_value,
+            segment_cmp_cursor,
+            doc_id: cursor.doc_id,
+            tie_break_order,
+        }
+    }
 
-impl QuickwitSegmentCollector {
+    /// Returns true if hits with this sorting field value sort before the cursor, whatever
+    /// the rest of their key. This lets us skip computing the rest of the key.
     #[inline]
-    fn at_capacity(&self) -> bool {
-        self.hits.len() >= self.max_hits
+    fn is_before_sorting_field_value(&self, sorting_field_value: u64) -> bool {
+        sorting_field_value > self.sorting_field_value
     }
 
     #[inline]
-    fn collect_top_k(&mut self, doc_id: DocId, score: Score) {
-        let sorting_field_value: u64 = self.sort_by.compute_sorting_field(doc_id, score);
-        if self.at_capacity() {
-            if let Some(limit_sorting_field) = self.hits.peek().map(|head| head.sorting_field_value)
-            {
-                // In case of a tie, we keep the document with a lower `DocId`.
-                if limit_sorting_field < sorting_field_value {
-                    if let Some(mut head) = self.hits.peek_mut() {
-                        head.sorting_field_value = sorting_field_value;
-                        head.doc_id = doc_id;
-                    }
+    fn is_after(&self, hit: &PartialHitHeapItem) -> bool {
+        // Hits are sorted by decreasing sorting field values.
+        self.sorting_field_value
+            .cmp(&hit.sorting_field_value)
+            .then_with(|| {
+                self.secondary_sorting_field_values
+                    .cmp(&hit.secondary_sorting_field_values)
+            })
+            .then_with(|| {
+                let tie_break_cmp = hit
+                    .tie_breaker_value
+                    .cmp(&self.tie_breaker_value)
+                    .then(self.segment_cmp_cursor)
+                    .then_with(|| hit.doc_id.cmp(&self.doc_id));
+                match self.tie_break_order {
+                    SortOrder::Asc => tie_break_cmp,
+                    SortOrder::Desc => tie_break_cmp.reverse(),
                 }
-            }
-        } else {
-            // we have not reached capacity yet, so we can just push the
-            // element.
-            self.hits.push(PartialHitHeapItem {
-                sorting_field_value,
-                doc_id,
-            });
-        }
+            })
+            == Ordering::Greater
     }
+}
 
-    #[inline]
-    fn accept_document(&self, doc_id: DocId) -> bool {
-        if let Some(ref timestamp_filter) = self.timestamp_filter_opt {
-            return timestamp_filter.is_within_range(doc_id);
+/// Keeps the best hits of each group of documents sharing a value of a fast field: the best one
+/// when collapsing the hits, up to `max_hits_per_group` of them when diversifying them.
+struct CollapseField {
+    /// Term ordinals of string fields, values of the other fast fields.
+    collapse_column: Column<u64>,
+    /// Resolves the term ordinals of string fields into their terms.
+    str_column_opt: Option<StrColumn>,
+    max_hits_per_group: usize,
+    /// Documents without a value belong to the group `None`.
+    best_hits_per_group: HashMap<Option<u64>, BinaryHeap<PartialHitHeapItem>>,
+}
+
+/// Opens the column of a fast field keying the documents, along with the string column resolving
+/// its term ordinals if it is a string field.
+fn open_key_column(
+    field_name: &str,
+    segment_reader: &SegmentReader,
+) -> tantivy::Result<(Column<u64>, Option<StrColumn>)> {
+    let fast_fields = segment_reader.fast_fields();
+    let str_column_opt = fast_fields.str(field_name)?;
+    let key_column = match &str_column_opt {
+        Some(str_column) => str_column.ords().clone(),
+        None => fast_fields
+            .u64_lenient(field_name)?
+            .map(|(column, _column_type)| column)
+            .unwrap_or_else(|| Column::build_empty_column(segment_reader.max_doc())),
+    };
+    Ok((key_column, str_column_opt))
+}
+
+/// Resolves a value of a key column opened with `open_key_column` into its key.
+fn resolve_key(
+    value_opt: Option<u64>,
+    str_column_opt: Option<&StrColumn>,
+) -> tantivy::Result<Option<String>> {
+    match (value_opt, str_column_opt) {
+        (Some(term_ord), Some(str_column)) => {
+            let mut term = String::new();
+            str_column.ord_to_str(term_ord, &mut term)?;
+            Ok(Some(term))
         }
-        true
+        (Some(value), None) => Ok(Some(value.to_string())),
+        (None, _) => Ok(None),
     }
 }
 
-impl SegmentCollector for QuickwitSegmentCollector {
-    type Fruit = tantivy::Result<LeafSearchResponse>;
+impl CollapseField {
+    fn open(
+        field_name: &str,
+        max_hits_per_group: usize,
+        segment_reader: &SegmentReader,
+    ) -> tantivy::Result<Self> {
+        let (collapse_column, str_column_opt) = open_key_column(field_name, segment_reader)?;
+        Ok(CollapseField {
+            collapse_column,
+            str_column_opt,
+            max_hits_per_group,
+            best_hits_per_group: HashMap::new(),
+        })
+    }
+
+    #[inline]
+    fn collect(&mut self, hit: PartialHitHeapItem) {
+        let group = self.collapse_column.first(hit.doc_id);
+        let best_hits = self.best_hits_per_group.entry(group).or_default();
+        offer_to_top_k(best_hits, hit, self.max_hits_per_group);
+    }
+
+    /// Returns the `max_hits` best hits, from the best to the worst, along with the key of their
+    /// group.
+    fn harvest(
+        self,
+        max_hits: usize,
+    ) -> tantivy::Result<Vec<(PartialHitHeapItem, Option<String>)>> {
+        let mut best_hits: Vec<(Option<u64>, PartialHitHeapItem)> = self
+            .best_hits_per_group
+            .into_iter()
+            .flat_map(|(group, hits)| hits.into_iter().map(move |hit| (group, hit)))
+            .collect();
+        best_hits.sort_unstable_by(|(_, left_hit), (_, right_hit)| left_hit.cmp(right_hit));
+        best_hits.truncate(max_hits);
+        best_hits
+            .into_iter()
+            .map(|(group, hit)| {
+                let collapse_key = resolve_key(group, self.str_column_opt.as_ref())?;
+                Ok((hit, collapse_key))
+            })
+            .collect()
+    }
+}
+
+/// Reads the dedup keys of the hits, see `SearchRequest::dedup_field`.
+struct DedupField {
+    dedup_column: Column<u64>,
+    str_column_opt: Option<StrColumn>,
+}
+
+impl DedupField {
+    fn open(field_name: &str, segment_reader: &SegmentReader) -> tantivy::Result<Self> {
+        let (dedup_column, str_column_opt) = open_key_column(field_name, segment_reader)?;
+        Ok(DedupField {
+            dedup_column,
+            str_column_opt,
+        })
+    }
+
+    fn dedup_key(&self, doc_id: DocId) -> tantivy::Result<Option<String>> {
+        resolve_key(
+            self.dedup_column.first(doc_id),
+            self.str_column_opt.as_ref(),
+        )
+    }
+}
+
+enum AggregationSegmentCollectors {
+    FindTraceIdsSegmentCollector(Box<FindTraceIdsSegmentCollector>),
+    DistinctCountSegmentCollector(DistinctCountSegmentCollector),
+    TimestampRangeSegmentCollector(TimestampRangeSegmentCollector),
+    TantivyAggregationSegmentCollector(AggregationSegmentCollector),
+}
+
+/// Quickwit collector working at the scale of the segment.
+pub struct QuickwitSegmentCollector {
+    num_hits: u64,
+    split_id: String,
+    sort_by: SortingFieldComputer,
+    secondary_sort_by: Vec<SortingFieldComputer>,
+    /// Whether the hits carry their score, which is the case when sorting by score.
+    keep_score: bool,
+    tie_breaker_opt: Option<TieBreakerComputer>,
+    hits: AdaptiveTopK<PartialHitHeapItem>,
+    max_hits: usize,
+    segment_ord: u32,
+    timestamp_filter_opt: Option<TimestampFilter>,
+    fast_field_equals_filter_opt: Option<FastFieldEqualsFilter>,
+    fast_field_range_filter_opt: Option<FastFieldRangeFilter>,
+    hash_partition_filters: Vec<HashPartitionFilter>,
+    search_after_opt: Option<SegmentSearchAfter>,
+    docvalue_fields_reader_opt: Option<DocValueFieldsReader>,
+    aggregation: Option<AggregationSegmentCollectors>,
+    /// Collects the second ranking of the rank fusion.
+    rank_fusion_segment_collector_opt: Option<Box<QuickwitSegmentCollector>>,
+    bucket_sampler_opt: Option<BucketSampler<PartialHitHeapItem>>,
+    collapse_field_opt: Option<CollapseField>,
+    dedup_field_opt: Option<DedupField>,
+    /// Collects all the matching doc ids instead of the top-K, see
+    /// `QuickwitCollector::collect_doc_id_sets`.
+    doc_id_set_opt: Option<DocIdSet>,
+    /// Number of hits after which counting stops, if any.
+    count_hits_threshold_opt: Option<u64>,
+    num_hits_is_lower_bound: bool,
+    /// Whether the hits carry the decoded values of their sort criteria.
+    return_sort_values: bool,
+    /// Types of the values of the sort criteria, reported along with the sort values.
+    sort_value_types: Vec<SortValueType>,
+    /// Documents scoring below this threshold are skipped.
+    min_score_opt: Option<Score>,
+    soft_deadline_opt: Option<Instant>,
+    /// Whether collection stopped because the soft deadline passed.
+    timed_out: bool,
+    /// Whether the documents of the segment are visited in the order of the sort criterion, in
+    /// which case a document that does not make the top-K rules out all the following ones.
+    visits_docs_in_sort_order: bool,
+    /// Whether the top-K can no longer change, see `visits_docs_in_sort_order`.
+    top_k_is_final: bool,
+    /// Whether the documents are only counted and aggregated, see
+    /// `QuickwitCollector::aggregation_only`.
+    aggregation_only: bool,
+    tie_break_order: SortOrder,
+    /// Plain counters, so as not to slow down the collection.
+    collection_stats: CollectionStats,
+}
+
+impl QuickwitSegmentCollector {
+    #[inline]
+    fn at_capacity(&self) -> bool {
+        self.hits.len() >= self.max_hits
+    }
+
+    #[inline]
+    fn collect_top_k(&mut self, doc_id: DocId, score: Score) {
+        let sorting_field_value: u64 = self.sort_by.compute_sorting_field(doc_id, score);
+        if self.at_capacity() {
+            if let Some(limit_sorting_field) =
+                self.hits.worst().map(|worst| worst.sorting_field_value)
+            {
+                if limit_sorting_field > sorting_field_value {
+                    self.top_k_is_final = self.visits_docs_in_sort_order;
+                    return;
+                }
+            }
+        }
+        if let Some(search_after) = &self.search_after_opt {
+            if search_after.is_before_sorting_field_value(sorting_field_value) {
+                return;
+            }
+        }
+        let hit = self.compute_hit(doc_id, score, sorting_field_value);
+        if let Some(search_after) = &self.search_after_opt {
+            if !search_after.is_after(&hit) {
+                return;
+            }
+        }
+        if let Some(collapse_field) = &mut self.collapse_field_opt {
+            collapse_field.collect(hit);
+            return;
+        }
+        if offer_to_top_k(&mut self.hits, hit, self.max_hits) {
+            self.collection_stats.num_docs_entered_top_k += 1;
+        }
+    }
+
+    #[inline]
+    fn compute_hit(
+        &mut self,
+        doc_id: DocId,
+        score: Score,
+        sorting_field_value: u64,
+    ) -> PartialHitHeapItem {
+        let secondary_sorting_field_values: Vec<u64> = self
+            .secondary_sort_by
+            .iter()
+            .map(|sort_by| sort_by.compute_sorting_field(doc_id, score))
+            .collect();
+        let tie_breaker_value = self
+            .tie_breaker_opt
+            .as_mut()
+            .map(|tie_breaker| tie_breaker.compute_tie_breaker(doc_id))
+            .unwrap_or(0);
+        PartialHitHeapItem {
+            sorting_field_value,
+            secondary_sorting_field_values,
+            tie_breaker_value,
+            doc_id,
+            score_opt: self.keep_score.then_some(score),
+            tie_break_order: self.tie_break_order,
+        }
+    }
+
+    #[inline]
+    fn collect_bucket_sample(&mut self, doc_id: DocId, score: Score) {
+        let Some(bucket_key) = self
+            .bucket_sampler_opt
+            .as_ref()
+            .and_then(|bucket_sampler| bucket_sampler.bucket_key(doc_id))
+        else {
+            return;
+        };
+        let sorting_field_value: u64 = self.sort_by.compute_sorting_field(doc_id, score);
+        let hit = self.compute_hit(doc_id, score, sorting_field_value);
+        if let Some(bucket_sampler) = &mut self.bucket_sampler_opt {
+            bucket_sampler.collect(bucket_key, hit);
+        }
+    }
+
+    /// Returns true if the collector only counts documents, and may therefore stop once
+    /// the count threshold is reached.
+    fn may_stop_early(&self) -> bool {
+        self.count_hits_threshold_opt.is_some()
+            && self.max_hits == 0
+            && self.aggregation.is_none()
+            && self.bucket_sampler_opt.is_none()
+            && self.doc_id_set_opt.is_none()
+            // Documents are not scored when stopping early.
+            && self.min_score_opt.is_none()
+    }
+
+    /// Returns true if the collector no longer needs documents, either because the soft
+    /// deadline passed or because counting is over and the top-K cannot change anymore, see
+    /// `may_stop_early` and `top_k_is_final`.
+    #[inline]
+    fn is_done(&self) -> bool {
+        self.timed_out
+            || (self.num_hits_is_lower_bound && (self.may_stop_early() || self.top_k_is_final))
+    }
+
+    /// Returns true if no document of the segment is within the time range of the request, in
+    /// which case the query does not even need to run on the segment.
+    fn is_outside_time_range(&self) -> bool {
+        matches!(self.timestamp_filter_opt, Some(TimestampFilter::NoMatch))
+    }
+
+    /// Stops the collection if the soft deadline has passed. The hits collected so far remain
+    /// a valid, if partial, top-K.
+    fn check_soft_deadline(&mut self) {
+        if let Some(soft_deadline) = self.soft_deadline_opt {
+            self.timed_out = Instant::now() >= soft_deadline;
+        }
+    }
+
+    #[inline]
+    fn accept_document(&mut self, doc_id: DocId) -> bool {
+        // The equality filter is checked first, as it is expected to be the most selective.
+        if let Some(ref fast_field_equals_filter) = self.fast_field_equals_filter_opt {
+            if !fast_field_equals_filter.is_match(doc_id) {
+                return false;
+            }
+        }
+        if let Some(ref fast_field_range_filter) = self.fast_field_range_filter_opt {
+            if !fast_field_range_filter.is_match(doc_id) {
+                return false;
+            }
+        }
+        if let Some(ref timestamp_filter) = self.timestamp_filter_opt {
+            if !timestamp_filter.is_within_range(doc_id) {
+                self.collection_stats.num_docs_rejected_by_timestamp_filter += 1;
+                return false;
+            }
+        }
+        // Hashing the value of a text field is the most expensive check, so it comes last.
+        self.hash_partition_filters
+            .iter_mut()
+            .all(|hash_partition_filter| hash_partition_filter.is_match(doc_id))
+    }
+}
+
+impl SegmentCollector for QuickwitSegmentCollector {
+    type Fruit = tantivy::Result<LeafSearchResponse>;
 
     #[inline]
     fn collect(&mut self, doc_id: DocId, score: Score) {
+        self.collection_stats.num_docs_visited += 1;
         if !self.accept_document(doc_id) {
+            self.collection_stats.num_docs_rejected += 1;
             return;
         }
+        self.collection_stats.num_docs_accepted += 1;
+        if let Some(min_score) = self.min_score_opt {
+            if score < min_score {
+                return;
+            }
+        }
 
-        self.num_hits += 1;
-        self.collect_top_k(doc_id, score);
+        match self.count_hits_threshold_opt {
+            Some(count_hits_threshold) if self.num_hits >= count_hits_threshold => {
+                self.num_hits_is_lower_bound = true;
+            }
+            _ => self.num_hits += 1,
+        }
+        if let Some(doc_id_set) = &mut self.doc_id_set_opt {
+            // The doc id set replaces the top-K.
+            doc_id_set.insert(doc_id);
+        } else if !self.aggregation_only {
+            // Pure aggregation searches only drive the aggregation segment collector.
+            // Count-only searches skip the top-K machinery altogether.
+            if self.max_hits > 0 {
+                self.collect_top_k(doc_id, score);
+                if let Some(rank_fusion_segment_collector) =
+                    &mut self.rank_fusion_segment_collector_opt
+                {
+                    rank_fusion_segment_collector.collect_top_k(doc_id, score);
+                }
+            }
+            self.collect_bucket_sample(doc_id, score);
+        }
 
         match self.aggregation.as_mut() {
             Some(AggregationSegmentCollectors::FindTraceIdsSegmentCollector(collector)) => {
                 collector.collect(doc_id, score)
             }
+            Some(AggregationSegmentCollectors::DistinctCountSegmentCollector(collector)) => {
+                collector.collect(doc_id, score)
+            }
+            Some(AggregationSegmentCollectors::TimestampRangeSegmentCollector(collector)) => {
+                collector.collect(doc_id, score)
+            }
             Some(AggregationSegmentCollectors::TantivyAggregationSegmentCollector(collector)) => {
                 collector.collect(doc_id, score)
             }
@@ -254,38 +1367,152 @@ impl SegmentCollector for QuickwitSegmentCollector {
         let segment_ord = self.segment_ord;
         // TODO use into_iter_sorted() once it gets stable.
         let split_id = self.split_id;
-        let partial_hits: Vec<PartialHit> = self
-            .hits
-            .into_sorted_vec()
-            .into_iter()
-            .map(|hit| PartialHit {
-                sorting_field_value: hit.sorting_field_value,
-                segment_ord,
-                doc_id: hit.doc_id,
-                split_id: split_id.clone(),
-            })
-            .collect();
+        let docvalue_fields_reader_opt = self.docvalue_fields_reader_opt;
+        // The sort values are only decoded for the harvested hits.
+        let (sort_value_computers, sort_value_types): (Vec<&SortingFieldComputer>, Vec<i32>) =
+            if self.return_sort_values {
+                let sort_value_computers = std::iter::once(&self.sort_by)
+                    .chain(&self.secondary_sort_by)
+                    .collect();
+                let sort_value_types = self
+                    .sort_value_types
+                    .iter()
+                    .map(|sort_value_type| *sort_value_type as i32)
+                    .collect();
+                (sort_value_computers, sort_value_types)
+            } else {
+                (Vec::new(), Vec::new())
+            };
+        let to_partial_hit = |hit: PartialHitHeapItem| PartialHit {
+            sorting_field_value: hit.sorting_field_value,
+            segment_ord,
+            doc_id: hit.doc_id,
+            split_id: split_id.clone(),
+            tie_breaker_value: hit.tie_breaker_value,
+            docvalue_fields: docvalue_fields_reader_opt
+                .as_ref()
+                .map(|docvalue_fields_reader| docvalue_fields_reader.read(hit.doc_id))
+                .unwrap_or_default(),
+            secondary_sorting_field_values: hit.secondary_sorting_field_values,
+            score: hit.score_opt,
+            collapse_key: None,
+            dedup_key: None,
+            sort_values: sort_value_computers
+                .iter()
+                .map(|sort_value_computer| {
+                    sort_value_computer
+                        .compute_sort_value(hit.doc_id, hit.score_opt.unwrap_or_default())
+                        .to_string()
+                })
+                .collect(),
+        };
+        let mut partial_hits: Vec<PartialHit> = match self.collapse_field_opt {
+            Some(collapse_field) => collapse_field
+                .harvest(self.max_hits)?
+                .into_iter()
+                .map(|(hit, collapse_key)| PartialHit {
+                    collapse_key,
+                    ..to_partial_hit(hit)
+                })
+                .collect(),
+            None => self
+                .hits
+                .into_sorted_vec()
+                .into_iter()
+                .map(to_partial_hit)
+                .collect(),
+        };
+        if let Some(dedup_field) = &self.dedup_field_opt {
+            for partial_hit in &mut partial_hits {
+                partial_hit.dedup_key = dedup_field.dedup_key(partial_hit.doc_id)?;
+            }
+        }
+        let bucket_samples: Vec<LeafBucketSamples> = match self.bucket_sampler_opt {
+            Some(bucket_sampler) => bucket_sampler
+                .harvest()?
+                .into_iter()
+                .map(|(key, hits)| LeafBucketSamples {
+                    key,
+                    partial_hits: hits.into_iter().map(to_partial_hit).collect(),
+                })
+                .collect(),
+            None => Vec::new(),
+        };
 
         let intermediate_aggregation_result = match self.aggregation {
             Some(AggregationSegmentCollectors::FindTraceIdsSegmentCollector(collector)) => {
                 let fruit = collector.harvest();
+                let serialized = serialize_tagged_fruit(AggregationKind::FindTraceIds, &fruit)
+                    .expect("Collector fruit should be serializable.");
+                Some(serialized)
+            }
+            Some(AggregationSegmentCollectors::DistinctCountSegmentCollector(collector)) => {
+                let serialized =
+                    serialize_tagged_fruit(AggregationKind::DistinctCount, &collector.harvest())
+                        .expect("Collector fruit should be serializable.");
+                Some(serialized)
+            }
+            Some(AggregationSegmentCollectors::TimestampRangeSegmentCollector(collector)) => {
                 let serialized =
-                    postcard::to_allocvec(&fruit).expect("Collector fruit should be serializable.");
+                    serialize_tagged_fruit(AggregationKind::TimestampRange, &collector.harvest())
+                        .expect("Collector fruit should be serializable.");
                 Some(serialized)
             }
             Some(AggregationSegmentCollectors::TantivyAggregationSegmentCollector(collector)) => {
-                let serialized = postcard::to_allocvec(&collector.harvest()?)
-                    .expect("Collector fruit should be serializable.");
+                let serialized =
+                    serialize_tagged_fruit(AggregationKind::Tantivy, &collector.harvest()?)
+                        .expect("Collector fruit should be serializable.");
                 Some(serialized)
             }
             None => None,
         };
+        let doc_id_sets: Vec<SegmentDocIdSet> = match &self.doc_id_set_opt {
+            Some(doc_id_set) => vec![SegmentDocIdSet {
+                split_id: split_id.clone(),
+                segment_ord,
+                doc_ids: doc_id_set
+                    .to_bytes()
+                    .expect("Doc id set should be serializable."),
+            }],
+            None => Vec::new(),
+        };
+        let min_sorting_field_value = min_sorting_field_value(&partial_hits);
+        // The rankings are fused when merging the segment responses.
+        let rankings = match self.rank_fusion_segment_collector_opt {
+            Some(rank_fusion_segment_collector) => vec![
+                Ranking {
+                    partial_hits: partial_hits.clone(),
+                },
+                Ranking {
+                    partial_hits: rank_fusion_segment_collector.harvest()?.partial_hits,
+                },
+            ],
+            None => Vec::new(),
+        };
         Ok(LeafSearchResponse {
             intermediate_aggregation_result,
             num_hits: self.num_hits,
             partial_hits,
             failed_splits: Vec::new(),
             num_attempted_splits: 1,
+            num_successful_splits: 1,
+            sort_value_types,
+            top_hits_aggregation: None,
+            estimated_total_hits: None,
+            rankings,
+            bucket_samples,
+            // Documents left unvisited are not counted either.
+            num_hits_is_lower_bound: self.num_hits_is_lower_bound || self.timed_out,
+            timed_out: self.timed_out,
+            collection_stats: Some(self.collection_stats),
+            clamped_max_hits: None,
+            doc_id_sets,
+            // Set by the leaf search, which knows where the data of the split came from.
+            cache_status: None,
+            min_sorting_field_value,
+            // Set by the leaf search, which knows which split was searched.
+            split_id: None,
+            aggregation_is_partial: false,
         })
     }
 }
@@ -297,392 +1524,5842 @@ pub enum QuickwitAggregations {
     /// Aggregation used by the Jaeger service to find trace IDs that match a
     /// [`quickwit_proto::jaeger::storage::v1::FindTraceIDsRequest`].
     FindTraceIdsAggregation(FindTraceIdsCollector),
+    /// Estimates the number of distinct values of a fast field, see [`DistinctCountCollector`].
+    DistinctCountAggregation(DistinctCountCollector),
+    /// Returns the smallest and the largest timestamps of the matching documents, see
+    /// [`TimestampRangeCollector`].
+    TimestampRangeAggregation(TimestampRangeCollector),
     /// Your classic Tantivy aggregation.
     TantivyAggregations(Aggregations),
 }
 
 impl QuickwitAggregations {
+    /// Returns the kind of the intermediate results of the aggregations.
+    pub(crate) fn kind(&self) -> AggregationKind {
+        match self {
+            QuickwitAggregations::FindTraceIdsAggregation(_) => AggregationKind::FindTraceIds,
+            QuickwitAggregations::DistinctCountAggregation(_) => AggregationKind::DistinctCount,
+            QuickwitAggregations::TimestampRangeAggregation(_) => AggregationKind::TimestampRange,
+            QuickwitAggregations::TantivyAggregations(_) => AggregationKind::Tantivy,
+        }
+    }
+
     fn fast_field_names(&self) -> HashSet<String> {
         match self {
             QuickwitAggregations::FindTraceIdsAggregation(collector) => {
                 collector.fast_field_names()
             }
+            QuickwitAggregations::DistinctCountAggregation(collector) => {
+                collector.fast_field_names()
+            }
+            QuickwitAggregations::TimestampRangeAggregation(collector) => {
+                collector.fast_field_names()
+            }
             QuickwitAggregations::TantivyAggregations(aggregations) => {
                 get_fast_field_names(aggregations)
             }
         }
     }
-}
 
-/// The quickwit collector is the tantivy Collector used in Quickwit.
-///
-/// It defines the data that should be accumulated about the documents matching
-/// the query.
-#[derive(Clone)]
-pub(crate) struct QuickwitCollector {
-    pub split_id: String,
-    pub start_offset: usize,
-    pub max_hits: usize,
-    pub sort_by: SortBy,
-    timestamp_filter_builder_opt: Option<TimestampFilterBuilder>,
-    pub aggregation: Option<QuickwitAggregations>,
-    pub aggregation_limits: AggregationLimits,
-}
+    /// Rejects the aggregations if they may create more buckets than the limit, before any
+    /// document is collected, see `estimate_num_buckets`. The error names the aggregation at
+    /// which the estimate crosses the limit.
+    pub(crate) fn check_bucket_limit(&self, bucket_limit: u32) -> crate::Result<()> {
+        let QuickwitAggregations::TantivyAggregations(aggregations) = self else {
+            return Ok(());
+        };
+        let mut num_buckets = 0;
+        estimate_num_buckets(aggregations, "", 1, bucket_limit as u64, &mut num_buckets)
+            .map_err(SearchError::InvalidAggregationRequest)
+    }
 
-impl QuickwitCollector {
-    pub fn fast_field_names(&self) -> HashSet<String> {
-        let mut fast_field_names = HashSet::default();
-        match &self.sort_by {
-            SortBy::DocId | SortBy::Score { .. } => {}
-            SortBy::FastField { field_name, .. } => {
-                fast_field_names.insert(field_name.clone());
-            }
-        }
-        if let Some(aggregations) = &self.aggregation {
-            fast_field_names.extend(aggregations.fast_field_names());
-        }
-        if let Some(timestamp_filter_builder) = &self.timestamp_filter_builder_opt {
-            fast_field_names.insert(timestamp_filter_builder.timestamp_field_name.clone());
-        }
-        fast_field_names
+    /// Rejects the aggregations referencing a field that does not exist in the schema, is not
+    /// a fast field, or has a type the aggregation cannot handle. The error names the field
+    /// and the aggregation referencing it.
+    pub(crate) fn validate_fields(&self, schema: &Schema) -> crate::Result<()> {
+        let QuickwitAggregations::TantivyAggregations(aggregations) = self else {
+            return Ok(());
+        };
+        validate_aggregation_fields(aggregations, "", schema)
+            .map_err(SearchError::InvalidAggregationRequest)
     }
 
-    pub fn warmup_info(&self) -> WarmupInfo {
-        WarmupInfo {
-            term_dict_field_names: Default::default(),
-            fast_field_names: self.fast_field_names(),
-            field_norms: self.requires_scoring(),
-            ..WarmupInfo::default()
+    /// Returns the fast fields that the aggregations read with specific column types, along with
+    /// the aggregation reading them. The fields read with any column type are left out.
+    fn typed_fast_fields(&self) -> Vec<(String, &str, FastFieldColumnTypes)> {
+        match self {
+            QuickwitAggregations::FindTraceIdsAggregation(collector) => {
+                let usage = "the find trace ids aggregation".to_string();
+                let mut typed_fast_fields = vec![
+                    (
+                        usage.clone(),
+                        collector.trace_id_field_name.as_str(),
+                        FastFieldColumnTypes::TEXT,
+                    ),
+                    (
+                        usage.clone(),
+                        collector.span_timestamp_field_name.as_str(),
+                        FastFieldColumnTypes::DATETIME,
+                    ),
+                ];
+                if let Some(service_name_field) = &collector.service_name_field {
+                    typed_fast_fields.push((
+                        usage,
+                        service_name_field.as_str(),
+                        FastFieldColumnTypes::TEXT,
+                    ));
+                }
+                typed_fast_fields
+            }
+            QuickwitAggregations::DistinctCountAggregation(_) => Vec::new(),
+            QuickwitAggregations::TimestampRangeAggregation(collector) => collector
+                .timestamp_range
+                .field
+                .iter()
+                .map(|field_name| {
+                    (
+                        "the timestamp range aggregation".to_string(),
+                        field_name.as_str(),
+                        FastFieldColumnTypes::DATETIME,
+                    )
+                })
+                .collect(),
+            QuickwitAggregations::TantivyAggregations(aggregations) => {
+                let mut typed_fast_fields = Vec::new();
+                collect_typed_aggregation_fields(aggregations, "", &mut typed_fast_fields);
+                typed_fast_fields
+            }
         }
     }
 }
 
-impl Collector for QuickwitCollector {
-    type Child = QuickwitSegmentCollector;
-    type Fruit = LeafSearchResponse;
+/// Column types with which a sort criterion or an aggregation reads a fast field, see
+/// `QuickwitCollector::validate_fast_field_column_types`.
+#[derive(Clone, Copy, Debug)]
+struct FastFieldColumnTypes {
+    /// Describes the column types in errors, as in "a numeric field".
+    description: &'static str,
+    column_types: &'static [ColumnType],
+}
 
-    fn for_segment(
-        &self,
-        segment_ord: SegmentOrdinal,
-        segment_reader: &SegmentReader,
-    ) -> tantivy::Result<Self::Child> {
-        let sort_by = resolve_sort_by(&self.sort_by, segment_reader)?;
-        // Regardless of the start_offset, we need to collect top-K
-        // starting from 0 for every leaves.
-        let leaf_max_hits = self.max_hits + self.start_offset;
+impl FastFieldColumnTypes {
+    const NUMERIC: Self = FastFieldColumnTypes {
+        description: "a numeric",
+        column_types: &[ColumnType::U64, ColumnType::I64, ColumnType::F64],
+    };
+    /// Sort columns are opened leniently, see `open_sort_column`.
+    const SORTABLE: Self = FastFieldColumnTypes {
+        description: "a numeric, boolean or datetime",
+        column_types: &[
+            ColumnType::U64,
+            ColumnType::I64,
+            ColumnType::F64,
+            ColumnType::Bool,
+            ColumnType::DateTime,
+        ],
+    };
+    const NUMERIC_OR_DATETIME: Self = FastFieldColumnTypes {
+        description: "a numeric or datetime",
+        column_types: &[
+            ColumnType::U64,
+            ColumnType::I64,
+            ColumnType::F64,
+            ColumnType::DateTime,
+        ],
+    };
+    const FLOAT: Self = FastFieldColumnTypes {
+        description: "a float",
+        column_types: &[ColumnType::F64],
+    };
+    const DATETIME: Self = FastFieldColumnTypes {
+        description: "a datetime",
+        column_types: &[ColumnType::DateTime],
+    };
+    const TEXT: Self = FastFieldColumnTypes {
+        description: "a text",
+        column_types: &[ColumnType::Str],
+    };
 
-        let timestamp_filter_opt = match &self.timestamp_filter_builder_opt {
-            Some(timestamp_filter_builder) => timestamp_filter_builder.build(segment_reader)?,
-            None => None,
+    fn intersects(&self, other: &FastFieldColumnTypes) -> bool {
+        self.column_types
+            .iter()
+            .any(|column_type| other.column_types.contains(column_type))
+    }
+}
+
+/// Collects the fields of the aggregations and of their sub-aggregations that are read with
+/// specific column types, see `QuickwitAggregations::typed_fast_fields`. `terms` and
+/// `value_count` aggregations read any column type.
+fn collect_typed_aggregation_fields<'a>(
+    aggregations: &'a Aggregations,
+    parent_path: &str,
+    typed_fast_fields: &mut Vec<(String, &'a str, FastFieldColumnTypes)>,
+) {
+    // Sibling aggregations are visited in a deterministic order for the error to be stable.
+    for (name, aggregation) in aggregations.iter().sorted_by_key(|(name, _)| *name) {
+        let path = if parent_path.is_empty() {
+            name.clone()
+        } else {
+            format!("{parent_path}.{name}")
         };
-        let aggregation = match &self.aggregation {
-            Some(QuickwitAggregations::FindTraceIdsAggregation(collector)) => {
-                Some(AggregationSegmentCollectors::FindTraceIdsSegmentCollector(
-                    Box::new(collector.for_segment(0, segment_reader)?),
-                ))
+        let column_types_opt = match &aggregation.agg {
+            AggregationVariants::Terms(_) | AggregationVariants::Count(_) => None,
+            AggregationVariants::DateHistogram(_) => Some(FastFieldColumnTypes::DATETIME),
+            AggregationVariants::Range(_)
+            | AggregationVariants::Histogram(_)
+            | AggregationVariants::Average(_)
+            | AggregationVariants::Max(_)
+            | AggregationVariants::Min(_)
+            | AggregationVariants::Stats(_)
+            | AggregationVariants::Sum(_)
+            | AggregationVariants::Percentiles(_) => {
+                Some(FastFieldColumnTypes::NUMERIC_OR_DATETIME)
             }
-            Some(QuickwitAggregations::TantivyAggregations(aggs)) => Some(
-                AggregationSegmentCollectors::TantivyAggregationSegmentCollector(
-                    AggregationSegmentCollector::from_agg_req_and_reader(
-                        aggs,
-                        segment_reader,
-                        &self.aggregation_limits,
-                    )?,
-                ),
-            ),
-            None => None,
         };
-        Ok(QuickwitSegmentCollector {
-            num_hits: 0u64,
-            split_id: self.split_id.clone(),
-            sort_by,
-            hits: BinaryHeap::with_capacity(leaf_max_hits),
-            segment_ord,
-            max_hits: leaf_max_hits,
-            timestamp_filter_opt,
-            aggregation,
-        })
+        if let Some(column_types) = column_types_opt {
+            let (field_name, _) = aggregation_field(aggregation);
+            typed_fast_fields.push((format!("aggregation `{path}`"), field_name, column_types));
+        }
+        collect_typed_aggregation_fields(&aggregation.sub_aggregation, &path, typed_fast_fields);
     }
+}
 
-    fn requires_scoring(&self) -> bool {
-        // We do not need BM25 scoring in Quickwit if it is not opted-in.
-        // By returning false, we inform tantivy that it does not need to decompress
-        // term frequencies.
-        match self.sort_by {
-            SortBy::DocId | SortBy::FastField { .. } => false,
-            SortBy::Score { .. } => true,
+/// Returns the fast fields read by a sort criterion.
+fn sort_field_names(sort_by: &SortBy) -> Vec<&str> {
+    match sort_by {
+        SortBy::DocId | SortBy::Score { .. } | SortBy::Random { .. } => Vec::new(),
+        SortBy::FastField { field_name, .. }
+        | SortBy::FieldExists { field_name, .. }
+        | SortBy::HashPartition { field_name, .. } => vec![field_name.as_str()],
+        SortBy::ScoreWithBoost { boost_field, .. } => vec![boost_field.as_str()],
+        SortBy::GeoDistance {
+            lat_field,
+            lon_field,
+            ..
+        } => vec![lat_field.as_str(), lon_field.as_str()],
+    }
+}
+
+/// Rejects the sort criteria reading a field of the schema that is not a fast field. The segments
+/// would read it as an empty column, and return the hits in a meaningless order.
+///
+/// Fields absent from the schema are accepted, as they may be captured by the dynamic field, or
+/// be paths within JSON fields.
+fn validate_sort_fields_are_fast<'a>(
+    sort_by: impl IntoIterator<Item = &'a SortBy>,
+    schema: &Schema,
+) -> crate::Result<()> {
+    for field_name in sort_by.into_iter().flat_map(sort_field_names) {
+        let Some((field, _json_path)) = schema.find_field(field_name) else {
+            continue;
+        };
+        if !schema.get_field_entry(field).is_fast() {
+            return Err(SearchError::InvalidArgument(format!(
+                "cannot sort by field `{field_name}`, which is not a fast field"
+            )));
         }
     }
+    Ok(())
+}
 
-    fn merge_fruits(
-        &self,
-        segment_fruits: Vec<tantivy::Result<LeafSearchResponse>>,
-    ) -> tantivy::Result<Self::Fruit> {
-        let segment_fruits: tantivy::Result<Vec<LeafSearchResponse>> =
-            segment_fruits.into_iter().collect();
-        // We want the hits in [start_offset..start_offset + max_hits).
-        // All leaves will return their top [0..max_hits) documents.
-        // We compute the overall [0..start_offset + max_hits) documents ...
-        let num_hits = self.start_offset + self.max_hits;
-        let mut merged_leaf_response =
-            merge_leaf_responses(&self.aggregation, segment_fruits?, num_hits)?;
-        // ... and drop the first [..start_offsets) hits.
-        merged_leaf_response
-            .partial_hits
-            .drain(
-                0..self
-                    .start_offset
-                    .min(merged_leaf_response.partial_hits.len()),
-            )
-            .count(); //< we just use count as a way to consume the entire iterator.
-        Ok(merged_leaf_response)
+/// Returns the fast fields that a sort criterion reads with specific column types. The fields
+/// sorted by presence or hashed into partitions are read with any column type.
+fn typed_sort_fields(sort_by: &SortBy) -> Vec<(&str, FastFieldColumnTypes)> {
+    match sort_by {
+        SortBy::DocId
+        | SortBy::Score { .. }
+        | SortBy::Random { .. }
+        | SortBy::FieldExists { .. }
+        | SortBy::HashPartition { .. } => Vec::new(),
+        SortBy::FastField {
+            field_name,
+            aggregate: SortValueAggregate::Sum,
+            ..
+        } => vec![(field_name.as_str(), FastFieldColumnTypes::NUMERIC)],
+        SortBy::FastField { field_name, .. } => {
+            vec![(field_name.as_str(), FastFieldColumnTypes::SORTABLE)]
+        }
+        SortBy::ScoreWithBoost { boost_field, .. } => {
+            vec![(boost_field.as_str(), FastFieldColumnTypes::SORTABLE)]
+        }
+        SortBy::GeoDistance {
+            lat_field,
+            lon_field,
+            ..
+        } => vec![
+            (lat_field.as_str(), FastFieldColumnTypes::FLOAT),
+            (lon_field.as_str(), FastFieldColumnTypes::FLOAT),
+        ],
     }
 }
 
-fn map_error(err: postcard::Error) -> TantivyError {
-    TantivyError::InternalError(format!("Merge Result Postcard Error: {}", err))
+/// Returns the name of the field of an aggregation, and whether the aggregation requires the
+/// field to hold numerical or datetime values.
+fn aggregation_field(aggregation: &Aggregation) -> (&str, bool) {
+    match &aggregation.agg {
+        AggregationVariants::Terms(terms) => (&terms.field, false),
+        AggregationVariants::Range(range) => (&range.field, true),
+        AggregationVariants::Histogram(histogram) => (&histogram.field, true),
+        AggregationVariants::DateHistogram(date_histogram) => (&date_histogram.field, true),
+        AggregationVariants::Average(average) => (&average.field, true),
+        AggregationVariants::Count(count) => (&count.field, false),
+        AggregationVariants::Max(max) => (&max.field, true),
+        AggregationVariants::Min(min) => (&min.field, true),
+        AggregationVariants::Stats(stats) => (&stats.field, true),
+        AggregationVariants::Sum(sum) => (&sum.field, true),
+        AggregationVariants::Percentiles(percentiles) => (&percentiles.field, true),
+    }
 }
 
-/// Merges a set of Leaf Results.
-fn merge_leaf_responses(
+/// Checks the fields of the aggregations and of their sub-aggregations against the schema,
+/// and fails with the path of the first invalid aggregation.
+///
+/// Fields absent from the schema are accepted when the schema holds the dynamic field, as they
+/// may be captured by it. So are the paths within JSON fields, whose types are only known per
+/// split.
+fn validate_aggregation_fields(
+    aggregations: &Aggregations,
+    parent_path: &str,
+    schema: &Schema,
+) -> Result<(), String> {
+    // Sibling aggregations are visited in a deterministic order for the error to be stable.
+    for (name, aggregation) in aggregations.iter().sorted_by_key(|(name, _)| *name) {
+        let path = if parent_path.is_empty() {
+            name.clone()
+        } else {
+            format!("{parent_path}.{name}")
+        };
+        let (field_name, requires_numerical_field) = aggregation_field(aggregation);
+        match schema.find_field(field_name) {
+            Some((field, _json_path)) => {
+                let field_entry = schema.get_field_entry(field);
+                if !field_entry.is_fast() {
+                    return Err(format!(
+                        "aggregation `{path}` references field `{field_name}`, which is not a \
+                         fast field"
+                    ));
+                }
+                let is_numerical_field = matches!(
+                    field_entry.field_type(),
+                    FieldType::U64(_)
+                        | FieldType::I64(_)
+                        | FieldType::F64(_)
+                        | FieldType::Date(_)
+                        | FieldType::JsonObject(_)
+                );
+                if requires_numerical_field && !is_numerical_field {
+                    return Err(format!(
+                        "aggregation `{path}` requires a numerical or datetime field, but field \
+                         `{field_name}` is of type {:?}",
+                        field_entry.field_type().value_type()
+                    ));
+                }
+            }
+            None if schema.get_field(DYNAMIC_FIELD_NAME).is_ok() => {}
+            None => {
+                return Err(format!(
+                    "aggregation `{path}` references field `{field_name}`, which does not exist"
+                ));
+            }
+        }
+        validate_aggregation_fields(&aggregation.sub_aggregation, &path, schema)?;
+    }
+    Ok(())
+}
+
+/// Returns an upper bound of the number of buckets of a single bucket of the parent aggregation,
+/// or `None` for metric aggregations, which do not create buckets.
+///
+/// The number of buckets of a `terms` or `date_histogram` aggregation, or of a `histogram`
+/// aggregation without hard bounds, depends on the data, and only the bucket limit enforced
+/// during the collection applies to them: they count as a single bucket.
+fn num_buckets_upper_bound(aggregation: &Aggregation) -> Option<u64> {
+    match &aggregation.agg {
+        AggregationVariants::Range(range) => Some(range.ranges.len() as u64),
+        AggregationVariants::Histogram(histogram) => {
+            let num_buckets = histogram
+                .hard_bounds
+                .as_ref()
+                .filter(|_| histogram.interval > 0.0)
+                .map(|bounds| {
+                    let num_intervals = (bounds.max - bounds.min) / histogram.interval;
+                    (num_intervals.max(0.0) as u64).saturating_add(1)
+                })
+                .unwrap_or(1);
+            Some(num_buckets)
+        }
+        AggregationVariants::Terms(_) | AggregationVariants::DateHistogram(_) => Some(1),
+        _ => None,
+    }
+}
+
+/// Adds to `num_buckets` the estimated number of buckets created by the aggregations, for
+/// `num_parent_buckets` buckets of their parent, and fails with the path of the aggregation at
+/// which the estimate exceeds the bucket limit.
+fn estimate_num_buckets(
+    aggregations: &Aggregations,
+    parent_path: &str,
+    num_parent_buckets: u64,
+    bucket_limit: u64,
+    num_buckets: &mut u64,
+) -> Result<(), String> {
+    // Sibling aggregations are visited in a deterministic order for the error to be stable.
+    for (name, aggregation) in aggregations.iter().sorted_by_key(|(name, _)| *name) {
+        let Some(num_buckets_per_parent) = num_buckets_upper_bound(aggregation) else {
+            continue;
+        };
+        let path = if parent_path.is_empty() {
+            name.clone()
+        } else {
+            format!("{parent_path}.{name}")
+        };
+        let num_aggregation_buckets = num_parent_buckets.saturating_mul(num_buckets_per_parent);
+        *num_buckets = num_buckets.saturating_add(num_aggregation_buckets);
+        if *num_buckets > bucket_limit {
+            return Err(format!(
+                "aggregation `{path}` may create {num_aggregation_buckets} buckets, which brings \
+                 the aggregations above the bucket limit of {bucket_limit}"
+            ));
+        }
+        estimate_num_buckets(
+            &aggregation.sub_aggregation,
+            &path,
+            num_aggregation_buckets,
+            bucket_limit,
+            num_buckets,
+        )?;
+    }
+    Ok(())
+}
+
+/// Number of documents visited between two checks of the soft deadline, which keeps the cost
+/// of reading the clock negligible.
+const SOFT_DEADLINE_CHECK_INTERVAL: u64 = 1_024;
+
+/// The quickwit collector is the tantivy Collector used in Quickwit.
+///
+/// It defines the data that should be accumulated about the documents matching
+/// the query.
+#[derive(Clone)]
+pub(crate) struct QuickwitCollector {
+    pub(crate) split_id: String,
+    pub(crate) start_offset: usize,
+    pub(crate) max_hits: usize,
+    /// Sort criteria, by decreasing priority. It is never empty.
+    pub(crate) sort_by: Vec<SortBy>,
+    timestamp_filter_builder_opt: Option<TimestampFilterBuilder>,
+    fast_field_equals_filter_builder_opt: Option<FastFieldEqualsFilterBuilder>,
+    fast_field_range_filter_builder_opt: Option<FastFieldRangeFilterBuilder>,
+    pub(crate) aggregation: Option<QuickwitAggregations>,
+    pub(crate) aggregation_limits: AggregationLimits,
+    pub(crate) round_robin_splits: bool,
+    pub(crate) search_after: Option<PartialHit>,
+    pub(crate) tie_breaker_field: Option<String>,
+    pub(crate) top_hits_aggregation: Option<TopHitsAggregations>,
+    pub(crate) rank_fusion: Option<ReciprocalRankFusion>,
+    pub(crate) bucket_samples: Option<BucketSamplesRequest>,
+    pub(crate) collapse_field: Option<String>,
+    /// Fast field grouping the hits, along with the maximum number of hits per group.
+    pub(crate) diversify_by: Option<(String, usize)>,
+    /// Character ending the source prefix of the split ids, along with the maximum number of hits
+    /// per source. Only applied when merging the hits of the splits.
+    pub(crate) max_hits_per_source: Option<(char, usize)>,
+    pub(crate) dedup_field: Option<String>,
+    pub(crate) count_hits: CountHits,
+    pub(crate) return_sort_values: bool,
+    pub(crate) min_score: Option<f32>,
+    /// Instant after which the collection stops and returns the hits collected so far.
+    pub(crate) soft_deadline_opt: Option<Instant>,
+    /// Sort settings of the index of the split, which tell the order in which the documents of
+    /// its segments are stored.
+    pub(crate) index_sort_opt: Option<IndexSortByField>,
+    /// Whether the request only asks for aggregations, without any hit nor sort, in which case
+    /// the segment collectors skip the hit machinery: no sort value, tie breaker or top-K.
+    pub(crate) aggregation_only: bool,
+    /// Order of the hits tying on all the sort criteria, which the segment collectors and the
+    /// merge must agree on.
+    pub(crate) tie_break_order: SortOrder,
+    /// Maximum number of top hits collected, `start_offset` included, whatever `max_hits`.
+    pub(crate) max_hits_ceiling: Option<usize>,
+    /// The `max_hits` the collector was left with, when the ceiling reduced it.
+    pub(crate) clamped_max_hits: Option<u64>,
+    /// Whether the segment collectors return the set of all their matching doc ids instead of
+    /// their top-K, whatever `max_hits`.
+    pub(crate) collect_doc_id_sets: bool,
+}
+
+/// Fuses the ranking of the collector with a second ranking, see `reciprocal_rank_fusion`.
+#[derive(Clone, Debug)]
+pub(crate) struct ReciprocalRankFusion {
+    /// Sort criteria of the second ranking. It is never empty.
+    pub sort_by: Vec<SortBy>,
+    pub rank_constant: u32,
+}
+
+/// Builds a `QuickwitCollector` field by field. By default, the collector sorts by doc id and
+/// returns no hit.
+pub(crate) struct QuickwitCollectorBuilder {
+    collector: QuickwitCollector,
+}
+
+impl Default for QuickwitCollectorBuilder {
+    fn default() -> Self {
+        let collector = QuickwitCollector {
+            split_id: String::new(),
+            start_offset: 0,
+            max_hits: 0,
+            sort_by: vec![SortBy::DocId],
+            timestamp_filter_builder_opt: None,
+            fast_field_equals_filter_builder_opt: None,
+            fast_field_range_filter_builder_opt: None,
+            aggregation: None,
+            aggregation_limits: AggregationLimits::default(),
+            round_robin_splits: false,
+            search_after: None,
+            tie_breaker_field: None,
+            top_hits_aggregation: None,
+            rank_fusion: None,
+            bucket_samples: None,
+            collapse_field: None,
+            diversify_by: None,
+            max_hits_per_source: None,
+            dedup_field: None,
+            count_hits: CountHits::default(),
+            return_sort_values: false,
+            min_score: None,
+            soft_deadline_opt: None,
+            index_sort_opt: None,
+            aggregation_only: false,
+            tie_break_order: SortOrder::Asc,
+            max_hits_ceiling: None,
+            clamped_max_hits: None,
+            collect_doc_id_sets: false,
+        };
+        QuickwitCollectorBuilder { collector }
+    }
+}
+
+impl QuickwitCollectorBuilder {
+    /// Sets the id of the split searched by the collector, which the hits refer to.
+    pub(crate) fn split_id(mut self, split_id: String) -> Self {
+        self.collector.split_id = split_id;
+        self
+    }
+
+    /// Sets the number of top hits to skip.
+    pub(crate) fn start_offset(mut self, start_offset: usize) -> Self {
+        self.collector.start_offset = start_offset;
+        self
+    }
+
+    /// Sets the number of hits to return, after the skipped ones.
+    pub(crate) fn max_hits(mut self, max_hits: usize) -> Self {
+        self.collector.max_hits = max_hits;
+        self
+    }
+
+    /// Sets the sort criteria, by decreasing priority. Without any criterion, the hits are
+    /// sorted by doc id.
+    pub(crate) fn sort_by(mut self, sort_by: Vec<SortBy>) -> Self {
+        self.collector.sort_by = sort_by;
+        self
+    }
+
+    /// Sets the filter retaining the documents within a time range.
+    pub(crate) fn timestamp_filter(
+        mut self,
+        timestamp_filter_builder_opt: Option<TimestampFilterBuilder>,
+    ) -> Self {
+        self.collector.timestamp_filter_builder_opt = timestamp_filter_builder_opt;
+        self
+    }
+
+    pub(crate) fn fast_field_equals_filter(
+        mut self,
+        fast_field_equals_filter_builder_opt: Option<FastFieldEqualsFilterBuilder>,
+    ) -> Self {
+        self.collector.fast_field_equals_filter_builder_opt = fast_field_equals_filter_builder_opt;
+        self
+    }
+
+    pub(crate) fn fast_field_range_filter(
+        mut self,
+        fast_field_range_filter_builder_opt: Option<FastFieldRangeFilterBuilder>,
+    ) -> Self {
+        self.collector.fast_field_range_filter_builder_opt = fast_field_range_filter_builder_opt;
+        self
+    }
+
+    /// Sets the aggregation computed over the matching documents.
+    pub(crate) fn aggregation(mut self, aggregation_opt: Option<QuickwitAggregations>) -> Self {
+        self.collector.aggregation = aggregation_opt;
+        self
+    }
+
+    /// Sets the memory and bucket limits of the aggregation.
+    pub(crate) fn aggregation_limits(mut self, aggregation_limits: AggregationLimits) -> Self {
+        self.collector.aggregation_limits = aggregation_limits;
+        self
+    }
+
+    /// Sets whether the hits are taken from the splits in turn when merging, rather than by
+    /// rank.
+    pub(crate) fn round_robin_splits(mut self, round_robin_splits: bool) -> Self {
+        self.collector.round_robin_splits = round_robin_splits;
+        self
+    }
+
+    /// Sets the hit after which the hits are collected, to paginate.
+    pub(crate) fn search_after(mut self, search_after_opt: Option<PartialHit>) -> Self {
+        self.collector.search_after = search_after_opt;
+        self
+    }
+
+    /// Sets the fast field breaking the ties between the hits.
+    pub(crate) fn tie_breaker_field(mut self, tie_breaker_field_opt: Option<String>) -> Self {
+        self.collector.tie_breaker_field = tie_breaker_field_opt;
+        self
+    }
+
+    pub(crate) fn top_hits_aggregation(
+        mut self,
+        top_hits_aggregation_opt: Option<TopHitsAggregations>,
+    ) -> Self {
+        self.collector.top_hits_aggregation = top_hits_aggregation_opt;
+        self
+    }
+
+    pub(crate) fn rank_fusion(mut self, rank_fusion_opt: Option<ReciprocalRankFusion>) -> Self {
+        self.collector.rank_fusion = rank_fusion_opt;
+        self
+    }
+
+    pub(crate) fn bucket_samples(
+        mut self,
+        bucket_samples_opt: Option<BucketSamplesRequest>,
+    ) -> Self {
+        self.collector.bucket_samples = bucket_samples_opt;
+        self
+    }
+
+    /// Sets the fast field whose value only one hit may have.
+    pub(crate) fn collapse_field(mut self, collapse_field_opt: Option<String>) -> Self {
+        self.collector.collapse_field = collapse_field_opt;
+        self
+    }
+
+    /// Sets the fast field grouping the hits, along with the maximum number of hits kept per
+    /// group, so that no group dominates the hits.
+    pub(crate) fn diversify_by(mut self, diversify_by_opt: Option<(String, usize)>) -> Self {
+        self.collector.diversify_by = diversify_by_opt;
+        self
+    }
+
+    /// Sets the character ending the source prefix of the split ids, along with the maximum
+    /// number of hits kept per source, so that no source dominates the hits.
+    pub(crate) fn max_hits_per_source(
+        mut self,
+        max_hits_per_source_opt: Option<(char, usize)>,
+    ) -> Self {
+        self.collector.max_hits_per_source = max_hits_per_source_opt;
+        self
+    }
+
+    /// Sets the fast field identifying the copies of a document, only one of which is kept.
+    pub(crate) fn dedup_field(mut self, dedup_field_opt: Option<String>) -> Self {
+        self.collector.dedup_field = dedup_field_opt;
+        self
+    }
+
+    pub(crate) fn count_hits(mut self, count_hits: CountHits) -> Self {
+        self.collector.count_hits = count_hits;
+        self
+    }
+
+    /// Sets whether the hits carry the values they are sorted by.
+    pub(crate) fn return_sort_values(mut self, return_sort_values: bool) -> Self {
+        self.collector.return_sort_values = return_sort_values;
+        self
+    }
+
+    /// Sets the score below which the documents are skipped, which requires sorting by score.
+    pub(crate) fn min_score(mut self, min_score_opt: Option<f32>) -> Self {
+        self.collector.min_score = min_score_opt;
+        self
+    }
+
+    /// Sets the instant after which the collection stops and returns the hits collected so far.
+    pub(crate) fn soft_deadline(mut self, soft_deadline_opt: Option<Instant>) -> Self {
+        self.collector.soft_deadline_opt = soft_deadline_opt;
+        self
+    }
+
+    /// Sets the order of the hits tying on all the sort criteria.
+    pub(crate) fn tie_break_order(mut self, tie_break_order: SortOrder) -> Self {
+        self.collector.tie_break_order = tie_break_order;
+        self
+    }
+
+    /// Sets the maximum number of top hits collected, skipped ones included. The collector
+    /// reduces its `max_hits` to stay within the ceiling and reports the reduced value in the
+    /// `clamped_max_hits` of its response.
+    pub(crate) fn max_hits_ceiling(mut self, max_hits_ceiling_opt: Option<usize>) -> Self {
+        self.collector.max_hits_ceiling = max_hits_ceiling_opt;
+        self
+    }
+
+    /// Sets whether the segment collectors return the set of all their matching doc ids, see
+    /// [`DocIdSet`], instead of their top-K. The sets take up to `max_doc / 8` bytes per segment.
+    pub(crate) fn collect_doc_id_sets(mut self, collect_doc_id_sets: bool) -> Self {
+        self.collector.collect_doc_id_sets = collect_doc_id_sets;
+        self
+    }
+
+    /// Builds the collector, after checking that the settings are consistent.
+    pub(crate) fn build(self) -> crate::Result<QuickwitCollector> {
+        let mut collector = self.collector;
+        if collector.sort_by.is_empty() {
+            collector.sort_by.push(SortBy::DocId);
+        }
+        // Without scoring, all the documents would score 0.
+        if collector.min_score.is_some() && !collector.requires_scoring() {
+            return Err(SearchError::InvalidArgument(
+                "min_score is only supported when sorting by `_score`".to_string(),
+            ));
+        }
+        if let Some((_, max_hits_per_group)) = &collector.diversify_by {
+            if collector.collapse_field.is_some() {
+                return Err(SearchError::InvalidArgument(
+                    "diversify_by cannot be combined with collapse_field".to_string(),
+                ));
+            }
+            if *max_hits_per_group == 0 {
+                return Err(SearchError::InvalidArgument(
+                    "the max_hits_per_group of diversify_by must be at least 1".to_string(),
+                ));
+            }
+        }
+        if let Some((_, max_hits_per_source)) = &collector.max_hits_per_source {
+            // The fusion of the rankings would override the capped hits.
+            if collector.rank_fusion.is_some() {
+                return Err(SearchError::InvalidArgument(
+                    "max_hits_per_source cannot be combined with rank_fusion".to_string(),
+                ));
+            }
+            if *max_hits_per_source == 0 {
+                return Err(SearchError::InvalidArgument(
+                    "the max_hits of max_hits_per_source must be at least 1".to_string(),
+                ));
+            }
+        }
+        collector.validate_fast_field_column_types()?;
+        if let Some(max_hits_ceiling) = collector.max_hits_ceiling {
+            let max_hits = collector
+                .max_hits
+                .min(max_hits_ceiling.saturating_sub(collector.start_offset));
+            if max_hits < collector.max_hits {
+                collector.max_hits = max_hits;
+                collector.clamped_max_hits = Some(max_hits as u64);
+            }
+        }
+        // Dashboards typically only request aggregations: the hits then do not need to be
+        // sorted, nor even considered.
+        collector.aggregation_only = collector.aggregation.is_some()
+            && collector.max_hits == 0
+            && matches!(collector.sort_by[..], [SortBy::DocId])
+            && collector.rank_fusion.is_none()
+            && collector.bucket_samples.is_none();
+        Ok(collector)
+    }
+}
+
+impl QuickwitCollector {
+    /// Creates a [`QuickwitCollectorBuilder`].
+    pub(crate) fn builder() -> QuickwitCollectorBuilder {
+        QuickwitCollectorBuilder::default()
+    }
+
+    /// Returns the fast fields read by the collector.
+    pub fn fast_field_names(&self) -> HashSet<String> {
+        let mut fast_field_names = HashSet::default();
+        let rank_fusion_sort_by: &[SortBy] = self
+            .rank_fusion
+            .as_ref()
+            .map(|rank_fusion| &rank_fusion.sort_by[..])
+            .unwrap_or_default();
+        for sort_by in self.sort_by.iter().chain(rank_fusion_sort_by) {
+            fast_field_names.extend(sort_field_names(sort_by).into_iter().map(str::to_string));
+        }
+        if let Some(aggregations) = &self.aggregation {
+            fast_field_names.extend(aggregations.fast_field_names());
+        }
+        if let Some(timestamp_filter_builder) = &self.timestamp_filter_builder_opt {
+            fast_field_names.insert(timestamp_filter_builder.timestamp_field_name.clone());
+        }
+        if let Some(fast_field_equals_filter_builder) = &self.fast_field_equals_filter_builder_opt {
+            fast_field_names.insert(fast_field_equals_filter_builder.field_name.clone());
+        }
+        if let Some(fast_field_range_filter_builder) = &self.fast_field_range_filter_builder_opt {
+            fast_field_names.insert(fast_field_range_filter_builder.field_name.clone());
+        }
+        if let Some(tie_breaker_field) = &self.tie_breaker_field {
+            fast_field_names.insert(tie_breaker_field.clone());
+        }
+        if let Some(top_hits_aggregation) = &self.top_hits_aggregation {
+            fast_field_names.extend(top_hits_aggregation.fast_field_names());
+        }
+        if let Some(bucket_samples) = &self.bucket_samples {
+            fast_field_names.insert(bucket_samples.field.clone());
+        }
+        if let Some(collapse_field) = &self.collapse_field {
+            fast_field_names.insert(collapse_field.clone());
+        }
+        if let Some((diversify_field, _)) = &self.diversify_by {
+            fast_field_names.insert(diversify_field.clone());
+        }
+        if let Some(dedup_field) = &self.dedup_field {
+            fast_field_names.insert(dedup_field.clone());
+        }
+        fast_field_names
+    }
+
+    /// Rejects the collectors whose sort criteria and aggregations read the same fast field with
+    /// column types they cannot agree on, as in sorting by a field that an aggregation reads as
+    /// text. Such a field could only be read by one of them, and the search would fail later
+    /// with an opaque error from the warmup or the collection.
+    fn validate_fast_field_column_types(&self) -> crate::Result<()> {
+        let Some(aggregation) = &self.aggregation else {
+            return Ok(());
+        };
+        let typed_aggregation_fields = aggregation.typed_fast_fields();
+        let rank_fusion_sort_by: &[SortBy] = self
+            .rank_fusion
+            .as_ref()
+            .map(|rank_fusion| &rank_fusion.sort_by[..])
+            .unwrap_or_default();
+        for sort_by in self.sort_by.iter().chain(rank_fusion_sort_by) {
+            for (sort_field_name, sort_column_types) in typed_sort_fields(sort_by) {
+                for (usage, field_name, aggregation_column_types) in &typed_aggregation_fields {
+                    if *field_name == sort_field_name
+                        && !sort_column_types.intersects(aggregation_column_types)
+                    {
+                        return Err(SearchError::InvalidArgument(format!(
+                            "field `{sort_field_name}` is sorted as {} field, but {usage} reads \
+                             it as {} field",
+                            sort_column_types.description, aggregation_column_types.description
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if the documents of the segment are stored in the order of the primary sort
+    /// criterion, so that the segment collector may stop once its top-K cannot change anymore.
+    ///
+    /// The documents must all have a value, as the sort settings of the index do not tell where
+    /// the documents without one are stored. Stopping early is also ruled out when anything
+    /// else than the top-K needs all the matching documents, and when the hits must be counted
+    /// exactly.
+    fn visits_docs_in_sort_order(&self, sort_by: &SortingFieldComputer) -> bool {
+        let Some(index_sort) = &self.index_sort_opt else {
+            return false;
+        };
+        let Some(SortBy::FastField {
+            field_name, order, ..
+        }) = self.sort_by.first()
+        else {
+            return false;
+        };
+        let is_index_sort_order = matches!(
+            (order, &index_sort.order),
+            (SortOrder::Asc, Order::Asc) | (SortOrder::Desc, Order::Desc)
+        );
+        let is_sort_column_full = match sort_by {
+            SortingFieldComputer::FastField { sort_column, .. } => sort_column.is_full(),
+            _ => false,
+        };
+        *field_name == index_sort.field
+            && is_index_sort_order
+            && is_sort_column_full
+            && self.max_hits > 0
+            && self.count_hits != CountHits::CountAll
+            && self.aggregation.is_none()
+            && self.rank_fusion.is_none()
+            && self.bucket_samples.is_none()
+            && self.collapse_field.is_none()
+            && self.diversify_by.is_none()
+            && !self.collect_doc_id_sets
+    }
+
+    /// Returns what must be warmed up before running the collector.
+    pub fn warmup_info(&self) -> WarmupInfo {
+        WarmupInfo {
+            term_dict_field_names: Default::default(),
+            fast_field_names: self.fast_field_names(),
+            field_norms: self.requires_scoring(),
+            ..WarmupInfo::default()
+        }
+    }
+}
+
+impl Collector for QuickwitCollector {
+    type Child = QuickwitSegmentCollector;
+    type Fruit = LeafSearchResponse;
+
+    fn for_segment(
+        &self,
+        segment_ord: SegmentOrdinal,
+        segment_reader: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        let (sorting_field_computers, sort_value_types): (Vec<SortingFieldComputer>, Vec<_>) =
+            resolve_sort_by(&self.sort_by, segment_reader)?
+                .into_iter()
+                .unzip();
+        let mut sort_by_iter = sorting_field_computers.into_iter();
+        let sort_by = sort_by_iter.next().unwrap_or(SortingFieldComputer::DocId);
+        let secondary_sort_by: Vec<SortingFieldComputer> = sort_by_iter.collect();
+        let keep_score = self.sort_by.iter().any(|sort_by| {
+            matches!(
+                sort_by,
+                SortBy::Score { .. } | SortBy::ScoreWithBoost { .. }
+            )
+        });
+        let tie_breaker_opt = self
+            .tie_breaker_field
+            .as_ref()
+            .filter(|_| !self.aggregation_only)
+            .map(|field_name| TieBreakerComputer::open(field_name, segment_reader))
+            .transpose()?;
+        // Regardless of the start_offset, we need to collect top-K
+        // starting from 0 for every leaves, unless no hit is requested at all.
+        let leaf_max_hits = if self.max_hits == 0 {
+            0
+        } else {
+            self.max_hits + self.start_offset
+        };
+        // The ceiling bounds the size of the top-K of the segments.
+        let leaf_max_hits = match self.max_hits_ceiling {
+            Some(max_hits_ceiling) => leaf_max_hits.min(max_hits_ceiling),
+            None => leaf_max_hits,
+        };
+        let count_hits_threshold_opt = match self.count_hits {
+            CountHits::CountAll => None,
+            CountHits::Underestimate => Some(leaf_max_hits as u64),
+            CountHits::Threshold(count_hits_threshold) => Some(count_hits_threshold),
+        };
+
+        let timestamp_filter_opt = match &self.timestamp_filter_builder_opt {
+            Some(timestamp_filter_builder) => timestamp_filter_builder.build(segment_reader)?,
+            None => None,
+        };
+        let fast_field_equals_filter_opt = self
+            .fast_field_equals_filter_builder_opt
+            .as_ref()
+            .map(|fast_field_equals_filter_builder| {
+                fast_field_equals_filter_builder.build(segment_reader)
+            })
+            .transpose()?;
+        let fast_field_range_filter_opt = self
+            .fast_field_range_filter_builder_opt
+            .as_ref()
+            .map(|fast_field_range_filter_builder| {
+                fast_field_range_filter_builder.build(segment_reader)
+            })
+            .transpose()?;
+        let hash_partition_filters = self
+            .sort_by
+            .iter()
+            .filter_map(|sort_by| match sort_by {
+                SortBy::HashPartition {
+                    field_name,
+                    modulus,
+                    bucket,
+                } => Some(HashPartitionFilter::open(
+                    field_name,
+                    *modulus,
+                    *bucket,
+                    segment_reader,
+                )),
+                _ => None,
+            })
+            .collect::<tantivy::Result<Vec<_>>>()?;
+        let search_after_opt = self.search_after.as_ref().map(|cursor| {
+            SegmentSearchAfter::new(cursor, &self.split_id, segment_ord, self.tie_break_order)
+        });
+        let visits_docs_in_sort_order = self.visits_docs_in_sort_order(&sort_by);
+        let docvalue_fields_reader_opt = self
+            .top_hits_aggregation
+            .as_ref()
+            .map(|top_hits_aggregation| {
+                DocValueFieldsReader::open(&top_hits_aggregation.fast_field_names(), segment_reader)
+            })
+            .transpose()?;
+        let aggregation = match &self.aggregation {
+            Some(QuickwitAggregations::FindTraceIdsAggregation(collector)) => {
+                Some(AggregationSegmentCollectors::FindTraceIdsSegmentCollector(
+                    Box::new(collector.for_segment(0, segment_reader)?),
+                ))
+            }
+            Some(QuickwitAggregations::DistinctCountAggregation(collector)) => {
+                Some(AggregationSegmentCollectors::DistinctCountSegmentCollector(
+                    collector.for_segment(0, segment_reader)?,
+                ))
+            }
+            Some(QuickwitAggregations::TimestampRangeAggregation(collector)) => Some(
+                AggregationSegmentCollectors::TimestampRangeSegmentCollector(
+                    collector.for_segment(0, segment_reader)?,
+                ),
+            ),
+            Some(QuickwitAggregations::TantivyAggregations(aggs)) => Some(
+                AggregationSegmentCollectors::TantivyAggregationSegmentCollector(
+                    AggregationSegmentCollector::from_agg_req_and_reader(
+                        aggs,
+                        segment_reader,
+                        &self.aggregation_limits,
+                    )?,
+                ),
+            ),
+            None => None,
+        };
+        let rank_fusion_segment_collector_opt = self
+            .rank_fusion
+            .as_ref()
+            .map(|rank_fusion| {
+                // The second ranking only needs the hits, the documents being filtered upstream.
+                let rank_fusion_collector = QuickwitCollector {
+                    sort_by: rank_fusion.sort_by.clone(),
+                    timestamp_filter_builder_opt: None,
+                    fast_field_equals_filter_builder_opt: None,
+                    fast_field_range_filter_builder_opt: None,
+                    aggregation: None,
+                    search_after: None,
+                    rank_fusion: None,
+                    bucket_samples: None,
+                    collapse_field: None,
+                    diversify_by: None,
+                    dedup_field: None,
+                    collect_doc_id_sets: false,
+                    count_hits: CountHits::CountAll,
+                    return_sort_values: false,
+                    ..self.clone()
+                };
+                rank_fusion_collector
+                    .for_segment(segment_ord, segment_reader)
+                    .map(Box::new)
+            })
+            .transpose()?;
+        let bucket_sampler_opt = self
+            .bucket_samples
+            .as_ref()
+            .map(|bucket_samples| BucketSampler::open(bucket_samples, segment_reader))
+            .transpose()?;
+        let collapse_field_opt = match (&self.collapse_field, &self.diversify_by) {
+            (Some(field_name), _) => Some(CollapseField::open(field_name, 1, segment_reader)?),
+            (None, Some((field_name, max_hits_per_group))) => Some(CollapseField::open(
+                field_name,
+                *max_hits_per_group,
+                segment_reader,
+            )?),
+            (None, None) => None,
+        };
+        let dedup_field_opt = self
+            .dedup_field
+            .as_ref()
+            .map(|field_name| DedupField::open(field_name, segment_reader))
+            .transpose()?;
+        let doc_id_set_opt = self.collect_doc_id_sets.then(DocIdSet::default);
+        Ok(QuickwitSegmentCollector {
+            num_hits: 0u64,
+            split_id: self.split_id.clone(),
+            sort_by,
+            secondary_sort_by,
+            keep_score,
+            tie_breaker_opt,
+            // Does not allocate for count-only searches, and does not allocate more than the
+            // segment can fill for pathological offsets.
+            hits: AdaptiveTopK::new(
+                leaf_max_hits,
+                leaf_max_hits.min(segment_reader.max_doc() as usize),
+            ),
+            segment_ord,
+            max_hits: leaf_max_hits,
+            timestamp_filter_opt,
+            fast_field_equals_filter_opt,
+            fast_field_range_filter_opt,
+            hash_partition_filters,
+            search_after_opt,
+            docvalue_fields_reader_opt,
+            aggregation,
+            rank_fusion_segment_collector_opt,
+            bucket_sampler_opt,
+            collapse_field_opt,
+            dedup_field_opt,
+            doc_id_set_opt,
+            count_hits_threshold_opt,
+            num_hits_is_lower_bound: false,
+            return_sort_values: self.return_sort_values,
+            sort_value_types,
+            min_score_opt: self.min_score,
+            soft_deadline_opt: self.soft_deadline_opt,
+            timed_out: false,
+            visits_docs_in_sort_order,
+            top_k_is_final: false,
+            aggregation_only: self.aggregation_only,
+            tie_break_order: self.tie_break_order,
+            collection_stats: CollectionStats::default(),
+        })
+    }
+
+    fn collect_segment(
+        &self,
+        weight: &dyn Weight,
+        segment_ord: SegmentOrdinal,
+        segment_reader: &SegmentReader,
+    ) -> tantivy::Result<<Self::Child as SegmentCollector>::Fruit> {
+        let mut segment_collector = self.for_segment(segment_ord, segment_reader)?;
+        // Only the segments partially overlapping the time range check the timestamps of their
+        // documents one by one.
+        if segment_collector.is_outside_time_range() {
+            return Ok(segment_collector.harvest());
+        }
+        let alive_bitset_opt = segment_reader.alive_bitset();
+        let is_alive = |doc_id: DocId| {
+            alive_bitset_opt
+                .map(|alive_bitset| alive_bitset.is_alive(doc_id))
+                .unwrap_or(true)
+        };
+        // The soft deadline may have passed before the segment is even visited.
+        segment_collector.check_soft_deadline();
+        if segment_collector.may_stop_early()
+            || segment_collector.visits_docs_in_sort_order
+            || self.soft_deadline_opt.is_some()
+        {
+            // The documents are visited one at a time to stop as soon as the collector is done
+            // or the soft deadline passes.
+            let requires_scoring = self.requires_scoring();
+            let mut scorer = weight.scorer(segment_reader, 1.0)?;
+            let mut doc_id = scorer.doc();
+            let mut num_visited_docs: u64 = 0;
+            while doc_id != TERMINATED && !segment_collector.is_done() {
+                if is_alive(doc_id) {
+                    let score = if requires_scoring {
+                        scorer.score()
+                    } else {
+                        0.0
+                    };
+                    segment_collector.collect(doc_id, score);
+                }
+                num_visited_docs += 1;
+                if num_visited_docs % SOFT_DEADLINE_CHECK_INTERVAL == 0 {
+                    segment_collector.check_soft_deadline();
+                }
+                doc_id = scorer.advance();
+            }
+        } else if self.requires_scoring() {
+            weight.for_each(segment_reader, &mut |doc_id, score| {
+                if is_alive(doc_id) {
+                    segment_collector.collect(doc_id, score);
+                }
+            })?;
+        } else {
+            weight.for_each_no_score(segment_reader, &mut |doc_ids| {
+                for &doc_id in doc_ids {
+                    if is_alive(doc_id) {
+                        segment_collector.collect(doc_id, 0.0);
+                    }
+                }
+            })?;
+        }
+        Ok(segment_collector.harvest())
+    }
+
+    fn requires_scoring(&self) -> bool {
+        // We do not need BM25 scoring in Quickwit if it is not opted-in.
+        // By returning false, we inform tantivy that it does not need to decompress
+        // term frequencies.
+        let rank_fusion_sort_by: &[SortBy] = self
+            .rank_fusion
+            .as_ref()
+            .map(|rank_fusion| &rank_fusion.sort_by[..])
+            .unwrap_or_default();
+        self.sort_by
+            .iter()
+            .chain(rank_fusion_sort_by)
+            .any(|sort_by| match sort_by {
+                SortBy::DocId
+                | SortBy::FastField { .. }
+                | SortBy::GeoDistance { .. }
+                | SortBy::Random { .. }
+                | SortBy::FieldExists { .. }
+                | SortBy::HashPartition { .. } => false,
+                SortBy::Score { .. } | SortBy::ScoreWithBoost { .. } => true,
+            })
+    }
+
+    fn merge_fruits(
+        &self,
+        segment_fruits: Vec<tantivy::Result<LeafSearchResponse>>,
+    ) -> tantivy::Result<Self::Fruit> {
+        let segment_fruits: tantivy::Result<Vec<LeafSearchResponse>> =
+            segment_fruits.into_iter().collect();
+        let merge_options = MergeOptions {
+            round_robin_splits: self.round_robin_splits,
+            bucket_samples_size: self
+                .bucket_samples
+                .as_ref()
+                .map(|bucket_samples| bucket_samples.size),
+            collapse: self.collapse_field.is_some(),
+            max_hits_per_group: self
+                .diversify_by
+                .as_ref()
+                .map(|(_, max_hits_per_group)| *max_hits_per_group),
+            max_hits_per_source: self.max_hits_per_source,
+            dedup: self.dedup_field.is_some(),
+            rank_fusion_constant: self
+                .rank_fusion
+                .as_ref()
+                .map(|rank_fusion| rank_fusion.rank_constant),
+            tie_break_order: self.tie_break_order,
+            aggregation_only: self.aggregation_only,
+        };
+        let mut merged_leaf_response = merge_search_responses_with_options(
+            segment_fruits?,
+            self.start_offset,
+            self.max_hits,
+            &self.aggregation,
+            merge_options,
+        )?;
+        merged_leaf_response.clamped_max_hits =
+            merge_clamped_max_hits([merged_leaf_response.clamped_max_hits, self.clamped_max_hits]);
+
+        // The top hits aggregations can only be computed once the final hits are known.
+        if let Some(top_hits_aggregation) = &self.top_hits_aggregation {
+            let top_hits_aggregation_result =
+                top_hits_aggregation.compute(&merged_leaf_response.partial_hits);
+            let serialized = serde_json::to_string(&top_hits_aggregation_result)
+                .map_err(|err| TantivyError::InternalError(err.to_string()))?;
+            merged_leaf_response.top_hits_aggregation = Some(serialized);
+        }
+        Ok(merged_leaf_response)
+    }
+}
+
+/// Counts the bytes of a serialized value without writing them.
+#[derive(Default)]
+struct SerializedLen(usize);
+
+impl postcard::ser_flavors::Flavor for SerializedLen {
+    type Output = usize;
+
+    fn try_push(&mut self, _data: u8) -> postcard::Result<()> {
+        self.0 += 1;
+        Ok(())
+    }
+
+    fn try_extend(&mut self, data: &[u8]) -> postcard::Result<()> {
+        self.0 += data.len();
+        Ok(())
+    }
+
+    fn finalize(self) -> postcard::Result<usize> {
+        Ok(self.0)
+    }
+}
+
+/// Serializes an aggregation fruit into a buffer allocated to its exact size.
+///
+/// `postcard::to_allocvec` grows its buffer by doubling it, so the peak memory of serializing
+/// a large intermediate aggregation result can reach three times its serialized size. Measuring
+/// the size first costs a second pass over the fruit, but no allocation.
+pub(crate) fn serialize_fruit<T: Serialize>(fruit: &T) -> postcard::Result<Vec<u8>> {
+    let serialized_len = postcard::serialize_with_flavor(fruit, SerializedLen::default())?;
+    postcard::to_extend(fruit, Vec::with_capacity(serialized_len))
+}
+
+/// Deserializes the aggregation fruits of the leaf responses one at a time and merges them as
+/// they come, releasing the serialized fruit of each leaf response along the way. This way, at
+/// most one fruit is held in memory on top of the merged one.
+///
+/// The fruits must have been produced by aggregations of the given kind. A single fruit is passed
+/// through as is, without being decoded, see [`ensure_tagged_fruit`]. Otherwise, a fruit that
+/// cannot be decoded is left out of the merge: its split is reported among the failed splits of its
+/// leaf response, which is flagged as carrying a partial aggregation.
+fn merge_serialized_fruits<T: Serialize + DeserializeOwned>(
+    leaf_responses: &mut [LeafSearchResponse],
+    kind: AggregationKind,
+    mut merge_fn: impl FnMut(T, T) -> tantivy::Result<T>,
+) -> tantivy::Result<Option<Vec<u8>>> {
+    let num_fruits = leaf_responses
+        .iter()
+        .filter(|leaf_response| leaf_response.intermediate_aggregation_result.is_some())
+        .count();
+    if num_fruits == 1 {
+        let serialized = leaf_responses
+            .iter_mut()
+            .find_map(|leaf_response| leaf_response.intermediate_aggregation_result.take())
+            .expect("One leaf response should have an intermediate aggregation result.");
+        return ensure_tagged_fruit(serialized, kind).map(Some);
+    }
+    let mut merged_fruit_opt: Option<T> = None;
+    for leaf_response in leaf_responses {
+        let Some(serialized) = leaf_response.intermediate_aggregation_result.take() else {
+            continue;
+        };
+        let fruit: T = match deserialize_tagged_fruit(&serialized, kind) {
+            Ok(fruit) => fruit,
+            Err(error) => {
+                report_undecodable_fruit(leaf_response, error);
+                continue;
+            }
+        };
+        drop(serialized);
+        merged_fruit_opt = Some(match merged_fruit_opt {
+            Some(merged_fruit) => merge_fn(merged_fruit, fruit)?,
+            None => fruit,
+        });
+    }
+    merged_fruit_opt
+        .map(|merged_fruit| serialize_tagged_fruit(kind, &merged_fruit))
+        .transpose()
+}
+
+/// Records that the intermediate aggregation result of a leaf response could not be decoded. The
+/// hits of the split are kept, so the split still counts as successful.
+fn report_undecodable_fruit(leaf_response: &mut LeafSearchResponse, error: TantivyError) {
+    leaf_response.aggregation_is_partial = true;
+    leaf_response.failed_splits.push(SplitSearchError {
+        error: format!("failed to decode the intermediate aggregation result: {error}"),
+        split_id: leaf_response.split_id.clone().unwrap_or_default(),
+        retryable_error: false,
+        code: SplitSearchErrorCode::Aggregation as i32,
+    });
+}
+
+/// Returns the serialized intermediate result of the aggregations when no document was
+/// collected.
+///
+/// Tantivy fills the aggregations missing from an intermediate result with empty ones when
+/// finalizing it, so an empty intermediate result finalizes into the full aggregation structure.
+fn empty_intermediate_aggregation_result(
+    aggregations_opt: &Option<QuickwitAggregations>,
+) -> tantivy::Result<Option<Vec<u8>>> {
+    let serialized = match aggregations_opt {
+        Some(QuickwitAggregations::FindTraceIdsAggregation(collector)) => serialize_tagged_fruit(
+            AggregationKind::FindTraceIds,
+            &collector.merge_fruits(Vec::new())?,
+        )?,
+        Some(QuickwitAggregations::DistinctCountAggregation(collector)) => serialize_tagged_fruit(
+            AggregationKind::DistinctCount,
+            &collector.merge_fruits(Vec::new())?,
+        )?,
+        Some(QuickwitAggregations::TimestampRangeAggregation(collector)) => serialize_tagged_fruit(
+            AggregationKind::TimestampRange,
+            &collector.merge_fruits(Vec::new())?,
+        )?,
+        Some(QuickwitAggregations::TantivyAggregations(_)) => serialize_tagged_fruit(
+            AggregationKind::Tantivy,
+            &IntermediateAggregationResults::default(),
+        )?,
+        None => return Ok(None),
+    };
+    Ok(Some(serialized))
+}
+
+/// Settings of [`merge_search_responses_with_options`] other than the page of hits. The default
+/// options merge the responses as a collector built with the default settings of
+/// [`QuickwitCollectorBuilder`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct MergeOptions {
+    /// Whether the hits are taken from the responses in turn, rather than by rank.
+    pub round_robin_splits: bool,
+    /// Number of hits kept per bucket, when the responses carry bucket samples.
+    pub bucket_samples_size: Option<usize>,
+    /// Whether only the best hit of each collapse group is kept.
+    pub collapse: bool,
+    /// Maximum number of hits kept per group of hits sharing a `collapse_key`, when the hits
+    /// are diversified.
+    pub max_hits_per_group: Option<usize>,
+    /// Character ending the source prefix of the split ids, along with the maximum number of hits
+    /// kept per source, see `split_source`.
+    pub max_hits_per_source: Option<(char, usize)>,
+    /// Whether only one copy of each deduplicated document is kept.
+    pub dedup: bool,
+    /// Rank constant of the reciprocal rank fusion of the rankings of the responses, if the
+    /// hits are fused.
+    pub rank_fusion_constant: Option<u32>,
+    /// Order of the hits tying on all the sort criteria.
+    pub tie_break_order: SortOrder,
+    /// Whether the request only asks for aggregations, in which case the partial hits are
+    /// neither sorted nor truncated, see `QuickwitCollector::aggregation_only`.
+    pub aggregation_only: bool,
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        MergeOptions {
+            round_robin_splits: false,
+            bucket_samples_size: None,
+            collapse: false,
+            max_hits_per_group: None,
+            max_hits_per_source: None,
+            dedup: false,
+            rank_fusion_constant: None,
+            tie_break_order: SortOrder::Asc,
+            aggregation_only: false,
+        }
+    }
+}
+
+/// Merges leaf search responses into the response for the hits in
+/// `[start_offset..start_offset + max_hits)`, with the default [`MergeOptions`].
+#[cfg(test)]
+pub(crate) fn merge_search_responses(
+    leaf_responses: Vec<LeafSearchResponse>,
+    start_offset: usize,
+    max_hits: usize,
+    aggregations_opt: &Option<QuickwitAggregations>,
+) -> tantivy::Result<LeafSearchResponse> {
+    merge_search_responses_with_options(
+        leaf_responses,
+        start_offset,
+        max_hits,
+        aggregations_opt,
+        MergeOptions::default(),
+    )
+}
+
+/// Merges leaf search responses into the response for the hits in
+/// `[start_offset..start_offset + max_hits)`. This is the merge of [`QuickwitCollector`], minus
+/// the top hits aggregations, which need the documents of the hits.
+pub(crate) fn merge_search_responses_with_options(
+    leaf_responses: Vec<LeafSearchResponse>,
+    start_offset: usize,
+    max_hits: usize,
+    aggregations_opt: &Option<QuickwitAggregations>,
+    merge_options: MergeOptions,
+) -> tantivy::Result<LeafSearchResponse> {
+    // We want the hits in [start_offset..start_offset + max_hits).
+    // All leaves will return their top [0..max_hits) documents.
+    // We compute the overall [0..start_offset + max_hits) documents ...
+    // Aggregation-only requests return no hit, whatever the start offset.
+    let num_hits = if merge_options.aggregation_only {
+        0
+    } else {
+        start_offset + max_hits
+    };
+    let mut merged_leaf_response =
+        merge_leaf_responses(aggregations_opt, leaf_responses, num_hits, merge_options)?;
+    if let Some(rank_constant) = merge_options.rank_fusion_constant {
+        merged_leaf_response.partial_hits = reciprocal_rank_fusion(
+            &merged_leaf_response.rankings,
+            rank_constant,
+            num_hits,
+            merge_options.tie_break_order,
+        );
+    }
+    // ... and drop the first [..start_offsets) hits.
+    merged_leaf_response
+        .partial_hits
+        .drain(0..start_offset.min(merged_leaf_response.partial_hits.len()))
+        .count(); //< we just use count as a way to consume the entire iterator.
+    merged_leaf_response.min_sorting_field_value =
+        min_sorting_field_value(&merged_leaf_response.partial_hits);
+    Ok(merged_leaf_response)
+}
+
+/// Merges a set of Leaf Results.
+fn merge_leaf_responses(
     aggregations_opt: &Option<QuickwitAggregations>,
     mut leaf_responses: Vec<LeafSearchResponse>,
     max_hits: usize,
+    merge_options: MergeOptions,
 ) -> tantivy::Result<LeafSearchResponse> {
+    let MergeOptions {
+        round_robin_splits,
+        bucket_samples_size: bucket_samples_size_opt,
+        dedup,
+        max_hits_per_source: max_hits_per_source_opt,
+        tie_break_order,
+        ..
+    } = merge_options;
+    let max_hits_per_group_opt = if merge_options.collapse {
+        Some(1)
+    } else {
+        merge_options.max_hits_per_group
+    };
     // Optimization: No merging needed if there is only one result.
     if leaf_responses.len() == 1 {
-        return Ok(leaf_responses.pop().unwrap());
+        let mut leaf_response = leaf_responses.pop().unwrap();
+        // The intermediate aggregation result is passed through as is, without being decoded.
+        leaf_response.intermediate_aggregation_result = match (
+            aggregations_opt,
+            leaf_response.intermediate_aggregation_result.take(),
+        ) {
+            (Some(aggregations), Some(serialized)) => {
+                Some(ensure_tagged_fruit(serialized, aggregations.kind())?)
+            }
+            (_, None) => empty_intermediate_aggregation_result(aggregations_opt)?,
+            (None, Some(serialized)) => Some(serialized),
+        };
+        if dedup {
+            // A split can hold several copies of a document once merged.
+            leaf_response.partial_hits = dedup_partial_hits(
+                std::mem::take(&mut leaf_response.partial_hits),
+                tie_break_order,
+            );
+        }
+        if let Some((split_id_separator, max_hits_per_source)) = max_hits_per_source_opt {
+            // The hits of a single split are not capped by its segment collectors.
+            leaf_response.partial_hits = cap_partial_hits_per_source(
+                std::mem::take(&mut leaf_response.partial_hits),
+                split_id_separator,
+                max_hits_per_source,
+                tie_break_order,
+            );
+        }
+        return Ok(leaf_response);
+    }
+    let mut merged_intermediate_aggregation_result = match aggregations_opt {
+        Some(QuickwitAggregations::FindTraceIdsAggregation(collector)) => merge_serialized_fruits(
+            &mut leaf_responses,
+            AggregationKind::FindTraceIds,
+            |merged_fruit, fruit| collector.merge_fruits(vec![merged_fruit, fruit]),
+        )?,
+        Some(QuickwitAggregations::DistinctCountAggregation(collector)) => merge_serialized_fruits(
+            &mut leaf_responses,
+            AggregationKind::DistinctCount,
+            |merged_fruit, fruit| collector.merge_fruits(vec![merged_fruit, fruit]),
+        )?,
+        // The ranges of the leaves are merged by taking the element-wise min and max.
+        Some(QuickwitAggregations::TimestampRangeAggregation(collector)) => {
+            merge_serialized_fruits(
+                &mut leaf_responses,
+                AggregationKind::TimestampRange,
+                |merged_fruit, fruit| collector.merge_fruits(vec![merged_fruit, fruit]),
+            )?
+        }
+        // The buckets are only ordered and truncated to their size when the merged result is
+        // finalized, so that an order by sub-aggregation accounts for all the leaves.
+        Some(QuickwitAggregations::TantivyAggregations(_)) => merge_serialized_fruits(
+            &mut leaf_responses,
+            AggregationKind::Tantivy,
+            |mut merged_fruit: IntermediateAggregationResults, fruit| {
+                merged_fruit.merge_fruits(fruit)?;
+                Ok(merged_fruit)
+            },
+        )?,
+        None => None,
+    };
+    // Without any result to merge, e.g. when no split matched, the client still expects the
+    // structure of the requested aggregations.
+    if merged_intermediate_aggregation_result.is_none() {
+        merged_intermediate_aggregation_result =
+            empty_intermediate_aggregation_result(aggregations_opt)?;
+    }
+    let num_attempted_splits = leaf_responses
+        .iter()
+        .map(|leaf_response| leaf_response.num_attempted_splits)
+        .sum();
+    let num_successful_splits = leaf_responses
+        .iter()
+        .map(|leaf_response| leaf_response.num_successful_splits)
+        .sum();
+    let num_hits: u64 = leaf_responses
+        .iter()
+        .map(|leaf_response| leaf_response.num_hits)
+        .sum();
+    let num_hits_is_lower_bound = leaf_responses
+        .iter()
+        .any(|leaf_response| leaf_response.num_hits_is_lower_bound);
+    let timed_out = leaf_responses
+        .iter()
+        .any(|leaf_response| leaf_response.timed_out);
+    let aggregation_is_partial = leaf_responses
+        .iter()
+        .any(|leaf_response| leaf_response.aggregation_is_partial);
+    // A split reported by several leaf responses is only reported once.
+    let failed_splits = leaf_responses
+        .iter()
+        .flat_map(|leaf_response| leaf_response.failed_splits.iter())
+        .unique_by(|failed_split| failed_split.split_id.clone())
+        .cloned()
+        .collect_vec();
+    let estimated_total_hits = merge_estimated_total_hits(
+        leaf_responses
+            .iter()
+            .map(|leaf_response| leaf_response.estimated_total_hits.as_ref()),
+    );
+    let num_rankings = leaf_responses
+        .iter()
+        .map(|leaf_response| leaf_response.rankings.len())
+        .max()
+        .unwrap_or(0);
+    let rankings = (0..num_rankings)
+        .map(|ranking_ord| {
+            let ranked_partial_hits_per_leaf: Vec<Vec<PartialHit>> = leaf_responses
+                .iter_mut()
+                .flat_map(|leaf_response| leaf_response.rankings.get_mut(ranking_ord))
+                .map(|ranking| std::mem::take(&mut ranking.partial_hits))
+                .collect();
+            Ranking {
+                partial_hits: merge_partial_hits(
+                    ranked_partial_hits_per_leaf,
+                    max_hits,
+                    tie_break_order,
+                ),
+            }
+        })
+        .collect();
+    let bucket_samples = match bucket_samples_size_opt {
+        Some(bucket_samples_size) => {
+            merge_bucket_samples(&mut leaf_responses, bucket_samples_size, tie_break_order)
+        }
+        None => Vec::new(),
+    };
+    let sort_value_types = merge_sort_value_types(
+        leaf_responses
+            .iter()
+            .map(|leaf_response| leaf_response.sort_value_types.as_slice()),
+    )?;
+    let collection_stats = merge_collection_stats(
+        leaf_responses
+            .iter()
+            .map(|leaf_response| leaf_response.collection_stats.as_ref()),
+    );
+    let clamped_max_hits = merge_clamped_max_hits(
+        leaf_responses
+            .iter()
+            .map(|leaf_response| leaf_response.clamped_max_hits),
+    );
+    let cache_status = merge_cache_status(
+        leaf_responses
+            .iter()
+            .map(|leaf_response| leaf_response.cache_status.as_ref()),
+    );
+    // The doc id sets are per segment, so there is nothing to merge.
+    let doc_id_sets: Vec<SegmentDocIdSet> = leaf_responses
+        .iter_mut()
+        .flat_map(|leaf_response| std::mem::take(&mut leaf_response.doc_id_sets))
+        .collect();
+    let partial_hits_per_leaf: Vec<Vec<PartialHit>> = leaf_responses
+        .into_iter()
+        .map(|leaf_response| leaf_response.partial_hits)
+        .collect();
+    let top_k_partial_hits = if max_hits == 0 {
+        // No hit is returned, e.g. for aggregation-only requests: sorting the hits is wasted
+        // work.
+        Vec::new()
+    } else if round_robin_splits
+        || max_hits_per_group_opt.is_some()
+        || max_hits_per_source_opt.is_some()
+        || dedup
+    {
+        let mut all_partial_hits = concat_partial_hits(partial_hits_per_leaf);
+        if dedup {
+            // The same document can be found in several leaves.
+            all_partial_hits = dedup_partial_hits(all_partial_hits, tie_break_order);
+        }
+        if let Some(max_hits_per_group) = max_hits_per_group_opt {
+            // The same group can be found in several leaves.
+            all_partial_hits =
+                collapse_partial_hits(all_partial_hits, max_hits_per_group, tie_break_order);
+        }
+        if let Some((split_id_separator, max_hits_per_source)) = max_hits_per_source_opt {
+            // The same source can be found in several leaves.
+            all_partial_hits = cap_partial_hits_per_source(
+                all_partial_hits,
+                split_id_separator,
+                max_hits_per_source,
+                tie_break_order,
+            );
+        }
+        if round_robin_splits {
+            round_robin_partial_hits(all_partial_hits, max_hits, tie_break_order)
+        } else {
+            top_k_partial_hits(all_partial_hits, max_hits, tie_break_order)
+        }
+    } else {
+        merge_partial_hits(partial_hits_per_leaf, max_hits, tie_break_order)
+    };
+    Ok(LeafSearchResponse {
+        intermediate_aggregation_result: merged_intermediate_aggregation_result,
+        num_hits,
+        partial_hits: top_k_partial_hits,
+        failed_splits,
+        num_attempted_splits,
+        num_successful_splits,
+        top_hits_aggregation: None,
+        estimated_total_hits,
+        rankings,
+        bucket_samples,
+        num_hits_is_lower_bound,
+        timed_out,
+        sort_value_types,
+        collection_stats,
+        clamped_max_hits,
+        // Computed once the page of hits is known.
+        min_sorting_field_value: None,
+        doc_id_sets,
+        cache_status,
+        split_id: None,
+        aggregation_is_partial,
+    })
+}
+
+/// Returns the smallest sorting field value of the hits, i.e. the value of the last hit of a
+/// top-K, which documents must beat to enter it.
+fn min_sorting_field_value(partial_hits: &[PartialHit]) -> Option<u64> {
+    partial_hits
+        .iter()
+        .map(|partial_hit| partial_hit.sorting_field_value)
+        .min()
+}
+
+/// Merges the `clamped_max_hits` of several responses: the hits are cut at the smallest of them.
+pub(crate) fn merge_clamped_max_hits(
+    clamped_max_hits: impl IntoIterator<Item = Option<u64>>,
+) -> Option<u64> {
+    clamped_max_hits.into_iter().flatten().min()
+}
+
+/// Sums the collection statistics reported by several responses.
+pub(crate) fn merge_collection_stats<'a>(
+    collection_stats_iter: impl IntoIterator<Item = Option<&'a CollectionStats>>,
+) -> Option<CollectionStats> {
+    collection_stats_iter
+        .into_iter()
+        .flatten()
+        .fold(None, |merged_opt, collection_stats| {
+            let mut merged: CollectionStats = merged_opt.unwrap_or_default();
+            merged.num_docs_visited += collection_stats.num_docs_visited;
+            merged.num_docs_rejected += collection_stats.num_docs_rejected;
+            merged.num_docs_rejected_by_timestamp_filter +=
+                collection_stats.num_docs_rejected_by_timestamp_filter;
+            merged.num_docs_accepted += collection_stats.num_docs_accepted;
+            merged.num_docs_entered_top_k += collection_stats.num_docs_entered_top_k;
+            Some(merged)
+        })
+}
+
+/// Sums the numbers of warm and cold splits reported by several responses.
+pub(crate) fn merge_cache_status<'a>(
+    cache_status_iter: impl IntoIterator<Item = Option<&'a CacheStatus>>,
+) -> Option<CacheStatus> {
+    cache_status_iter
+        .into_iter()
+        .flatten()
+        .fold(None, |merged_opt, cache_status| {
+            let mut merged: CacheStatus = merged_opt.unwrap_or_default();
+            merged.num_warm_splits += cache_status.num_warm_splits;
+            merged.num_cold_splits += cache_status.num_cold_splits;
+            Some(merged)
+        })
+}
+
+/// Merges the types of the sort values reported by several responses. The responses searching
+/// splits without value for a criterion report `NO_VALUE` for it, and the other ones must agree
+/// on its type.
+pub(crate) fn merge_sort_value_types<'a>(
+    sort_value_types_per_response: impl IntoIterator<Item = &'a [i32]>,
+) -> tantivy::Result<Vec<i32>> {
+    let type_name = |sort_value_type: i32| {
+        SortValueType::from_i32(sort_value_type)
+            .map(|sort_value_type| sort_value_type.as_str_name())
+            .unwrap_or("UNKNOWN")
+    };
+    let no_value = SortValueType::NoValue as i32;
+    let mut merged_sort_value_types: Vec<i32> = Vec::new();
+    for sort_value_types in sort_value_types_per_response {
+        if merged_sort_value_types.len() < sort_value_types.len() {
+            merged_sort_value_types.resize(sort_value_types.len(), no_value);
+        }
+        for (merged_sort_value_type, &sort_value_type) in
+            merged_sort_value_types.iter_mut().zip(sort_value_types)
+        {
+            if sort_value_type == no_value || sort_value_type == *merged_sort_value_type {
+                continue;
+            }
+            if *merged_sort_value_type != no_value {
+                return Err(TantivyError::InternalError(format!(
+                    "The responses disagree on the type of a sort value: `{}` and `{}`.",
+                    type_name(*merged_sort_value_type),
+                    type_name(sort_value_type)
+                )));
+            }
+            *merged_sort_value_type = sort_value_type;
+        }
+    }
+    Ok(merged_sort_value_types)
+}
+
+/// Merges the samples of the buckets of several responses, keeping the `size` best hits of
+/// each bucket. The buckets are ordered by key.
+fn merge_bucket_samples(
+    leaf_responses: &mut [LeafSearchResponse],
+    size: usize,
+    tie_break_order: SortOrder,
+) -> Vec<LeafBucketSamples> {
+    let mut partial_hits_per_bucket: HashMap<String, Vec<PartialHit>> = HashMap::new();
+    for leaf_bucket_samples in leaf_responses
+        .iter_mut()
+        .flat_map(|leaf_response| std::mem::take(&mut leaf_response.bucket_samples))
+    {
+        partial_hits_per_bucket
+            .entry(leaf_bucket_samples.key)
+            .or_default()
+            .extend(leaf_bucket_samples.partial_hits);
+    }
+    let mut bucket_samples: Vec<LeafBucketSamples> = partial_hits_per_bucket
+        .into_iter()
+        .map(|(key, partial_hits)| LeafBucketSamples {
+            key,
+            partial_hits: top_k_partial_hits(partial_hits, size, tie_break_order),
+        })
+        .collect();
+    bucket_samples.sort_unstable_by(|left, right| cmp_bucket_keys(&left.key, &right.key));
+    bucket_samples
+}
+
+/// Mutates partial_hits so that it contains the top-num_hitso hits,
+/// and so that these elements are sorted.
+///
+/// TODO we could possibly optimize the sort away (but I doubt it matters).
+fn top_k_partial_hits(
+    mut partial_hits: Vec<PartialHit>,
+    num_hits: usize,
+    tie_break_order: SortOrder,
+) -> Vec<PartialHit> {
+    partial_hits.sort_unstable_by(|left, right| {
+        let left_key = partial_hit_sorting_key(left, tie_break_order);
+        let right_key = partial_hit_sorting_key(right, tie_break_order);
+        left_key.cmp(&right_key)
+    });
+    partial_hits.truncate(num_hits);
+    partial_hits
+}
+
+/// Concatenates several lists of partial hits.
+///
+/// The output is allocated at once: collecting a flattened iterator instead grows the vector
+/// step by step, as the iterator cannot tell its length, which adds up on large fan-outs.
+pub fn concat_partial_hits(partial_hits_per_leaf: Vec<Vec<PartialHit>>) -> Vec<PartialHit> {
+    let num_partial_hits: usize = partial_hits_per_leaf.iter().map(Vec::len).sum();
+    let mut all_partial_hits = Vec::with_capacity(num_partial_hits);
+    for partial_hits in partial_hits_per_leaf {
+        all_partial_hits.extend(partial_hits);
+    }
+    all_partial_hits
+}
+
+/// Merges several lists of partial hits into the `num_hits` best hits, in the order of
+/// `partial_hit_sorting_key` with the given `tie_break_order`.
+///
+/// The hits of leaf responses being already sorted, only the heads of the lists are compared
+/// in a k-way merge, which stops after `num_hits` hits instead of sorting all the hits.
+/// Lists that are not sorted are sorted beforehand.
+pub fn merge_partial_hits(
+    partial_hits_lists: Vec<Vec<PartialHit>>,
+    num_hits: usize,
+    tie_break_order: SortOrder,
+) -> Vec<PartialHit> {
+    let num_partial_hits: usize = partial_hits_lists.iter().map(Vec::len).sum();
+    let mut partial_hit_iters: Vec<std::vec::IntoIter<PartialHit>> =
+        Vec::with_capacity(partial_hits_lists.len());
+    let mut heads: BinaryHeap<PartialHitListHead> =
+        BinaryHeap::with_capacity(partial_hits_lists.len());
+    for mut partial_hits in partial_hits_lists {
+        let is_sorted = partial_hits.windows(2).all(|pair| {
+            partial_hit_sorting_key(&pair[0], tie_break_order)
+                <= partial_hit_sorting_key(&pair[1], tie_break_order)
+        });
+        if !is_sorted {
+            partial_hits = top_k_partial_hits(partial_hits, num_hits, tie_break_order);
+        }
+        let mut partial_hit_iter = partial_hits.into_iter();
+        if let Some(partial_hit) = partial_hit_iter.next() {
+            heads.push(PartialHitListHead {
+                partial_hit,
+                list_ord: partial_hit_iters.len(),
+                tie_break_order,
+            });
+            partial_hit_iters.push(partial_hit_iter);
+        }
+    }
+    let mut merged_partial_hits = Vec::with_capacity(num_hits.min(num_partial_hits));
+    while merged_partial_hits.len() < num_hits {
+        let Some(PartialHitListHead {
+            partial_hit,
+            list_ord,
+            ..
+        }) = heads.pop()
+        else {
+            break;
+        };
+        if let Some(next_partial_hit) = partial_hit_iters[list_ord].next() {
+            heads.push(PartialHitListHead {
+                partial_hit: next_partial_hit,
+                list_ord,
+                tie_break_order,
+            });
+        }
+        merged_partial_hits.push(partial_hit);
+    }
+    merged_partial_hits
+}
+
+/// Best remaining hit of a list merged by `merge_partial_hits`.
+///
+/// The order is the inverse of the order of `partial_hit_sorting_key` so that the max-heap
+/// yields the best hit first.
+struct PartialHitListHead {
+    partial_hit: PartialHit,
+    list_ord: usize,
+    tie_break_order: SortOrder,
+}
+
+impl PartialOrd for PartialHitListHead {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PartialHitListHead {
+    fn cmp(&self, other: &Self) -> Ordering {
+        partial_hit_sorting_key(&other.partial_hit, self.tie_break_order).cmp(
+            &partial_hit_sorting_key(&self.partial_hit, self.tie_break_order),
+        )
+    }
+}
+
+impl PartialEq for PartialHitListHead {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for PartialHitListHead {}
+
+/// Keeps the `max_hits_per_group` best hits of each group of hits sharing a `collapse_key`.
+fn collapse_partial_hits(
+    partial_hits: Vec<PartialHit>,
+    max_hits_per_group: usize,
+    tie_break_order: SortOrder,
+) -> Vec<PartialHit> {
+    let mut num_hits_per_collapse_key: HashMap<Option<String>, usize> = HashMap::new();
+    top_k_partial_hits(partial_hits, usize::MAX, tie_break_order)
+        .into_iter()
+        .filter(|partial_hit| {
+            let num_hits = num_hits_per_collapse_key
+                .entry(partial_hit.collapse_key.clone())
+                .or_default();
+            *num_hits += 1;
+            *num_hits <= max_hits_per_group
+        })
+        .collect()
+}
+
+/// Keeps the `max_hits_per_source` best hits of each source, see `split_source`.
+fn cap_partial_hits_per_source(
+    partial_hits: Vec<PartialHit>,
+    split_id_separator: char,
+    max_hits_per_source: usize,
+    tie_break_order: SortOrder,
+) -> Vec<PartialHit> {
+    let mut num_hits_per_source: HashMap<String, usize> = HashMap::new();
+    top_k_partial_hits(partial_hits, usize::MAX, tie_break_order)
+        .into_iter()
+        .filter(|partial_hit| {
+            let source = split_source(&partial_hit.split_id, split_id_separator);
+            let num_hits = num_hits_per_source.entry(source.to_string()).or_default();
+            *num_hits += 1;
+            *num_hits <= max_hits_per_source
+        })
+        .collect()
+}
+
+/// Returns the source of a split, i.e. the prefix of its id ending at the separator. The splits
+/// whose id does not contain the separator belong to the unnamed source `""`.
+fn split_source(split_id: &str, split_id_separator: char) -> &str {
+    split_id
+        .split_once(split_id_separator)
+        .map(|(source, _)| source)
+        .unwrap_or_default()
+}
+
+/// Drops the hits sharing a `dedup_key` with a better hit. The hits without key are kept.
+fn dedup_partial_hits(
+    partial_hits: Vec<PartialHit>,
+    tie_break_order: SortOrder,
+) -> Vec<PartialHit> {
+    let mut seen_dedup_keys: HashSet<String> = HashSet::new();
+    top_k_partial_hits(partial_hits, usize::MAX, tie_break_order)
+        .into_iter()
+        .filter(|partial_hit| match &partial_hit.dedup_key {
+            Some(dedup_key) => seen_dedup_keys.insert(dedup_key.clone()),
+            None => true,
+        })
+        .collect()
+}
+
+/// Fuses rankings with the reciprocal rank fusion and returns the `num_hits` best hits.
+///
+/// Each hit scores the sum over the rankings holding it of `1 / (rank_constant + rank)`,
+/// where ranks start at 1. The score, mapped to a `u64` preserving its order, becomes the
+/// sorting field value of the returned hits so that they can be merged like any other hits.
+fn reciprocal_rank_fusion(
+    rankings: &[Ranking],
+    rank_constant: u32,
+    num_hits: usize,
+    tie_break_order: SortOrder,
+) -> Vec<PartialHit> {
+    let mut fused_hits: HashMap<(&str, SegmentOrdinal, DocId), (f64, &PartialHit)> = HashMap::new();
+    for ranking in rankings {
+        for (rank_ord, partial_hit) in ranking.partial_hits.iter().enumerate() {
+            let rank = rank_ord as f64 + 1.0;
+            let reciprocal_rank = 1.0 / (rank_constant as f64 + rank);
+            let doc_key = (
+                partial_hit.split_id.as_str(),
+                partial_hit.segment_ord,
+                partial_hit.doc_id,
+            );
+            fused_hits.entry(doc_key).or_insert((0.0, partial_hit)).0 += reciprocal_rank;
+        }
+    }
+    let partial_hits: Vec<PartialHit> = fused_hits
+        .into_values()
+        .map(|(score, partial_hit)| PartialHit {
+            // The bits of positive floats are ordered like the floats.
+            sorting_field_value: score.to_bits(),
+            secondary_sorting_field_values: Vec::new(),
+            tie_breaker_value: 0,
+            ..partial_hit.clone()
+        })
+        .collect();
+    top_k_partial_hits(partial_hits, num_hits, tie_break_order)
+}
+
+/// Selects `num_hits` hits by picking in turn the best remaining hit of each split,
+/// visiting splits in increasing `split_id` order.
+///
+/// Each split contributes in turn until it runs out of hits, so that every split is
+/// represented in the result, unlike the global top-K computed by `top_k_partial_hits`.
+fn round_robin_partial_hits(
+    partial_hits: Vec<PartialHit>,
+    num_hits: usize,
+    tie_break_order: SortOrder,
+) -> Vec<PartialHit> {
+    let mut partial_hits_per_split: BTreeMap<String, Vec<PartialHit>> = BTreeMap::new();
+    for partial_hit in partial_hits {
+        partial_hits_per_split
+            .entry(partial_hit.split_id.clone())
+            .or_default()
+            .push(partial_hit);
+    }
+    let mut split_hit_iters: Vec<std::vec::IntoIter<PartialHit>> = partial_hits_per_split
+        .into_values()
+        .map(|split_partial_hits| {
+            top_k_partial_hits(split_partial_hits, num_hits, tie_break_order).into_iter()
+        })
+        .collect();
+    let mut round_robin_hits = Vec::with_capacity(num_hits);
+    while round_robin_hits.len() < num_hits && !split_hit_iters.is_empty() {
+        split_hit_iters.retain_mut(|split_hit_iter| {
+            if round_robin_hits.len() >= num_hits {
+                return true;
+            }
+            if let Some(partial_hit) = split_hit_iter.next() {
+                round_robin_hits.push(partial_hit);
+                true
+            } else {
+                false
+            }
+        });
+    }
+    round_robin_hits
+}
+
+/// Parses a comma separated list of sort fields, such as `-timestamp,+severity`.
+///
+/// A `-` prefix sorts the field in descending order and a `+` prefix in ascending order, other
+/// fields are sorted in `sort_order_opt`, or in their default order, see `default_sort_order`.
+/// Documents without a value for a fast field are placed according to `missing`. A field prefixed
+/// with `_exists:`, like `-_exists:error_code`, sorts the documents by whether they have a value
+/// for the field. A `:first`, `:min`, `:max` or `:sum` suffix, like `-scores:max`, picks how the
+/// values of a multivalued fast field are reduced, see `SortValueAggregate`.
+///
+/// Sorting a field in both orders is rejected.
+pub(crate) fn parse_sort_by_field(
+    sort_by_field: &str,
+    sort_order_opt: Option<SortOrder>,
+    missing: MissingValue,
+    schema_opt: Option<&Schema>,
+) -> crate::Result<Vec<SortBy>> {
+    let invalid_sort_field = |sort_field: &str, reason: &str| {
+        SearchError::InvalidArgument(format!("invalid sort field `{sort_field}`: {reason}"))
+    };
+    let mut sort_order_per_field: HashMap<&str, SortOrder> = HashMap::new();
+    sort_by_field
+        .split(',')
+        .map(|sort_field| {
+            let sort_field = sort_field.trim();
+            let (field_name, order_opt) = if let Some(field_name) = sort_field.strip_prefix('-') {
+                (field_name.trim(), Some(SortOrder::Desc))
+            } else if let Some(field_name) = sort_field.strip_prefix('+') {
+                (field_name.trim(), Some(SortOrder::Asc))
+            } else {
+                (sort_field, None)
+            };
+            if field_name.is_empty() {
+                return Err(invalid_sort_field(sort_field, "the field name is missing"));
+            }
+            if field_name.starts_with(['-', '+']) {
+                return Err(invalid_sort_field(
+                    sort_field,
+                    "a field takes at most one order prefix",
+                ));
+            }
+            let order = order_opt
+                .or(sort_order_opt)
+                .unwrap_or_else(|| default_sort_order(field_name, schema_opt));
+            if let Some(previous_order) = sort_order_per_field.insert(field_name, order) {
+                if previous_order != order {
+                    return Err(invalid_sort_field(
+                        sort_field,
+                        "the field is sorted in both orders",
+                    ));
+                }
+            }
+            let sort_by = if field_name == "_score" {
+                SortBy::Score { order }
+            } else if let Some(field_name) = field_name.strip_prefix("_exists:") {
+                SortBy::FieldExists {
+                    field_name: field_name.trim().to_string(),
+                    order,
+                }
+            } else {
+                let (field_name, aggregate) = parse_sort_value_aggregate(field_name)?;
+                SortBy::FastField {
+                    field_name: field_name.to_string(),
+                    order,
+                    missing,
+                    aggregate,
+                }
+            };
+            Ok(sort_by)
+        })
+        .collect()
+}
+
+/// Order of a sort field given neither an order prefix nor a request `sort_order`.
+///
+/// `_score`, `_exists:` and datetime fields sort in descending order, so that the most relevant,
+/// present or recent documents come first. Other fields, and fields unknown to the schema, sort in
+/// ascending order.
+fn default_sort_order(field_name: &str, schema_opt: Option<&Schema>) -> SortOrder {
+    if field_name == "_score" || field_name.starts_with("_exists:") {
+        return SortOrder::Desc;
+    }
+    let field_name = field_name
+        .split_once(':')
+        .map_or(field_name, |(field_name, _aggregate)| field_name.trim());
+    let is_datetime_field = schema_opt.map_or(false, |schema| {
+        schema
+            .find_field(field_name)
+            .map_or(false, |(field, _json_path)| {
+                matches!(
+                    schema.get_field_entry(field).field_type(),
+                    FieldType::Date(_)
+                )
+            })
+    });
+    if is_datetime_field {
+        SortOrder::Desc
+    } else {
+        SortOrder::Asc
+    }
+}
+
+/// Splits the reduction of the values of a multivalued field, like `:max` in `scores:max`, from
+/// the field name. Field names cannot contain `:`.
+fn parse_sort_value_aggregate(field_name: &str) -> crate::Result<(&str, SortValueAggregate)> {
+    let Some((field_name, aggregate)) = field_name.rsplit_once(':') else {
+        return Ok((field_name, SortValueAggregate::First));
+    };
+    let field_name = field_name.trim();
+    if field_name == "_score" {
+        return Err(SearchError::InvalidArgument(
+            "the values of `_score` cannot be aggregated".to_string(),
+        ));
+    }
+    let aggregate = aggregate.trim().parse().map_err(|error| {
+        SearchError::InvalidArgument(format!(
+            "invalid aggregate of the sort field `{field_name}`: {error}"
+        ))
+    })?;
+    Ok((field_name, aggregate))
+}
+
+/// Returns the hash partition of the request, whose bucket must be lower than its modulus.
+fn hash_partition(search_request: &SearchRequest) -> crate::Result<Option<SortBy>> {
+    let Some(hash_partition) = &search_request.hash_partition else {
+        return Ok(None);
+    };
+    if hash_partition.bucket >= hash_partition.modulus {
+        return Err(SearchError::InvalidArgument(format!(
+            "the bucket of hash_partition must be lower than its modulus, got the bucket {} for \
+             the modulus {}",
+            hash_partition.bucket, hash_partition.modulus
+        )));
+    }
+    Ok(Some(SortBy::HashPartition {
+        field_name: hash_partition.field_name.clone(),
+        modulus: hash_partition.modulus,
+        bucket: hash_partition.bucket,
+    }))
+}
+
+/// Default constant `k` of the reciprocal rank fusion score `1 / (k + rank)`.
+const DEFAULT_RANK_CONSTANT: u32 = 60;
+
+fn parse_rank_fusion(
+    search_request: &SearchRequest,
+    missing: MissingValue,
+    schema_opt: Option<&Schema>,
+) -> crate::Result<Option<ReciprocalRankFusion>> {
+    let Some(rank_fusion) = search_request.rank_fusion.as_ref() else {
+        return Ok(None);
+    };
+    let sort_order_opt = rank_fusion.sort_order.and_then(SortOrder::from_i32);
+    Ok(Some(ReciprocalRankFusion {
+        sort_by: parse_sort_by_field(
+            &rank_fusion.sort_by_field,
+            sort_order_opt,
+            missing,
+            schema_opt,
+        )?,
+        rank_constant: rank_fusion.rank_constant.unwrap_or(DEFAULT_RANK_CONSTANT),
+    }))
+}
+
+/// Builds the QuickwitCollector, in function of the information that was requested by the user.
+pub(crate) fn make_collector_for_split(
+    split_id: String,
+    doc_mapper: &dyn DocMapper,
+    search_request: &SearchRequest,
+    aggregation_limits: AggregationLimits,
+    searcher_config: &SearcherConfig,
+    soft_deadline_opt: Option<Instant>,
+) -> crate::Result<QuickwitCollector> {
+    let max_hits_ceiling_opt = resolve_max_hits_ceiling(searcher_config, search_request)?;
+    let (mut aggregation, _terms_aggregation_pages) = parse_aggregation_request(search_request)?;
+    if let Some(QuickwitAggregations::TimestampRangeAggregation(collector)) = &mut aggregation {
+        if !collector.resolve_field(doc_mapper.timestamp_field_name()) {
+            return Err(SearchError::InvalidAggregationRequest(
+                "the timestamp range aggregation requires a field, as the index has no timestamp \
+                 field"
+                    .to_string(),
+            ));
+        }
+    }
+    let top_hits_aggregation = match &search_request.top_hits_aggregation_request {
+        Some(top_hits_aggregation) => Some(serde_json::from_str(top_hits_aggregation)?),
+        None => None,
+    };
+    let bucket_samples = match &search_request.bucket_samples_request {
+        Some(bucket_samples) => Some(serde_json::from_str(bucket_samples)?),
+        None => None,
+    };
+    let timestamp_precision = doc_mapper
+        .timestamp_field_name()
+        .map(|timestamp_field_name| {
+            timestamp_field_precision(&doc_mapper.schema(), timestamp_field_name)
+        })
+        .unwrap_or_default();
+    let (start_timestamp_opt, end_timestamp_opt) = request_time_range(search_request)?;
+    let timestamp_filter_builder_opt = create_timestamp_filter_builder(
+        doc_mapper.timestamp_field_name(),
+        timestamp_precision,
+        start_timestamp_opt,
+        end_timestamp_opt,
+    );
+    let fast_field_equals_filter_builder_opt =
+        search_request
+            .fast_field_equals
+            .as_ref()
+            .map(|fast_field_equals| {
+                FastFieldEqualsFilterBuilder::new(
+                    fast_field_equals.field_name.clone(),
+                    fast_field_equals.value.clone(),
+                )
+            });
+    let fast_field_range_filter_builder_opt =
+        search_request
+            .fast_field_range
+            .as_ref()
+            .map(|fast_field_range| {
+                FastFieldRangeFilterBuilder::new(
+                    fast_field_range.field_name.clone(),
+                    fast_field_range.lower_bound,
+                    fast_field_range.upper_bound,
+                )
+            });
+    let sort_order_opt = search_request.sort_order.and_then(SortOrder::from_i32);
+    let schema = doc_mapper.schema();
+    let missing: MissingValue = match &search_request.sort_missing {
+        Some(sort_missing) => sort_missing
+            .parse()
+            .map_err(|err| SearchError::InvalidArgument(format!("invalid sort_missing: {err}")))?,
+        None => MissingValue::default(),
+    };
+    let mut sort_by: Vec<SortBy> = search_request
+        .random_sample_seed
+        .map(|seed| SortBy::Random { seed })
+        .into_iter()
+        .collect();
+    sort_by.extend(
+        search_request
+            .sort_by_geo_distance
+            .iter()
+            .map(|geo_distance| SortBy::GeoDistance {
+                lat_field: geo_distance.lat_field.clone(),
+                lon_field: geo_distance.lon_field.clone(),
+                center: GeoPoint {
+                    lat: geo_distance.lat,
+                    lon: geo_distance.lon,
+                },
+                // Nearest first, like other numeric fields.
+                order: sort_order_opt.unwrap_or(SortOrder::Asc),
+                missing,
+            }),
+    );
+    if let Some(sort_by_field) = &search_request.sort_by_field {
+        sort_by.extend(parse_sort_by_field(
+            sort_by_field,
+            sort_order_opt,
+            missing,
+            Some(&schema),
+        )?);
+    } else if sort_by.is_empty() && search_request.max_hits > 0 {
+        // Without hits to return, sorting by doc id keeps the collection as cheap as possible.
+        if let Some(default_sort_by_field) = doc_mapper.default_sort_by_field() {
+            sort_by.extend(parse_sort_by_field(
+                default_sort_by_field,
+                sort_order_opt,
+                missing,
+                Some(&schema),
+            )?);
+        }
+    }
+    // The hash partition filters the documents, which are still sorted by the criteria above.
+    sort_by.extend(hash_partition(search_request)?);
+    let mut rank_fusion = parse_rank_fusion(search_request, missing, Some(&schema))?;
+    if let Some(score_boost) = &search_request.score_boost {
+        let rank_fusion_sort_by: &mut [SortBy] = rank_fusion
+            .as_mut()
+            .map(|rank_fusion| &mut rank_fusion.sort_by[..])
+            .unwrap_or_default();
+        let mut is_score_boosted = false;
+        for sort_criterion in sort_by.iter_mut().chain(rank_fusion_sort_by) {
+            if let SortBy::Score { order } = *sort_criterion {
+                *sort_criterion = SortBy::ScoreWithBoost {
+                    boost_field: score_boost.field_name.clone(),
+                    factor: score_boost.factor,
+                    order,
+                };
+                is_score_boosted = true;
+            }
+        }
+        if !is_score_boosted {
+            return Err(SearchError::InvalidArgument(
+                "score_boost is only supported when sorting by `_score`".to_string(),
+            ));
+        }
+    }
+    let rank_fusion_sort_by: &[SortBy] = rank_fusion
+        .as_ref()
+        .map(|rank_fusion| &rank_fusion.sort_by[..])
+        .unwrap_or_default();
+    validate_sort_fields_are_fast(sort_by.iter().chain(rank_fusion_sort_by), &schema)?;
+    QuickwitCollector::builder()
+        .split_id(split_id)
+        .start_offset(search_request.start_offset as usize)
+        .max_hits(search_request.max_hits as usize)
+        .sort_by(sort_by)
+        .timestamp_filter(timestamp_filter_builder_opt)
+        .fast_field_equals_filter(fast_field_equals_filter_builder_opt)
+        .fast_field_range_filter(fast_field_range_filter_builder_opt)
+        .aggregation(aggregation)
+        .aggregation_limits(aggregation_limits)
+        .round_robin_splits(search_request.round_robin_splits)
+        .search_after(search_request.search_after.clone())
+        .tie_breaker_field(search_request.tie_breaker_field.clone())
+        .top_hits_aggregation(top_hits_aggregation)
+        .rank_fusion(rank_fusion)
+        .bucket_samples(bucket_samples)
+        .collapse_field(search_request.collapse_field.clone())
+        .diversify_by(diversify_by(search_request))
+        .max_hits_per_source(max_hits_per_source(search_request)?)
+        .collect_doc_id_sets(search_request.collect_doc_id_sets)
+        .dedup_field(search_request.dedup_field.clone())
+        .count_hits(CountHits::from_search_request(search_request))
+        .return_sort_values(search_request.return_sort_values)
+        .min_score(search_request.min_score)
+        .soft_deadline(soft_deadline_opt)
+        .tie_break_order(tie_break_order(search_request))
+        .max_hits_ceiling(max_hits_ceiling_opt)
+        .build()
+}
+
+/// Returns the `diversify_by` setting of the collector for the request.
+fn diversify_by(search_request: &SearchRequest) -> Option<(String, usize)> {
+    search_request.diversify_by.as_ref().map(|diversify_by| {
+        (
+            diversify_by.field_name.clone(),
+            diversify_by.max_hits_per_group as usize,
+        )
+    })
+}
+
+/// Returns the `max_hits_per_source` setting of the collector for the request.
+fn max_hits_per_source(search_request: &SearchRequest) -> crate::Result<Option<(char, usize)>> {
+    let Some(max_hits_per_source) = &search_request.max_hits_per_source else {
+        return Ok(None);
+    };
+    let split_id_separator = &max_hits_per_source.split_id_separator;
+    let mut chars = split_id_separator.chars();
+    let (Some(separator), None) = (chars.next(), chars.next()) else {
+        return Err(SearchError::InvalidArgument(format!(
+            "the split_id_separator of max_hits_per_source must be a single character, got \
+             `{split_id_separator}`"
+        )));
+    };
+    Ok(Some((separator, max_hits_per_source.max_hits as usize)))
+}
+
+/// Returns the ceiling of the number of top hits of the request, `start_offset` included, set by
+/// the searcher config. The requests exceeding it are rejected instead of clamped when the
+/// searcher is configured to do so.
+pub(crate) fn resolve_max_hits_ceiling(
+    searcher_config: &SearcherConfig,
+    search_request: &SearchRequest,
+) -> crate::Result<Option<usize>> {
+    let Some(max_hits_ceiling) = searcher_config.max_hits_ceiling else {
+        return Ok(None);
+    };
+    let num_top_hits = search_request.start_offset + search_request.max_hits;
+    if searcher_config.max_hits_ceiling_mode == MaxHitsCeilingMode::Reject
+        && num_top_hits > max_hits_ceiling
+    {
+        return Err(SearchError::InvalidArgument(format!(
+            "start_offset + max_hits ({num_top_hits}) exceeds the max_hits_ceiling of the \
+             searcher ({max_hits_ceiling})"
+        )));
+    }
+    Ok(Some(max_hits_ceiling as usize))
+}
+
+/// Hard ceiling of the aggregation memory limit a request can ask for, in bytes.
+pub(crate) const MAX_AGGREGATION_MEMORY_LIMIT: u64 = 5_000_000_000;
+
+/// Hard ceiling of the aggregation bucket limit a request can ask for.
+pub(crate) const MAX_AGGREGATION_BUCKET_LIMIT: u32 = 1_000_000;
+
+/// Returns the aggregation bucket limit of the request, see
+/// `aggregation_limits_from_searcher_context`.
+pub(crate) fn aggregation_bucket_limit(
+    searcher_context: &SearcherContext,
+    search_request: &SearchRequest,
+) -> u32 {
+    search_request
+        .aggregation_bucket_limit
+        .map(|bucket_limit| bucket_limit.min(MAX_AGGREGATION_BUCKET_LIMIT))
+        .unwrap_or(searcher_context.searcher_config.aggregation_bucket_limit)
+}
+
+/// Returns the aggregation limits of the request, which default to the limits of the searcher
+/// config. The limits set by the request are capped by `MAX_AGGREGATION_MEMORY_LIMIT` and
+/// `MAX_AGGREGATION_BUCKET_LIMIT`, requests exceeding them being rejected beforehand.
+pub fn aggregation_limits_from_searcher_context(
+    searcher_context: &Arc<SearcherContext>,
+    search_request: &SearchRequest,
+) -> AggregationLimits {
+    let memory_limit = search_request
+        .aggregation_memory_limit
+        .map(|memory_limit| memory_limit.min(MAX_AGGREGATION_MEMORY_LIMIT))
+        .unwrap_or_else(|| {
+            searcher_context
+                .searcher_config
+                .aggregation_memory_limit
+                .get_bytes()
+        });
+    let bucket_limit = aggregation_bucket_limit(searcher_context, search_request);
+    AggregationLimits::new(Some(memory_limit), Some(bucket_limit))
+}
+
+/// Parses the aggregations of the request. The `terms` aggregations paginated with
+/// `terms_aggregation_after` are widened, see `TermsAggregationPages`, and their pages are returned
+/// along with the aggregations.
+pub(crate) fn parse_aggregation_request(
+    search_request: &SearchRequest,
+) -> crate::Result<(Option<QuickwitAggregations>, Option<TermsAggregationPages>)> {
+    let mut aggregation_opt: Option<QuickwitAggregations> =
+        match &search_request.aggregation_request {
+            Some(aggregation) => Some(
+                serde_json::from_str(aggregation)
+                    .map_err(|err| SearchError::InvalidAggregationRequest(err.to_string()))?,
+            ),
+            None => None,
+        };
+    let Some(terms_aggregation_after) = &search_request.terms_aggregation_after else {
+        return Ok((aggregation_opt, None));
+    };
+    let Some(QuickwitAggregations::TantivyAggregations(aggregations)) = &mut aggregation_opt else {
+        return Err(SearchError::InvalidAggregationRequest(
+            "terms_aggregation_after requires `terms` aggregations".to_string(),
+        ));
+    };
+    let terms_aggregation_pages =
+        TermsAggregationPages::parse(terms_aggregation_after, aggregations)
+            .map_err(SearchError::InvalidAggregationRequest)?;
+    Ok((aggregation_opt, Some(terms_aggregation_pages)))
+}
+
+/// Builds a QuickwitCollector that's only useful for merging fruits.
+///
+/// This collector only needs `start_offset` & `max_hit` so the other attributes
+/// can be set to default.
+pub(crate) fn make_merge_collector(
+    search_request: &SearchRequest,
+    searcher_context: &Arc<SearcherContext>,
+) -> crate::Result<QuickwitCollector> {
+    let (aggregation, _terms_aggregation_pages) = parse_aggregation_request(search_request)?;
+    let max_hits_ceiling_opt =
+        resolve_max_hits_ceiling(&searcher_context.searcher_config, search_request)?;
+    let top_hits_aggregation = match &search_request.top_hits_aggregation_request {
+        Some(top_hits_aggregation) => Some(serde_json::from_str(top_hits_aggregation)?),
+        None => None,
+    };
+    let bucket_samples = match &search_request.bucket_samples_request {
+        Some(bucket_samples) => Some(serde_json::from_str(bucket_samples)?),
+        None => None,
+    };
+    // Only the rank constant matters to merge the rankings.
+    let rank_fusion = parse_rank_fusion(search_request, MissingValue::default(), None)?;
+    QuickwitCollector::builder()
+        .start_offset(search_request.start_offset as usize)
+        .max_hits(search_request.max_hits as usize)
+        .aggregation(aggregation)
+        .aggregation_limits(aggregation_limits_from_searcher_context(
+            searcher_context,
+            search_request,
+        ))
+        .round_robin_splits(search_request.round_robin_splits)
+        .top_hits_aggregation(top_hits_aggregation)
+        .rank_fusion(rank_fusion)
+        .bucket_samples(bucket_samples)
+        .collapse_field(search_request.collapse_field.clone())
+        .diversify_by(diversify_by(search_request))
+        .max_hits_per_source(max_hits_per_source(search_request)?)
+        .collect_doc_id_sets(search_request.collect_doc_id_sets)
+        .dedup_field(search_request.dedup_field.clone())
+        .count_hits(CountHits::from_search_request(search_request))
+        .tie_break_order(tie_break_order(search_request))
+        .max_hits_ceiling(max_hits_ceiling_opt)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::time::{Duration, Instant};
+
+    use itertools::Itertools;
+    use proptest::prelude::*;
+    use quickwit_config::{MaxHitsCeilingMode, SearcherConfig};
+    use quickwit_doc_mapper::{DefaultDocMapper, DocMapper};
+    use quickwit_proto::{
+        CacheStatus, CollectionStats, CountHitsMode, DiversifyBy, FastFieldEquals, FastFieldRange,
+        HashPartition, LeafSearchResponse, MaxHitsPerSource, PartialHit, Ranking, ScoreBoost,
+        SearchRequest, SortOrder, SortValueType, SplitSearchError, SplitSearchErrorCode,
+    };
+    use serde_json::json;
+    use tantivy::aggregation::AggregationLimits;
+    use tantivy::collector::{Collector, Count, DocSetCollector};
+    use tantivy::fastfield::Column;
+    use tantivy::query::{AllQuery, TermQuery};
+    use tantivy::schema::IndexRecordOption;
+    use tantivy::{
+        doc, DateTime, DocAddress, Document, Index, IndexSettings, IndexSortByField, Order,
+        Searcher, TantivyError, Term,
+    };
+
+    use super::{
+        make_collector_for_split, merge_search_responses, merge_search_responses_with_options,
+        parse_sort_by_field, reciprocal_rank_fusion, FindTraceIdsCollector, GeoPoint, MergeOptions,
+        MissingNumber, MissingValue, PartialHitHeapItem, QuickwitAggregations, QuickwitCollector,
+        ReciprocalRankFusion, SegmentSearchAfter, SortBy, SortColumn, SortValueAggregate,
+        SortingFieldComputer, TimestampFilter,
+    };
+    use crate::aggregation_result::{
+        deserialize_tagged_fruit, serialize_tagged_fruit, AggregationKind,
+    };
+    use crate::collector::{
+        collapse_partial_hits, concat_partial_hits, merge_partial_hits, merge_serialized_fruits,
+        merge_sort_value_types, round_robin_partial_hits, score_sorting_field, serialize_fruit,
+        top_k_partial_hits,
+    };
+    use crate::ordering::{f32_to_u64, f64_to_u64, i64_to_u64};
+    use crate::root::finalize_aggregation;
+    use crate::{partial_hit_sorting_key, DocIdSet, SearchError};
+
+    fn make_test_doc_mapper() -> DefaultDocMapper {
+        serde_json::from_value(json!({
+            "timestamp_field": "timestamp",
+            "field_mappings": [
+                {"name": "timestamp", "type": "datetime", "fast": true},
+                {"name": "body", "type": "text"},
+                {"name": "response_time", "type": "f64", "fast": true},
+                {"name": "status_code", "type": "u64", "fast": true},
+            ]
+        }))
+        .unwrap()
+    }
+
+    fn make_test_collector(search_request: &SearchRequest) -> QuickwitCollector {
+        make_collector_for_split(
+            "split1".to_string(),
+            &make_test_doc_mapper(),
+            search_request,
+            AggregationLimits::default(),
+            &SearcherConfig::default(),
+            None,
+        )
+        .unwrap()
+    }
+
+    /// Asserts that the collector warms up exactly the expected fast fields, term
+    /// dictionaries and field norms: no less, or the search fails, and no more, or the
+    /// warmup gets more expensive than necessary.
+    #[track_caller]
+    fn assert_warmup_info(
+        collector: &QuickwitCollector,
+        expected_fast_field_names: &[&str],
+        expected_term_dict_field_names: &[&str],
+        expected_field_norms: bool,
+    ) {
+        let to_field_name_set = |field_names: &[&str]| -> HashSet<String> {
+            field_names
+                .iter()
+                .map(|field_name| field_name.to_string())
+                .collect()
+        };
+        let warmup_info = collector.warmup_info();
+        assert_eq!(
+            warmup_info.fast_field_names,
+            to_field_name_set(expected_fast_field_names)
+        );
+        assert_eq!(
+            warmup_info.term_dict_field_names,
+            to_field_name_set(expected_term_dict_field_names)
+        );
+        assert_eq!(warmup_info.field_norms, expected_field_norms);
+        assert_eq!(collector.requires_scoring(), expected_field_norms);
+    }
+
+    #[test]
+    fn test_collector_warmup_info_sort_by_doc_id() {
+        let collector = make_test_collector(&SearchRequest::default());
+        assert_warmup_info(&collector, &[], &[], false);
+    }
+
+    #[test]
+    fn test_collector_warmup_info_sort_by_fast_field() {
+        let collector = make_test_collector(&SearchRequest {
+            sort_by_field: Some("response_time".to_string()),
+            sort_order: Some(SortOrder::Asc as i32),
+            ..Default::default()
+        });
+        assert_warmup_info(&collector, &["response_time"], &[], false);
+    }
+
+    #[test]
+    fn test_collector_warmup_info_sort_by_score() {
+        let collector = make_test_collector(&SearchRequest {
+            sort_by_field: Some("_score".to_string()),
+            ..Default::default()
+        });
+        assert_warmup_info(&collector, &[], &[], true);
+    }
+
+    #[test]
+    fn test_make_collector_min_score_requires_scoring() {
+        let collector = make_test_collector(&SearchRequest {
+            sort_by_field: Some("_score".to_string()),
+            min_score: Some(1.5),
+            ..Default::default()
+        });
+        assert_eq!(collector.min_score, Some(1.5));
+        let error = make_collector_for_split(
+            "split1".to_string(),
+            &make_test_doc_mapper(),
+            &SearchRequest {
+                sort_by_field: Some("timestamp".to_string()),
+                min_score: Some(1.5),
+                ..Default::default()
+            },
+            AggregationLimits::default(),
+            &SearcherConfig::default(),
+            None,
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(error, SearchError::InvalidArgument(_)));
+    }
+
+    #[test]
+    fn test_collector_warmup_info_timestamp_filter() {
+        let collector = make_test_collector(&SearchRequest {
+            start_timestamp: Some(1_662_529_435),
+            ..Default::default()
+        });
+        assert_warmup_info(&collector, &["timestamp"], &[], false);
+    }
+
+    #[test]
+    fn test_collector_warmup_info_fast_field_equals_filter() {
+        let collector = make_test_collector(&SearchRequest {
+            fast_field_equals: Some(FastFieldEquals {
+                field_name: "status_code".to_string(),
+                value: "404".to_string(),
+            }),
+            ..Default::default()
+        });
+        assert_warmup_info(&collector, &["status_code"], &[], false);
+    }
+
+    #[test]
+    fn test_collector_warmup_info_fast_field_range_filter() {
+        let collector = make_test_collector(&SearchRequest {
+            fast_field_range: Some(FastFieldRange {
+                field_name: "response_time".to_string(),
+                lower_bound: Some(100),
+                upper_bound: None,
+            }),
+            ..Default::default()
+        });
+        assert_warmup_info(&collector, &["response_time"], &[], false);
+    }
+
+    #[test]
+    fn test_collector_warmup_info_terms_aggregation() {
+        let aggregation_request = json!({
+            "status_codes": {
+                "terms": {"field": "status_code"}
+            }
+        });
+        let collector = make_test_collector(&SearchRequest {
+            aggregation_request: Some(aggregation_request.to_string()),
+            ..Default::default()
+        });
+        assert_warmup_info(&collector, &["status_code"], &[], false);
+    }
+
+    #[test]
+    fn test_collector_warmup_info_combined() {
+        let aggregation_request = json!({
+            "status_codes": {
+                "terms": {"field": "status_code"},
+                "aggs": {
+                    "avg_response_time": {"avg": {"field": "response_time"}}
+                }
+            }
+        });
+        let collector = make_test_collector(&SearchRequest {
+            sort_by_field: Some("response_time".to_string()),
+            start_timestamp: Some(1_662_529_435),
+            end_timestamp: Some(1_662_639_435),
+            aggregation_request: Some(aggregation_request.to_string()),
+            ..Default::default()
+        });
+        assert_warmup_info(
+            &collector,
+            &["response_time", "status_code", "timestamp"],
+            &[],
+            false,
+        );
+        let collector = make_test_collector(&SearchRequest {
+            sort_by_field: Some("_score".to_string()),
+            end_timestamp: Some(1_662_639_435),
+            aggregation_request: Some(aggregation_request.to_string()),
+            ..Default::default()
+        });
+        assert_warmup_info(
+            &collector,
+            &["response_time", "status_code", "timestamp"],
+            &[],
+            true,
+        );
+    }
+
+    #[test]
+    fn test_partial_hit_ordered_by_sorting_field() {
+        let lesser_score = PartialHitHeapItem {
+            sorting_field_value: 1u64,
+            secondary_sorting_field_values: Vec::new(),
+            tie_breaker_value: 0u64,
+            doc_id: 1u32,
+            score_opt: None,
+            tie_break_order: SortOrder::Asc,
+        };
+        let higher_score = PartialHitHeapItem {
+            sorting_field_value: 2u64,
+            secondary_sorting_field_values: Vec::new(),
+            tie_breaker_value: 0u64,
+            doc_id: 1u32,
+            score_opt: None,
+            tie_break_order: SortOrder::Asc,
+        };
+        assert_eq!(lesser_score.cmp(&higher_score), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_partial_hit_ordered_by_secondary_sorting_fields() {
+        let make_hit = |secondary_sorting_field_values: Vec<u64>, doc_id: u32| PartialHitHeapItem {
+            sorting_field_value: 1u64,
+            secondary_sorting_field_values,
+            tie_breaker_value: 0u64,
+            doc_id,
+            score_opt: None,
+            tie_break_order: SortOrder::Asc,
+        };
+        // The secondary fields are compared in order...
+        assert_eq!(
+            make_hit(vec![1, 5], 0).cmp(&make_hit(vec![2, 0], 1)),
+            Ordering::Greater
+        );
+        assert_eq!(
+            make_hit(vec![2, 5], 1).cmp(&make_hit(vec![2, 0], 0)),
+            Ordering::Less
+        );
+        // ... and the `DocId` is only used when all of them tie.
+        assert_eq!(
+            make_hit(vec![2, 5], 0).cmp(&make_hit(vec![2, 5], 1)),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn test_sorting_field_computer_missing_value_ignores_order() {
+        let compute_missing = |order: SortOrder, missing: MissingValue<u64>| {
+            let sorting_field_computer = SortingFieldComputer::FastField {
+                sort_column: SortColumn::U64(Column::build_empty_column(1), ColumnType::U64),
+                order,
+                missing,
+                aggregate: SortValueAggregate::First,
+            };
+            sorting_field_computer.compute_sorting_field(0, 1.0)
+        };
+        for order in [SortOrder::Asc, SortOrder::Desc] {
+            assert_eq!(compute_missing(order, MissingValue::First), u64::MAX);
+            assert_eq!(compute_missing(order, MissingValue::Last), 0);
+        }
+        assert_eq!(
+            compute_missing(SortOrder::Desc, MissingValue::Value(10)),
+            10
+        );
+        assert_eq!(
+            compute_missing(SortOrder::Asc, MissingValue::Value(10)),
+            u64::MAX - 10
+        );
+    }
+
+    #[test]
+    fn test_parse_missing_value() {
+        assert_eq!("_first".parse(), Ok(MissingValue::First));
+        assert_eq!("_last".parse(), Ok(MissingValue::Last));
+        assert_eq!(
+            "42".parse(),
+            Ok(MissingValue::Value(MissingNumber::U64(42)))
+        );
+        assert_eq!(
+            "-1".parse(),
+            Ok(MissingValue::Value(MissingNumber::I64(-1)))
+        );
+        assert_eq!(
+            "2.5".parse(),
+            Ok(MissingValue::Value(MissingNumber::F64(2.5)))
+        );
+        assert!("_middle".parse::<MissingValue>().is_err());
+    }
+
+    #[test]
+    fn test_segment_search_after_round_trips_partial_hit() {
+        let cursor = PartialHit {
+            sorting_field_value: 10,
+            secondary_sorting_field_values: vec![3],
+            tie_breaker_value: 0,
+            split_id: "split_2".to_string(),
+            segment_ord: 1,
+            doc_id: 5,
+            docvalue_fields: Default::default(),
+            score: None,
+            collapse_key: None,
+            sort_values: Vec::new(),
+            dedup_key: None,
+        };
+        let make_hit = |sorting_field_value: u64, secondary_sorting_field_value: u64, doc_id| {
+            PartialHitHeapItem {
+                sorting_field_value,
+                secondary_sorting_field_values: vec![secondary_sorting_field_value],
+                tie_breaker_value: 0,
+                doc_id,
+                score_opt: None,
+                tie_break_order: SortOrder::Asc,
+            }
+        };
+        let search_after = SegmentSearchAfter::new(&cursor, "split_2", 1, SortOrder::Asc);
+        // The cursor's own document is excluded, the next tied document is included.
+        assert!(!search_after.is_after(&make_hit(10, 3, 5)));
+        assert!(search_after.is_after(&make_hit(10, 3, 6)));
+        assert!(!search_after.is_after(&make_hit(10, 4, 9)));
+        assert!(search_after.is_after(&make_hit(10, 2, 0)));
+        assert!(search_after.is_before_sorting_field_value(11));
+        assert!(!search_after.is_before_sorting_field_value(10));
+        assert!(search_after.is_after(&make_hit(9, 9, 0)));
+        // Tied documents of other segments are ordered by split id then segment ordinal.
+        let previous_split_search_after =
+            SegmentSearchAfter::new(&cursor, "split_1", 3, SortOrder::Asc);
+        assert!(!previous_split_search_after.is_after(&make_hit(10, 3, 9)));
+        let next_segment_search_after =
+            SegmentSearchAfter::new(&cursor, "split_2", 2, SortOrder::Asc);
+        assert!(next_segment_search_after.is_after(&make_hit(10, 3, 0)));
+    }
+
+    #[test]
+    fn test_collector_default_sort_by_field() {
+        let doc_mapper: DefaultDocMapper = serde_json::from_value(json!({
+            "timestamp_field": "timestamp",
+            "default_sort_by_field": "-timestamp,status_code",
+            "field_mappings": [
+                {"name": "timestamp", "type": "datetime", "fast": true},
+                {"name": "status_code", "type": "u64", "fast": true},
+            ]
+        }))
+        .unwrap();
+        let make_collector = |search_request: &SearchRequest| -> QuickwitCollector {
+            make_collector_for_split(
+                "split1".to_string(),
+                &doc_mapper,
+                search_request,
+                AggregationLimits::default(),
+                &SearcherConfig::default(),
+                None,
+            )
+            .unwrap()
+        };
+        // The request does not specify a sort, so the default one applies.
+        let collector = make_collector(&SearchRequest {
+            max_hits: 10,
+            sort_order: Some(SortOrder::Desc as i32),
+            ..Default::default()
+        });
+        assert!(matches!(
+            &collector.sort_by[..],
+            [
+                SortBy::FastField { field_name: first_field_name, order: SortOrder::Desc, .. },
+                SortBy::FastField { field_name: second_field_name, order: SortOrder::Desc, .. },
+            ] if first_field_name == "timestamp" && second_field_name == "status_code"
+        ));
+        // The sort of the request overrides the default one.
+        let collector = make_collector(&SearchRequest {
+            max_hits: 10,
+            sort_by_field: Some("status_code".to_string()),
+            ..Default::default()
+        });
+        assert!(matches!(
+            &collector.sort_by[..],
+            [SortBy::FastField { field_name, .. }] if field_name == "status_code"
+        ));
+        // Without hits to return, the hits are not sorted.
+        let collector = make_collector(&SearchRequest::default());
+        assert!(matches!(collector.sort_by[..], [SortBy::DocId]));
+        // Without a default sort, the hits are sorted by doc id.
+        let collector = make_test_collector(&SearchRequest {
+            max_hits: 10,
+            ..Default::default()
+        });
+        assert!(matches!(collector.sort_by[..], [SortBy::DocId]));
+    }
+
+    #[test]
+    fn test_collector_default_sort_order_per_field_type() {
+        // Without a sort order, `_score`, `_exists:` and datetime fields sort in descending
+        // order, and the other fields in ascending order.
+        let collector = make_test_collector(&SearchRequest {
+            max_hits: 10,
+            sort_by_field: Some("timestamp,status_code,_score".to_string()),
+            ..Default::default()
+        });
+        assert!(matches!(
+            &collector.sort_by[..],
+            [
+                SortBy::FastField { field_name: first_field_name, order: SortOrder::Desc, .. },
+                SortBy::FastField { field_name: second_field_name, order: SortOrder::Asc, .. },
+                SortBy::Score { order: SortOrder::Desc },
+            ] if first_field_name == "timestamp" && second_field_name == "status_code"
+        ));
+        let sort_by = parse_sort_by_field(
+            "_exists:status_code,response_time:max,missing_field",
+            None,
+            MissingValue::default(),
+            Some(&make_test_doc_mapper().schema()),
+        )
+        .unwrap();
+        assert!(matches!(
+            &sort_by[..],
+            [
+                SortBy::FieldExists {
+                    order: SortOrder::Desc,
+                    ..
+                },
+                SortBy::FastField {
+                    order: SortOrder::Asc,
+                    ..
+                },
+                SortBy::FastField {
+                    order: SortOrder::Asc,
+                    ..
+                },
+            ]
+        ));
+        // The sort order of the request overrides the defaults, and order prefixes override the
+        // sort order of the request.
+        let collector = make_test_collector(&SearchRequest {
+            max_hits: 10,
+            sort_by_field: Some("timestamp,status_code,-_score".to_string()),
+            sort_order: Some(SortOrder::Asc as i32),
+            ..Default::default()
+        });
+        assert!(matches!(
+            &collector.sort_by[..],
+            [
+                SortBy::FastField {
+                    order: SortOrder::Asc,
+                    ..
+                },
+                SortBy::FastField {
+                    order: SortOrder::Asc,
+                    ..
+                },
+                SortBy::Score {
+                    order: SortOrder::Desc
+                },
+            ]
+        ));
+        let collector = make_test_collector(&SearchRequest {
+            max_hits: 10,
+            sort_by_field: Some("+timestamp,-status_code".to_string()),
+            ..Default::default()
+        });
+        assert!(matches!(
+            &collector.sort_by[..],
+            [
+                SortBy::FastField {
+                    order: SortOrder::Asc,
+                    ..
+                },
+                SortBy::FastField {
+                    order: SortOrder::Desc,
+                    ..
+                },
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_collector_sort_by_several_fields() {
+        let collector = make_test_collector(&SearchRequest {
+            sort_by_field: Some("response_time, -status_code,_score".to_string()),
+            sort_order: Some(SortOrder::Asc as i32),
+            ..Default::default()
+        });
+        assert!(matches!(
+            &collector.sort_by[..],
+            [
+                SortBy::FastField { field_name: first_field_name, order: SortOrder::Asc, .. },
+                SortBy::FastField { field_name: second_field_name, order: SortOrder::Desc, .. },
+                SortBy::Score { order: SortOrder::Asc },
+            ] if first_field_name == "response_time" && second_field_name == "status_code"
+        ));
+        assert_warmup_info(&collector, &["response_time", "status_code"], &[], true);
+    }
+
+    #[test]
+    fn test_parse_sort_by_field_with_order_prefixes() {
+        let sort_by = parse_sort_by_field(
+            "-timestamp, +severity,status_code,-_score",
+            Some(SortOrder::Asc),
+            MissingValue::default(),
+            None,
+        )
+        .unwrap();
+        assert!(matches!(
+            &sort_by[..],
+            [
+                SortBy::FastField { field_name: first_field_name, order: SortOrder::Desc, .. },
+                SortBy::FastField { field_name: second_field_name, order: SortOrder::Asc, .. },
+                SortBy::FastField { field_name: third_field_name, order: SortOrder::Asc, .. },
+                SortBy::Score { order: SortOrder::Desc },
+            ] if first_field_name == "timestamp"
+                && second_field_name == "severity"
+                && third_field_name == "status_code"
+        ));
+        // Fields without a prefix fall back to the default order.
+        let sort_by = parse_sort_by_field(
+            "+timestamp,severity,_exists:error_code",
+            Some(SortOrder::Desc),
+            MissingValue::default(),
+            None,
+        )
+        .unwrap();
+        assert!(matches!(
+            &sort_by[..],
+            [
+                SortBy::FastField { order: SortOrder::Asc, .. },
+                SortBy::FastField { order: SortOrder::Desc, .. },
+                SortBy::FieldExists { field_name, order: SortOrder::Desc },
+            ] if field_name == "error_code"
+        ));
+        // Repeating a field in the same order is harmless.
+        assert!(parse_sort_by_field(
+            "-timestamp,timestamp",
+            Some(SortOrder::Desc),
+            MissingValue::default(),
+            None,
+        )
+        .is_ok());
+        for invalid_sort_by_field in [
+            "-timestamp,+timestamp",
+            "timestamp,-timestamp",
+            "+-timestamp",
+            "--timestamp",
+            "-",
+            "timestamp,+",
+        ] {
+            let error = parse_sort_by_field(
+                invalid_sort_by_field,
+                Some(SortOrder::Asc),
+                MissingValue::default(),
+                None,
+            )
+            .unwrap_err();
+            assert!(
+                matches!(error, SearchError::InvalidArgument(_)),
+                "{invalid_sort_by_field}: {error}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_concat_partial_hits() {
+        let make_hits = |split_id: &str, num_hits: u32| -> Vec<PartialHit> {
+            (0..num_hits)
+                .map(|doc_id| PartialHit {
+                    split_id: split_id.to_string(),
+                    doc_id,
+                    ..Default::default()
+                })
+                .collect()
+        };
+        let all_partial_hits = concat_partial_hits(vec![
+            make_hits("split1", 2),
+            Vec::new(),
+            make_hits("split2", 3),
+        ]);
+        assert_eq!(all_partial_hits.capacity(), 5);
+        let hit_addresses: Vec<(&str, u32)> = all_partial_hits
+            .iter()
+            .map(|partial_hit| (partial_hit.split_id.as_str(), partial_hit.doc_id))
+            .collect();
+        assert_eq!(
+            hit_addresses,
+            vec![
+                ("split1", 0),
+                ("split1", 1),
+                ("split2", 0),
+                ("split2", 1),
+                ("split2", 2),
+            ]
+        );
+        // However large the fan-out, the hits are moved into a single allocation, which is never
+        // reallocated.
+        let all_partial_hits =
+            concat_partial_hits((0..500).map(|_| make_hits("split1", 100)).collect());
+        assert_eq!(all_partial_hits.len(), 50_000);
+        assert_eq!(all_partial_hits.capacity(), 50_000);
+    }
+
+    #[test]
+    fn test_merge_partial_hits_no_tie() {
+        let make_doc = |sorting_field_value: u64| PartialHit {
+            sorting_field_value,
+            split_id: "split1".to_string(),
+            segment_ord: 0u32,
+            doc_id: 0u32,
+            tie_breaker_value: 0,
+            docvalue_fields: Default::default(),
+            secondary_sorting_field_values: Vec::new(),
+            score: None,
+            collapse_key: None,
+            sort_values: Vec::new(),
+            dedup_key: None,
+        };
+        assert_eq!(
+            top_k_partial_hits(
+                vec![make_doc(1u64), make_doc(3u64), make_doc(2u64),],
+                2,
+                SortOrder::Asc
+            ),
+            vec![make_doc(3), make_doc(2)]
+        );
+    }
+
+    #[test]
+    fn test_merge_partial_hits_keeps_scores() {
+        let make_doc = |score: f32| PartialHit {
+            sorting_field_value: f32_to_u64(score),
+            split_id: "split1".to_string(),
+            segment_ord: 0u32,
+            doc_id: score as u32,
+            tie_breaker_value: 0,
+            docvalue_fields: Default::default(),
+            secondary_sorting_field_values: Vec::new(),
+            score: Some(score),
+            collapse_key: None,
+            sort_values: Vec::new(),
+            dedup_key: None,
+        };
+        let top_scores: Vec<Option<f32>> = top_k_partial_hits(
+            vec![make_doc(1.5), make_doc(3.5), make_doc(2.5)],
+            2,
+            SortOrder::Asc,
+        )
+        .into_iter()
+        .map(|partial_hit| partial_hit.score)
+        .collect();
+        assert_eq!(top_scores, vec![Some(3.5), Some(2.5)]);
+    }
+
+    #[test]
+    fn test_partial_hit_heap_item_order_matches_merge_order() {
+        let mut heap_items = Vec::new();
+        for doc_id in 0..12u32 {
+            heap_items.push(PartialHitHeapItem {
+                sorting_field_value: (doc_id % 2) as u64,
+                secondary_sorting_field_values: vec![(doc_id % 3) as u64],
+                tie_breaker_value: (doc_id % 4 / 2) as u64,
+                doc_id: 11 - doc_id,
+                score_opt: None,
+                tie_break_order: SortOrder::Asc,
+            });
+        }
+        let mut partial_hits: Vec<PartialHit> = heap_items
+            .iter()
+            .map(|heap_item| PartialHit {
+                sorting_field_value: heap_item.sorting_field_value,
+                secondary_sorting_field_values: heap_item.secondary_sorting_field_values.clone(),
+                tie_breaker_value: heap_item.tie_breaker_value,
+                split_id: "split1".to_string(),
+                segment_ord: 0,
+                doc_id: heap_item.doc_id,
+                ..Default::default()
+            })
+            .collect();
+        // The best heap items are the lowest.
+        heap_items.sort();
+        partial_hits.sort_by(|left, right| {
+            partial_hit_sorting_key(left, SortOrder::Asc)
+                .cmp(&partial_hit_sorting_key(right, SortOrder::Asc))
+        });
+        let heap_item_doc_ids: Vec<u32> = heap_items.iter().map(|hit| hit.doc_id).collect();
+        let partial_hit_doc_ids: Vec<u32> = partial_hits.iter().map(|hit| hit.doc_id).collect();
+        assert_eq!(heap_item_doc_ids, partial_hit_doc_ids);
+    }
+
+    #[test]
+    fn test_merge_fruits_with_ties_is_deterministic() {
+        let make_leaf_response = |split_id: &str| {
+            // All the hits tie on their sorting value.
+            let partial_hits: Vec<PartialHit> = (0..2u32)
+                .flat_map(|segment_ord| {
+                    (0..3u32).map(move |doc_id| PartialHit {
+                        sorting_field_value: 7,
+                        split_id: split_id.to_string(),
+                        segment_ord,
+                        doc_id,
+                        ..Default::default()
+                    })
+                })
+                .collect();
+            LeafSearchResponse {
+                num_hits: partial_hits.len() as u64,
+                partial_hits,
+                ..Default::default()
+            }
+        };
+        let collector = make_test_collector(&SearchRequest {
+            max_hits: 8,
+            ..Default::default()
+        });
+        let merge = |split_ids: &[&str]| -> Vec<(String, u32, u32)> {
+            let leaf_responses = split_ids
+                .iter()
+                .map(|split_id| Ok(make_leaf_response(split_id)))
+                .collect();
+            collector
+                .merge_fruits(leaf_responses)
+                .unwrap()
+                .partial_hits
+                .into_iter()
+                .map(|hit| (hit.split_id, hit.segment_ord, hit.doc_id))
+                .collect()
+        };
+        let merged_hits = merge(&["split_a", "split_b"]);
+        // Ties are broken by split id, then segment ordinal, then doc id.
+        let expected_hits: Vec<(String, u32, u32)> = [
+            ("split_a", 0, 0),
+            ("split_a", 0, 1),
+            ("split_a", 0, 2),
+            ("split_a", 1, 0),
+            ("split_a", 1, 1),
+            ("split_a", 1, 2),
+            ("split_b", 0, 0),
+            ("split_b", 0, 1),
+        ]
+        .into_iter()
+        .map(|(split_id, segment_ord, doc_id)| (split_id.to_string(), segment_ord, doc_id))
+        .collect();
+        assert_eq!(merged_hits, expected_hits);
+        for _ in 0..3 {
+            assert_eq!(merge(&["split_b", "split_a"]), expected_hits);
+            assert_eq!(merge(&["split_a", "split_b"]), expected_hits);
+        }
+    }
+
+    #[test]
+    fn test_geo_point_haversine_distance() {
+        let paris = GeoPoint {
+            lat: 48.8566,
+            lon: 2.3522,
+        };
+        let london = GeoPoint {
+            lat: 51.5074,
+            lon: -0.1278,
+        };
+        assert_eq!(paris.haversine_distance(&paris), 0.0);
+        let distance = paris.haversine_distance(&london);
+        assert!((distance - 343_560.0).abs() < 1_000.0, "{distance}");
+        assert_eq!(distance, london.haversine_distance(&paris));
+    }
+
+    #[test]
+    fn test_collapse_partial_hits() {
+        let make_hit =
+            |split_id: &str, sorting_field_value: u64, collapse_key: Option<&str>| PartialHit {
+                sorting_field_value,
+                split_id: split_id.to_string(),
+                segment_ord: 0u32,
+                doc_id: sorting_field_value as u32,
+                tie_breaker_value: 0,
+                docvalue_fields: Default::default(),
+                secondary_sorting_field_values: Vec::new(),
+                score: None,
+                collapse_key: collapse_key.map(ToString::to_string),
+                sort_values: Vec::new(),
+                dedup_key: None,
+            };
+        let collapsed_hits: Vec<(String, u64)> = collapse_partial_hits(
+            vec![
+                make_hit("split_1", 3, Some("req1")),
+                make_hit("split_2", 5, Some("req1")),
+                make_hit("split_1", 4, Some("req2")),
+                make_hit("split_2", 1, None),
+                make_hit("split_1", 2, None),
+            ],
+            1,
+            SortOrder::Asc,
+        )
+        .into_iter()
+        .map(|partial_hit| (partial_hit.split_id, partial_hit.sorting_field_value))
+        .collect();
+        assert_eq!(
+            collapsed_hits,
+            vec![
+                ("split_2".to_string(), 5),
+                ("split_1".to_string(), 4),
+                ("split_1".to_string(), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_partial_hits_with_tie() {
+        let make_hit_given_split_id = |split_id: u64| PartialHit {
+            sorting_field_value: 0u64,
+            split_id: format!("split_{split_id}"),
+            segment_ord: 0u32,
+            doc_id: 0u32,
+            tie_breaker_value: 0,
+            docvalue_fields: Default::default(),
+            secondary_sorting_field_values: Vec::new(),
+            score: None,
+            collapse_key: None,
+            sort_values: Vec::new(),
+            dedup_key: None,
+        };
+        assert_eq!(
+            top_k_partial_hits(
+                vec![
+                    make_hit_given_split_id(1u64),
+                    make_hit_given_split_id(3u64),
+                    make_hit_given_split_id(2u64),
+                ],
+                2,
+                SortOrder::Asc
+            ),
+            vec![make_hit_given_split_id(1), make_hit_given_split_id(2)]
+        );
+    }
+
+    #[test]
+    fn test_tie_break_order_with_cross_split_ties() {
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let status_code_field = schema.get_field("status_code").unwrap();
+        let split_ids = ["split_1", "split_2", "split_3"];
+        let searchers: Vec<Searcher> = split_ids
+            .iter()
+            .map(|_| {
+                let index = Index::create_in_ram(schema.clone());
+                let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+                for doc_id in 0..6u64 {
+                    index_writer
+                        .add_document(doc!(status_code_field => 200 + doc_id % 2))
+                        .unwrap();
+                }
+                index_writer.commit().unwrap();
+                index.reader().unwrap().searcher()
+            })
+            .collect();
+
+        for tie_break_order in [SortOrder::Asc, SortOrder::Desc] {
+            let search = |max_hits: u64, search_after: Option<PartialHit>| -> Vec<PartialHit> {
+                let search_request = SearchRequest {
+                    max_hits,
+                    sort_by_field: Some("-status_code".to_string()),
+                    search_after,
+                    tie_break_order: Some(tie_break_order as i32),
+                    ..Default::default()
+                };
+                let leaf_responses = split_ids
+                    .iter()
+                    .zip(&searchers)
+                    .map(|(split_id, searcher)| {
+                        let collector = make_collector_for_split(
+                            split_id.to_string(),
+                            &doc_mapper,
+                            &search_request,
+                            AggregationLimits::default(),
+                            &SearcherConfig::default(),
+                            None,
+                        )
+                        .unwrap();
+                        searcher.search(&AllQuery, &collector)
+                    })
+                    .collect();
+                make_test_collector(&search_request)
+                    .merge_fruits(leaf_responses)
+                    .unwrap()
+                    .partial_hits
+            };
+            let doc_addresses = |partial_hits: &[PartialHit]| -> Vec<(String, u32)> {
+                partial_hits
+                    .iter()
+                    .map(|partial_hit| (partial_hit.split_id.clone(), partial_hit.doc_id))
+                    .collect()
+            };
+            // The documents with the status code 201 come first, the ties being ordered by
+            // document address in the tie break order.
+            let mut expected_hits: Vec<(u64, String, u32)> = split_ids
+                .iter()
+                .flat_map(|split_id| {
+                    (0..6u32).map(|doc_id| (200 + doc_id as u64 % 2, split_id.to_string(), doc_id))
+                })
+                .collect();
+            expected_hits.sort_by(|left, right| {
+                let tie_break_cmp = (&left.1, left.2).cmp(&(&right.1, right.2));
+                right.0.cmp(&left.0).then(match tie_break_order {
+                    SortOrder::Asc => tie_break_cmp,
+                    SortOrder::Desc => tie_break_cmp.reverse(),
+                })
+            });
+            let expected_doc_addresses: Vec<(String, u32)> = expected_hits
+                .into_iter()
+                .map(|(_, split_id, doc_id)| (split_id, doc_id))
+                .collect();
+            assert_eq!(doc_addresses(&search(100, None)), expected_doc_addresses);
+
+            // Paginating visits every document exactly once, in the same order, which requires
+            // the segment collectors and the merge to break ties the same way.
+            let mut paginated_hits = Vec::new();
+            let mut search_after = None;
+            loop {
+                let page = search(4, search_after);
+                let Some(last_hit) = page.last() else {
+                    break;
+                };
+                search_after = Some(last_hit.clone());
+                paginated_hits.extend(page);
+            }
+            assert_eq!(doc_addresses(&paginated_hits), expected_doc_addresses);
+        }
+    }
+
+    #[test]
+    fn test_merge_partial_hits_round_robin_splits() {
+        let make_hit = |split_id: &str, sorting_field_value: u64| PartialHit {
+            sorting_field_value,
+            split_id: split_id.to_string(),
+            segment_ord: 0u32,
+            doc_id: sorting_field_value as u32,
+            tie_breaker_value: 0,
+            docvalue_fields: Default::default(),
+            secondary_sorting_field_values: Vec::new(),
+            score: None,
+            collapse_key: None,
+            sort_values: Vec::new(),
+            dedup_key: None,
+        };
+        // `split_1` holds the global top hits and would fill the whole page
+        // with a plain top-K merge.
+        let partial_hits = vec![
+            make_hit("split_1", 100),
+            make_hit("split_1", 99),
+            make_hit("split_1", 98),
+            make_hit("split_1", 97),
+            make_hit("split_2", 20),
+            make_hit("split_2", 21),
+            make_hit("split_2", 22),
+            make_hit("split_3", 10),
+            make_hit("split_3", 11),
+        ];
+        assert_eq!(
+            top_k_partial_hits(partial_hits.clone(), 3, SortOrder::Asc),
+            vec![
+                make_hit("split_1", 100),
+                make_hit("split_1", 99),
+                make_hit("split_1", 98),
+            ]
+        );
+        assert_eq!(
+            round_robin_partial_hits(partial_hits.clone(), 6, SortOrder::Asc),
+            vec![
+                make_hit("split_1", 100),
+                make_hit("split_2", 22),
+                make_hit("split_3", 11),
+                make_hit("split_1", 99),
+                make_hit("split_2", 21),
+                make_hit("split_3", 10),
+            ]
+        );
+        // Once a split runs out of hits, the remaining splits keep taking turns.
+        assert_eq!(
+            round_robin_partial_hits(partial_hits, 8, SortOrder::Asc),
+            vec![
+                make_hit("split_1", 100),
+                make_hit("split_2", 22),
+                make_hit("split_3", 11),
+                make_hit("split_1", 99),
+                make_hit("split_2", 21),
+                make_hit("split_3", 10),
+                make_hit("split_1", 98),
+                make_hit("split_2", 20),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reciprocal_rank_fusion() {
+        let make_hit = |doc_id: u32, sorting_field_value: u64| PartialHit {
+            sorting_field_value,
+            split_id: "split_1".to_string(),
+            segment_ord: 0u32,
+            doc_id,
+            tie_breaker_value: 0,
+            docvalue_fields: Default::default(),
+            secondary_sorting_field_values: Vec::new(),
+            score: None,
+            collapse_key: None,
+            sort_values: Vec::new(),
+            dedup_key: None,
+        };
+        let make_ranking = |doc_ids: &[u32]| Ranking {
+            partial_hits: doc_ids
+                .iter()
+                .enumerate()
+                .map(|(rank_ord, doc_id)| make_hit(*doc_id, 100 - rank_ord as u64))
+                .collect(),
+        };
+        let rankings = vec![make_ranking(&[1, 2, 3]), make_ranking(&[3, 1, 4])];
+        let fused_hit = |doc_id: u32, score: f64| make_hit(doc_id, score.to_bits());
+        let k = 60.0;
+        assert_eq!(
+            reciprocal_rank_fusion(&rankings, 60, 10, SortOrder::Asc),
+            vec![
+                fused_hit(1, 1.0 / (k + 1.0) + 1.0 / (k + 2.0)),
+                fused_hit(3, 1.0 / (k + 3.0) + 1.0 / (k + 1.0)),
+                fused_hit(2, 1.0 / (k + 2.0)),
+                fused_hit(4, 1.0 / (k + 3.0)),
+            ]
+        );
+        let k = 0.0;
+        assert_eq!(
+            reciprocal_rank_fusion(&rankings, 0, 2, SortOrder::Asc),
+            vec![
+                fused_hit(1, 1.0 / (k + 1.0) + 1.0 / (k + 2.0)),
+                fused_hit(3, 1.0 / (k + 3.0) + 1.0 / (k + 1.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_fruits_fuses_merged_rankings() {
+        let make_hit = |split_id: &str, sorting_field_value: u64| PartialHit {
+            sorting_field_value,
+            split_id: split_id.to_string(),
+            segment_ord: 0u32,
+            doc_id: sorting_field_value as u32,
+            tie_breaker_value: 0,
+            docvalue_fields: Default::default(),
+            secondary_sorting_field_values: Vec::new(),
+            score: None,
+            collapse_key: None,
+            sort_values: Vec::new(),
+            dedup_key: None,
+        };
+        let make_leaf_response = |rankings: Vec<Vec<PartialHit>>| LeafSearchResponse {
+            partial_hits: rankings[0].clone(),
+            rankings: rankings
+                .into_iter()
+                .map(|partial_hits| Ranking { partial_hits })
+                .collect(),
+            ..Default::default()
+        };
+        let collector = QuickwitCollector {
+            max_hits: 3,
+            rank_fusion: Some(ReciprocalRankFusion {
+                sort_by: vec![SortBy::DocId],
+                rank_constant: 60,
+            }),
+            ..make_test_collector(&SearchRequest::default())
+        };
+        // `split_1` leads the first ranking and `split_2` leads the second one.
+        let leaf_responses = vec![
+            Ok(make_leaf_response(vec![
+                vec![make_hit("split_1", 10), make_hit("split_1", 9)],
+                vec![make_hit("split_1", 9)],
+            ])),
+            Ok(make_leaf_response(vec![
+                vec![make_hit("split_2", 5)],
+                vec![make_hit("split_2", 20), make_hit("split_2", 5)],
+            ])),
+        ];
+        let merged_response = collector.merge_fruits(leaf_responses).unwrap();
+        let doc_ids: Vec<(&str, u32)> = merged_response
+            .partial_hits
+            .iter()
+            .map(|partial_hit| (partial_hit.split_id.as_str(), partial_hit.doc_id))
+            .collect();
+        // The hits held by both merged rankings come first: split_1/9 scores 1/62 + 1/62 and
+        // split_2/5 scores 1/63 + 1/63, while split_1/10 and split_2/20 both score 1/61.
+        assert_eq!(
+            doc_ids,
+            vec![("split_1", 9), ("split_2", 5), ("split_1", 10)]
+        );
+        assert_eq!(merged_response.rankings.len(), 2);
+        assert_eq!(merged_response.rankings[1].partial_hits.len(), 3);
+    }
+
+    fn make_sorted_hit(split_id: &str, sorting_field_value: u64) -> PartialHit {
+        PartialHit {
+            sorting_field_value,
+            split_id: split_id.to_string(),
+            segment_ord: 0u32,
+            doc_id: sorting_field_value as u32,
+            tie_breaker_value: 0,
+            docvalue_fields: Default::default(),
+            secondary_sorting_field_values: Vec::new(),
+            score: None,
+            collapse_key: None,
+            sort_values: Vec::new(),
+            dedup_key: None,
+        }
+    }
+
+    #[test]
+    fn test_merge_search_responses() {
+        let make_leaf_response =
+            |split_id: &str, sorting_field_values: &[u64]| LeafSearchResponse {
+                num_hits: sorting_field_values.len() as u64,
+                partial_hits: sorting_field_values
+                    .iter()
+                    .map(|&sorting_field_value| make_sorted_hit(split_id, sorting_field_value))
+                    .collect(),
+                num_attempted_splits: 1,
+                num_successful_splits: 1,
+                ..Default::default()
+            };
+        let hit_addresses = |leaf_response: &LeafSearchResponse| -> Vec<(String, u32)> {
+            leaf_response
+                .partial_hits
+                .iter()
+                .map(|partial_hit| (partial_hit.split_id.clone(), partial_hit.doc_id))
+                .collect()
+        };
+        let leaf_responses = vec![
+            make_leaf_response("split_1", &[9, 5, 2]),
+            make_leaf_response("split_2", &[8, 7, 1]),
+        ];
+        let merged_response = merge_search_responses(leaf_responses, 1, 3, &None).unwrap();
+        assert_eq!(merged_response.num_hits, 6);
+        assert_eq!(merged_response.num_attempted_splits, 2);
+        assert_eq!(merged_response.num_successful_splits, 2);
+        assert_eq!(
+            hit_addresses(&merged_response),
+            vec![
+                ("split_2".to_string(), 8),
+                ("split_2".to_string(), 7),
+                ("split_1".to_string(), 5),
+            ]
+        );
+        assert!(merged_response.intermediate_aggregation_result.is_none());
+
+        // A single response is not merged, but its skipped hits are still dropped.
+        let merged_response =
+            merge_search_responses(vec![make_leaf_response("split_1", &[9, 5, 2])], 2, 3, &None)
+                .unwrap();
+        assert_eq!(
+            hit_addresses(&merged_response),
+            vec![("split_1".to_string(), 2)]
+        );
+
+        // Past the last hit, the page is empty.
+        let merged_response =
+            merge_search_responses(vec![make_leaf_response("split_1", &[9])], 5, 3, &None).unwrap();
+        assert!(merged_response.partial_hits.is_empty());
+        assert_eq!(merged_response.num_hits, 1);
+    }
+
+    #[test]
+    fn test_merge_search_responses_with_options_matches_merge_fruits() {
+        let make_leaf_response =
+            |split_id: &str, sorting_field_values: [u64; 3]| LeafSearchResponse {
+                num_hits: 3,
+                partial_hits: sorting_field_values
+                    .iter()
+                    .map(|&sorting_field_value| make_sorted_hit(split_id, sorting_field_value))
+                    .collect(),
+                ..Default::default()
+            };
+        let leaf_responses = vec![
+            make_leaf_response("split_1", [10, 9, 8]),
+            make_leaf_response("split_2", [5, 4, 3]),
+        ];
+        let collector = QuickwitCollector {
+            start_offset: 1,
+            max_hits: 3,
+            round_robin_splits: true,
+            ..make_test_collector(&SearchRequest::default())
+        };
+        let merged_response = merge_search_responses_with_options(
+            leaf_responses.clone(),
+            1,
+            3,
+            &None,
+            MergeOptions {
+                round_robin_splits: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            merged_response,
+            collector
+                .merge_fruits(leaf_responses.into_iter().map(Ok).collect())
+                .unwrap()
+        );
+        // The splits take turns, whereas a merge by rank would only return hits of `split_1`.
+        assert_eq!(
+            merged_response.partial_hits,
+            vec![
+                make_sorted_hit("split_2", 5),
+                make_sorted_hit("split_1", 9),
+                make_sorted_hit("split_2", 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_search_responses_max_hits_per_source() {
+        let make_leaf_response =
+            |split_id: &str, sorting_field_values: &[u64]| LeafSearchResponse {
+                num_hits: sorting_field_values.len() as u64,
+                partial_hits: sorting_field_values
+                    .iter()
+                    .map(|&sorting_field_value| make_sorted_hit(split_id, sorting_field_value))
+                    .collect(),
+                ..Default::default()
+            };
+        // Both sources hold more hits than their quota, and `source-a` holds the best ones.
+        let leaf_responses = vec![
+            make_leaf_response("source-a/split_1", &[10, 8, 6]),
+            make_leaf_response("source-b/split_1", &[7, 3]),
+            make_leaf_response("source-a/split_2", &[9, 5]),
+            make_leaf_response("source-b/split_2", &[4, 2]),
+            // Without the separator, the split belongs to the unnamed source.
+            make_leaf_response("split_3", &[11, 1, 0]),
+        ];
+        let merge_options = MergeOptions {
+            max_hits_per_source: Some(('/', 2)),
+            ..Default::default()
+        };
+        let merged_response = merge_search_responses_with_options(
+            leaf_responses.clone(),
+            0,
+            10,
+            &None,
+            merge_options,
+        )
+        .unwrap();
+        assert_eq!(merged_response.num_hits, 12);
+        // The hits kept are still in the global sort order.
+        assert_eq!(
+            merged_response.partial_hits,
+            vec![
+                make_sorted_hit("split_3", 11),
+                make_sorted_hit("source-a/split_1", 10),
+                make_sorted_hit("source-a/split_2", 9),
+                make_sorted_hit("source-b/split_1", 7),
+                make_sorted_hit("source-b/split_2", 4),
+                make_sorted_hit("split_3", 1),
+            ]
+        );
+        let collector = make_test_collector(&SearchRequest {
+            max_hits: 10,
+            max_hits_per_source: Some(MaxHitsPerSource {
+                split_id_separator: "/".to_string(),
+                max_hits: 2,
+            }),
+            ..Default::default()
+        });
+        assert_eq!(
+            collector
+                .merge_fruits(leaf_responses.clone().into_iter().map(Ok).collect())
+                .unwrap(),
+            merged_response
+        );
+
+        // The quota applies before the skipped hits are dropped.
+        let merged_response =
+            merge_search_responses_with_options(leaf_responses.clone(), 2, 3, &None, merge_options)
+                .unwrap();
+        assert_eq!(
+            merged_response.partial_hits,
+            vec![
+                make_sorted_hit("source-a/split_2", 9),
+                make_sorted_hit("source-b/split_1", 7),
+                make_sorted_hit("source-b/split_2", 4),
+            ]
+        );
+
+        // A single response is capped as well.
+        let merged_response = merge_search_responses_with_options(
+            leaf_responses[..1].to_vec(),
+            0,
+            10,
+            &None,
+            merge_options,
+        )
+        .unwrap();
+        assert_eq!(
+            merged_response.partial_hits,
+            vec![
+                make_sorted_hit("source-a/split_1", 10),
+                make_sorted_hit("source-a/split_1", 8),
+            ]
+        );
+
+        let error = make_collector_for_split(
+            "split1".to_string(),
+            &make_test_doc_mapper(),
+            &SearchRequest {
+                max_hits_per_source: Some(MaxHitsPerSource {
+                    split_id_separator: "::".to_string(),
+                    max_hits: 2,
+                }),
+                ..Default::default()
+            },
+            AggregationLimits::default(),
+            &SearcherConfig::default(),
+            None,
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(error, SearchError::InvalidArgument(_)));
+        let error = QuickwitCollector::builder()
+            .max_hits_per_source(Some(('/', 0)))
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(error, SearchError::InvalidArgument(_)));
+    }
+
+    #[test]
+    fn test_merge_fruits_dedups_failed_splits() {
+        let make_split_error = |split_id: &str, code: SplitSearchErrorCode| SplitSearchError {
+            error: format!("{code:?}"),
+            split_id: split_id.to_string(),
+            retryable_error: true,
+            code: code as i32,
+        };
+        let leaf_responses = vec![
+            Ok(LeafSearchResponse {
+                failed_splits: vec![
+                    make_split_error("split_1", SplitSearchErrorCode::Timeout),
+                    make_split_error("split_2", SplitSearchErrorCode::Storage),
+                ],
+                num_attempted_splits: 2,
+                ..Default::default()
+            }),
+            Ok(LeafSearchResponse {
+                failed_splits: vec![make_split_error("split_1", SplitSearchErrorCode::Storage)],
+                num_attempted_splits: 1,
+                ..Default::default()
+            }),
+        ];
+        let collector = make_test_collector(&SearchRequest::default());
+        let merged_response = collector.merge_fruits(leaf_responses).unwrap();
+        assert_eq!(merged_response.num_attempted_splits, 3);
+        let failed_splits: Vec<(&str, SplitSearchErrorCode)> = merged_response
+            .failed_splits
+            .iter()
+            .map(|failed_split| (failed_split.split_id.as_str(), failed_split.code()))
+            .collect();
+        assert_eq!(
+            failed_splits,
+            vec![
+                ("split_1", SplitSearchErrorCode::Timeout),
+                ("split_2", SplitSearchErrorCode::Storage),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_fruits_counts_successful_splits() {
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let status_code_field = schema.get_field("status_code").unwrap();
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        index_writer
+            .add_document(doc!(status_code_field => 200u64))
+            .unwrap();
+        index_writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        let collector = make_test_collector(&SearchRequest {
+            max_hits: 10,
+            ..Default::default()
+        });
+        let successful_leaf_response = searcher.search(&AllQuery, &collector).unwrap();
+        assert_eq!(successful_leaf_response.num_attempted_splits, 1);
+        assert_eq!(successful_leaf_response.num_successful_splits, 1);
+
+        let failed_leaf_response = LeafSearchResponse {
+            failed_splits: vec![SplitSearchError {
+                error: "timeout".to_string(),
+                split_id: "split_2".to_string(),
+                retryable_error: true,
+                code: SplitSearchErrorCode::Timeout as i32,
+            }],
+            num_attempted_splits: 1,
+            ..Default::default()
+        };
+        let leaf_responses = vec![
+            Ok(successful_leaf_response.clone()),
+            Ok(failed_leaf_response),
+            Ok(successful_leaf_response),
+        ];
+        let merged_response = collector.merge_fruits(leaf_responses).unwrap();
+        assert_eq!(merged_response.num_attempted_splits, 3);
+        assert_eq!(merged_response.num_successful_splits, 2);
+        assert_eq!(merged_response.failed_splits.len(), 1);
+        assert_eq!(merged_response.num_hits, 2);
     }
-    let merged_intermediate_aggregation_result = match aggregations_opt {
-        Some(QuickwitAggregations::FindTraceIdsAggregation(collector)) => {
-            let fruits: Vec<
-                <<FindTraceIdsCollector as Collector>::Child as SegmentCollector>::Fruit,
-            > = leaf_responses
+
+    #[test]
+    fn test_merge_fruits_sort_value_types() {
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let status_code_field = schema.get_field("status_code").unwrap();
+        let response_time_field = schema.get_field("response_time").unwrap();
+        let make_searcher = |doc: Document| {
+            let index = Index::create_in_ram(schema.clone());
+            let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+            index_writer.add_document(doc).unwrap();
+            index_writer.commit().unwrap();
+            index.reader().unwrap().searcher()
+        };
+        let searcher = make_searcher(doc!(status_code_field => 200u64));
+        // The split without status code reports no type for it.
+        let searcher_without_status_code = make_searcher(doc!(response_time_field => 0.5f64));
+        let search_request = SearchRequest {
+            max_hits: 10,
+            sort_by_field: Some("status_code".to_string()),
+            return_sort_values: true,
+            ..Default::default()
+        };
+        let collector = make_test_collector(&search_request);
+        let leaf_responses = vec![
+            searcher_without_status_code.search(&AllQuery, &collector),
+            searcher.search(&AllQuery, &collector),
+        ];
+        let merged_response = collector.merge_fruits(leaf_responses).unwrap();
+        assert_eq!(
+            merged_response.sort_value_types,
+            [SortValueType::U64 as i32]
+        );
+
+        let collector = make_test_collector(&SearchRequest {
+            return_sort_values: false,
+            ..search_request
+        });
+        let leaf_response = searcher.search(&AllQuery, &collector).unwrap();
+        assert!(leaf_response.sort_value_types.is_empty());
+
+        let error = merge_sort_value_types([
+            &[SortValueType::U64 as i32][..],
+            &[SortValueType::F64 as i32][..],
+        ])
+        .unwrap_err();
+        assert!(error.to_string().contains("`U64` and `F64`"));
+    }
+
+    #[test]
+    fn test_collector_soft_deadline() {
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let status_code_field = schema.get_field("status_code").unwrap();
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        for status_code in 0..10u64 {
+            index_writer
+                .add_document(doc!(status_code_field => status_code))
+                .unwrap();
+        }
+        index_writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        let collector = make_test_collector(&SearchRequest {
+            max_hits: 3,
+            sort_by_field: Some("-status_code".to_string()),
+            ..Default::default()
+        });
+        let search = |soft_deadline: Instant| {
+            let collector = QuickwitCollector {
+                soft_deadline_opt: Some(soft_deadline),
+                ..collector.clone()
+            };
+            searcher.search(&AllQuery, &collector).unwrap()
+        };
+        let status_codes = |leaf_response: &LeafSearchResponse| -> Vec<u64> {
+            leaf_response
+                .partial_hits
                 .iter()
-                .filter_map(|leaf_response| {
-                    leaf_response.intermediate_aggregation_result.as_ref().map(
-                        |intermediate_aggregation_result| {
-                            postcard::from_bytes(intermediate_aggregation_result.as_slice())
-                                .map_err(map_error)
+                .map(|partial_hit| partial_hit.sorting_field_value)
+                .collect()
+        };
+
+        let leaf_response = search(Instant::now() + Duration::from_secs(3_600));
+        assert!(!leaf_response.timed_out);
+        assert!(!leaf_response.num_hits_is_lower_bound);
+        assert_eq!(leaf_response.num_hits, 10);
+        assert_eq!(status_codes(&leaf_response), vec![9, 8, 7]);
+
+        // The deadline passed before the collection started.
+        let leaf_response = search(Instant::now());
+        assert!(leaf_response.timed_out);
+        assert!(leaf_response.num_hits_is_lower_bound);
+        assert_eq!(leaf_response.num_hits, 0);
+        assert!(leaf_response.partial_hits.is_empty());
+    }
+
+    #[test]
+    fn test_collector_builder() {
+        let collector = QuickwitCollector::builder().build().unwrap();
+        assert!(matches!(collector.sort_by[..], [SortBy::DocId]));
+        assert_eq!(collector.max_hits, 0);
+        assert!(!collector.requires_scoring());
+
+        let collector = QuickwitCollector::builder()
+            .split_id("split1".to_string())
+            .start_offset(5)
+            .max_hits(10)
+            .sort_by(vec![
+                SortBy::Score {
+                    order: SortOrder::Desc,
+                },
+                SortBy::FastField {
+                    field_name: "status_code".to_string(),
+                    order: SortOrder::Asc,
+                    missing: MissingValue::Last,
+                    aggregate: SortValueAggregate::First,
+                },
+            ])
+            .min_score(Some(1.0))
+            .build()
+            .unwrap();
+        assert_eq!(collector.split_id, "split1");
+        assert_eq!(collector.start_offset, 5);
+        assert_eq!(collector.max_hits, 10);
+        assert!(collector.requires_scoring());
+        assert_warmup_info(&collector, &["status_code"], &[], true);
+
+        // An empty list of sort criteria sorts by doc id.
+        let aggregation: QuickwitAggregations = serde_json::from_value(json!({
+            "status_codes": {
+                "terms": {"field": "status_code"}
+            }
+        }))
+        .unwrap();
+        let collector = QuickwitCollector::builder()
+            .sort_by(Vec::new())
+            .aggregation(Some(aggregation))
+            .build()
+            .unwrap();
+        assert!(matches!(collector.sort_by[..], [SortBy::DocId]));
+        assert!(collector.aggregation_only);
+
+        // Without scoring, all the documents would score 0.
+        let error = QuickwitCollector::builder()
+            .min_score(Some(1.0))
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(error, SearchError::InvalidArgument(_)));
+    }
+
+    #[test]
+    fn test_merge_terms_aggregation_ordered_by_sub_aggregation() {
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let status_code_field = schema.get_field("status_code").unwrap();
+        let response_time_field = schema.get_field("response_time").unwrap();
+        let aggregation_request = json!({
+            "status_codes": {
+                "terms": {
+                    "field": "status_code",
+                    "size": 2,
+                    "order": {"total_response_time": "desc"}
+                },
+                "aggs": {
+                    "total_response_time": {"sum": {"field": "response_time"}}
+                }
+            }
+        })
+        .to_string();
+        let collector = make_test_collector(&SearchRequest {
+            aggregation_request: Some(aggregation_request),
+            ..Default::default()
+        });
+        // The status code 200 is only second in each leaf, but first overall.
+        let leaf_responses = [(500, 60.0), (404, 61.0), (503, 62.0)]
+            .into_iter()
+            .map(|(status_code, response_time)| {
+                let index = Index::create_in_ram(schema.clone());
+                let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+                for (status_code, response_time) in [(200u64, 50.0), (status_code, response_time)] {
+                    index_writer
+                        .add_document(doc!(
+                            status_code_field => status_code,
+                            response_time_field => response_time,
+                        ))
+                        .unwrap();
+                }
+                index_writer.commit().unwrap();
+                let searcher = index.reader().unwrap().searcher();
+                searcher.search(&AllQuery, &collector)
+            })
+            .collect();
+        let merged_response = collector.merge_fruits(leaf_responses).unwrap();
+        let aggregation_json = finalize_aggregation(
+            merged_response.intermediate_aggregation_result,
+            collector.aggregation.clone(),
+            None,
+        )
+        .unwrap()
+        .unwrap();
+        let aggregation: serde_json::Value = serde_json::from_str(&aggregation_json).unwrap();
+        let buckets: Vec<(f64, f64)> = aggregation["status_codes"]["buckets"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|bucket| {
+                (
+                    bucket["key"].as_f64().unwrap(),
+                    bucket["total_response_time"]["value"].as_f64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(buckets, vec![(200.0, 150.0), (503.0, 62.0)]);
+    }
+
+    #[test]
+    fn test_collector_aggregation_only() {
+        let aggregation_request = json!({
+            "status_codes": {
+                "terms": {"field": "status_code"}
+            }
+        })
+        .to_string();
+        let collector = make_test_collector(&SearchRequest {
+            max_hits: 0,
+            aggregation_request: Some(aggregation_request.clone()),
+            ..Default::default()
+        });
+        assert!(collector.aggregation_only);
+        assert!(
+            !make_test_collector(&SearchRequest {
+                max_hits: 0,
+                sort_by_field: Some("status_code".to_string()),
+                aggregation_request: Some(aggregation_request.clone()),
+                ..Default::default()
+            })
+            .aggregation_only
+        );
+        assert!(
+            !make_test_collector(&SearchRequest {
+                max_hits: 10,
+                aggregation_request: Some(aggregation_request),
+                ..Default::default()
+            })
+            .aggregation_only
+        );
+
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let status_code_field = schema.get_field("status_code").unwrap();
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        for status_code in [200u64, 200, 404, 500, 200] {
+            index_writer
+                .add_document(doc!(status_code_field => status_code))
+                .unwrap();
+        }
+        index_writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+
+        let leaf_response = searcher.search(&AllQuery, &collector).unwrap();
+        let expected_leaf_response = searcher
+            .search(
+                &AllQuery,
+                &QuickwitCollector {
+                    aggregation_only: false,
+                    ..collector.clone()
+                },
+            )
+            .unwrap();
+        assert_eq!(leaf_response.num_hits, 5);
+        assert!(leaf_response.partial_hits.is_empty());
+        assert!(leaf_response.intermediate_aggregation_result.is_some());
+        assert_eq!(
+            leaf_response.intermediate_aggregation_result,
+            expected_leaf_response.intermediate_aggregation_result
+        );
+    }
+
+    #[test]
+    fn test_merge_fruits_aggregation_only_skips_hits() {
+        let aggregation_request = json!({
+            "status_codes": {
+                "terms": {"field": "status_code"}
+            }
+        })
+        .to_string();
+        let aggregation_only_collector = make_test_collector(&SearchRequest {
+            max_hits: 0,
+            aggregation_request: Some(aggregation_request.clone()),
+            ..Default::default()
+        });
+        assert!(aggregation_only_collector.aggregation_only);
+        let collector = make_test_collector(&SearchRequest {
+            max_hits: 10,
+            aggregation_request: Some(aggregation_request),
+            ..Default::default()
+        });
+
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let status_code_field = schema.get_field("status_code").unwrap();
+        let make_searcher = |status_codes: &[u64]| {
+            let index = Index::create_in_ram(schema.clone());
+            let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+            for &status_code in status_codes {
+                index_writer
+                    .add_document(doc!(status_code_field => status_code))
+                    .unwrap();
+            }
+            index_writer.commit().unwrap();
+            index.reader().unwrap().searcher()
+        };
+        let searchers = [make_searcher(&[200, 200, 404]), make_searcher(&[500, 200])];
+        // The leaves return hits, which the aggregation-only merge neither sorts nor keeps.
+        let leaf_responses: Vec<LeafSearchResponse> = searchers
+            .iter()
+            .map(|searcher| searcher.search(&AllQuery, &collector).unwrap())
+            .collect();
+        assert!(leaf_responses
+            .iter()
+            .all(|leaf_response| !leaf_response.partial_hits.is_empty()));
+        let merged_response = aggregation_only_collector
+            .merge_fruits(leaf_responses.iter().cloned().map(Ok).collect())
+            .unwrap();
+        let expected_merged_response = collector
+            .merge_fruits(leaf_responses.into_iter().map(Ok).collect())
+            .unwrap();
+        assert_eq!(merged_response.num_hits, 5);
+        assert!(merged_response.partial_hits.is_empty());
+        assert_eq!(expected_merged_response.partial_hits.len(), 5);
+        assert_eq!(
+            merged_response.intermediate_aggregation_result,
+            expected_merged_response.intermediate_aggregation_result
+        );
+    }
+
+    #[test]
+    fn test_collector_hit_heap_capacity_clamped_to_segment_size() {
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let status_code_field = schema.get_field("status_code").unwrap();
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        for status_code in [200u64, 404, 500, 201, 503] {
+            index_writer
+                .add_document(doc!(status_code_field => status_code))
+                .unwrap();
+        }
+        index_writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        let segment_reader = searcher.segment_reader(0);
+
+        let collector = make_test_collector(&SearchRequest {
+            max_hits: 10,
+            start_offset: 1_000_000,
+            sort_by_field: Some("status_code".to_string()),
+            ..Default::default()
+        });
+        let segment_collector = collector.for_segment(0, segment_reader).unwrap();
+        assert!(segment_collector.hits.capacity() < 1_000);
+        assert_eq!(segment_collector.max_hits, 1_000_010);
+
+        // The clamp only bounds the allocation: the segment yields the same hits as with a
+        // request whose heap capacity does not need clamping.
+        let leaf_response = searcher.search(&AllQuery, &collector).unwrap();
+        let unclamped_leaf_response = searcher
+            .search(
+                &AllQuery,
+                &make_test_collector(&SearchRequest {
+                    max_hits: 5,
+                    sort_by_field: Some("status_code".to_string()),
+                    ..Default::default()
+                }),
+            )
+            .unwrap();
+        assert_eq!(leaf_response.num_hits, 5);
+        assert_eq!(leaf_response.partial_hits.len(), 5);
+        assert_eq!(leaf_response, unclamped_leaf_response);
+    }
+
+    #[test]
+    fn test_aggregations_check_bucket_limit() {
+        let aggregations: QuickwitAggregations = serde_json::from_value(json!({
+            "colors": {"terms": {"field": "color"}},
+            "price_ranges": {
+                "range": {
+                    "field": "price",
+                    "ranges": [{"to": 10.0}, {"from": 10.0, "to": 100.0}, {"from": 100.0}]
+                },
+                "aggs": {
+                    "price_histogram": {
+                        "histogram": {
+                            "field": "price",
+                            "interval": 1.0,
+                            "hard_bounds": {"min": 0.0, "max": 99.0}
                         },
-                    )
-                })
-                .collect::<Result<_, _>>()?;
-            let merged_fruit = collector.merge_fruits(fruits)?;
-            let serialized = postcard::to_allocvec(&merged_fruit).map_err(map_error)?;
-            Some(serialized)
+                        "aggs": {
+                            "price_percentiles": {"percentiles": {"field": "price"}}
+                        }
+                    }
+                }
+            }
+        }))
+        .unwrap();
+        // 1 terms bucket + 3 range buckets + 3 * 100 histogram buckets.
+        aggregations.check_bucket_limit(304).unwrap();
+        let SearchError::InvalidAggregationRequest(message) =
+            aggregations.check_bucket_limit(303).unwrap_err()
+        else {
+            panic!("expected an invalid aggregation request");
+        };
+        assert!(
+            message.contains("`price_ranges.price_histogram`"),
+            "{message}"
+        );
+        let SearchError::InvalidAggregationRequest(message) =
+            aggregations.check_bucket_limit(3).unwrap_err()
+        else {
+            panic!("expected an invalid aggregation request");
+        };
+        assert!(message.contains("`price_ranges`"), "{message}");
+
+        let metric_aggregations: QuickwitAggregations = serde_json::from_value(json!({
+            "price_percentiles": {"percentiles": {"field": "price"}}
+        }))
+        .unwrap();
+        metric_aggregations.check_bucket_limit(0).unwrap();
+    }
+
+    #[test]
+    fn test_aggregations_validate_fields() {
+        let doc_mapper: DefaultDocMapper = serde_json::from_value(json!({
+            "field_mappings": [
+                {"name": "body", "type": "text"},
+                {"name": "service", "type": "text", "tokenizer": "raw", "fast": true},
+                {"name": "status_code", "type": "u64", "fast": true},
+            ]
+        }))
+        .unwrap();
+        let schema = doc_mapper.schema();
+        let validate_fields = |aggregations: serde_json::Value| -> Result<(), String> {
+            let aggregations: QuickwitAggregations = serde_json::from_value(aggregations).unwrap();
+            match aggregations.validate_fields(&schema) {
+                Ok(()) => Ok(()),
+                Err(SearchError::InvalidAggregationRequest(message)) => Err(message),
+                Err(error) => panic!("unexpected error {error:?}"),
+            }
+        };
+        validate_fields(json!({
+            "services": {
+                "terms": {"field": "service"},
+                "aggs": {"max_status_code": {"max": {"field": "status_code"}}}
+            }
+        }))
+        .unwrap();
+
+        let message = validate_fields(json!({
+            "services": {
+                "terms": {"field": "service"},
+                "aggs": {"avg_latency": {"avg": {"field": "latency"}}}
+            }
+        }))
+        .unwrap_err();
+        assert_eq!(
+            message,
+            "aggregation `services.avg_latency` references field `latency`, which does not exist"
+        );
+
+        let message = validate_fields(json!({
+            "bodies": {"terms": {"field": "body"}}
+        }))
+        .unwrap_err();
+        assert_eq!(
+            message,
+            "aggregation `bodies` references field `body`, which is not a fast field"
+        );
+
+        let message = validate_fields(json!({
+            "status_codes": {
+                "terms": {"field": "status_code"},
+                "aggs": {"services": {"stats": {"field": "service"}}}
+            }
+        }))
+        .unwrap_err();
+        assert!(
+            message.starts_with(
+                "aggregation `status_codes.services` requires a numerical or datetime field, but \
+                 field `service` is of type"
+            ),
+            "{message}"
+        );
+
+        // Dynamic fields are only known per split.
+        let dynamic_doc_mapper: DefaultDocMapper = serde_json::from_value(json!({
+            "mode": "dynamic",
+            "field_mappings": []
+        }))
+        .unwrap();
+        let aggregations: QuickwitAggregations = serde_json::from_value(json!({
+            "avg_latency": {"avg": {"field": "latency"}}
+        }))
+        .unwrap();
+        aggregations
+            .validate_fields(&dynamic_doc_mapper.schema())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_collector_rejects_conflicting_fast_field_column_types() {
+        let build_collector = |sort_by: Vec<SortBy>, aggregation: QuickwitAggregations| {
+            QuickwitCollector::builder()
+                .max_hits(10)
+                .sort_by(sort_by)
+                .aggregation(Some(aggregation))
+                .build()
+        };
+        let sort_by_field = |field_name: &str, aggregate: SortValueAggregate| SortBy::FastField {
+            field_name: field_name.to_string(),
+            order: SortOrder::Desc,
+            missing: MissingValue::default(),
+            aggregate,
+        };
+        let tantivy_aggregations = |aggregations: serde_json::Value| -> QuickwitAggregations {
+            serde_json::from_value(aggregations).unwrap()
+        };
+        let expect_invalid_argument = |result: crate::Result<QuickwitCollector>| -> String {
+            match result {
+                Err(SearchError::InvalidArgument(message)) => message,
+                Err(error) => panic!("unexpected error {error:?}"),
+                Ok(_) => panic!("the collector should be rejected"),
+            }
+        };
+
+        // Sorting by a field that an aggregation reads as text.
+        let find_trace_ids = QuickwitAggregations::FindTraceIdsAggregation(FindTraceIdsCollector {
+            num_traces: 10,
+            trace_id_field_name: "trace_id".to_string(),
+            span_timestamp_field_name: "span_start_timestamp".to_string(),
+            service_name_field: None,
+            service_name: None,
+        });
+        let message = expect_invalid_argument(build_collector(
+            vec![sort_by_field("trace_id", SortValueAggregate::First)],
+            find_trace_ids.clone(),
+        ));
+        assert_eq!(
+            message,
+            "field `trace_id` is sorted as a numeric, boolean or datetime field, but the find \
+             trace ids aggregation reads it as a text field"
+        );
+        build_collector(
+            vec![sort_by_field(
+                "span_start_timestamp",
+                SortValueAggregate::First,
+            )],
+            find_trace_ids,
+        )
+        .unwrap();
+
+        // Summing the values of a field that a sub-aggregation buckets by date.
+        let date_histogram = tantivy_aggregations(json!({
+            "status_codes": {
+                "terms": {"field": "status_code"},
+                "aggs": {
+                    "per_day": {
+                        "date_histogram": {"field": "timestamp", "fixed_interval": "1d"}
+                    }
+                }
+            }
+        }));
+        let message = expect_invalid_argument(build_collector(
+            vec![
+                SortBy::Score {
+                    order: SortOrder::Desc,
+                },
+                sort_by_field("timestamp", SortValueAggregate::Sum),
+            ],
+            date_histogram.clone(),
+        ));
+        assert_eq!(
+            message,
+            "field `timestamp` is sorted as a numeric field, but aggregation \
+             `status_codes.per_day` reads it as a datetime field"
+        );
+        build_collector(
+            vec![sort_by_field("timestamp", SortValueAggregate::Max)],
+            date_histogram.clone(),
+        )
+        .unwrap();
+        // `terms` aggregations read any column type.
+        build_collector(
+            vec![sort_by_field("status_code", SortValueAggregate::Sum)],
+            date_histogram,
+        )
+        .unwrap();
+
+        // The coordinates of the geo distance are read as floats.
+        let message = expect_invalid_argument(build_collector(
+            vec![SortBy::GeoDistance {
+                lat_field: "lat".to_string(),
+                lon_field: "lon".to_string(),
+                center: GeoPoint { lat: 0.0, lon: 0.0 },
+                order: SortOrder::Asc,
+                missing: MissingValue::default(),
+            }],
+            tantivy_aggregations(json!({
+                "per_lon": {"date_histogram": {"field": "lon", "fixed_interval": "1d"}}
+            })),
+        ));
+        assert_eq!(
+            message,
+            "field `lon` is sorted as a float field, but aggregation `per_lon` reads it as a \
+             datetime field"
+        );
+    }
+
+    #[test]
+    fn test_collector_sort_by_score_with_boost() {
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let body_field = schema.get_field("body").unwrap();
+        let timestamp_field = schema.get_field("timestamp").unwrap();
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        // Both documents get the same BM25 score.
+        for timestamp_secs in [1_000, 2_000_000] {
+            index_writer
+                .add_document(doc!(
+                    body_field => "log",
+                    timestamp_field => DateTime::from_timestamp_secs(timestamp_secs),
+                ))
+                .unwrap();
+        }
+        index_writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        let query = TermQuery::new(
+            Term::from_field_text(body_field, "log"),
+            IndexRecordOption::WithFreqs,
+        );
+        let search = |score_boost_opt: Option<ScoreBoost>| -> Vec<u32> {
+            let collector = make_test_collector(&SearchRequest {
+                max_hits: 2,
+                sort_by_field: Some("_score".to_string()),
+                score_boost: score_boost_opt,
+                ..Default::default()
+            });
+            searcher
+                .search(&query, &collector)
+                .unwrap()
+                .partial_hits
+                .iter()
+                .map(|partial_hit| partial_hit.doc_id)
+                .collect()
+        };
+        // Ties are broken by doc id.
+        assert_eq!(search(None), vec![0, 1]);
+        let score_boost = ScoreBoost {
+            field_name: "timestamp".to_string(),
+            factor: 0.1,
+        };
+        assert_eq!(search(Some(score_boost.clone())), vec![1, 0]);
+
+        let error = make_collector_for_split(
+            "split1".to_string(),
+            &make_test_doc_mapper(),
+            &SearchRequest {
+                sort_by_field: Some("status_code".to_string()),
+                score_boost: Some(score_boost),
+                ..Default::default()
+            },
+            AggregationLimits::default(),
+            &SearcherConfig::default(),
+            None,
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(error, SearchError::InvalidArgument(_)));
+    }
+
+    #[test]
+    fn test_collector_rejects_sorting_by_non_fast_field() {
+        let make_collector = |search_request: SearchRequest| {
+            make_collector_for_split(
+                "split1".to_string(),
+                &make_test_doc_mapper(),
+                &search_request,
+                AggregationLimits::default(),
+                &SearcherConfig::default(),
+                None,
+            )
+        };
+        let sort_error = |search_request: SearchRequest| -> String {
+            match make_collector(search_request).err().unwrap() {
+                SearchError::InvalidArgument(error_message) => error_message,
+                error => panic!("expected an invalid argument error, got `{error}`"),
+            }
+        };
+        let expected_error = "cannot sort by field `body`, which is not a fast field";
+        assert_eq!(
+            sort_error(SearchRequest {
+                sort_by_field: Some("body".to_string()),
+                ..Default::default()
+            }),
+            expected_error
+        );
+        assert_eq!(
+            sort_error(SearchRequest {
+                sort_by_field: Some("-status_code,body".to_string()),
+                ..Default::default()
+            }),
+            expected_error
+        );
+        assert_eq!(
+            sort_error(SearchRequest {
+                sort_by_field: Some("_score".to_string()),
+                score_boost: Some(ScoreBoost {
+                    field_name: "body".to_string(),
+                    factor: 0.1,
+                }),
+                ..Default::default()
+            }),
+            expected_error
+        );
+
+        // Sorting by doc id or score reads no field, and fields absent from the schema may be
+        // captured by the dynamic field.
+        assert!(make_collector(SearchRequest::default()).is_ok());
+        for sort_by_field in ["_score", "status_code", "missing_field"] {
+            assert!(make_collector(SearchRequest {
+                sort_by_field: Some(sort_by_field.to_string()),
+                ..Default::default()
+            })
+            .is_ok());
+        }
+    }
+
+    #[test]
+    fn test_collector_sort_by_field_exists() {
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let status_code_field = schema.get_field("status_code").unwrap();
+        let timestamp_field = schema.get_field("timestamp").unwrap();
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        for (timestamp_secs, status_code_opt) in [
+            (1, Some(500)),
+            (2, None),
+            (3, Some(0)),
+            (4, None),
+            (5, Some(404)),
+        ] {
+            let mut doc = doc!(timestamp_field => DateTime::from_timestamp_secs(timestamp_secs));
+            if let Some(status_code) = status_code_opt {
+                doc.add_u64(status_code_field, status_code);
+            }
+            index_writer.add_document(doc).unwrap();
+        }
+        index_writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        let search = |sort_by_field: &str, sort_order: SortOrder| -> Vec<u32> {
+            let collector = make_test_collector(&SearchRequest {
+                max_hits: 5,
+                sort_by_field: Some(sort_by_field.to_string()),
+                sort_order: Some(sort_order as i32),
+                ..Default::default()
+            });
+            searcher
+                .search(&AllQuery, &collector)
+                .unwrap()
+                .partial_hits
+                .iter()
+                .map(|partial_hit| partial_hit.doc_id)
+                .collect()
+        };
+        // The documents with a status code come first, whatever the value, then the most
+        // recent documents.
+        assert_eq!(
+            search("_exists:status_code,-timestamp", SortOrder::Desc),
+            vec![4, 2, 0, 3, 1]
+        );
+        assert_eq!(
+            search("_exists:status_code,-timestamp", SortOrder::Asc),
+            vec![3, 1, 4, 2, 0]
+        );
+        assert_eq!(
+            search("-_exists:status_code,timestamp", SortOrder::Asc),
+            vec![0, 2, 4, 1, 3]
+        );
+
+        let collector = make_test_collector(&SearchRequest {
+            sort_by_field: Some("-_exists:status_code,_score".to_string()),
+            ..Default::default()
+        });
+        assert!(matches!(
+            &collector.sort_by[..],
+            [
+                SortBy::FieldExists { field_name, order: SortOrder::Desc },
+                SortBy::Score { order: SortOrder::Desc },
+            ] if field_name == "status_code"
+        ));
+        assert_warmup_info(&collector, &["status_code"], &[], true);
+    }
+
+    #[test]
+    fn test_collector_sort_by_multivalued_field_aggregate() {
+        let doc_mapper: DefaultDocMapper = serde_json::from_value(json!({
+            "field_mappings": [
+                {"name": "scores", "type": "array<i64>", "fast": true},
+            ]
+        }))
+        .unwrap();
+        let schema = doc_mapper.schema();
+        let scores_field = schema.get_field("scores").unwrap();
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        for scores in [vec![5i64, -3], vec![0], vec![4, 7, -10], Vec::new()] {
+            let mut doc = Document::default();
+            for score in scores {
+                doc.add_i64(scores_field, score);
+            }
+            index_writer.add_document(doc).unwrap();
+        }
+        index_writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        let search = |sort_by_field: &str| -> Vec<(u32, String)> {
+            let collector = make_collector_for_split(
+                "split1".to_string(),
+                &doc_mapper,
+                &SearchRequest {
+                    max_hits: 10,
+                    sort_by_field: Some(sort_by_field.to_string()),
+                    sort_order: Some(SortOrder::Desc as i32),
+                    return_sort_values: true,
+                    ..Default::default()
+                },
+                AggregationLimits::default(),
+                &SearcherConfig::default(),
+                None,
+            )
+            .unwrap();
+            searcher
+                .search(&AllQuery, &collector)
+                .unwrap()
+                .partial_hits
+                .into_iter()
+                .map(|partial_hit| (partial_hit.doc_id, partial_hit.sort_values[0].clone()))
+                .collect()
+        };
+        let to_hits = |hits: &[(u32, &str)]| -> Vec<(u32, String)> {
+            hits.iter()
+                .map(|(doc_id, sort_value)| (*doc_id, sort_value.to_string()))
+                .collect()
+        };
+        // The document without a value comes last whatever the aggregate.
+        assert_eq!(
+            search("scores"),
+            to_hits(&[(0, "5"), (2, "4"), (1, "0"), (3, "null")])
+        );
+        assert_eq!(search("scores:first"), search("scores"));
+        assert_eq!(
+            search("scores:min"),
+            to_hits(&[(1, "0"), (0, "-3"), (2, "-10"), (3, "null")])
+        );
+        assert_eq!(
+            search("scores:max"),
+            to_hits(&[(2, "7"), (0, "5"), (1, "0"), (3, "null")])
+        );
+        assert_eq!(
+            search("scores:sum"),
+            to_hits(&[(0, "2"), (2, "1"), (1, "0"), (3, "null")])
+        );
+        assert_eq!(
+            search("+scores:max"),
+            to_hits(&[(1, "0"), (0, "5"), (2, "7"), (3, "null")])
+        );
+
+        for invalid_sort_by_field in ["scores:median", "_score:max"] {
+            let error = parse_sort_by_field(
+                invalid_sort_by_field,
+                Some(SortOrder::Desc),
+                MissingValue::default(),
+                None,
+            )
+            .unwrap_err();
+            assert!(
+                matches!(error, SearchError::InvalidArgument(_)),
+                "{invalid_sort_by_field}: {error}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_collector_collection_stats() {
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let status_code_field = schema.get_field("status_code").unwrap();
+        let timestamp_field = schema.get_field("timestamp").unwrap();
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        for doc_ord in 0..10u64 {
+            let status_code = if doc_ord % 3 == 0 { 500 } else { 200 };
+            index_writer
+                .add_document(doc!(
+                    timestamp_field => DateTime::from_timestamp_secs(100 + doc_ord as i64),
+                    status_code_field => status_code,
+                ))
+                .unwrap();
+        }
+        index_writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        let collector = make_test_collector(&SearchRequest {
+            max_hits: 2,
+            sort_by_field: Some("timestamp".to_string()),
+            sort_order: Some(SortOrder::Asc as i32),
+            start_timestamp: Some(103),
+            fast_field_equals: Some(FastFieldEquals {
+                field_name: "status_code".to_string(),
+                value: "200".to_string(),
+            }),
+            ..Default::default()
+        });
+        let leaf_response = searcher.search(&AllQuery, &collector).unwrap();
+        let collection_stats = leaf_response.collection_stats.clone().unwrap();
+        // The documents 0, 3, 6 and 9 have the wrong status code, and the documents 1 and 2
+        // are out of the time range. Only the first two accepted documents enter the top-K,
+        // the following ones sorting after them.
+        assert_eq!(
+            collection_stats,
+            CollectionStats {
+                num_docs_visited: 10,
+                num_docs_rejected: 6,
+                num_docs_rejected_by_timestamp_filter: 2,
+                num_docs_accepted: 4,
+                num_docs_entered_top_k: 2,
+            }
+        );
+        assert_eq!(
+            collection_stats.num_docs_rejected + collection_stats.num_docs_accepted,
+            searcher.search(&AllQuery, &Count).unwrap() as u64
+        );
+        assert_eq!(leaf_response.num_hits, collection_stats.num_docs_accepted);
+
+        // The statistics of the merged responses are summed.
+        let merged_response = collector
+            .merge_fruits(vec![Ok(leaf_response.clone()), Ok(leaf_response)])
+            .unwrap();
+        let merged_collection_stats = merged_response.collection_stats.unwrap();
+        assert_eq!(merged_collection_stats.num_docs_visited, 20);
+        assert_eq!(merged_collection_stats.num_docs_rejected, 12);
+        assert_eq!(
+            merged_collection_stats.num_docs_rejected_by_timestamp_filter,
+            4
+        );
+        assert_eq!(merged_collection_stats.num_docs_accepted, 8);
+        assert_eq!(merged_collection_stats.num_docs_entered_top_k, 4);
+    }
+
+    #[test]
+    fn test_collector_timestamp_filter_per_segment_overlap() {
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let timestamp_field = schema.get_field("timestamp").unwrap();
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        // The segments are respectively inside, outside and across the time range [100, 200).
+        for timestamps_secs in [100..110, 300..310, 190..210] {
+            for timestamp_secs in timestamps_secs {
+                index_writer
+                    .add_document(doc!(
+                        timestamp_field => DateTime::from_timestamp_secs(timestamp_secs),
+                    ))
+                    .unwrap();
+            }
+            index_writer.commit().unwrap();
+        }
+        let searcher = index.reader().unwrap().searcher();
+        assert_eq!(searcher.segment_readers().len(), 3);
+        let collector = make_test_collector(&SearchRequest {
+            max_hits: 100,
+            start_timestamp: Some(100),
+            end_timestamp: Some(200),
+            ..Default::default()
+        });
+        let timestamp_filters: Vec<Option<TimestampFilter>> = searcher
+            .segment_readers()
+            .iter()
+            .map(|segment_reader| {
+                collector
+                    .timestamp_filter_builder_opt
+                    .as_ref()
+                    .unwrap()
+                    .build(segment_reader)
+                    .unwrap()
+            })
+            .collect();
+        assert!(matches!(
+            &timestamp_filters[..],
+            [
+                None,
+                Some(TimestampFilter::NoMatch),
+                Some(TimestampFilter::Range { .. })
+            ]
+        ));
+
+        // The documents within the range, checked one by one.
+        let naive_num_hits = searcher
+            .segment_readers()
+            .iter()
+            .map(|segment_reader| {
+                let timestamp_column = segment_reader.fast_fields().date("timestamp").unwrap();
+                (0..segment_reader.max_doc())
+                    .filter(|&doc_id| {
+                        let timestamp = timestamp_column.first(doc_id).unwrap();
+                        (DateTime::from_timestamp_secs(100)..DateTime::from_timestamp_secs(200))
+                            .contains(&timestamp)
+                    })
+                    .count() as u64
+            })
+            .sum::<u64>();
+        assert_eq!(naive_num_hits, 20);
+        let leaf_response = searcher.search(&AllQuery, &collector).unwrap();
+        assert_eq!(leaf_response.num_hits, naive_num_hits);
+        assert_eq!(leaf_response.partial_hits.len(), 20);
+        // The segment outside the range is skipped without visiting its documents, and only
+        // the documents of the segment across the range are checked one by one.
+        assert_eq!(
+            leaf_response.collection_stats.unwrap(),
+            CollectionStats {
+                num_docs_visited: 30,
+                num_docs_rejected: 10,
+                num_docs_rejected_by_timestamp_filter: 10,
+                num_docs_accepted: 20,
+                num_docs_entered_top_k: 20,
+            }
+        );
+    }
+
+    #[test]
+    fn test_collector_max_hits_ceiling() {
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let timestamp_field = schema.get_field("timestamp").unwrap();
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        for doc_ord in 0..10i64 {
+            index_writer
+                .add_document(doc!(
+                    timestamp_field => DateTime::from_timestamp_secs(100 + doc_ord),
+                ))
+                .unwrap();
+        }
+        index_writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        let make_request = |max_hits: u64| SearchRequest {
+            start_offset: 2,
+            max_hits,
+            sort_by_field: Some("timestamp".to_string()),
+            ..Default::default()
+        };
+        let make_collector = |searcher_config: &SearcherConfig, max_hits: u64| {
+            make_collector_for_split(
+                "split1".to_string(),
+                &doc_mapper,
+                &make_request(max_hits),
+                AggregationLimits::default(),
+                searcher_config,
+                None,
+            )
+        };
+
+        // The hits are cut at the ceiling, skipped hits included.
+        let clamp_config = SearcherConfig {
+            max_hits_ceiling: Some(5),
+            ..Default::default()
+        };
+        let collector = make_collector(&clamp_config, 8).unwrap();
+        assert_eq!(collector.max_hits, 3);
+        let leaf_response = searcher.search(&AllQuery, &collector).unwrap();
+        assert_eq!(leaf_response.num_hits, 10);
+        assert_eq!(leaf_response.partial_hits.len(), 3);
+        assert_eq!(leaf_response.clamped_max_hits, Some(3));
+
+        let collector = make_collector(&clamp_config, 3).unwrap();
+        let leaf_response = searcher.search(&AllQuery, &collector).unwrap();
+        assert_eq!(leaf_response.partial_hits.len(), 3);
+        assert_eq!(leaf_response.clamped_max_hits, None);
+
+        let reject_config = SearcherConfig {
+            max_hits_ceiling: Some(5),
+            max_hits_ceiling_mode: MaxHitsCeilingMode::Reject,
+            ..Default::default()
+        };
+        let error = make_collector(&reject_config, 8).err().unwrap();
+        let SearchError::InvalidArgument(error_message) = error else {
+            panic!("expected an invalid argument error, got `{error}`");
+        };
+        assert_eq!(
+            error_message,
+            "start_offset + max_hits (10) exceeds the max_hits_ceiling of the searcher (5)"
+        );
+        let collector = make_collector(&reject_config, 3).unwrap();
+        let leaf_response = searcher.search(&AllQuery, &collector).unwrap();
+        assert_eq!(leaf_response.partial_hits.len(), 3);
+        assert_eq!(leaf_response.clamped_max_hits, None);
+    }
+
+    #[test]
+    fn test_collector_diversify_by() {
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let status_code_field = schema.get_field("status_code").unwrap();
+        let timestamp_field = schema.get_field("timestamp").unwrap();
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        // The status code 200 dominates the documents.
+        for (doc_ord, status_code) in [200u64, 200, 200, 200, 200, 200, 200, 200, 500, 404]
+            .into_iter()
+            .enumerate()
+        {
+            index_writer
+                .add_document(doc!(
+                    timestamp_field => DateTime::from_timestamp_secs(100 + doc_ord as i64),
+                    status_code_field => status_code,
+                ))
+                .unwrap();
         }
-        Some(QuickwitAggregations::TantivyAggregations(_)) => {
-            let fruits: Vec<IntermediateAggregationResults> = leaf_responses
+        index_writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        let search_request = SearchRequest {
+            max_hits: 5,
+            sort_by_field: Some("timestamp".to_string()),
+            diversify_by: Some(DiversifyBy {
+                field_name: "status_code".to_string(),
+                max_hits_per_group: 2,
+            }),
+            ..Default::default()
+        };
+        let make_collector = |split_id: &str| {
+            make_collector_for_split(
+                split_id.to_string(),
+                &doc_mapper,
+                &search_request,
+                AggregationLimits::default(),
+                &SearcherConfig::default(),
+                None,
+            )
+            .unwrap()
+        };
+        let num_hits_per_group = |partial_hits: &[PartialHit]| -> HashMap<String, usize> {
+            partial_hits
                 .iter()
-                .filter_map(|leaf_response| {
-                    leaf_response.intermediate_aggregation_result.as_ref().map(
-                        |intermediate_aggregation_result| {
-                            postcard::from_bytes(intermediate_aggregation_result.as_slice())
-                                .map_err(map_error)
-                        },
-                    )
-                })
-                .collect::<Result<_, _>>()?;
+                .map(|partial_hit| partial_hit.collapse_key.clone().unwrap())
+                .counts()
+        };
+        let collector = make_collector("split1");
+        assert!(collector.fast_field_names().contains("status_code"));
+        let leaf_response = searcher.search(&AllQuery, &collector).unwrap();
+        assert_eq!(leaf_response.num_hits, 10);
+        assert_eq!(
+            num_hits_per_group(&leaf_response.partial_hits),
+            HashMap::from_iter([
+                ("200".to_string(), 2),
+                ("404".to_string(), 1),
+                ("500".to_string(), 1),
+            ])
+        );
 
-            let mut fruit_iter = fruits.into_iter();
-            if let Some(first_fruit) = fruit_iter.next() {
-                let mut merged_fruit = first_fruit;
-                for fruit in fruit_iter {
-                    merged_fruit.merge_fruits(fruit)?;
-                }
-                let serialized = postcard::to_allocvec(&merged_fruit).map_err(map_error)?;
+        // The merge caps the groups found in several leaves again.
+        let other_leaf_response = searcher
+            .search(&AllQuery, &make_collector("split2"))
+            .unwrap();
+        let merged_response = collector
+            .merge_fruits(vec![Ok(leaf_response), Ok(other_leaf_response)])
+            .unwrap();
+        assert_eq!(merged_response.num_hits, 20);
+        assert_eq!(merged_response.partial_hits.len(), 5);
+        assert!(num_hits_per_group(&merged_response.partial_hits)
+            .values()
+            .all(|num_hits| *num_hits <= 2));
 
-                Some(serialized)
-            } else {
-                None
+        let error = QuickwitCollector::builder()
+            .collapse_field(Some("status_code".to_string()))
+            .diversify_by(Some(("status_code".to_string(), 2)))
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(error, SearchError::InvalidArgument(_)));
+        let error = QuickwitCollector::builder()
+            .diversify_by(Some(("status_code".to_string(), 0)))
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(error, SearchError::InvalidArgument(_)));
+    }
+
+    #[test]
+    fn test_collector_collect_doc_id_sets() {
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let body_field = schema.get_field("body").unwrap();
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        // Two segments, so that the doc id sets are per segment.
+        for segment_ord in 0..2u32 {
+            for doc_ord in 0..1_000u32 {
+                let body = if (doc_ord + segment_ord) % 3 == 0 {
+                    "error"
+                } else {
+                    "info"
+                };
+                index_writer.add_document(doc!(body_field => body)).unwrap();
             }
+            index_writer.commit().unwrap();
         }
-        None => None,
-    };
-    let num_attempted_splits = leaf_responses
-        .iter()
-        .map(|leaf_response| leaf_response.num_attempted_splits)
-        .sum();
-    let num_hits: u64 = leaf_responses
-        .iter()
-        .map(|leaf_response| leaf_response.num_hits)
-        .sum();
-    let failed_splits = leaf_responses
-        .iter()
-        .flat_map(|leaf_response| leaf_response.failed_splits.iter())
-        .cloned()
-        .collect_vec();
-    let all_partial_hits: Vec<PartialHit> = leaf_responses
-        .into_iter()
-        .flat_map(|leaf_response| leaf_response.partial_hits)
-        .collect();
-    // TODO optimize
-    let top_k_partial_hits = top_k_partial_hits(all_partial_hits, max_hits);
-    Ok(LeafSearchResponse {
-        intermediate_aggregation_result: merged_intermediate_aggregation_result,
-        num_hits,
-        partial_hits: top_k_partial_hits,
-        failed_splits,
-        num_attempted_splits,
-    })
-}
+        let searcher = index.reader().unwrap().searcher();
+        assert_eq!(searcher.segment_readers().len(), 2);
+        let query = TermQuery::new(
+            Term::from_field_text(body_field, "error"),
+            IndexRecordOption::Basic,
+        );
+        let collector = make_test_collector(&SearchRequest {
+            max_hits: 10,
+            collect_doc_id_sets: true,
+            ..Default::default()
+        });
+        let leaf_response = searcher.search(&query, &collector).unwrap();
+        assert!(leaf_response.partial_hits.is_empty());
+        assert_eq!(leaf_response.doc_id_sets.len(), 2);
 
-/// Mutates partial_hits so that it contains the top-num_hitso hits,
-/// and so that these elements are sorted.
-///
-/// TODO we could possibly optimize the sort away (but I doubt it matters).
-fn top_k_partial_hits(mut partial_hits: Vec<PartialHit>, num_hits: usize) -> Vec<PartialHit> {
-    partial_hits.sort_unstable_by(|left, right| {
-        let left_key = partial_hit_sorting_key(left);
-        let right_key = partial_hit_sorting_key(right);
-        left_key.cmp(&right_key)
-    });
-    partial_hits.truncate(num_hits);
-    partial_hits
-}
+        let doc_addresses: HashSet<DocAddress> = leaf_response
+            .doc_id_sets
+            .iter()
+            .flat_map(|segment_doc_id_set| {
+                assert_eq!(segment_doc_id_set.split_id, collector.split_id);
+                let doc_id_set = DocIdSet::from_bytes(&segment_doc_id_set.doc_ids).unwrap();
+                let segment_ord = segment_doc_id_set.segment_ord;
+                doc_id_set
+                    .iter()
+                    .map(move |doc_id| DocAddress::new(segment_ord, doc_id))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let expected_doc_addresses = searcher.search(&query, &DocSetCollector).unwrap();
+        assert_eq!(doc_addresses, expected_doc_addresses);
+        assert_eq!(leaf_response.num_hits, expected_doc_addresses.len() as u64);
+    }
 
-/// Builds the QuickwitCollector, in function of the information that was requested by the user.
-pub(crate) fn make_collector_for_split(
-    split_id: String,
-    doc_mapper: &dyn DocMapper,
-    search_request: &SearchRequest,
-    aggregation_limits: AggregationLimits,
-) -> crate::Result<QuickwitCollector> {
-    let aggregation = match &search_request.aggregation_request {
-        Some(aggregation) => Some(serde_json::from_str(aggregation)?),
-        None => None,
-    };
-    let timestamp_filter_builder_opt = create_timestamp_filter_builder(
-        doc_mapper.timestamp_field_name(),
-        search_request.start_timestamp,
-        search_request.end_timestamp,
-    );
-    let sort_order = search_request
-        .sort_order
-        .and_then(SortOrder::from_i32)
-        .unwrap_or(SortOrder::Desc);
-    let sort_by = search_request
-        .sort_by_field
-        .as_ref()
-        .map(|field_name| {
-            if field_name == "_score" {
-                SortBy::Score { order: sort_order }
-            } else {
-                SortBy::FastField {
-                    field_name: field_name.clone(),
-                    order: sort_order,
-                }
+    #[test]
+    fn test_collector_hash_partition() {
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let status_code_field = schema.get_field("status_code").unwrap();
+        let timestamp_field = schema.get_field("timestamp").unwrap();
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        for status_code in 0..16u64 {
+            index_writer
+                .add_document(doc!(
+                    timestamp_field => DateTime::from_timestamp_secs(100 - status_code as i64),
+                    status_code_field => status_code,
+                ))
+                .unwrap();
+        }
+        // Without a value, the document belongs to no bucket.
+        index_writer
+            .add_document(doc!(timestamp_field => DateTime::from_timestamp_secs(0)))
+            .unwrap();
+        index_writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        let search = |bucket: u64| -> Vec<u32> {
+            let collector = make_test_collector(&SearchRequest {
+                max_hits: 20,
+                sort_by_field: Some("timestamp".to_string()),
+                sort_order: Some(SortOrder::Asc as i32),
+                hash_partition: Some(HashPartition {
+                    field_name: "status_code".to_string(),
+                    modulus: 4,
+                    bucket,
+                }),
+                ..Default::default()
+            });
+            searcher
+                .search(&AllQuery, &collector)
+                .unwrap()
+                .partial_hits
+                .iter()
+                .map(|partial_hit| partial_hit.doc_id)
+                .collect()
+        };
+        // The membership is pinned, as it must not change across process runs. The hits of the
+        // bucket are sorted by the other criteria.
+        assert_eq!(search(1), vec![12, 8, 4, 0]);
+        let mut doc_ids: Vec<u32> = Vec::new();
+        for bucket in 0..4 {
+            let bucket_doc_ids = search(bucket);
+            for _ in 0..3 {
+                assert_eq!(search(bucket), bucket_doc_ids);
             }
-        })
-        .unwrap_or(SortBy::DocId);
-
-    Ok(QuickwitCollector {
-        split_id,
-        start_offset: search_request.start_offset as usize,
-        max_hits: search_request.max_hits as usize,
-        sort_by,
-        timestamp_filter_builder_opt,
-        aggregation,
-        aggregation_limits,
-    })
-}
+            doc_ids.extend(bucket_doc_ids);
+        }
+        // The buckets partition the documents with a value.
+        doc_ids.sort();
+        assert_eq!(doc_ids, (0..16).collect::<Vec<u32>>());
 
-pub fn aggregation_limits_from_searcher_context(
-    searcher_context: &Arc<SearcherContext>,
-) -> AggregationLimits {
-    AggregationLimits::new(
-        Some(
-            searcher_context
-                .searcher_config
-                .aggregation_memory_limit
-                .get_bytes(),
-        ),
-        Some(searcher_context.searcher_config.aggregation_bucket_limit),
-    )
-}
+        let collector = make_test_collector(&SearchRequest {
+            max_hits: 20,
+            hash_partition: Some(HashPartition {
+                field_name: "status_code".to_string(),
+                modulus: 4,
+                bucket: 1,
+            }),
+            ..Default::default()
+        });
+        assert_warmup_info(&collector, &["status_code"], &[], false);
 
-/// Builds a QuickwitCollector that's only useful for merging fruits.
-///
-/// This collector only needs `start_offset` & `max_hit` so the other attributes
-/// can be set to default.
-pub(crate) fn make_merge_collector(
-    search_request: &SearchRequest,
-    searcher_context: &Arc<SearcherContext>,
-) -> crate::Result<QuickwitCollector> {
-    let aggregation = match &search_request.aggregation_request {
-        Some(aggregation) => Some(serde_json::from_str(aggregation)?),
-        None => None,
-    };
-    Ok(QuickwitCollector {
-        split_id: String::default(),
-        start_offset: search_request.start_offset as usize,
-        max_hits: search_request.max_hits as usize,
-        sort_by: SortBy::DocId,
-        timestamp_filter_builder_opt: None,
-        aggregation,
-        aggregation_limits: aggregation_limits_from_searcher_context(searcher_context),
-    })
-}
+        for (modulus, bucket) in [(4, 4), (0, 0)] {
+            let error = make_collector_for_split(
+                "split1".to_string(),
+                &doc_mapper,
+                &SearchRequest {
+                    hash_partition: Some(HashPartition {
+                        field_name: "status_code".to_string(),
+                        modulus,
+                        bucket,
+                    }),
+                    ..Default::default()
+                },
+                AggregationLimits::default(),
+                &SearcherConfig::default(),
+                None,
+            )
+            .err()
+            .unwrap();
+            assert!(matches!(error, SearchError::InvalidArgument(_)), "{error}");
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use std::cmp::Ordering;
+    #[test]
+    fn test_collector_stops_early_on_index_sorted_by_sort_field() {
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let status_code_field = schema.get_field("status_code").unwrap();
+        let timestamp_field = schema.get_field("timestamp").unwrap();
+        let make_index = |index_sort_opt: Option<IndexSortByField>| {
+            let index = Index::builder()
+                .schema(schema.clone())
+                .settings(IndexSettings {
+                    sort_by_field: index_sort_opt,
+                    ..Default::default()
+                })
+                .create_in_ram()
+                .unwrap();
+            let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+            for status_code in [3, 7, 0, 9, 5, 1, 8, 2, 6, 4] {
+                index_writer
+                    .add_document(doc!(
+                        status_code_field => status_code as u64,
+                        timestamp_field => DateTime::from_timestamp_secs(status_code),
+                    ))
+                    .unwrap();
+            }
+            index_writer.commit().unwrap();
+            index
+        };
+        let index_sort = IndexSortByField {
+            field: "status_code".to_string(),
+            order: Order::Desc,
+        };
+        let sorted_index = make_index(Some(index_sort.clone()));
+        let unsorted_index = make_index(None);
+        let search = |index: &Index, search_request: &SearchRequest| {
+            let collector = QuickwitCollector {
+                index_sort_opt: Some(index_sort.clone()),
+                ..make_test_collector(search_request)
+            };
+            let searcher = index.reader().unwrap().searcher();
+            let segment_collector = collector
+                .for_segment(0, searcher.segment_reader(0))
+                .unwrap();
+            let leaf_response = searcher.search(&AllQuery, &collector).unwrap();
+            let status_codes: Vec<u64> = leaf_response
+                .partial_hits
+                .iter()
+                .map(|partial_hit| partial_hit.sorting_field_value)
+                .collect();
+            (
+                segment_collector.visits_docs_in_sort_order,
+                status_codes,
+                leaf_response.num_hits,
+                leaf_response.num_hits_is_lower_bound,
+            )
+        };
+        let underestimate_request = SearchRequest {
+            max_hits: 3,
+            sort_by_field: Some("-status_code".to_string()),
+            count_hits: CountHitsMode::Underestimate as i32,
+            ..Default::default()
+        };
+        assert_eq!(
+            search(&sorted_index, &underestimate_request),
+            (true, vec![9, 8, 7], 3, true)
+        );
+        assert_eq!(
+            search(&unsorted_index, &underestimate_request),
+            (false, vec![9, 8, 7], 3, true)
+        );
+        // Exact counts need all the documents.
+        let count_all_request = SearchRequest {
+            count_hits: CountHitsMode::CountAll as i32,
+            ..underestimate_request.clone()
+        };
+        assert_eq!(
+            search(&sorted_index, &count_all_request),
+            (false, vec![9, 8, 7], 10, false)
+        );
+        // The index is sorted in the opposite order.
+        let ascending_request = SearchRequest {
+            sort_by_field: Some("status_code".to_string()),
+            ..underestimate_request.clone()
+        };
+        let (visits_docs_in_sort_order, status_codes, _, _) =
+            search(&sorted_index, &ascending_request);
+        assert!(!visits_docs_in_sort_order);
+        assert_eq!(status_codes, vec![u64::MAX, u64::MAX - 1, u64::MAX - 2]);
+        // The documents rejected by the timestamp filter do not stop the collection.
+        let timestamp_filter_request = SearchRequest {
+            end_timestamp: Some(5),
+            ..underestimate_request.clone()
+        };
+        assert_eq!(
+            search(&sorted_index, &timestamp_filter_request),
+            (true, vec![4, 3, 2], 3, true)
+        );
+    }
 
-    use proptest::prelude::*;
-    use quickwit_proto::PartialHit;
+    #[test]
+    fn test_merge_fruits_ors_timed_out() {
+        let leaf_responses = vec![
+            Ok(LeafSearchResponse {
+                num_hits: 3,
+                num_attempted_splits: 1,
+                ..Default::default()
+            }),
+            Ok(LeafSearchResponse {
+                num_hits: 1,
+                num_attempted_splits: 1,
+                num_hits_is_lower_bound: true,
+                timed_out: true,
+                ..Default::default()
+            }),
+        ];
+        let collector = make_test_collector(&SearchRequest::default());
+        let merged_response = collector.merge_fruits(leaf_responses).unwrap();
+        assert_eq!(merged_response.num_hits, 4);
+        assert!(merged_response.num_hits_is_lower_bound);
+        assert!(merged_response.timed_out);
+    }
+
+    #[test]
+    fn test_collector_min_sorting_field_value() {
+        let doc_mapper = make_test_doc_mapper();
+        let schema = doc_mapper.schema();
+        let status_code_field = schema.get_field("status_code").unwrap();
+        let make_searcher = |status_codes: &[u64]| {
+            let index = Index::create_in_ram(schema.clone());
+            let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+            for &status_code in status_codes {
+                index_writer
+                    .add_document(doc!(status_code_field => status_code))
+                    .unwrap();
+            }
+            index_writer.commit().unwrap();
+            index.reader().unwrap().searcher()
+        };
+        let searchers = [
+            make_searcher(&[404, 200, 503, 201, 500]),
+            make_searcher(&[302, 504]),
+        ];
+        let collector = make_test_collector(&SearchRequest {
+            max_hits: 3,
+            sort_by_field: Some("-status_code".to_string()),
+            ..Default::default()
+        });
+        let smallest_sorting_field_value = |leaf_response: &LeafSearchResponse| {
+            leaf_response
+                .partial_hits
+                .iter()
+                .map(|partial_hit| partial_hit.sorting_field_value)
+                .min()
+        };
+        let leaf_responses: Vec<LeafSearchResponse> = searchers
+            .iter()
+            .map(|searcher| searcher.search(&AllQuery, &collector).unwrap())
+            .collect();
+        for leaf_response in &leaf_responses {
+            assert!(leaf_response.min_sorting_field_value.is_some());
+            assert_eq!(
+                leaf_response.min_sorting_field_value,
+                smallest_sorting_field_value(leaf_response)
+            );
+        }
+        assert_eq!(leaf_responses[0].min_sorting_field_value, Some(404));
+
+        let merged_response = collector
+            .merge_fruits(leaf_responses.into_iter().map(Ok).collect())
+            .unwrap();
+        assert_eq!(merged_response.partial_hits.len(), 3);
+        assert_eq!(merged_response.min_sorting_field_value, Some(500));
 
-    use super::PartialHitHeapItem;
-    use crate::collector::{f32_to_u64, top_k_partial_hits};
+        // Without hits, there is no minimum.
+        let leaf_response = searchers[0]
+            .search(
+                &AllQuery,
+                &make_test_collector(&SearchRequest {
+                    sort_by_field: Some("-status_code".to_string()),
+                    ..Default::default()
+                }),
+            )
+            .unwrap();
+        assert!(leaf_response.partial_hits.is_empty());
+        assert!(leaf_response.min_sorting_field_value.is_none());
+    }
 
     #[test]
-    fn test_partial_hit_ordered_by_sorting_field() {
-        let lesser_score = PartialHitHeapItem {
-            sorting_field_value: 1u64,
-            doc_id: 1u32,
+    fn test_timestamp_range_aggregation_across_leaves() {
+        let search_request = SearchRequest {
+            aggregation_request: Some(r#"{"timestamp_range": {}}"#.to_string()),
+            ..Default::default()
         };
-        let higher_score = PartialHitHeapItem {
-            sorting_field_value: 2u64,
-            doc_id: 1u32,
+        // The field defaults to the timestamp field of the doc mapper.
+        let collector = make_test_collector(&search_request);
+        assert_warmup_info(&collector, &["timestamp"], &[], false);
+
+        let doc_mapper = make_test_doc_mapper();
+        let timestamp_field = doc_mapper.schema().get_field("timestamp").unwrap();
+        // Each leaf covers a time range disjoint from the others, and the last one is empty.
+        let leaf_responses: Vec<LeafSearchResponse> =
+            [&[1_000, 1_500][..], &[10, 20, 30], &[100_000], &[]]
+                .into_iter()
+                .map(|timestamps_secs| {
+                    let index = Index::create_in_ram(doc_mapper.schema());
+                    let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+                    for timestamp_secs in timestamps_secs {
+                        index_writer
+                            .add_document(doc!(
+                                timestamp_field => DateTime::from_timestamp_secs(*timestamp_secs),
+                            ))
+                            .unwrap();
+                    }
+                    index_writer.commit().unwrap();
+                    let searcher = index.reader().unwrap().searcher();
+                    searcher.search(&AllQuery, &collector).unwrap()
+                })
+                .collect();
+        let aggregations_opt = collector.aggregation.clone();
+        let merged_response =
+            merge_search_responses(leaf_responses, 0, 0, &aggregations_opt).unwrap();
+        let aggregation_json = finalize_aggregation(
+            merged_response.intermediate_aggregation_result,
+            aggregations_opt.clone(),
+            None,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&aggregation_json).unwrap(),
+            json!({"min_timestamp": 10_000_000, "max_timestamp": 100_000_000_000i64})
+        );
+
+        // Without any matching document, the range is null.
+        let merged_response = merge_search_responses(
+            vec![LeafSearchResponse::default(); 2],
+            0,
+            0,
+            &aggregations_opt,
+        )
+        .unwrap();
+        let aggregation_json = finalize_aggregation(
+            merged_response.intermediate_aggregation_result,
+            aggregations_opt,
+            None,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&aggregation_json).unwrap(),
+            json!({"min_timestamp": null, "max_timestamp": null})
+        );
+
+        // Without a timestamp field, the aggregation must name a field.
+        let doc_mapper: DefaultDocMapper = serde_json::from_value(json!({
+            "field_mappings": [{"name": "created_at", "type": "datetime", "fast": true}]
+        }))
+        .unwrap();
+        let make_collector = |search_request: &SearchRequest| {
+            make_collector_for_split(
+                "split1".to_string(),
+                &doc_mapper,
+                search_request,
+                AggregationLimits::default(),
+                &SearcherConfig::default(),
+                None,
+            )
         };
-        assert_eq!(lesser_score.cmp(&higher_score), Ordering::Greater);
+        let error = make_collector(&search_request).err().unwrap();
+        assert!(matches!(error, SearchError::InvalidAggregationRequest(_)));
+        let collector = make_collector(&SearchRequest {
+            aggregation_request: Some(
+                r#"{"timestamp_range": {"field": "created_at"}}"#.to_string(),
+            ),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_warmup_info(&collector, &["created_at"], &[], false);
     }
 
     #[test]
-    fn test_merge_partial_hits_no_tie() {
-        let make_doc = |sorting_field_value: u64| PartialHit {
-            sorting_field_value,
-            split_id: "split1".to_string(),
-            segment_ord: 0u32,
-            doc_id: 0u32,
+    fn test_merge_fruits_sums_cache_status() {
+        let split_response = |cache_status_opt: Option<CacheStatus>| {
+            Ok(LeafSearchResponse {
+                num_attempted_splits: 1,
+                cache_status: cache_status_opt,
+                ..Default::default()
+            })
+        };
+        let warm = CacheStatus {
+            num_warm_splits: 1,
+            num_cold_splits: 0,
         };
+        let cold = CacheStatus {
+            num_warm_splits: 0,
+            num_cold_splits: 1,
+        };
+        let leaf_responses = vec![
+            split_response(Some(warm.clone())),
+            split_response(Some(cold.clone())),
+            split_response(Some(warm)),
+            // A split skipped once the soft deadline passed was not fetched at all.
+            split_response(None),
+            // The response of another leaf, already merged.
+            split_response(Some(CacheStatus {
+                num_warm_splits: 3,
+                num_cold_splits: 2,
+            })),
+            split_response(Some(cold)),
+        ];
+        let collector = make_test_collector(&SearchRequest::default());
+        let merged_response = collector.merge_fruits(leaf_responses).unwrap();
         assert_eq!(
-            top_k_partial_hits(vec![make_doc(1u64), make_doc(3u64), make_doc(2u64),], 2),
-            vec![make_doc(3), make_doc(2)]
+            merged_response.cache_status,
+            Some(CacheStatus {
+                num_warm_splits: 5,
+                num_cold_splits: 4,
+            })
         );
+
+        let merged_response = collector
+            .merge_fruits(vec![split_response(None), split_response(None)])
+            .unwrap();
+        assert!(merged_response.cache_status.is_none());
     }
 
     #[test]
-    fn test_merge_partial_hits_with_tie() {
-        let make_hit_given_split_id = |split_id: u64| PartialHit {
-            sorting_field_value: 0u64,
-            split_id: format!("split_{split_id}"),
-            segment_ord: 0u32,
-            doc_id: 0u32,
+    fn test_serialize_fruit_allocates_exact_len() {
+        // Mimics the buckets of a high-cardinality terms aggregation.
+        let fruit: Vec<(String, u64)> = (0..100_000u64)
+            .map(|term| (format!("term-{term}"), term))
+            .collect();
+        let serialized = serialize_fruit(&fruit).unwrap();
+        assert_eq!(serialized.capacity(), serialized.len());
+        assert_eq!(serialized, postcard::to_allocvec(&fruit).unwrap());
+    }
+
+    #[test]
+    fn test_merge_serialized_fruits_from_many_leaves() {
+        let num_leaves = 100;
+        let num_terms_per_leaf = 1_000;
+        let mut leaf_responses: Vec<LeafSearchResponse> = (0..num_leaves)
+            .map(|leaf_ord| {
+                let fruit: BTreeMap<String, u64> = (0..num_terms_per_leaf)
+                    .map(|term| {
+                        (
+                            format!("term-{}", leaf_ord * num_terms_per_leaf / 2 + term),
+                            1,
+                        )
+                    })
+                    .collect();
+                LeafSearchResponse {
+                    intermediate_aggregation_result: Some(
+                        serialize_tagged_fruit(AggregationKind::Tantivy, &fruit).unwrap(),
+                    ),
+                    ..Default::default()
+                }
+            })
+            .collect();
+        leaf_responses.push(LeafSearchResponse::default());
+        let serialized = merge_serialized_fruits(
+            &mut leaf_responses,
+            AggregationKind::Tantivy,
+            |mut merged_fruit: BTreeMap<String, u64>, fruit| {
+                for (term, count) in fruit {
+                    *merged_fruit.entry(term).or_default() += count;
+                }
+                Ok(merged_fruit)
+            },
+        )
+        .unwrap()
+        .unwrap();
+        // The serialized fruits are released as soon as they are merged.
+        assert!(leaf_responses
+            .iter()
+            .all(|leaf_response| leaf_response.intermediate_aggregation_result.is_none()));
+        let merged_fruit: BTreeMap<String, u64> =
+            deserialize_tagged_fruit(&serialized, AggregationKind::Tantivy).unwrap();
+        assert_eq!(
+            merged_fruit.len(),
+            (num_leaves + 1) * num_terms_per_leaf / 2
+        );
+        assert_eq!(
+            merged_fruit.values().sum::<u64>(),
+            (num_leaves * num_terms_per_leaf) as u64
+        );
+        assert_eq!(merged_fruit["term-0"], 1);
+        assert_eq!(merged_fruit["term-500"], 2);
+    }
+
+    #[test]
+    fn test_merge_serialized_fruits_skips_undecodable_fruit() {
+        let make_leaf_response = |split_id: &str, serialized_fruit: Vec<u8>| LeafSearchResponse {
+            intermediate_aggregation_result: Some(serialized_fruit),
+            split_id: Some(split_id.to_string()),
+            num_attempted_splits: 1,
+            num_successful_splits: 1,
+            ..Default::default()
         };
+        let fruit: BTreeMap<String, u64> =
+            BTreeMap::from_iter([("term-a".to_string(), 1), ("term-b".to_string(), 2)]);
+        let serialized_fruit = serialize_tagged_fruit(AggregationKind::Tantivy, &fruit).unwrap();
+        // A truncated fruit keeps a valid kind tag but cannot be decoded.
+        let truncated_fruit = serialized_fruit[..serialized_fruit.len() - 2].to_vec();
+        let mut leaf_responses = vec![
+            make_leaf_response("split_1", serialized_fruit.clone()),
+            make_leaf_response("split_corrupt", truncated_fruit),
+            make_leaf_response("split_2", serialized_fruit),
+        ];
+        let serialized = merge_serialized_fruits(
+            &mut leaf_responses,
+            AggregationKind::Tantivy,
+            |mut merged_fruit: BTreeMap<String, u64>, fruit| {
+                for (term, count) in fruit {
+                    *merged_fruit.entry(term).or_default() += count;
+                }
+                Ok(merged_fruit)
+            },
+        )
+        .unwrap()
+        .unwrap();
+        let merged_fruit: BTreeMap<String, u64> =
+            deserialize_tagged_fruit(&serialized, AggregationKind::Tantivy).unwrap();
         assert_eq!(
-            top_k_partial_hits(
-                vec![
-                    make_hit_given_split_id(1u64),
-                    make_hit_given_split_id(3u64),
-                    make_hit_given_split_id(2u64),
-                ],
-                2
-            ),
-            vec![make_hit_given_split_id(1), make_hit_given_split_id(2)]
+            merged_fruit,
+            BTreeMap::from_iter([("term-a".to_string(), 2), ("term-b".to_string(), 4)])
+        );
+        assert!(!leaf_responses[0].aggregation_is_partial);
+        assert!(leaf_responses[0].failed_splits.is_empty());
+        assert!(leaf_responses[1].aggregation_is_partial);
+        assert_eq!(leaf_responses[1].failed_splits.len(), 1);
+        let failed_split = &leaf_responses[1].failed_splits[0];
+        assert_eq!(failed_split.split_id, "split_corrupt");
+        assert_eq!(failed_split.code(), SplitSearchErrorCode::Aggregation);
+        assert!(!failed_split.retryable_error);
+
+        // The failure is carried over to the merged response.
+        let merged_response =
+            merge_leaf_responses(&None, leaf_responses, 10, MergeOptions::default()).unwrap();
+        assert!(merged_response.aggregation_is_partial);
+        assert!(merged_response.split_id.is_none());
+        assert_eq!(merged_response.failed_splits.len(), 1);
+        assert_eq!(merged_response.num_successful_splits, 3);
+    }
+
+    #[test]
+    fn test_merge_serialized_fruits_passes_single_fruit_through() {
+        let fruit: BTreeMap<String, u64> = BTreeMap::from_iter([("term".to_string(), 1)]);
+        let mut serialized_fruit =
+            serialize_tagged_fruit(AggregationKind::Tantivy, &fruit).unwrap();
+        // Trailing bytes would be dropped by a decode/encode round-trip.
+        serialized_fruit.push(0);
+        let mut leaf_responses = vec![
+            LeafSearchResponse::default(),
+            LeafSearchResponse {
+                intermediate_aggregation_result: Some(serialized_fruit.clone()),
+                ..Default::default()
+            },
+            LeafSearchResponse::default(),
+        ];
+        let serialized = merge_serialized_fruits(
+            &mut leaf_responses,
+            AggregationKind::Tantivy,
+            |_: BTreeMap<String, u64>, _| panic!("There is a single fruit."),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(serialized, serialized_fruit);
+
+        let mut leaf_responses = vec![LeafSearchResponse {
+            intermediate_aggregation_result: Some(serialized_fruit.clone()),
+            ..Default::default()
+        }];
+        let error = merge_serialized_fruits(
+            &mut leaf_responses,
+            AggregationKind::DistinctCount,
+            |_: BTreeMap<String, u64>, _| panic!("There is a single fruit."),
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(error, TantivyError::InternalError(_)));
+
+        // A single leaf response is passed through as a whole.
+        let leaf_response = LeafSearchResponse {
+            intermediate_aggregation_result: Some(serialized_fruit.clone()),
+            num_hits: 1,
+            ..Default::default()
+        };
+        let merged_response = merge_search_responses(
+            vec![leaf_response],
+            0,
+            10,
+            &Some(QuickwitAggregations::TantivyAggregations(Default::default())),
+        )
+        .unwrap();
+        assert_eq!(
+            merged_response.intermediate_aggregation_result,
+            Some(serialized_fruit)
         );
     }
 
+    #[test]
+    fn test_merge_serialized_fruits_without_fruits() {
+        let mut leaf_responses = vec![LeafSearchResponse::default(); 2];
+        let merged_opt = merge_serialized_fruits(
+            &mut leaf_responses,
+            AggregationKind::Tantivy,
+            |_: u64, _: u64| panic!("There is no fruit to merge."),
+        )
+        .unwrap();
+        assert!(merged_opt.is_none());
+    }
+
     prop_compose! {
         // Turns out, zero's and negative zero's u64 representation is not same.
         // It is not relevant for our use case. For simplicity we filter the negative
@@ -692,11 +7369,109 @@ mod tests {
         }
     }
 
+    prop_compose! {
+        // `any::<f32>()` only yields finite values, so the non-finite values pathological
+        // scorers can yield are added explicitly.
+        fn any_score()(val in prop_oneof![
+            8 => any_f32_without_negative_zero(),
+            1 => Just(f32::NAN),
+            1 => Just(f32::INFINITY),
+            1 => Just(f32::NEG_INFINITY),
+            1 => Just(f32::MAX),
+            1 => Just(f32::MIN),
+        ]) -> f32 {
+            val
+        }
+    }
+
+    prop_compose! {
+        fn any_f64_without_negative_zero()(val in any::<f64>().prop_filter("Value can't be negative zero", |val| *val != -0.0)) -> f64 {
+            val
+        }
+    }
+
     proptest! {
         #![proptest_config(ProptestConfig::with_cases(10000))]
         #[test]
         fn test_proptest_f32_to_u64_compare_arbitrary(a in any_f32_without_negative_zero(), b in any_f32_without_negative_zero()) {
             prop_assert_eq!(a < b, f32_to_u64(a) < f32_to_u64(b))
         }
+
+        #[test]
+        fn test_proptest_score_sorting_field_compare_arbitrary(a in any_score(), b in any_score()) {
+            for order in [SortOrder::Asc, SortOrder::Desc] {
+                let key_a = score_sorting_field(a, order);
+                let key_b = score_sorting_field(b, order);
+                if a.is_nan() || b.is_nan() {
+                    prop_assert_eq!(key_a < key_b, a.is_nan() && !b.is_nan());
+                    continue;
+                }
+                let clamped_a = a.clamp(f32::MIN, f32::MAX);
+                let clamped_b = b.clamp(f32::MIN, f32::MAX);
+                let a_ranks_higher = match order {
+                    SortOrder::Desc => clamped_a > clamped_b,
+                    SortOrder::Asc => clamped_a < clamped_b,
+                };
+                prop_assert_eq!(a_ranks_higher, key_a > key_b);
+            }
+        }
+
+        #[test]
+        fn test_proptest_f64_to_u64_compare_arbitrary(a in any_f64_without_negative_zero(), b in any_f64_without_negative_zero()) {
+            prop_assert_eq!(a < b, f64_to_u64(a) < f64_to_u64(b))
+        }
+
+        #[test]
+        fn test_proptest_i64_to_u64_compare_arbitrary(a in any::<i64>(), b in any::<i64>()) {
+            prop_assert_eq!(a < b, i64_to_u64(a) < i64_to_u64(b))
+        }
+    }
+
+    prop_compose! {
+        // Few distinct values so that ties are frequent.
+        fn arbitrary_partial_hits(split_id: String)(
+            keys in proptest::collection::vec((0u64..4, 0u64..3), 0..20)
+        ) -> Vec<PartialHit> {
+            keys.into_iter()
+                .enumerate()
+                .map(|(doc_id, (sorting_field_value, tie_breaker_value))| PartialHit {
+                    sorting_field_value,
+                    split_id: split_id.clone(),
+                    segment_ord: 0,
+                    doc_id: doc_id as u32,
+                    tie_breaker_value,
+                    ..Default::default()
+                })
+                .collect()
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_proptest_merge_partial_hits_matches_top_k(
+            partial_hits_lists in (0usize..6).prop_flat_map(|num_lists| {
+                (0..num_lists)
+                    .map(|list_ord| arbitrary_partial_hits(format!("split_{list_ord}")))
+                    .collect::<Vec<_>>()
+            }),
+            sort_lists in any::<bool>(),
+            num_hits in 0usize..30,
+        ) {
+            // Leaf responses hold sorted lists, but unsorted ones must be merged correctly too.
+            let partial_hits_lists: Vec<Vec<PartialHit>> = if sort_lists {
+                partial_hits_lists
+                    .into_iter()
+                    .map(|partial_hits| top_k_partial_hits(partial_hits, usize::MAX, SortOrder::Asc))
+                    .collect()
+            } else {
+                partial_hits_lists
+            };
+            let all_partial_hits: Vec<PartialHit> =
+                partial_hits_lists.iter().flatten().cloned().collect();
+            prop_assert_eq!(
+                merge_partial_hits(partial_hits_lists, num_hits, SortOrder::Asc),
+                top_k_partial_hits(all_partial_hits, num_hits, SortOrder::Asc)
+            );
+        }
     }
 }
@@ -0,0 +1,362 @@
+// Copyright (C) 2023 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+use std::hash::Hasher;
+
+use fnv::FnvHasher;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use tantivy::collector::{Collector, SegmentCollector};
+use tantivy::columnar::StrColumn;
+use tantivy::fastfield::Column;
+use tantivy::{DocId, Score, SegmentReader};
+
+use crate::collector::mix_u64;
+
+/// Precision used when the request does not set one, for 16KiB of registers and an estimate
+/// within about 1% of the actual count.
+const DEFAULT_PRECISION: u8 = 14;
+
+const MIN_PRECISION: u8 = 4;
+const MAX_PRECISION: u8 = 18;
+
+fn default_precision() -> u8 {
+    DEFAULT_PRECISION
+}
+
+fn deserialize_precision<'de, D>(deserializer: D) -> Result<u8, D::Error>
+where D: Deserializer<'de> {
+    let precision = u8::deserialize(deserializer)?;
+    if !(MIN_PRECISION..=MAX_PRECISION).contains(&precision) {
+        return Err(de::Error::custom(format!(
+            "precision must be between {MIN_PRECISION} and {MAX_PRECISION}, but got {precision}"
+        )));
+    }
+    Ok(precision)
+}
+
+/// Estimates the number of distinct values of a fast field among the matching documents.
+///
+/// The values are counted with a HyperLogLog sketch, which has a fixed size whatever the number
+/// of values, and is therefore much cheaper than a `terms` aggregation on a high-cardinality
+/// field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DistinctCountCollector {
+    /// The name of the fast field whose distinct values are counted.
+    pub distinct_count_field: String,
+    /// The sketch holds `2^precision` registers, and the relative standard error of the
+    /// estimate is about `1.04 / sqrt(2^precision)`. It ranges from 4 to 18.
+    #[serde(
+        default = "default_precision",
+        deserialize_with = "deserialize_precision"
+    )]
+    pub precision: u8,
+}
+
+impl DistinctCountCollector {
+    /// The names of the fast fields accessed by this collector.
+    pub fn fast_field_names(&self) -> HashSet<String> {
+        HashSet::from_iter([self.distinct_count_field.clone()])
+    }
+}
+
+impl Collector for DistinctCountCollector {
+    type Fruit = HyperLogLog;
+    type Child = DistinctCountSegmentCollector;
+
+    fn for_segment(
+        &self,
+        _segment_local_id: u32,
+        segment_reader: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        let fast_fields = segment_reader.fast_fields();
+        // Term ordinals are local to the segment, so the terms themselves are hashed, once
+        // per segment.
+        let segment_values =
+            if let Some(str_column) = fast_fields.str(&self.distinct_count_field)? {
+                let num_terms = str_column.dictionary().num_terms();
+                SegmentValues::Terms {
+                    str_column,
+                    seen_term_ords: vec![false; num_terms],
+                }
+            } else {
+                let column = fast_fields
+                    .u64_lenient(&self.distinct_count_field)?
+                    .map(|(column, _)| column)
+                    .unwrap_or_else(|| Column::build_empty_column(segment_reader.max_doc()));
+                SegmentValues::Numbers(column)
+            };
+        Ok(DistinctCountSegmentCollector {
+            segment_values,
+            sketch: HyperLogLog::new(self.precision),
+        })
+    }
+
+    fn merge_fruits(
+        &self,
+        segment_fruits: Vec<<Self::Child as SegmentCollector>::Fruit>,
+    ) -> tantivy::Result<Self::Fruit> {
+        let mut merged_sketch = HyperLogLog::new(self.precision);
+        for sketch in &segment_fruits {
+            merged_sketch.merge(sketch);
+        }
+        Ok(merged_sketch)
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+}
+
+enum SegmentValues {
+    /// Column of numbers, booleans or datetimes, whose values are hashed as they come.
+    Numbers(Column<u64>),
+    /// Column of strings, whose terms are hashed when the segment is harvested.
+    Terms {
+        str_column: StrColumn,
+        seen_term_ords: Vec<bool>,
+    },
+}
+
+pub struct DistinctCountSegmentCollector {
+    segment_values: SegmentValues,
+    sketch: HyperLogLog,
+}
+
+impl SegmentCollector for DistinctCountSegmentCollector {
+    type Fruit = HyperLogLog;
+
+    fn collect(&mut self, doc: DocId, _score: Score) {
+        match &mut self.segment_values {
+            SegmentValues::Numbers(column) => {
+                for value in column.values_for_doc(doc) {
+                    self.sketch.insert_hash(mix_u64(value));
+                }
+            }
+            SegmentValues::Terms {
+                str_column,
+                seen_term_ords,
+            } => {
+                for term_ord in str_column.term_ords(doc) {
+                    seen_term_ords[term_ord as usize] = true;
+                }
+            }
+        }
+    }
+
+    fn harvest(mut self) -> Self::Fruit {
+        if let SegmentValues::Terms {
+            str_column,
+            seen_term_ords,
+        } = &self.segment_values
+        {
+            let mut buffer = String::new();
+            for (term_ord, _) in seen_term_ords.iter().enumerate().filter(|(_, seen)| **seen) {
+                let found_term = str_column
+                    .ord_to_str(term_ord as u64, &mut buffer)
+                    .expect("Failed to lookup term in the column term dictionary");
+                debug_assert!(found_term);
+                let mut hasher = FnvHasher::default();
+                hasher.write(buffer.as_bytes());
+                self.sketch.insert_hash(mix_u64(hasher.finish()));
+            }
+        }
+        self.sketch
+    }
+}
+
+/// HyperLogLog sketch, see "HyperLogLog: the analysis of a near-optimal cardinality estimation
+/// algorithm" by Flajolet et al.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HyperLogLog {
+    precision: u8,
+    /// Highest rank observed among the hashes routed to each register.
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    fn new(precision: u8) -> Self {
+        Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    /// Routes the hash to a register with its first `precision` bits, and records the rank of
+    /// the first set bit among the remaining ones.
+    fn insert_hash(&mut self, hash: u64) {
+        let register_index = (hash >> (64 - self.precision)) as usize;
+        // The guard bit bounds the rank when the remaining bits are all zeros.
+        let guard_bit = 1u64 << (self.precision - 1);
+        let rank = ((hash << self.precision) | guard_bit).leading_zeros() as u8 + 1;
+        let register = &mut self.registers[register_index];
+        *register = (*register).max(rank);
+    }
+
+    /// Merges the other sketch, which must have the same precision. The result is the sketch
+    /// of the union of the two sets of values.
+    pub(crate) fn merge(&mut self, other: &HyperLogLog) {
+        debug_assert_eq!(self.precision, other.precision);
+        for (register, other_register) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*other_register);
+        }
+    }
+
+    /// Returns the estimated number of distinct values, corrected with linear counting for
+    /// small cardinalities.
+    pub fn estimate(&self) -> u64 {
+        let num_registers = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / num_registers),
+        };
+        let inverse_sum: f64 = self
+            .registers
+            .iter()
+            .map(|register| 2f64.powi(-(*register as i32)))
+            .sum();
+        let raw_estimate = alpha * num_registers * num_registers / inverse_sum;
+        let num_empty_registers = self
+            .registers
+            .iter()
+            .filter(|register| **register == 0)
+            .count();
+        let estimate = if raw_estimate <= 2.5 * num_registers && num_empty_registers > 0 {
+            num_registers * (num_registers / num_empty_registers as f64).ln()
+        } else {
+            raw_estimate
+        };
+        estimate.round() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quickwit_doc_mapper::{DefaultDocMapper, DocMapper};
+    use serde_json::json;
+    use tantivy::collector::Collector;
+    use tantivy::query::AllQuery;
+    use tantivy::{doc, Index};
+
+    use super::*;
+    use crate::QuickwitAggregations;
+
+    #[test]
+    fn test_distinct_count_collector_serde() {
+        let aggregation: QuickwitAggregations =
+            serde_json::from_str(r#"{"distinct_count_field": "user_id"}"#).unwrap();
+        let QuickwitAggregations::DistinctCountAggregation(collector) = aggregation else {
+            panic!("Expected DistinctCountAggregation");
+        };
+        assert_eq!(collector.distinct_count_field, "user_id");
+        assert_eq!(collector.precision, DEFAULT_PRECISION);
+
+        let aggregation: QuickwitAggregations =
+            serde_json::from_str(r#"{"distinct_count_field": "user_id", "precision": 10}"#)
+                .unwrap();
+        let QuickwitAggregations::DistinctCountAggregation(collector) = aggregation else {
+            panic!("Expected DistinctCountAggregation");
+        };
+        assert_eq!(collector.precision, 10);
+
+        serde_json::from_str::<DistinctCountCollector>(
+            r#"{"distinct_count_field": "user_id", "precision": 19}"#,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_hyperloglog_estimate() {
+        for num_values in [0, 10, 1_000, 100_000] {
+            let mut sketch = HyperLogLog::new(DEFAULT_PRECISION);
+            // Duplicates do not count.
+            for value in (0..num_values).chain(0..num_values) {
+                sketch.insert_hash(mix_u64(value));
+            }
+            let estimate = sketch.estimate() as f64;
+            let error = (estimate - num_values as f64).abs();
+            assert!(
+                error <= 0.02 * num_values as f64,
+                "estimated {estimate} distinct values instead of {num_values}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_hyperloglog_merge() {
+        let mut sketch = HyperLogLog::new(DEFAULT_PRECISION);
+        let mut other_sketch = HyperLogLog::new(DEFAULT_PRECISION);
+        let mut union_sketch = HyperLogLog::new(DEFAULT_PRECISION);
+        for value in 0..10_000 {
+            sketch.insert_hash(mix_u64(value));
+            union_sketch.insert_hash(mix_u64(value));
+        }
+        // Half of the values are shared.
+        for value in 5_000..15_000 {
+            other_sketch.insert_hash(mix_u64(value));
+            union_sketch.insert_hash(mix_u64(value));
+        }
+        sketch.merge(&other_sketch);
+        assert_eq!(sketch, union_sketch);
+    }
+
+    #[test]
+    fn test_distinct_count_collector() {
+        let doc_mapper: DefaultDocMapper = serde_json::from_value(json!({
+            "field_mappings": [
+                {"name": "user_id", "type": "text", "tokenizer": "raw", "fast": true},
+                {"name": "status_code", "type": "u64", "fast": true},
+            ]
+        }))
+        .unwrap();
+        let schema = doc_mapper.schema();
+        let user_id_field = schema.get_field("user_id").unwrap();
+        let status_code_field = schema.get_field("status_code").unwrap();
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        // Each user shows up in both segments.
+        for _ in 0..2 {
+            for doc_id in 0..1_000u64 {
+                index_writer
+                    .add_document(doc!(
+                        user_id_field => format!("user-{}", doc_id % 100),
+                        status_code_field => doc_id % 7,
+                    ))
+                    .unwrap();
+            }
+            index_writer.commit().unwrap();
+        }
+        let searcher = index.reader().unwrap().searcher();
+        assert_eq!(searcher.segment_readers().len(), 2);
+
+        let count_distinct = |field_name: &str| {
+            let collector = DistinctCountCollector {
+                distinct_count_field: field_name.to_string(),
+                precision: DEFAULT_PRECISION,
+            };
+            searcher.search(&AllQuery, &collector).unwrap().estimate()
+        };
+        assert_eq!(count_distinct("user_id"), 100);
+        assert_eq!(count_distinct("status_code"), 7);
+        assert_eq!(count_distinct("missing_field"), 0);
+    }
+}
@@ -0,0 +1,214 @@
+// Copyright (C) 2023 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+use tantivy::DocId;
+
+/// Doc ids sharing their 16 high bits belong to the same chunk.
+const CHUNK_NUM_BITS: u32 = 16;
+
+/// Beyond this number of doc ids, the 16 low bits of the doc ids of a chunk take more room as a
+/// sorted array than as a bitmap.
+const MAX_ARRAY_LEN: usize = 4_096;
+
+/// Number of 64-bit words of the bitmap of a chunk, for one bit per doc id.
+const BITMAP_NUM_WORDS: usize = (1 << CHUNK_NUM_BITS) / 64;
+
+/// Set of doc ids of a segment, laid out as a roaring bitmap.
+///
+/// The doc ids are split into chunks of 2^16 doc ids, and each chunk stores the low bits of its
+/// doc ids either as a sorted array of `u16`, while it holds at most 4096 doc ids, or as a bitmap
+/// of 8KiB. The set thus takes at most 2 bytes per doc id, and at most about `max_doc / 8`
+/// bytes whatever the number of doc ids, which makes it suitable for set operations over all
+/// the documents matching a query, but not for displaying them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocIdSet {
+    /// Chunks holding at least one doc id, sorted by key.
+    chunks: Vec<Chunk>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Chunk {
+    /// High bits of the doc ids of the chunk.
+    key: u16,
+    container: Container,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum Container {
+    Array(Vec<u16>),
+    Bitmap(Vec<u64>),
+}
+
+impl Container {
+    fn insert(&mut self, low_bits: u16) -> bool {
+        match self {
+            Container::Array(values) => {
+                let Err(position) = values.binary_search(&low_bits) else {
+                    return false;
+                };
+                values.insert(position, low_bits);
+                if values.len() > MAX_ARRAY_LEN {
+                    let mut words = vec![0u64; BITMAP_NUM_WORDS];
+                    for value in values.iter() {
+                        words[*value as usize / 64] |= 1u64 << (value % 64);
+                    }
+                    *self = Container::Bitmap(words);
+                }
+                true
+            }
+            Container::Bitmap(words) => {
+                let word = &mut words[low_bits as usize / 64];
+                let mask = 1u64 << (low_bits % 64);
+                let is_new = *word & mask == 0;
+                *word |= mask;
+                is_new
+            }
+        }
+    }
+
+    fn contains(&self, low_bits: u16) -> bool {
+        match self {
+            Container::Array(values) => values.binary_search(&low_bits).is_ok(),
+            Container::Bitmap(words) => {
+                words[low_bits as usize / 64] & (1u64 << (low_bits % 64)) != 0
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Container::Array(values) => values.len(),
+            Container::Bitmap(words) => words.iter().map(|word| word.count_ones() as usize).sum(),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = u16> + '_> {
+        match self {
+            Container::Array(values) => Box::new(values.iter().copied()),
+            Container::Bitmap(words) => Box::new(
+                (0..=u16::MAX)
+                    .filter(move |value| words[*value as usize / 64] & (1u64 << (value % 64)) != 0),
+            ),
+        }
+    }
+}
+
+impl DocIdSet {
+    /// Adds a doc id to the set, and returns whether it was not already there. Doc ids are
+    /// cheaper to add in increasing order, which is the order in which tantivy collects them.
+    pub fn insert(&mut self, doc_id: DocId) -> bool {
+        let key = (doc_id >> CHUNK_NUM_BITS) as u16;
+        let low_bits = doc_id as u16;
+        let chunk_ord = match self.chunks.last() {
+            Some(last_chunk) if last_chunk.key == key => self.chunks.len() - 1,
+            _ => match self.chunks.binary_search_by_key(&key, |chunk| chunk.key) {
+                Ok(chunk_ord) => chunk_ord,
+                Err(chunk_ord) => {
+                    let chunk = Chunk {
+                        key,
+                        container: Container::Array(Vec::new()),
+                    };
+                    self.chunks.insert(chunk_ord, chunk);
+                    chunk_ord
+                }
+            },
+        };
+        self.chunks[chunk_ord].container.insert(low_bits)
+    }
+
+    /// Returns whether the doc id belongs to the set.
+    pub fn contains(&self, doc_id: DocId) -> bool {
+        let key = (doc_id >> CHUNK_NUM_BITS) as u16;
+        self.chunks
+            .binary_search_by_key(&key, |chunk| chunk.key)
+            .map(|chunk_ord| self.chunks[chunk_ord].container.contains(doc_id as u16))
+            .unwrap_or(false)
+    }
+
+    /// Returns the number of doc ids of the set.
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.container.len()).sum()
+    }
+
+    /// Returns whether the set holds no doc id.
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Returns the doc ids of the set in increasing order.
+    pub fn iter(&self) -> impl Iterator<Item = DocId> + '_ {
+        self.chunks.iter().flat_map(|chunk| {
+            let high_bits = (chunk.key as DocId) << CHUNK_NUM_BITS;
+            chunk
+                .container
+                .iter()
+                .map(move |low_bits| high_bits | low_bits as DocId)
+        })
+    }
+
+    /// Serializes the set, as carried by `SegmentDocIdSet::doc_ids`.
+    pub fn to_bytes(&self) -> postcard::Result<Vec<u8>> {
+        postcard::to_allocvec(self)
+    }
+
+    /// Deserializes a set serialized with [`DocIdSet::to_bytes`].
+    pub fn from_bytes(serialized: &[u8]) -> postcard::Result<Self> {
+        postcard::from_bytes(serialized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    #[test]
+    fn test_doc_id_set() {
+        let mut doc_id_set = DocIdSet::default();
+        assert!(doc_id_set.is_empty());
+        // The first chunk turns into a bitmap, the others remain arrays.
+        let doc_ids: BTreeSet<DocId> = (0..10_000u32)
+            .map(|doc_ord| doc_ord * 3)
+            .chain([70_000, 200_000, 65_536 * 3 + 1])
+            .collect();
+        for doc_id in doc_ids.iter().rev() {
+            assert!(doc_id_set.insert(*doc_id));
+        }
+        assert!(!doc_id_set.insert(3));
+        assert!(!doc_id_set.insert(70_000));
+        assert!(matches!(
+            doc_id_set.chunks[0].container,
+            Container::Bitmap(_)
+        ));
+        assert!(matches!(
+            doc_id_set.chunks[1].container,
+            Container::Array(_)
+        ));
+        assert_eq!(doc_id_set.len(), doc_ids.len());
+        assert!(doc_id_set.iter().eq(doc_ids.iter().copied()));
+        assert!(doc_id_set.contains(29_997));
+        assert!(!doc_id_set.contains(29_998));
+        assert!(!doc_id_set.contains(300_000));
+
+        let serialized = doc_id_set.to_bytes().unwrap();
+        assert_eq!(DocIdSet::from_bytes(&serialized).unwrap(), doc_id_set);
+    }
+}
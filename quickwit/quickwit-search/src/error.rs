@@ -19,7 +19,9 @@
 
 use quickwit_doc_mapper::QueryParserError;
 use quickwit_metastore::MetastoreError;
-use quickwit_proto::{tonic, ServiceError, ServiceErrorCode};
+use quickwit_proto::{
+    tonic, ServiceError, ServiceErrorCode, SplitSearchError, SplitSearchErrorCode,
+};
 use quickwit_storage::StorageResolverError;
 use serde::{Deserialize, Serialize};
 use tantivy::TantivyError;
@@ -42,6 +44,41 @@ pub enum SearchError {
     InvalidArgument(String),
     #[error("Invalid query: {0}")]
     InvalidQuery(String),
+    #[error("Failed to fetch split `{split_id}` from storage: {message}")]
+    StorageError { split_id: String, message: String },
+    #[error("Fetching split `{split_id}` from storage timed out: {message}")]
+    Timeout { split_id: String, message: String },
+    #[error("Field `{field_name}` is not a fast field in split `{split_id}`.")]
+    MissingFastField {
+        split_id: String,
+        field_name: String,
+    },
+}
+
+impl SearchError {
+    /// Machine-readable cause of the failure, reported along with the split that failed.
+    pub fn split_error_code(&self) -> SplitSearchErrorCode {
+        match self {
+            SearchError::StorageError { .. } => SplitSearchErrorCode::Storage,
+            SearchError::Timeout { .. } => SplitSearchErrorCode::Timeout,
+            SearchError::MissingFastField { .. } => SplitSearchErrorCode::MissingFastField,
+            _ => SplitSearchErrorCode::Internal,
+        }
+    }
+
+    /// Builds the error reported for a split whose search failed with this error.
+    ///
+    /// A missing fast field fails the same way on any searcher, so the split is not worth
+    /// retrying.
+    pub fn into_split_search_error(self, split_id: String) -> SplitSearchError {
+        let code = self.split_error_code();
+        SplitSearchError {
+            error: self.to_string(),
+            split_id,
+            retryable_error: code != SplitSearchErrorCode::MissingFastField,
+            code: code as i32,
+        }
+    }
 }
 
 impl ServiceError for SearchError {
@@ -53,6 +90,9 @@ impl ServiceError for SearchError {
             SearchError::InvalidQuery(_) => ServiceErrorCode::BadRequest,
             SearchError::InvalidArgument(_) => ServiceErrorCode::BadRequest,
             SearchError::InvalidAggregationRequest(_) => ServiceErrorCode::BadRequest,
+            SearchError::StorageError { .. } => ServiceErrorCode::Internal,
+            SearchError::Timeout { .. } => ServiceErrorCode::Unavailable,
+            SearchError::MissingFastField { .. } => ServiceErrorCode::BadRequest,
         }
     }
 }
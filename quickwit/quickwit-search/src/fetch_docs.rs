@@ -17,18 +17,25 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::sync::Arc;
 
 use anyhow::{Context, Ok};
 use futures::{StreamExt, TryStreamExt};
 use itertools::Itertools;
-use quickwit_doc_mapper::DocMapper;
-use quickwit_proto::{FetchDocsResponse, PartialHit, SearchRequest, SplitIdAndFooterOffsets};
+use quickwit_doc_mapper::{DocMapper, DYNAMIC_FIELD_NAME};
+use quickwit_proto::{
+    FetchDocsResponse, MatchOffset, PartialHit, SearchRequest, SplitIdAndFooterOffsets,
+};
 use quickwit_storage::Storage;
+use tantivy::postings::Postings;
 use tantivy::query::Query;
-use tantivy::schema::{Field, Value};
-use tantivy::{ReloadPolicy, Score, Searcher, SnippetGenerator, Term};
+use tantivy::schema::{Field, FieldType, IndexRecordOption, Schema, Value};
+use tantivy::tokenizer::TokenStream;
+use tantivy::{
+    DocAddress, DocId, DocSet, ReloadPolicy, Score, Searcher, SegmentOrdinal, SnippetGenerator,
+    Term,
+};
 use tracing::error;
 
 use crate::leaf::open_index_with_caches;
@@ -139,6 +146,8 @@ pub async fn fetch_docs(
                     leaf_json: document.content_json,
                     partial_hit: Some(partial_hit.clone()),
                     leaf_snippet_json: document.snippet_json,
+                    matched_fields: document.matched_fields,
+                    match_offsets: document.match_offsets,
                 })
             } else {
                 None
@@ -156,6 +165,8 @@ const NUM_CONCURRENT_REQUESTS: usize = 10;
 struct Document {
     content_json: String,
     snippet_json: Option<String>,
+    matched_fields: Vec<String>,
+    match_offsets: Vec<MatchOffset>,
 }
 
 /// Fetching docs from a specific split.
@@ -169,11 +180,23 @@ async fn fetch_docs_in_split(
     search_request_opt: Option<&SearchRequest>,
 ) -> anyhow::Result<Vec<(GlobalDocAddress, Document)>> {
     global_doc_addrs.sort_by_key(|doc| doc.doc_addr);
+    let return_matched_fields = search_request_opt
+        .map(|search_request| search_request.return_matched_fields)
+        .unwrap_or(false);
+    let return_highlights = search_request_opt
+        .map(|search_request| search_request.return_highlights)
+        .unwrap_or(false);
     // Opens the index without the ephemeral unbounded cache, this cache is indeed not useful
-    // when fetching docs as we will fetch them only once.
-    let index = open_index_with_caches(&searcher_context, index_storage, split, false)
-        .await
-        .with_context(|| "open-index-for-split")?;
+    // when fetching docs as we will fetch them only once. Computing the matched fields or the
+    // highlights however requires reading the postings we warm up.
+    let index = open_index_with_caches(
+        &searcher_context,
+        index_storage,
+        split,
+        return_matched_fields || return_highlights,
+    )
+    .await
+    .with_context(|| "open-index-for-split")?;
     let index_reader = index
         .reader_builder()
         // the docs are presorted so a cache size of NUM_CONCURRENT_REQUESTS is fine
@@ -186,8 +209,28 @@ async fn fetch_docs_in_split(
     } else {
         None
     };
+    let mut matched_fields_per_doc = match search_request_opt {
+        Some(search_request) if return_matched_fields => {
+            compute_matched_fields(&searcher, &*doc_mapper, search_request, &global_doc_addrs)
+                .await?
+        }
+        _ => HashMap::new(),
+    };
+    let mut term_positions_per_doc = match search_request_opt {
+        Some(search_request) if return_highlights => {
+            compute_term_positions(&searcher, &*doc_mapper, search_request, &global_doc_addrs)
+                .await?
+        }
+        _ => HashMap::new(),
+    };
 
     let doc_futures = global_doc_addrs.into_iter().map(|global_doc_addr| {
+        let matched_fields = matched_fields_per_doc
+            .remove(&global_doc_addr.doc_addr)
+            .unwrap_or_default();
+        let term_positions = term_positions_per_doc
+            .remove(&global_doc_addr.doc_addr)
+            .unwrap_or_default();
         let moved_searcher = searcher.clone();
         let moved_doc_mapper = doc_mapper.clone();
         let fields_snippet_generator_opt_clone = fields_snippet_generator_opt.clone();
@@ -200,12 +243,15 @@ async fn fetch_docs_in_split(
             let named_field_doc = moved_searcher.schema().to_named_doc(&doc);
             let content_json =
                 convert_document_to_json_string(named_field_doc, &*moved_doc_mapper)?;
+            let match_offsets = compute_match_offsets(&moved_searcher, &doc, &term_positions)?;
             if fields_snippet_generator_opt_clone.is_none() {
                 return Ok((
                     global_doc_addr,
                     Document {
                         content_json,
                         snippet_json: None,
+                        matched_fields,
+                        match_offsets,
                     },
                 ));
             }
@@ -217,6 +263,8 @@ async fn fetch_docs_in_split(
                     Document {
                         content_json,
                         snippet_json: None,
+                        matched_fields,
+                        match_offsets,
                     },
                 ));
             }
@@ -236,6 +284,8 @@ async fn fetch_docs_in_split(
                 Document {
                     content_json,
                     snippet_json: Some(snippet_json),
+                    matched_fields,
+                    match_offsets,
                 },
             ))
         })
@@ -248,6 +298,197 @@ async fn fetch_docs_in_split(
         .await
 }
 
+/// Byte separating the segments of the JSON path of a term.
+const JSON_PATH_SEGMENT_SEP: u8 = 1u8;
+/// Byte ending the JSON path of a term.
+const JSON_END_OF_PATH: u8 = 0u8;
+
+/// Returns, for each document holding at least one of the query terms, the sorted names
+/// of the fields holding them.
+///
+/// The documents are expected to be sorted by address.
+async fn compute_matched_fields(
+    searcher: &Searcher,
+    doc_mapper: &dyn DocMapper,
+    search_request: &SearchRequest,
+    global_doc_addrs: &[GlobalDocAddress],
+) -> anyhow::Result<HashMap<DocAddress, Vec<String>>> {
+    let schema = searcher.schema();
+    let (query, _) = doc_mapper.query(schema.clone(), search_request)?;
+    let mut terms: Vec<Term> = Vec::new();
+    query.query_terms(&mut |term, _need_position| {
+        terms.push(term.clone());
+    });
+    let doc_ids_per_segment = group_doc_ids_by_segment(global_doc_addrs);
+    let mut matched_fields: HashMap<DocAddress, BTreeSet<String>> = HashMap::new();
+    for term in &terms {
+        let field_name = matched_field_name(schema, term);
+        for (&segment_ord, doc_ids) in &doc_ids_per_segment {
+            let inverted_index = searcher
+                .segment_reader(segment_ord)
+                .inverted_index(term.field())?;
+            inverted_index.warm_postings(term, false).await?;
+            let Some(mut postings) =
+                inverted_index.read_postings(term, IndexRecordOption::Basic)?
+            else {
+                continue;
+            };
+            // The doc ids are sorted, so we can seek forward through the postings.
+            for &doc_id in doc_ids {
+                if postings.seek(doc_id) == doc_id {
+                    let doc_addr = DocAddress::new(segment_ord, doc_id);
+                    matched_fields
+                        .entry(doc_addr)
+                        .or_default()
+                        .insert(field_name.clone());
+                }
+            }
+        }
+    }
+    let matched_fields_per_doc = matched_fields
+        .into_iter()
+        .map(|(doc_addr, field_names)| (doc_addr, field_names.into_iter().collect()))
+        .collect();
+    Ok(matched_fields_per_doc)
+}
+
+/// Returns, for each document holding at least one of the query terms in a text field
+/// indexed with positions, the positions of these terms per field.
+///
+/// The documents are expected to be sorted by address.
+async fn compute_term_positions(
+    searcher: &Searcher,
+    doc_mapper: &dyn DocMapper,
+    search_request: &SearchRequest,
+    global_doc_addrs: &[GlobalDocAddress],
+) -> anyhow::Result<HashMap<DocAddress, BTreeMap<Field, BTreeSet<u32>>>> {
+    let schema = searcher.schema();
+    let (query, _) = doc_mapper.query(schema.clone(), search_request)?;
+    let mut terms: Vec<Term> = Vec::new();
+    query.query_terms(&mut |term, _need_position| {
+        if has_positions(schema, term.field()) {
+            terms.push(term.clone());
+        }
+    });
+    let doc_ids_per_segment = group_doc_ids_by_segment(global_doc_addrs);
+    let mut term_positions_per_doc: HashMap<DocAddress, BTreeMap<Field, BTreeSet<u32>>> =
+        HashMap::new();
+    let mut positions: Vec<u32> = Vec::new();
+    for term in &terms {
+        for (&segment_ord, doc_ids) in &doc_ids_per_segment {
+            let inverted_index = searcher
+                .segment_reader(segment_ord)
+                .inverted_index(term.field())?;
+            inverted_index.warm_postings(term, true).await?;
+            let Some(mut postings) =
+                inverted_index.read_postings(term, IndexRecordOption::WithFreqsAndPositions)?
+            else {
+                continue;
+            };
+            // The doc ids are sorted, so we can seek forward through the postings.
+            for &doc_id in doc_ids {
+                if postings.seek(doc_id) == doc_id {
+                    postings.positions(&mut positions);
+                    term_positions_per_doc
+                        .entry(DocAddress::new(segment_ord, doc_id))
+                        .or_default()
+                        .entry(term.field())
+                        .or_default()
+                        .extend(positions.iter().copied());
+                }
+            }
+        }
+    }
+    Ok(term_positions_per_doc)
+}
+
+/// Returns true if the field is a text field indexed with the positions of its tokens.
+fn has_positions(schema: &Schema, field: Field) -> bool {
+    match schema.get_field_entry(field).field_type() {
+        FieldType::Str(text_options) => text_options
+            .get_indexing_options()
+            .map(|indexing_options| indexing_options.index_option().has_positions())
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// Returns the byte offsets of the tokens of a document found at the given positions,
+/// by tokenizing the values of their fields again.
+fn compute_match_offsets(
+    searcher: &Searcher,
+    doc: &tantivy::Document,
+    term_positions: &BTreeMap<Field, BTreeSet<u32>>,
+) -> anyhow::Result<Vec<MatchOffset>> {
+    let mut match_offsets = Vec::new();
+    for (&field, positions) in term_positions {
+        let field_name = searcher.schema().get_field_name(field);
+        let tokenizer = searcher.index().tokenizer_for_field(field)?;
+        // Replays how tantivy assigns the positions of a multivalued field: the positions of
+        // a value follow those of the previous value, after a gap of one.
+        let mut value_start_position = 0u32;
+        for (value_ord, text) in doc.get_all(field).filter_map(Value::as_text).enumerate() {
+            let mut value_end_position = value_start_position;
+            tokenizer.token_stream(text).process(&mut |token| {
+                let position = value_start_position + token.position as u32;
+                value_end_position =
+                    value_end_position.max(position + token.position_length as u32);
+                if positions.contains(&position) {
+                    match_offsets.push(MatchOffset {
+                        field_name: field_name.to_string(),
+                        value_ord: value_ord as u32,
+                        position,
+                        start_offset: token.offset_from as u32,
+                        end_offset: token.offset_to as u32,
+                    });
+                }
+            });
+            value_start_position = value_end_position + 1;
+        }
+    }
+    Ok(match_offsets)
+}
+
+/// Groups the doc ids of the documents by segment, preserving their order.
+fn group_doc_ids_by_segment(
+    global_doc_addrs: &[GlobalDocAddress],
+) -> BTreeMap<SegmentOrdinal, Vec<DocId>> {
+    let mut doc_ids_per_segment: BTreeMap<SegmentOrdinal, Vec<DocId>> = BTreeMap::new();
+    for global_doc_addr in global_doc_addrs {
+        doc_ids_per_segment
+            .entry(global_doc_addr.doc_addr.segment_ord)
+            .or_default()
+            .push(global_doc_addr.doc_addr.doc_id);
+    }
+    doc_ids_per_segment
+}
+
+/// Returns the name of the field of a term. Fields of JSON objects are named after their
+/// full path, without the prefix of the dynamic field.
+fn matched_field_name(schema: &Schema, term: &Term) -> String {
+    let field_name = schema.get_field_name(term.field());
+    if !matches!(
+        schema.get_field_entry(term.field()).field_type(),
+        FieldType::JsonObject(_)
+    ) {
+        return field_name.to_string();
+    }
+    let value_bytes = term.serialized_value_bytes();
+    let json_path_len = value_bytes
+        .iter()
+        .position(|byte| *byte == JSON_END_OF_PATH)
+        .unwrap_or(value_bytes.len());
+    let json_path = value_bytes[..json_path_len]
+        .split(|byte| *byte == JSON_PATH_SEGMENT_SEP)
+        .map(String::from_utf8_lossy)
+        .join(".");
+    if field_name == DYNAMIC_FIELD_NAME {
+        json_path
+    } else {
+        format!("{field_name}.{json_path}")
+    }
+}
+
 // A struct to hold the snippet generators associated to
 // the snippet fields from a search request.
 #[derive(Clone)]
@@ -19,105 +19,400 @@
 
 use std::ops::{Bound, RangeBounds, RangeInclusive};
 
-use tantivy::columnar::Cardinality;
+use quickwit_proto::SearchRequest;
+use tantivy::columnar::{Cardinality, ColumnType, MonotonicallyMappableToU64, StrColumn};
 use tantivy::fastfield::Column;
-use tantivy::{DateTime, DocId, SegmentReader};
+use tantivy::schema::{FieldType, Schema};
+use tantivy::time::format_description::well_known::Rfc3339;
+use tantivy::time::OffsetDateTime;
+use tantivy::{DatePrecision, DateTime, DocId, SegmentReader, TantivyError};
 
-/// A filter that only retains docs within a time range.
+use crate::SearchError;
+
+/// A filter that only retains docs within a half-open time range `[start, end)`.
+///
+/// The start is inclusive and the end is exclusive, so that a document stamped exactly at the
+/// boundary of two contiguous windows `[t0, t1)` and `[t1, t2)` belongs to the second one only:
+/// back-to-back windows never count a document twice, nor miss it.
 #[derive(Clone)]
-pub struct TimestampFilter {
-    /// The time range represented as (lower_bound, upper_bound).
-    // TODO replace this with a RangeInclusive<DateTime> if it improves perf?
-    time_range: (Bound<DateTime>, Bound<DateTime>),
-    timestamp_column: Column<DateTime>,
+pub enum TimestampFilter {
+    /// No timestamp of the segment lies within the range, so no document matches.
+    NoMatch,
+    Range {
+        /// Inclusive lower bound.
+        start_timestamp_opt: Option<DateTime>,
+        /// Exclusive upper bound.
+        end_timestamp_opt: Option<DateTime>,
+        timestamp_column: Column<DateTime>,
+    },
 }
 
 impl TimestampFilter {
+    /// Documents without a timestamp are never within the range.
     #[inline]
     pub fn is_within_range(&self, doc_id: DocId) -> bool {
-        if let Some(ts) = self.timestamp_column.first(doc_id) {
-            self.time_range.contains(&ts)
-        } else {
-            false
+        let TimestampFilter::Range {
+            start_timestamp_opt,
+            end_timestamp_opt,
+            timestamp_column,
+        } = self
+        else {
+            return false;
+        };
+        let Some(ts) = timestamp_column.first(doc_id) else {
+            return false;
+        };
+        start_timestamp_opt.map_or(true, |start_timestamp| start_timestamp <= ts)
+            && end_timestamp_opt.map_or(true, |end_timestamp| ts < end_timestamp)
+    }
+}
+
+/// Returns the precision at which the timestamps of the field are stored, or the default
+/// precision if the field is not a datetime field of the schema.
+pub fn timestamp_field_precision(schema: &Schema, timestamp_field_name: &str) -> DatePrecision {
+    let Ok(timestamp_field) = schema.get_field(timestamp_field_name) else {
+        return DatePrecision::default();
+    };
+    match schema.get_field_entry(timestamp_field).field_type() {
+        FieldType::Date(date_options) => date_options.get_precision(),
+        _ => DatePrecision::default(),
+    }
+}
+
+/// Returns the start and end bounds of the time range of the request, which are either given as
+/// timestamps in seconds or as RFC3339 datetimes.
+pub fn request_time_range(
+    search_request: &SearchRequest,
+) -> crate::Result<(Option<DateTime>, Option<DateTime>)> {
+    let start_timestamp_opt = parse_timestamp_bound(
+        "start_timestamp",
+        search_request.start_timestamp,
+        search_request.start_timestamp_rfc3339.as_deref(),
+    )?;
+    let end_timestamp_opt = parse_timestamp_bound(
+        "end_timestamp",
+        search_request.end_timestamp,
+        search_request.end_timestamp_rfc3339.as_deref(),
+    )?;
+    Ok((start_timestamp_opt, end_timestamp_opt))
+}
+
+fn parse_timestamp_bound(
+    bound_name: &str,
+    timestamp_secs_opt: Option<i64>,
+    timestamp_rfc3339_opt: Option<&str>,
+) -> crate::Result<Option<DateTime>> {
+    match (timestamp_secs_opt, timestamp_rfc3339_opt) {
+        (None, None) => Ok(None),
+        (Some(timestamp_secs), None) => Ok(Some(DateTime::from_timestamp_secs(timestamp_secs))),
+        (None, Some(timestamp_rfc3339)) => {
+            let datetime = OffsetDateTime::parse(timestamp_rfc3339, &Rfc3339).map_err(|err| {
+                SearchError::InvalidArgument(format!(
+                    "failed to parse `{bound_name}_rfc3339` `{timestamp_rfc3339}` as an RFC3339 \
+                     datetime: {err}"
+                ))
+            })?;
+            Ok(Some(DateTime::from_utc(datetime)))
         }
+        (Some(_), Some(_)) => Err(SearchError::InvalidArgument(format!(
+            "`{bound_name}` and `{bound_name}_rfc3339` are mutually exclusive"
+        ))),
     }
 }
 
 /// Creates a timestamp field depending on the user request.
 ///
-/// The start/end timestamp are in seconds and are interpreted as
-/// a semi-open interval [start, end).
+/// The start/end timestamps are interpreted as a semi-open interval [start, end), see
+/// `request_time_range`.
 pub fn create_timestamp_filter_builder(
     timestamp_field_opt: Option<&str>,
-    start_timestamp_secs: Option<i64>,
-    end_timestamp_secs: Option<i64>,
+    timestamp_precision: DatePrecision,
+    start_timestamp_opt: Option<DateTime>,
+    end_timestamp_opt: Option<DateTime>,
 ) -> Option<TimestampFilterBuilder> {
     let timestamp_field = timestamp_field_opt?;
-    if start_timestamp_secs.is_none() && end_timestamp_secs.is_none() {
+    if start_timestamp_opt.is_none() && end_timestamp_opt.is_none() {
         return None;
     }
-    let start_timestamp_bound: Bound<DateTime> = start_timestamp_secs
-        .map(|timestamp_secs| Bound::Included(DateTime::from_timestamp_secs(timestamp_secs)))
-        .unwrap_or(Bound::Unbounded);
-    let end_timestamp_bound: Bound<DateTime> = end_timestamp_secs
-        .map(|timestamp_secs| Bound::Excluded(DateTime::from_timestamp_secs(timestamp_secs)))
-        .unwrap_or(Bound::Unbounded);
     Some(TimestampFilterBuilder::new(
         timestamp_field.to_string(),
-        start_timestamp_bound,
-        end_timestamp_bound,
+        timestamp_precision,
+        start_timestamp_opt,
+        end_timestamp_opt,
     ))
 }
 
+/// Builds the timestamp filter of each segment, see `TimestampFilter` for the semantics of the
+/// range.
+///
+/// The timestamps of a field are truncated to its precision when indexed, so the bounds are
+/// truncated the same way: a document stored at `t` is within the range if
+/// `truncate(start) <= t < truncate(end)`. Without this, a sub-second start bound on a field
+/// stored at a second precision would exclude the documents of that very second.
 #[derive(Clone, Debug)]
 pub struct TimestampFilterBuilder {
+    /// Name of the datetime fast field holding the timestamps.
     pub timestamp_field_name: String,
-    start_timestamp: Bound<DateTime>,
-    end_timestamp: Bound<DateTime>,
+    /// Inclusive lower bound.
+    start_timestamp_opt: Option<DateTime>,
+    /// Exclusive upper bound.
+    end_timestamp_opt: Option<DateTime>,
 }
 
 impl TimestampFilterBuilder {
+    /// Creates a filter builder for the range `[start, end)`, either bound being optional.
     pub fn new(
         timestamp_field_name: String,
-        start_timestamp: Bound<DateTime>,
-        end_timestamp: Bound<DateTime>,
+        timestamp_precision: DatePrecision,
+        start_timestamp_opt: Option<DateTime>,
+        end_timestamp_opt: Option<DateTime>,
     ) -> TimestampFilterBuilder {
+        let truncate = |timestamp: DateTime| timestamp.truncate(timestamp_precision);
         TimestampFilterBuilder {
             timestamp_field_name,
-            start_timestamp,
-            end_timestamp,
+            start_timestamp_opt: start_timestamp_opt.map(truncate),
+            end_timestamp_opt: end_timestamp_opt.map(truncate),
         }
     }
 
+    fn time_range(&self) -> (Bound<DateTime>, Bound<DateTime>) {
+        let start_bound = self
+            .start_timestamp_opt
+            .map_or(Bound::Unbounded, Bound::Included);
+        let end_bound = self
+            .end_timestamp_opt
+            .map_or(Bound::Unbounded, Bound::Excluded);
+        (start_bound, end_bound)
+    }
+
     /// None means that all documents are matching the timestamp range.
+    ///
+    /// Before looking at any document, compares the range with the timestamp range of the
+    /// segment: no filter is needed if every document of the segment has a timestamp within the
+    /// range, and no document matches if all the timestamps of the segment are outside of it.
     pub fn build(
         &self,
         segment_reader: &SegmentReader,
     ) -> tantivy::Result<Option<TimestampFilter>> {
+        if self.start_timestamp_opt.is_none() && self.end_timestamp_opt.is_none() {
+            return Ok(None);
+        }
         let timestamp_column_opt: Option<Column<DateTime>> =
             segment_reader
                 .fast_fields()
                 .column_opt::<DateTime>(&self.timestamp_field_name)?;
-        let timestamp_column = timestamp_column_opt
-            .unwrap_or_else(|| Column::build_empty_column(segment_reader.max_doc()));
-        let time_range = (self.start_timestamp, self.end_timestamp);
-        if time_range == (Bound::Unbounded, Bound::Unbounded) {
+        let Some(timestamp_column) = timestamp_column_opt else {
+            return Ok(Some(TimestampFilter::NoMatch));
+        };
+        let segment_range: RangeInclusive<DateTime> =
+            timestamp_column.min_value()..=timestamp_column.max_value();
+        // The documents without a timestamp only matter to the first check: they never match.
+        if timestamp_column.index.get_cardinality() == Cardinality::Full
+            && is_segment_always_within_timestamp_range(segment_range.clone(), self.time_range())
+        {
             return Ok(None);
         }
-        if timestamp_column.index.get_cardinality() == Cardinality::Full {
-            let segment_range: RangeInclusive<DateTime> =
-                timestamp_column.min_value()..=timestamp_column.max_value();
-            if is_segment_always_within_timestamp_range(segment_range, time_range) {
-                return Ok(None);
-            }
+        if is_segment_always_outside_timestamp_range(segment_range, self.time_range()) {
+            return Ok(Some(TimestampFilter::NoMatch));
         }
-        Ok(Some(TimestampFilter {
-            time_range,
+        Ok(Some(TimestampFilter::Range {
+            start_timestamp_opt: self.start_timestamp_opt,
+            end_timestamp_opt: self.end_timestamp_opt,
             timestamp_column,
         }))
     }
 }
 
+/// A filter that only retains docs whose fast field holds a given value.
+pub enum FastFieldEqualsFilter {
+    /// The value does not appear in the segment, so no document matches.
+    NoMatch,
+    Numerical {
+        column: Column<u64>,
+        value: u64,
+    },
+    Str {
+        str_column: StrColumn,
+        term_ord: u64,
+    },
+}
+
+impl FastFieldEqualsFilter {
+    #[inline]
+    pub fn is_match(&self, doc_id: DocId) -> bool {
+        match self {
+            FastFieldEqualsFilter::NoMatch => false,
+            FastFieldEqualsFilter::Numerical { column, value } => column
+                .values_for_doc(doc_id)
+                .any(|doc_value| doc_value == *value),
+            FastFieldEqualsFilter::Str {
+                str_column,
+                term_ord,
+            } => str_column
+                .term_ords(doc_id)
+                .any(|doc_term_ord| doc_term_ord == *term_ord),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FastFieldEqualsFilterBuilder {
+    pub field_name: String,
+    value: String,
+}
+
+impl FastFieldEqualsFilterBuilder {
+    pub fn new(field_name: String, value: String) -> FastFieldEqualsFilterBuilder {
+        FastFieldEqualsFilterBuilder { field_name, value }
+    }
+
+    /// Before looking at any document, checks whether the value can appear in the segment
+    /// at all, using the dictionary of str fields and the value range of numerical fields.
+    pub fn build(&self, segment_reader: &SegmentReader) -> tantivy::Result<FastFieldEqualsFilter> {
+        let fast_fields = segment_reader.fast_fields();
+        if let Some(str_column) = fast_fields.str(&self.field_name)? {
+            let term_ord_opt = str_column.dictionary().term_ord(self.value.as_bytes())?;
+            let Some(term_ord) = term_ord_opt else {
+                return Ok(FastFieldEqualsFilter::NoMatch);
+            };
+            return Ok(FastFieldEqualsFilter::Str {
+                str_column,
+                term_ord,
+            });
+        }
+        let Some((column, column_type)) = fast_fields.u64_lenient(&self.field_name)? else {
+            return Ok(FastFieldEqualsFilter::NoMatch);
+        };
+        let value = parse_fast_field_value(&self.value, column_type).ok_or_else(|| {
+            TantivyError::InvalidArgument(format!(
+                "Value `{}` is not a valid {:?} for fast field `{}`.",
+                self.value, column_type, self.field_name
+            ))
+        })?;
+        if value < column.min_value() || value > column.max_value() {
+            return Ok(FastFieldEqualsFilter::NoMatch);
+        }
+        Ok(FastFieldEqualsFilter::Numerical { column, value })
+    }
+}
+
+/// A filter that only retains docs whose numerical fast field holds a value within a range.
+pub enum FastFieldRangeFilter {
+    /// No value of the segment lies within the range, so no document matches.
+    NoMatch,
+    Range {
+        column: Column<u64>,
+        /// The range, mapped to the u64 representation of the values of the column.
+        range: RangeInclusive<u64>,
+    },
+}
+
+impl FastFieldRangeFilter {
+    #[inline]
+    pub fn is_match(&self, doc_id: DocId) -> bool {
+        match self {
+            FastFieldRangeFilter::NoMatch => false,
+            FastFieldRangeFilter::Range { column, range } => column
+                .values_for_doc(doc_id)
+                .any(|doc_value| range.contains(&doc_value)),
+        }
+    }
+}
+
+/// Builds the range filter of each segment. The range is `[lower_bound, upper_bound)`, like the
+/// time range of a request.
+#[derive(Clone, Debug)]
+pub struct FastFieldRangeFilterBuilder {
+    pub field_name: String,
+    lower_bound_opt: Option<i64>,
+    upper_bound_opt: Option<i64>,
+}
+
+impl FastFieldRangeFilterBuilder {
+    pub fn new(
+        field_name: String,
+        lower_bound_opt: Option<i64>,
+        upper_bound_opt: Option<i64>,
+    ) -> FastFieldRangeFilterBuilder {
+        FastFieldRangeFilterBuilder {
+            field_name,
+            lower_bound_opt,
+            upper_bound_opt,
+        }
+    }
+
+    /// Before looking at any document, checks whether the range intersects the value range of
+    /// the column.
+    pub fn build(&self, segment_reader: &SegmentReader) -> tantivy::Result<FastFieldRangeFilter> {
+        let Some((column, column_type)) =
+            segment_reader.fast_fields().u64_lenient(&self.field_name)?
+        else {
+            return Ok(FastFieldRangeFilter::NoMatch);
+        };
+        if !matches!(
+            column_type,
+            ColumnType::U64 | ColumnType::I64 | ColumnType::F64 | ColumnType::DateTime
+        ) {
+            return Err(TantivyError::InvalidArgument(format!(
+                "Range filters are not supported on {:?} fast field `{}`.",
+                column_type, self.field_name
+            )));
+        }
+        let Some(range) =
+            fast_field_range_to_u64(self.lower_bound_opt, self.upper_bound_opt, column_type)
+        else {
+            return Ok(FastFieldRangeFilter::NoMatch);
+        };
+        if *range.end() < column.min_value() || *range.start() > column.max_value() {
+            return Ok(FastFieldRangeFilter::NoMatch);
+        }
+        Ok(FastFieldRangeFilter::Range { column, range })
+    }
+}
+
+/// Maps the range `[lower_bound, upper_bound)` to an inclusive range of the u64 representation of
+/// a numerical or datetime fast field, or returns `None` if the range is empty. The bounds of
+/// datetime fields are timestamps in seconds.
+///
+/// The mapping to u64 preserves the order of the values and maps adjacent values to adjacent
+/// integers, so the exclusive upper bound becomes the integer right below it.
+fn fast_field_range_to_u64(
+    lower_bound_opt: Option<i64>,
+    upper_bound_opt: Option<i64>,
+    column_type: ColumnType,
+) -> Option<RangeInclusive<u64>> {
+    let to_u64 = |bound: i64| -> u64 {
+        match column_type {
+            ColumnType::I64 => bound.to_u64(),
+            ColumnType::F64 => (bound as f64).to_u64(),
+            ColumnType::DateTime => DateTime::from_timestamp_secs(bound).to_u64(),
+            _ => bound.max(0) as u64,
+        }
+    };
+    let lower_bound = lower_bound_opt.map(to_u64).unwrap_or(u64::MIN);
+    let upper_bound = match upper_bound_opt {
+        // Bounding an unsigned field by 0 or less excludes every value.
+        Some(upper_bound) if column_type == ColumnType::U64 && upper_bound <= 0 => return None,
+        Some(upper_bound) => to_u64(upper_bound).checked_sub(1)?,
+        None => u64::MAX,
+    };
+    if lower_bound > upper_bound {
+        return None;
+    }
+    Some(lower_bound..=upper_bound)
+}
+
+/// Parses a value into the u64 representation of a fast field of the given type.
+fn parse_fast_field_value(value: &str, column_type: ColumnType) -> Option<u64> {
+    match column_type {
+        ColumnType::U64 => value.parse::<u64>().ok(),
+        ColumnType::I64 => value.parse::<i64>().ok().map(i64::to_u64),
+        ColumnType::F64 => value.parse::<f64>().ok().map(f64::to_u64),
+        ColumnType::Bool => value.parse::<bool>().ok().map(bool::to_u64),
+        _ => None,
+    }
+}
+
 /// Determine if all docs of a segment always satisfy the requested timestamp range.
 ///
 /// Note:
@@ -130,11 +425,39 @@ fn is_segment_always_within_timestamp_range(
     timestamp_range.contains(segment_range.start()) && timestamp_range.contains(segment_range.end())
 }
 
+/// Determine if no doc of a segment can satisfy the requested timestamp range, with the same
+/// ranges as `is_segment_always_within_timestamp_range`.
+fn is_segment_always_outside_timestamp_range(
+    segment_range: RangeInclusive<DateTime>,
+    timestamp_range: impl RangeBounds<DateTime>,
+) -> bool {
+    let is_before_range = match timestamp_range.start_bound() {
+        Bound::Included(start) => segment_range.end() < start,
+        Bound::Excluded(start) => segment_range.end() <= start,
+        Bound::Unbounded => false,
+    };
+    let is_after_range = match timestamp_range.end_bound() {
+        Bound::Included(end) => segment_range.start() > end,
+        Bound::Excluded(end) => segment_range.start() >= end,
+        Bound::Unbounded => false,
+    };
+    is_before_range || is_after_range
+}
+
 #[cfg(test)]
 mod tests {
-    use tantivy::DateTime;
+    use std::ops::Bound;
+
+    use quickwit_proto::SearchRequest;
+    use tantivy::columnar::{ColumnType, MonotonicallyMappableToU64};
+    use tantivy::schema::{DateOptions, Schema};
+    use tantivy::{doc, DatePrecision, DateTime, Index};
 
-    use super::is_segment_always_within_timestamp_range;
+    use super::{
+        fast_field_range_to_u64, is_segment_always_outside_timestamp_range,
+        is_segment_always_within_timestamp_range, parse_fast_field_value, request_time_range,
+        timestamp_field_precision, TimestampFilter, TimestampFilterBuilder,
+    };
 
     const TEST_START: DateTime = DateTime::from_timestamp_secs(1_662_529_435);
     const TEST_MIDDLE: DateTime = DateTime::from_timestamp_secs(1_662_629_435);
@@ -168,4 +491,310 @@ mod tests {
             false
         );
     }
+
+    #[test]
+    fn test_fast_field_range_to_u64() {
+        assert_eq!(
+            fast_field_range_to_u64(Some(-3), Some(5), ColumnType::I64),
+            Some((-3i64).to_u64()..=4i64.to_u64())
+        );
+        assert_eq!(
+            fast_field_range_to_u64(None, None, ColumnType::I64),
+            Some(u64::MIN..=u64::MAX)
+        );
+        assert_eq!(
+            fast_field_range_to_u64(Some(-3), Some(5), ColumnType::U64),
+            Some(0..=4)
+        );
+        assert_eq!(
+            fast_field_range_to_u64(None, Some(0), ColumnType::U64),
+            None
+        );
+        assert_eq!(
+            fast_field_range_to_u64(Some(1), Some(2), ColumnType::DateTime),
+            Some(
+                DateTime::from_timestamp_secs(1).to_u64()
+                    ..=DateTime::from_timestamp_micros(1_999_999).to_u64()
+            )
+        );
+        let f64_range = fast_field_range_to_u64(Some(1), Some(2), ColumnType::F64).unwrap();
+        assert!(f64_range.contains(&1.0f64.to_u64()));
+        assert!(f64_range.contains(&1.999f64.to_u64()));
+        assert!(!f64_range.contains(&2.0f64.to_u64()));
+        // Empty ranges.
+        assert_eq!(
+            fast_field_range_to_u64(Some(5), Some(5), ColumnType::I64),
+            None
+        );
+        assert_eq!(
+            fast_field_range_to_u64(Some(6), Some(5), ColumnType::I64),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_fast_field_value() {
+        assert_eq!(parse_fast_field_value("42", ColumnType::U64), Some(42));
+        assert_eq!(
+            parse_fast_field_value("-42", ColumnType::I64),
+            Some((-42i64).to_u64())
+        );
+        assert_eq!(
+            parse_fast_field_value("4.2", ColumnType::F64),
+            Some(4.2f64.to_u64())
+        );
+        assert_eq!(parse_fast_field_value("true", ColumnType::Bool), Some(1));
+        assert_eq!(parse_fast_field_value("-42", ColumnType::U64), None);
+        assert_eq!(parse_fast_field_value("42", ColumnType::Bytes), None);
+    }
+
+    /// Indexes the given timestamps, in microseconds, into a field of the given precision and
+    /// returns those that fall within the bounds.
+    fn filter_timestamps(
+        precision: DatePrecision,
+        timestamps_micros: &[i64],
+        start_timestamp_opt: Option<DateTime>,
+        end_timestamp_opt: Option<DateTime>,
+    ) -> Vec<i64> {
+        let mut schema_builder = Schema::builder();
+        let timestamp_field = schema_builder.add_date_field(
+            "timestamp",
+            DateOptions::default().set_fast().set_precision(precision),
+        );
+        let schema = schema_builder.build();
+        assert_eq!(timestamp_field_precision(&schema, "timestamp"), precision);
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        for &timestamp_micros in timestamps_micros {
+            index_writer
+                .add_document(doc!(
+                    timestamp_field => DateTime::from_timestamp_micros(timestamp_micros)
+                ))
+                .unwrap();
+        }
+        index_writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        let segment_reader = searcher.segment_reader(0);
+        let timestamp_filter_builder = TimestampFilterBuilder::new(
+            "timestamp".to_string(),
+            precision,
+            start_timestamp_opt,
+            end_timestamp_opt,
+        );
+        let Some(timestamp_filter) = timestamp_filter_builder.build(segment_reader).unwrap() else {
+            return timestamps_micros.to_vec();
+        };
+        (0..segment_reader.max_doc())
+            .filter(|&doc_id| timestamp_filter.is_within_range(doc_id))
+            .map(|doc_id| timestamps_micros[doc_id as usize])
+            .collect()
+    }
+
+    #[test]
+    fn test_timestamp_filter_sub_second_range() {
+        let timestamps_micros = [
+            1_000_000, 1_000_100, 1_000_250, 1_000_999, 1_001_000, 1_500_000, 2_000_000,
+        ];
+        let start = Some(DateTime::from_timestamp_micros(1_000_100));
+        let end = Some(DateTime::from_timestamp_micros(1_001_000));
+        assert_eq!(
+            filter_timestamps(DatePrecision::Microseconds, &timestamps_micros, start, end),
+            [1_000_100, 1_000_250, 1_000_999]
+        );
+        // The bounds are truncated to the milliseconds, like the timestamps.
+        assert_eq!(
+            filter_timestamps(DatePrecision::Milliseconds, &timestamps_micros, start, end),
+            [1_000_000, 1_000_100, 1_000_250, 1_000_999]
+        );
+        // A millisecond start bound against a field stored at a second precision retains the
+        // documents of its second.
+        let start = Some(DateTime::from_timestamp_micros(1_250_000));
+        let end = Some(DateTime::from_timestamp_micros(2_500_000));
+        assert_eq!(
+            filter_timestamps(DatePrecision::Seconds, &timestamps_micros, start, end),
+            &timestamps_micros[..6]
+        );
+    }
+
+    #[test]
+    fn test_timestamp_filter_is_half_open() {
+        let timestamps_micros = [
+            0, 999_999, 1_000_000, 1_000_001, 1_999_999, 2_000_000, 2_999_999, 3_000_000,
+        ];
+        let at_secs = |timestamp_secs: i64| Some(DateTime::from_timestamp_secs(timestamp_secs));
+        // The start is inclusive and the end is exclusive.
+        assert_eq!(
+            filter_timestamps(
+                DatePrecision::Microseconds,
+                &timestamps_micros,
+                at_secs(1),
+                at_secs(2)
+            ),
+            [1_000_000, 1_000_001, 1_999_999]
+        );
+        assert_eq!(
+            filter_timestamps(
+                DatePrecision::Microseconds,
+                &timestamps_micros,
+                None,
+                at_secs(1)
+            ),
+            [0, 999_999]
+        );
+        assert_eq!(
+            filter_timestamps(
+                DatePrecision::Microseconds,
+                &timestamps_micros,
+                at_secs(3),
+                None
+            ),
+            [3_000_000]
+        );
+        // Back-to-back windows retain each document exactly once.
+        let windows = [
+            (None, at_secs(1)),
+            (at_secs(1), at_secs(2)),
+            (at_secs(2), at_secs(3)),
+            (at_secs(3), None),
+        ];
+        let mut filtered_timestamps_micros: Vec<i64> = windows
+            .into_iter()
+            .flat_map(|(start, end)| {
+                filter_timestamps(DatePrecision::Microseconds, &timestamps_micros, start, end)
+            })
+            .collect();
+        filtered_timestamps_micros.sort();
+        assert_eq!(filtered_timestamps_micros, timestamps_micros);
+    }
+
+    #[test]
+    fn test_is_segment_always_within_timestamp_range_boundaries() {
+        // A segment ending exactly at the end of the range is not entirely within it.
+        assert!(!is_segment_always_within_timestamp_range(
+            TEST_START..=TEST_END,
+            TEST_START..TEST_END
+        ));
+        assert!(is_segment_always_within_timestamp_range(
+            TEST_START..=TEST_END,
+            TEST_START..=TEST_END
+        ));
+        assert!(!is_segment_always_within_timestamp_range(
+            TEST_START..=TEST_END,
+            (Bound::Excluded(TEST_START), Bound::Unbounded)
+        ));
+    }
+
+    #[test]
+    fn test_is_segment_always_outside_timestamp_range() {
+        assert!(!is_segment_always_outside_timestamp_range(
+            TEST_START..=TEST_MIDDLE,
+            ..
+        ));
+        assert!(is_segment_always_outside_timestamp_range(
+            TEST_START..=TEST_MIDDLE,
+            TEST_END..
+        ));
+        // The end of the range is exclusive.
+        assert!(is_segment_always_outside_timestamp_range(
+            TEST_MIDDLE..=TEST_END,
+            TEST_START..TEST_MIDDLE
+        ));
+        assert!(!is_segment_always_outside_timestamp_range(
+            TEST_MIDDLE..=TEST_END,
+            TEST_START..=TEST_MIDDLE
+        ));
+        assert!(!is_segment_always_outside_timestamp_range(
+            TEST_START..=TEST_END,
+            TEST_MIDDLE..TEST_END
+        ));
+    }
+
+    #[test]
+    fn test_timestamp_filter_builder_short_circuits_segments() {
+        let mut schema_builder = Schema::builder();
+        let timestamp_field =
+            schema_builder.add_date_field("timestamp", DateOptions::default().set_fast());
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        for timestamp_secs in [10, 20, 30] {
+            index_writer
+                .add_document(doc!(
+                    timestamp_field => DateTime::from_timestamp_secs(timestamp_secs)
+                ))
+                .unwrap();
+        }
+        index_writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        let segment_reader = searcher.segment_reader(0);
+        let build_filter = |timestamp_field_name: &str, start_secs: i64, end_secs: i64| {
+            TimestampFilterBuilder::new(
+                timestamp_field_name.to_string(),
+                DatePrecision::Seconds,
+                Some(DateTime::from_timestamp_secs(start_secs)),
+                Some(DateTime::from_timestamp_secs(end_secs)),
+            )
+            .build(segment_reader)
+            .unwrap()
+        };
+        // The segment is fully inside the range: no filter is needed.
+        assert!(build_filter("timestamp", 0, 100).is_none());
+        assert!(build_filter("timestamp", 10, 31).is_none());
+
+        // The segment is fully outside the range: no document matches.
+        for (start_secs, end_secs) in [(40, 50), (0, 10), (31, 100)] {
+            assert!(matches!(
+                build_filter("timestamp", start_secs, end_secs),
+                Some(TimestampFilter::NoMatch)
+            ));
+        }
+        assert!(matches!(
+            build_filter("missing_timestamp", 0, 100),
+            Some(TimestampFilter::NoMatch)
+        ));
+
+        // The range cuts through the segment: the documents are checked one by one.
+        let timestamp_filter = build_filter("timestamp", 15, 30).unwrap();
+        assert!(matches!(timestamp_filter, TimestampFilter::Range { .. }));
+        let matching_doc_ids: Vec<u32> = (0..segment_reader.max_doc())
+            .filter(|&doc_id| timestamp_filter.is_within_range(doc_id))
+            .collect();
+        assert_eq!(matching_doc_ids, [1]);
+    }
+
+    #[test]
+    fn test_request_time_range() {
+        let search_request = SearchRequest {
+            start_timestamp: Some(1_673_363_615),
+            end_timestamp_rfc3339: Some("2023-01-10T15:13:35.123456+01:00".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            request_time_range(&search_request).unwrap(),
+            (
+                Some(DateTime::from_timestamp_secs(1_673_363_615)),
+                Some(DateTime::from_timestamp_micros(1_673_360_015_123_456))
+            )
+        );
+        assert_eq!(
+            request_time_range(&SearchRequest::default()).unwrap(),
+            (None, None)
+        );
+        let search_request = SearchRequest {
+            start_timestamp_rfc3339: Some("2023-01-10".to_string()),
+            ..Default::default()
+        };
+        let error = request_time_range(&search_request).unwrap_err();
+        assert!(error.to_string().starts_with(
+            "Invalid argument: failed to parse `start_timestamp_rfc3339` `2023-01-10`"
+        ));
+        let search_request = SearchRequest {
+            end_timestamp: Some(1_673_363_615),
+            end_timestamp_rfc3339: Some("2023-01-10T15:13:35Z".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            request_time_range(&search_request).unwrap_err().to_string(),
+            "Invalid argument: `end_timestamp` and `end_timestamp_rfc3339` are mutually exclusive"
+        );
+    }
 }
@@ -126,15 +126,58 @@ pub struct FindTraceIdsCollector {
     pub trace_id_field_name: String,
     /// The name of the fast field recording the spans' start timestamp.
     pub span_timestamp_field_name: String,
+    /// The name of the fast field storing the spans' service name. Must be set along with
+    /// `service_name`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_name_field: Option<String>,
+    /// When set, only the spans of this service are considered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_name: Option<String>,
 }
 
 impl FindTraceIdsCollector {
     /// The names of the fast fields accessed by this collector.
     pub fn fast_field_names(&self) -> HashSet<String> {
-        HashSet::from_iter([
+        let mut fast_field_names = HashSet::from_iter([
             self.trace_id_field_name.clone(),
             self.span_timestamp_field_name.clone(),
-        ])
+        ]);
+        if let Some(service_name_field) = &self.service_name_field {
+            fast_field_names.insert(service_name_field.clone());
+        }
+        fast_field_names
+    }
+
+    fn open_service_name_filter(
+        &self,
+        segment_reader: &SegmentReader,
+    ) -> tantivy::Result<Option<ServiceNameFilter>> {
+        let (service_name_field, service_name) =
+            match (&self.service_name_field, &self.service_name) {
+                (Some(service_name_field), Some(service_name)) => {
+                    (service_name_field, service_name)
+                }
+                (None, None) => return Ok(None),
+                _ => {
+                    return Err(tantivy::TantivyError::InvalidArgument(
+                        "`service_name_field` and `service_name` must be set together".to_string(),
+                    ))
+                }
+            };
+        let Some(service_name_column) = segment_reader.fast_fields().str(service_name_field)?
+        else {
+            return Ok(Some(ServiceNameFilter::NoMatch));
+        };
+        let Some(service_name_term_ord) = service_name_column
+            .dictionary()
+            .term_ord(service_name.as_bytes())?
+        else {
+            return Ok(Some(ServiceNameFilter::NoMatch));
+        };
+        Ok(Some(ServiceNameFilter::Match {
+            service_name_column,
+            service_name_term_ord,
+        }))
     }
 
     /// The field names of the term dictionaries accessed by this collector.
@@ -165,9 +208,11 @@ impl Collector for FindTraceIdsCollector {
         let span_timestamp_column: Column<DateTime> = segment_reader
             .fast_fields()
             .date(&self.span_timestamp_field_name)?;
+        let service_name_filter_opt = self.open_service_name_filter(segment_reader)?;
         Ok(FindTraceIdsSegmentCollector {
             trace_id_column,
             span_timestamp_column,
+            service_name_filter_opt,
             select_trace_ids: SelectTraceIds::new(self.num_traces),
         })
     }
@@ -204,9 +249,35 @@ fn merge_segment_fruits(mut segment_fruits: Vec<Vec<Span>>, num_traces: usize) -
     trace_ids
 }
 
+/// Restricts the spans to the ones of a given service. The service name is looked up once per
+/// segment in the dictionary of the column, so that documents are matched by term ordinal.
+enum ServiceNameFilter {
+    Match {
+        service_name_column: StrColumn,
+        service_name_term_ord: TermOrd,
+    },
+    /// The service does not appear in the segment.
+    NoMatch,
+}
+
+impl ServiceNameFilter {
+    fn is_match(&self, doc: DocId) -> bool {
+        match self {
+            ServiceNameFilter::Match {
+                service_name_column,
+                service_name_term_ord,
+            } => service_name_column
+                .term_ords(doc)
+                .any(|term_ord| term_ord == *service_name_term_ord),
+            ServiceNameFilter::NoMatch => false,
+        }
+    }
+}
+
 pub struct FindTraceIdsSegmentCollector {
     trace_id_column: StrColumn,
     span_timestamp_column: Column<DateTime>,
+    service_name_filter_opt: Option<ServiceNameFilter>,
     select_trace_ids: SelectTraceIds,
 }
 
@@ -227,6 +298,11 @@ impl SegmentCollector for FindTraceIdsSegmentCollector {
     type Fruit = Vec<Span>;
 
     fn collect(&mut self, doc: DocId, _score: Score) {
+        if let Some(service_name_filter) = &self.service_name_filter_opt {
+            if !service_name_filter.is_match(doc) {
+                return;
+            }
+        }
         let term_ord = self.trace_id_term_ord(doc);
         let span_timestamp = self.span_timestamp(doc);
         self.select_trace_ids.collect(term_ord, span_timestamp);
@@ -402,6 +478,8 @@ mod tests {
             num_traces: 10,
             trace_id_field_name: "trace_id".to_string(),
             span_timestamp_field_name: "span_timestamp".to_string(),
+            service_name_field: None,
+            service_name: None,
         })
         .unwrap();
         let aggregation: QuickwitAggregations = serde_json::from_str(&collector_json).unwrap();
@@ -411,6 +489,33 @@ mod tests {
         assert_eq!(collector.num_traces, 10);
         assert_eq!(collector.trace_id_field_name, "trace_id");
         assert_eq!(collector.span_timestamp_field_name, "span_timestamp");
+        assert!(collector.service_name_field.is_none());
+        assert!(collector.service_name.is_none());
+    }
+
+    #[test]
+    fn test_find_trace_ids_collector_fast_field_names() {
+        let mut collector = FindTraceIdsCollector {
+            num_traces: 10,
+            trace_id_field_name: "trace_id".to_string(),
+            span_timestamp_field_name: "span_timestamp".to_string(),
+            service_name_field: None,
+            service_name: None,
+        };
+        assert_eq!(
+            collector.fast_field_names(),
+            HashSet::from_iter(["trace_id".to_string(), "span_timestamp".to_string()])
+        );
+        collector.service_name_field = Some("service_name".to_string());
+        collector.service_name = Some("quickwit".to_string());
+        assert_eq!(
+            collector.fast_field_names(),
+            HashSet::from_iter([
+                "trace_id".to_string(),
+                "span_timestamp".to_string(),
+                "service_name".to_string()
+            ])
+        );
     }
 
     #[test]
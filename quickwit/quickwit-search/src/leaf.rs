@@ -17,19 +17,22 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
 use std::ops::Bound;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
 use futures::future::try_join_all;
 use itertools::{Either, Itertools};
+use quickwit_config::SearcherConfig;
 use quickwit_directories::{CachingDirectory, HotDirectory, StorageDirectory};
-use quickwit_doc_mapper::{DocMapper, WarmupInfo, QUICKWIT_TOKENIZER_MANAGER};
+use quickwit_doc_mapper::{DocMapper, QueryParserError, WarmupInfo, QUICKWIT_TOKENIZER_MANAGER};
 use quickwit_proto::{
-    LeafListTermsResponse, LeafSearchResponse, ListTermsRequest, SearchRequest,
-    SplitIdAndFooterOffsets, SplitSearchError,
+    CacheStatus, LeafListTermsResponse, LeafSearchResponse, ListTermsRequest, SearchRequest,
+    SplitIdAndFooterOffsets,
 };
 use quickwit_storage::{
     wrap_storage_with_long_term_cache, BundleStorage, MemorySizedCache, OwnedBytes, Storage,
@@ -43,21 +46,24 @@ use tantivy::{Index, ReloadPolicy, Searcher, Term};
 use tracing::*;
 
 use crate::collector::{
-    aggregation_limits_from_searcher_context, make_collector_for_split, make_merge_collector,
+    aggregation_bucket_limit, aggregation_limits_from_searcher_context, make_collector_for_split,
+    make_merge_collector, resolve_max_hits_ceiling, QuickwitAggregations,
 };
 use crate::service::SearcherContext;
+use crate::total_hits_estimate::estimate_total_hits;
 use crate::SearchError;
 
+/// Returns the footer of the split, and whether it was found in the cache.
 #[instrument(skip(index_storage, footer_cache))]
 async fn get_split_footer_from_cache_or_fetch(
     index_storage: Arc<dyn Storage>,
     split_and_footer_offsets: &SplitIdAndFooterOffsets,
     footer_cache: &MemorySizedCache<String>,
-) -> anyhow::Result<OwnedBytes> {
+) -> anyhow::Result<(OwnedBytes, bool)> {
     {
         let possible_val = footer_cache.get(&split_and_footer_offsets.split_id);
         if let Some(footer_data) = possible_val {
-            return Ok(footer_data);
+            return Ok((footer_data, true));
         }
     }
     let split_file = PathBuf::from(format!("{}.split", split_and_footer_offsets.split_id));
@@ -81,7 +87,7 @@ async fn get_split_footer_from_cache_or_fetch(
         footer_data_opt.clone(),
     );
 
-    Ok(footer_data_opt)
+    Ok((footer_data_opt, false))
 }
 
 /// Opens a `tantivy::Index` for the given split with several cache layers:
@@ -95,8 +101,26 @@ pub(crate) async fn open_index_with_caches(
     split_and_footer_offsets: &SplitIdAndFooterOffsets,
     ephemeral_unbounded_cache: bool,
 ) -> anyhow::Result<Index> {
+    let (index, _cache_status) = open_index_with_cache_status(
+        searcher_context,
+        index_storage,
+        split_and_footer_offsets,
+        ephemeral_unbounded_cache,
+    )
+    .await?;
+    Ok(index)
+}
+
+/// Same as `open_index_with_caches`, also telling whether the split was warm, i.e. whether its
+/// footer was found in the split footer cache.
+async fn open_index_with_cache_status(
+    searcher_context: &Arc<SearcherContext>,
+    index_storage: Arc<dyn Storage>,
+    split_and_footer_offsets: &SplitIdAndFooterOffsets,
+    ephemeral_unbounded_cache: bool,
+) -> anyhow::Result<(Index, CacheStatus)> {
     let split_file = PathBuf::from(format!("{}.split", split_and_footer_offsets.split_id));
-    let footer_data = get_split_footer_from_cache_or_fetch(
+    let (footer_data, is_warm) = get_split_footer_from_cache_or_fetch(
         index_storage.clone(),
         split_and_footer_offsets,
         &searcher_context.split_footer_cache,
@@ -121,7 +145,11 @@ pub(crate) async fn open_index_with_caches(
     };
     let mut index = Index::open(hot_directory)?;
     index.set_tokenizers(QUICKWIT_TOKENIZER_MANAGER.clone());
-    Ok(index)
+    let cache_status = CacheStatus {
+        num_warm_splits: is_warm as u64,
+        num_cold_splits: !is_warm as u64,
+    };
+    Ok((index, cache_status))
 }
 
 /// Tantivy search does not make it possible to fetch data asynchronously during
@@ -301,6 +329,98 @@ async fn warm_up_fieldnorms(searcher: &Searcher, requires_scoring: bool) -> anyh
     Ok(())
 }
 
+/// Returns what the leaf search of the request warms up on a split before running the search:
+/// the terms, term dictionaries and postings of the query, and the fast fields and field norms
+/// of the collector.
+///
+/// It only depends on the request and the doc mapping, which makes it possible to inspect the
+/// warmup of a request without running it.
+pub fn resolve_warmup_info(
+    doc_mapper: &dyn DocMapper,
+    search_request: &SearchRequest,
+) -> crate::Result<WarmupInfo> {
+    let quickwit_collector = make_collector_for_split(
+        String::new(),
+        doc_mapper,
+        search_request,
+        AggregationLimits::default(),
+        &SearcherConfig::default(),
+        None,
+    )?;
+    let (_query, mut warmup_info) = doc_mapper.query(doc_mapper.schema(), search_request)?;
+    warmup_info.merge(quickwit_collector.warmup_info());
+    Ok(warmup_info)
+}
+
+/// Estimates the number of bytes the warmup loads for each field of the split, summed over its
+/// segments.
+///
+/// Only the fast fields and the field norms are accounted for, as their size is known upfront.
+/// The term dictionaries and postings are left out, so the estimate is a lower bound when the
+/// warmup needs them.
+pub async fn estimate_warmup_num_bytes(
+    searcher: &Searcher,
+    warmup_info: &WarmupInfo,
+) -> anyhow::Result<BTreeMap<String, u64>> {
+    let schema = searcher.schema();
+    let mut num_bytes_per_field: BTreeMap<String, u64> = BTreeMap::new();
+    for segment_reader in searcher.segment_readers() {
+        for fast_field_name in &warmup_info.fast_field_names {
+            let column_handles = segment_reader
+                .fast_fields()
+                .list_dynamic_column_handles(fast_field_name)
+                .await?;
+            let num_bytes: u64 = column_handles
+                .iter()
+                .map(|column_handle| column_handle.file_slice().len() as u64)
+                .sum();
+            *num_bytes_per_field
+                .entry(fast_field_name.clone())
+                .or_default() += num_bytes;
+        }
+        if warmup_info.field_norms {
+            let fieldnorm_readers = segment_reader.fieldnorms_readers();
+            for (field, field_entry) in schema.fields() {
+                if let Some(file_slice) = fieldnorm_readers.get_inner_file().open_read(field) {
+                    *num_bytes_per_field
+                        .entry(field_entry.name().to_string())
+                        .or_default() += file_slice.len() as u64;
+                }
+            }
+        }
+    }
+    Ok(num_bytes_per_field)
+}
+
+/// Classifies an error raised while fetching the data of a split from the storage.
+fn split_storage_error(split_id: &str, error: anyhow::Error) -> SearchError {
+    let timed_out = error.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .map(|io_error| io_error.kind() == io::ErrorKind::TimedOut)
+            .unwrap_or(false)
+    });
+    let split_id = split_id.to_string();
+    let message = format!("{error:#}");
+    if timed_out {
+        SearchError::Timeout { split_id, message }
+    } else {
+        SearchError::StorageError { split_id, message }
+    }
+}
+
+/// Classifies an error raised while building the query against the schema of a split. The split
+/// may have been indexed before a field was declared as a fast field.
+fn split_query_error(split_id: &str, query_parser_error: QueryParserError) -> SearchError {
+    match query_parser_error.missing_fast_field() {
+        Some(field_name) => SearchError::MissingFastField {
+            split_id: split_id.to_string(),
+            field_name: field_name.to_string(),
+        },
+        None => SearchError::from(query_parser_error),
+    }
+}
+
 /// Apply a leaf search on a single split.
 #[instrument(skip(
     searcher_context,
@@ -317,17 +437,38 @@ async fn leaf_search_single_split(
     split: SplitIdAndFooterOffsets,
     doc_mapper: Arc<dyn DocMapper>,
     agg_limits: AggregationLimits,
+    soft_deadline_opt: Option<Instant>,
 ) -> crate::Result<LeafSearchResponse> {
+    // Once the soft deadline has passed, the split is not even fetched from the storage.
+    let is_past_soft_deadline = soft_deadline_opt
+        .map(|soft_deadline| Instant::now() >= soft_deadline)
+        .unwrap_or(false);
+    if is_past_soft_deadline {
+        return Ok(LeafSearchResponse {
+            num_attempted_splits: 1,
+            num_hits_is_lower_bound: true,
+            timed_out: true,
+            ..Default::default()
+        });
+    }
     let split_id = split.split_id.to_string();
-    let index = open_index_with_caches(searcher_context, storage, &split, true).await?;
+    let (index, cache_status) =
+        open_index_with_cache_status(searcher_context, storage, &split, true)
+            .await
+            .map_err(|error| split_storage_error(&split_id, error))?;
     let split_schema = index.schema();
-    let quickwit_collector = make_collector_for_split(
+    let mut quickwit_collector = make_collector_for_split(
         split_id.clone(),
         doc_mapper.as_ref(),
         search_request,
         agg_limits,
+        &searcher_context.searcher_config,
+        soft_deadline_opt,
     )?;
-    let (query, mut warmup_info) = doc_mapper.query(split_schema, search_request)?;
+    quickwit_collector.index_sort_opt = index.settings().sort_by_field.clone();
+    let (query, mut warmup_info) = doc_mapper
+        .query(split_schema, search_request)
+        .map_err(|query_parser_error| split_query_error(&split_id, query_parser_error))?;
     let reader = index
         .reader_builder()
         .reload_policy(ReloadPolicy::Manual)
@@ -337,11 +478,21 @@ async fn leaf_search_single_split(
     let collector_warmup_info = quickwit_collector.warmup_info();
     warmup_info.merge(collector_warmup_info);
 
-    warmup(&searcher, &warmup_info).await?;
+    warmup(&searcher, &warmup_info)
+        .await
+        .map_err(|error| split_storage_error(&split_id, error))?;
     let span = info_span!( "tantivy_search", split_id = %split.split_id);
+    let should_estimate_total_hits = search_request.estimate_total_hits;
     let leaf_search_response = crate::run_cpu_intensive(move || {
         let _span_guard = span.enter();
-        searcher.search(&query, &quickwit_collector)
+        let mut leaf_search_response = searcher.search(&query, &quickwit_collector)?;
+        leaf_search_response.cache_status = Some(cache_status);
+        leaf_search_response.split_id = Some(split.split_id.clone());
+        if should_estimate_total_hits {
+            leaf_search_response.estimated_total_hits =
+                Some(estimate_total_hits(&searcher, &*query)?);
+        }
+        tantivy::Result::Ok(leaf_search_response)
     })
     .await
     .map_err(|_| {
@@ -364,7 +515,17 @@ pub async fn leaf_search(
     splits: &[SplitIdAndFooterOffsets],
     doc_mapper: Arc<dyn DocMapper>,
 ) -> Result<LeafSearchResponse, SearchError> {
-    let agg_limits = aggregation_limits_from_searcher_context(&searcher_context);
+    let agg_limits = aggregation_limits_from_searcher_context(&searcher_context, request);
+    // Aggregations bound to exceed the bucket limit, and requests exceeding the max hits ceiling
+    // of a searcher configured to reject them, are rejected before any split is searched.
+    if let Some(aggregation_request) = &request.aggregation_request {
+        let aggregations: QuickwitAggregations = serde_json::from_str(aggregation_request)?;
+        aggregations.check_bucket_limit(aggregation_bucket_limit(&searcher_context, request))?;
+    }
+    resolve_max_hits_ceiling(&searcher_context.searcher_config, request)?;
+    let soft_deadline_opt = request
+        .soft_deadline_millis
+        .map(|soft_deadline_millis| Instant::now() + Duration::from_millis(soft_deadline_millis));
     let request = Arc::new(request.clone());
     let leaf_search_single_split_futures: Vec<_> = splits
         .iter()
@@ -392,6 +553,7 @@ pub async fn leaf_search(
                     split.clone(),
                     doc_mapper_clone,
                     agg_limits,
+                    soft_deadline_opt,
                 )
                 .await;
                 timer.observe_duration();
@@ -430,13 +592,13 @@ pub async fn leaf_search(
     .await
     .context("Failed to merge split search responses.")??;
 
-    merged_search_response
-        .failed_splits
-        .extend(errors.into_iter().map(|(split_id, err)| SplitSearchError {
-            split_id,
-            error: format!("{err}"),
-            retryable_error: true,
-        }));
+    // The failed splits were attempted too, but are not counted as successful.
+    merged_search_response.num_attempted_splits += errors.len() as u64;
+    merged_search_response.failed_splits.extend(
+        errors
+            .into_iter()
+            .map(|(split_id, err)| err.into_split_search_error(split_id)),
+    );
     Ok(merged_search_response)
 }
 
@@ -448,7 +610,9 @@ async fn leaf_list_terms_single_split(
     storage: Arc<dyn Storage>,
     split: SplitIdAndFooterOffsets,
 ) -> crate::Result<LeafListTermsResponse> {
-    let index = open_index_with_caches(searcher_context, storage, &split, true).await?;
+    let index = open_index_with_caches(searcher_context, storage, &split, true)
+        .await
+        .map_err(|error| split_storage_error(&split.split_id, error))?;
     let split_schema = index.schema();
     let reader = index
         .reader_builder()
@@ -606,11 +770,7 @@ pub async fn leaf_list_terms(
 
     let failed_splits = errors
         .into_iter()
-        .map(|(split_id, err)| SplitSearchError {
-            split_id,
-            error: err.to_string(),
-            retryable_error: true,
-        })
+        .map(|(split_id, err)| err.into_split_search_error(split_id))
         .collect();
     let merged_search_response = LeafListTermsResponse {
         num_hits: terms.len() as u64,
@@ -621,3 +781,81 @@ pub async fn leaf_list_terms(
 
     Ok(merged_search_response)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use quickwit_doc_mapper::{DefaultDocMapper, DocMapper};
+    use quickwit_proto::SearchRequest;
+    use serde_json::json;
+    use tantivy::{doc, Index};
+
+    use super::{estimate_warmup_num_bytes, resolve_warmup_info};
+
+    #[tokio::test]
+    async fn test_resolve_warmup_info_and_estimate_num_bytes() {
+        let doc_mapper: DefaultDocMapper = serde_json::from_value(json!({
+            "field_mappings": [
+                {"name": "body", "type": "text", "fieldnorms": true},
+                {"name": "status_code", "type": "u64", "fast": true},
+            ]
+        }))
+        .unwrap();
+        let schema = doc_mapper.schema();
+        let body_field = schema.get_field("body").unwrap();
+        let status_code_field = schema.get_field("status_code").unwrap();
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        for status_code in 0..100u64 {
+            index_writer
+                .add_document(doc!(body_field => "log", status_code_field => status_code))
+                .unwrap();
+        }
+        index_writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+
+        let warmup_info = resolve_warmup_info(
+            &doc_mapper,
+            &SearchRequest {
+                query: "body:log".to_string(),
+                max_hits: 10,
+                sort_by_field: Some("-status_code".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            warmup_info.fast_field_names,
+            HashSet::from_iter(["status_code".to_string()])
+        );
+        assert!(!warmup_info.field_norms);
+        assert_eq!(warmup_info.terms_grouped_by_field.len(), 1);
+        let num_bytes_per_field = estimate_warmup_num_bytes(&searcher, &warmup_info)
+            .await
+            .unwrap();
+        assert_eq!(
+            num_bytes_per_field.keys().collect::<Vec<_>>(),
+            vec!["status_code"]
+        );
+        assert!(num_bytes_per_field["status_code"] > 0);
+
+        // Sorting by score loads the field norms.
+        let warmup_info = resolve_warmup_info(
+            &doc_mapper,
+            &SearchRequest {
+                query: "body:log".to_string(),
+                max_hits: 10,
+                sort_by_field: Some("_score".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(warmup_info.fast_field_names.is_empty());
+        assert!(warmup_info.field_norms);
+        let num_bytes_per_field = estimate_warmup_num_bytes(&searcher, &warmup_info)
+            .await
+            .unwrap();
+        assert!(num_bytes_per_field["body"] > 0);
+    }
+}
@@ -22,14 +22,19 @@
 #![allow(clippy::bool_assert_comparison)]
 #![deny(clippy::disallowed_methods)]
 
+mod aggregation_result;
+mod bucket_samples;
 mod client;
 mod cluster_client;
 mod collector;
+mod distinct_count_collector;
+mod doc_id_set;
 mod error;
 mod fetch_docs;
 mod filters;
 mod find_trace_ids_collector;
 mod leaf;
+pub mod ordering;
 mod query_dsl;
 mod retry;
 mod root;
@@ -37,16 +42,25 @@ mod search_job_placer;
 mod search_response_rest;
 mod search_stream;
 mod service;
+mod terms_pagination;
 mod thread_pool;
+mod timestamp_range_collector;
+mod top_hits_aggregation;
+mod top_k;
+mod total_hits_estimate;
 
 mod metrics;
 #[cfg(test)]
 mod tests;
 
+use collector::parse_aggregation_request;
 pub use collector::QuickwitAggregations;
+#[doc(hidden)]
+#[cfg(any(test, feature = "testsuite"))]
+pub use collector::{concat_partial_hits, merge_partial_hits};
 use metrics::SEARCH_METRICS;
 use quickwit_doc_mapper::DocMapper;
-use root::{finalize_aggregation, validate_request};
+use root::{finalize_aggregation, validate_aggregation_request, validate_request};
 use service::SearcherContext;
 use tantivy::query::Query as TantivyQuery;
 use tantivy::schema::NamedFieldDocument;
@@ -54,23 +68,33 @@ use tantivy::schema::NamedFieldDocument;
 /// Refer to this as `crate::Result<T>`.
 pub type Result<T> = std::result::Result<T, SearchError>;
 
-use std::cmp::Reverse;
+use std::cmp::{Ordering, Reverse};
 use std::sync::Arc;
 
 use anyhow::Context;
+pub use distinct_count_collector::DistinctCountCollector;
+pub use doc_id_set::DocIdSet;
 pub use find_trace_ids_collector::FindTraceIdsCollector;
 use itertools::Itertools;
 use quickwit_config::{build_doc_mapper, QuickwitConfig, SearcherConfig};
 use quickwit_doc_mapper::tag_pruning::extract_tags_from_query;
 use quickwit_metastore::{ListSplitsQuery, Metastore, SplitMetadata, SplitState};
-use quickwit_proto::{Hit, PartialHit, SearchRequest, SearchResponse, SplitIdAndFooterOffsets};
+use quickwit_proto::{
+    Hit, PartialHit, SearchRequest, SearchResponse, SortOrder, SplitIdAndFooterOffsets,
+};
 use quickwit_storage::StorageUriResolver;
 use tantivy::DocAddress;
+pub use timestamp_range_collector::{
+    TimestampRange, TimestampRangeCollector, TimestampRangeParams,
+};
 
+use crate::bucket_samples::{assemble_bucket_samples, bucket_sample_partial_hits_to_fetch};
 pub use crate::client::{create_search_service_client, SearchServiceClient};
 pub use crate::cluster_client::ClusterClient;
 pub use crate::error::{parse_grpc_error, SearchError};
 use crate::fetch_docs::fetch_docs;
+use crate::filters::request_time_range;
+pub use crate::leaf::{estimate_warmup_num_bytes, resolve_warmup_info};
 use crate::leaf::{leaf_list_terms, leaf_search};
 pub use crate::root::{jobs_to_leaf_request, root_list_terms, root_search, SearchJob};
 pub use crate::search_job_placer::SearchJobPlacer;
@@ -78,6 +102,9 @@ pub use crate::search_response_rest::SearchResponseRest;
 pub use crate::search_stream::root_search_stream;
 pub use crate::service::{MockSearchService, SearchService, SearchServiceImpl};
 use crate::thread_pool::run_cpu_intensive;
+#[doc(hidden)]
+#[cfg(any(test, feature = "testsuite"))]
+pub use crate::top_k::{offer_to_top_k, SortedTopK, TopK};
 
 /// GlobalDocAddress serves as a hit address.
 #[derive(Clone, Eq, Debug, PartialEq, Hash, Ord, PartialOrd)]
@@ -98,13 +125,79 @@ impl GlobalDocAddress {
     }
 }
 
-fn partial_hit_sorting_key(partial_hit: &PartialHit) -> (Reverse<u64>, GlobalDocAddress) {
+/// Part of a sorting key ordering the hits tying on all the sort criteria, in `order`.
+///
+/// Keys are only ever compared with keys of the same order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct TieBreak<K> {
+    key: K,
+    order: SortOrder,
+}
+
+impl<K: Ord> PartialOrd for TieBreak<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord> Ord for TieBreak<K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.order {
+            SortOrder::Asc => self.key.cmp(&other.key),
+            SortOrder::Desc => other.key.cmp(&self.key),
+        }
+    }
+}
+
+/// Returns the order of the hits tying on all the sort criteria of the request.
+fn tie_break_order(search_request: &SearchRequest) -> SortOrder {
+    search_request
+        .tie_break_order
+        .and_then(SortOrder::from_i32)
+        .unwrap_or(SortOrder::Asc)
+}
+
+/// Returns the key by which partial hits are merged. The hits tying on all the sort criteria
+/// are ordered in `tie_break_order` by tie breaker value and then by document address, which is
+/// compared like a `GlobalDocAddress`, without allocating one.
+#[allow(clippy::type_complexity)]
+fn partial_hit_sorting_key(
+    partial_hit: &PartialHit,
+    tie_break_order: SortOrder,
+) -> (
+    Reverse<u64>,
+    Reverse<&[u64]>,
+    TieBreak<(u64, &str, u32, u32)>,
+) {
     (
         Reverse(partial_hit.sorting_field_value),
-        GlobalDocAddress::from_partial_hit(partial_hit),
+        Reverse(partial_hit.secondary_sorting_field_values.as_slice()),
+        TieBreak {
+            key: (
+                partial_hit.tie_breaker_value,
+                partial_hit.split_id.as_str(),
+                partial_hit.segment_ord,
+                partial_hit.doc_id,
+            ),
+            order: tie_break_order,
+        },
     )
 }
 
+/// Returns a hit only carrying the address of the document of `partial_hit`, as returned to
+/// requests with `hits_only_addresses`.
+fn address_only_hit(partial_hit: &PartialHit) -> Hit {
+    Hit {
+        partial_hit: Some(PartialHit {
+            split_id: partial_hit.split_id.clone(),
+            segment_ord: partial_hit.segment_ord,
+            doc_id: partial_hit.doc_id,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
 fn extract_split_and_footer_offsets(split_metadata: &SplitMetadata) -> SplitIdAndFooterOffsets {
     SplitIdAndFooterOffsets {
         split_id: split_metadata.split_id.clone(),
@@ -121,11 +214,19 @@ async fn list_relevant_splits(
     let mut query = ListSplitsQuery::for_index(&search_request.index_id)
         .with_split_state(SplitState::Published);
 
-    if let Some(start_ts) = search_request.start_timestamp {
+    // The time ranges of the splits are in seconds, so the bounds are rounded outwards.
+    let (start_timestamp_opt, end_timestamp_opt) = request_time_range(search_request)?;
+    if let Some(start_timestamp) = start_timestamp_opt {
+        let start_ts = start_timestamp
+            .into_timestamp_micros()
+            .div_euclid(1_000_000);
         query = query.with_time_range_start_gte(start_ts);
     }
 
-    if let Some(end_ts) = search_request.end_timestamp {
+    if let Some(end_timestamp) = end_timestamp_opt {
+        let end_timestamp_micros = end_timestamp.into_timestamp_micros();
+        let end_ts = end_timestamp_micros.div_euclid(1_000_000)
+            + (end_timestamp_micros.rem_euclid(1_000_000) != 0) as i64;
         query = query.with_time_range_end_lt(end_ts);
     }
 
@@ -189,6 +290,7 @@ pub async fn single_node_search(
 
     // Validates the query by effectively building it against the current schema.
     doc_mapper.query(doc_mapper.schema(), search_request)?;
+    validate_aggregation_request(search_request, &*doc_mapper)?;
     let searcher_context = Arc::new(SearcherContext::new(SearcherConfig::default()));
 
     let leaf_search_response = leaf_search(
@@ -201,42 +303,70 @@ pub async fn single_node_search(
     .await
     .context("Failed to perform leaf search.")?;
 
-    let search_request_opt = if !search_request.snippet_fields.is_empty() {
-        Some(search_request)
+    let (hits, bucket_samples) = if search_request.hits_only_addresses {
+        // The documents are not fetched, the hits only carry their address.
+        let hits = leaf_search_response
+            .partial_hits
+            .iter()
+            .map(address_only_hit)
+            .collect();
+        (hits, Vec::new())
     } else {
-        None
+        let search_request_opt = if !search_request.snippet_fields.is_empty()
+            || search_request.return_matched_fields
+            || search_request.return_highlights
+        {
+            Some(search_request)
+        } else {
+            None
+        };
+
+        // The samples of the buckets are fetched along with the hits.
+        let partial_hits_to_fetch: Vec<PartialHit> = leaf_search_response
+            .partial_hits
+            .iter()
+            .cloned()
+            .chain(bucket_sample_partial_hits_to_fetch(
+                &leaf_search_response.partial_hits,
+                &leaf_search_response.bucket_samples,
+            ))
+            .collect();
+        let fetch_docs_response = fetch_docs(
+            searcher_context.clone(),
+            partial_hits_to_fetch,
+            index_storage,
+            &split_metadata,
+            doc_mapper,
+            search_request_opt,
+        )
+        .await
+        .context("Failed to perform fetch docs.")?;
+        let mut hits: Vec<Hit> = fetch_docs_response
+            .hits
+            .into_iter()
+            .map(|leaf_hit| Hit {
+                json: leaf_hit.leaf_json,
+                partial_hit: leaf_hit.partial_hit,
+                snippet: leaf_hit.leaf_snippet_json,
+                matched_fields: leaf_hit.matched_fields,
+                match_offsets: leaf_hit.match_offsets,
+            })
+            .collect();
+        let bucket_samples = assemble_bucket_samples(
+            &mut hits,
+            &leaf_search_response.partial_hits,
+            leaf_search_response.bucket_samples,
+        );
+        (hits, bucket_samples)
     };
 
-    let fetch_docs_response = fetch_docs(
-        searcher_context.clone(),
-        leaf_search_response.partial_hits,
-        index_storage,
-        &split_metadata,
-        doc_mapper,
-        search_request_opt,
-    )
-    .await
-    .context("Failed to perform fetch docs.")?;
-    let hits: Vec<Hit> = fetch_docs_response
-        .hits
-        .into_iter()
-        .map(|leaf_hit| Hit {
-            json: leaf_hit.leaf_json,
-            partial_hit: leaf_hit.partial_hit,
-            snippet: leaf_hit.leaf_snippet_json,
-        })
-        .collect();
     let elapsed = start_instant.elapsed();
 
-    let aggregations: Option<QuickwitAggregations> = search_request
-        .aggregation_request
-        .as_ref()
-        .map(|agg| serde_json::from_str(agg))
-        .transpose()?;
-
+    let (aggregations, terms_aggregation_pages) = parse_aggregation_request(search_request)?;
     let aggregation = finalize_aggregation(
         leaf_search_response.intermediate_aggregation_result,
         aggregations,
+        terms_aggregation_pages.as_ref(),
     )?;
     Ok(SearchResponse {
         aggregation,
@@ -248,6 +378,14 @@ pub async fn single_node_search(
             .iter()
             .map(|error| format!("{error:?}"))
             .collect_vec(),
+        top_hits_aggregation: leaf_search_response.top_hits_aggregation,
+        estimated_total_hits: leaf_search_response.estimated_total_hits,
+        bucket_samples,
+        num_hits_is_lower_bound: leaf_search_response.num_hits_is_lower_bound,
+        timed_out: leaf_search_response.timed_out,
+        sort_value_types: leaf_search_response.sort_value_types,
+        clamped_max_hits: leaf_search_response.clamped_max_hits,
+        aggregation_is_partial: leaf_search_response.aggregation_is_partial,
     })
 }
 
@@ -0,0 +1,107 @@
+// Copyright (C) 2023 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Order-preserving conversions of numbers to the `u64` keys the hits are sorted by.
+//!
+//! The `sorting_field_value` of a hit sorted by `_score` in descending order is the key of its
+//! score, as returned by [`f32_to_u64`], so [`u64_to_f32`] decodes it back to the score. In
+//! ascending order, the key is `u64::MAX` minus the key of the score.
+
+/// Converts a float to an unsigned integer while preserving order.
+/// See `<https://lemire.me/blog/2020/12/14/converting-floating-point-numbers-to-integers-while-preserving-order/>`
+pub fn f32_to_u64(value: f32) -> u64 {
+    let value_u32 = u32::from_le_bytes(value.to_le_bytes());
+    let mut mask = (value_u32 as i32 >> 31) as u32;
+    mask |= 0x80000000;
+    (value_u32 ^ mask) as u64
+}
+
+/// Converts back a key returned by [`f32_to_u64`] to the float it was computed from.
+pub fn u64_to_f32(value: u64) -> f32 {
+    let value_u32 = value as u32;
+    // The sign bit of the key is set for positive floats, whose other bits are left as is.
+    let mask = if value_u32 & 0x80000000 != 0 {
+        0x80000000
+    } else {
+        u32::MAX
+    };
+    f32::from_le_bytes((value_u32 ^ mask).to_le_bytes())
+}
+
+/// Converts a double to an unsigned integer while preserving order, like [`f32_to_u64`].
+pub fn f64_to_u64(value: f64) -> u64 {
+    let value_u64 = u64::from_le_bytes(value.to_le_bytes());
+    let mut mask = (value_u64 as i64 >> 63) as u64;
+    mask |= 0x8000000000000000;
+    value_u64 ^ mask
+}
+
+/// Converts back a key returned by [`f64_to_u64`] to the double it was computed from.
+pub fn u64_to_f64(value: u64) -> f64 {
+    let mask = if value & 0x8000000000000000 != 0 {
+        0x8000000000000000
+    } else {
+        u64::MAX
+    };
+    f64::from_le_bytes((value ^ mask).to_le_bytes())
+}
+
+/// Converts a signed integer to an unsigned integer while preserving order, by flipping its
+/// sign bit.
+pub fn i64_to_u64(value: i64) -> u64 {
+    (value as u64) ^ 0x8000000000000000
+}
+
+/// Converts back a key returned by [`i64_to_u64`] to the signed integer it was computed from.
+pub fn u64_to_i64(value: u64) -> i64 {
+    (value ^ 0x8000000000000000) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_u64_to_f32_decodes_scores() {
+        for score in [0.0, 1.5, -1.5, f32::MIN_POSITIVE, f32::MAX, f32::MIN] {
+            assert_eq!(u64_to_f32(f32_to_u64(score)), score);
+        }
+        // Negative zero and zero have different keys, each decoding back to itself.
+        assert!(u64_to_f32(f32_to_u64(-0.0)).is_sign_negative());
+    }
+
+    proptest! {
+        #[test]
+        fn test_proptest_f32_round_trip(value in any::<f32>().prop_filter("Value can't be negative zero", |value| *value != -0.0)) {
+            prop_assert_eq!(u64_to_f32(f32_to_u64(value)), value);
+        }
+
+        #[test]
+        fn test_proptest_f64_round_trip(value in any::<f64>().prop_filter("Value can't be negative zero", |value| *value != -0.0)) {
+            prop_assert_eq!(u64_to_f64(f64_to_u64(value)), value);
+        }
+
+        #[test]
+        fn test_proptest_i64_round_trip(value in any::<i64>()) {
+            prop_assert_eq!(u64_to_i64(i64_to_u64(value)), value);
+        }
+    }
+}
@@ -23,8 +23,8 @@ use super::RetryPolicy;
 use crate::SearchError;
 
 /// Retry policy for LeafSearchRequest.
-/// A retry is made either on an error or if there are some failing splits.
-/// In the last case, a retry request is built on failing splits only.
+/// A retry is made either on an error or if there are some retryable failing splits.
+/// In the last case, a retry request is built on these failing splits only.
 pub struct LeafSearchRetryPolicy {}
 
 impl RetryPolicy<LeafSearchRequest, LeafSearchResponse, SearchError> for LeafSearchRetryPolicy {
@@ -36,15 +36,15 @@ impl RetryPolicy<LeafSearchRequest, LeafSearchResponse, SearchError> for LeafSea
     ) -> Option<LeafSearchRequest> {
         match response_res {
             Ok(response) => {
-                if response.failed_splits.is_empty() {
-                    return None;
-                }
                 request.split_offsets.retain(|split_metadata| {
-                    response
-                        .failed_splits
-                        .iter()
-                        .any(|failed_split| failed_split.split_id == split_metadata.split_id)
+                    response.failed_splits.iter().any(|failed_split| {
+                        failed_split.retryable_error
+                            && failed_split.split_id == split_metadata.split_id
+                    })
                 });
+                if request.split_offsets.is_empty() {
+                    return None;
+                }
                 Some(request)
             }
             Err(_) => Some(request),
@@ -56,7 +56,7 @@ impl RetryPolicy<LeafSearchRequest, LeafSearchResponse, SearchError> for LeafSea
 mod tests {
     use quickwit_proto::{
         LeafSearchRequest, LeafSearchResponse, SearchRequest, SplitIdAndFooterOffsets,
-        SplitSearchError,
+        SplitSearchError, SplitSearchErrorCode,
     };
 
     use crate::retry::search::LeafSearchRetryPolicy;
@@ -126,6 +126,7 @@ mod tests {
             error: "error".to_string(),
             split_id: "split_2".to_string(),
             retryable_error: true,
+            code: SplitSearchErrorCode::Internal as i32,
         };
         let response_res = Ok(LeafSearchResponse {
             num_hits: 0,
@@ -137,4 +138,24 @@ mod tests {
         let retry_request = retry_policy.retry_request(request, &response_res).unwrap();
         assert_eq!(retry_request, expected_retry_request);
     }
+
+    #[test]
+    fn test_should_not_retry_on_non_retryable_failed_splits() {
+        let retry_policy = LeafSearchRetryPolicy {};
+        let request = mock_leaf_search_request();
+        let split_error = SplitSearchError {
+            error: "error".to_string(),
+            split_id: "split_2".to_string(),
+            retryable_error: false,
+            code: SplitSearchErrorCode::MissingFastField as i32,
+        };
+        let response_res = Ok(LeafSearchResponse {
+            num_hits: 0,
+            partial_hits: Vec::new(),
+            failed_splits: vec![split_error],
+            num_attempted_splits: 1,
+            ..Default::default()
+        });
+        assert!(retry_policy.retry_request(request, &response_res).is_none())
+    }
 }
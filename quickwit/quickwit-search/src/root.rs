@@ -17,35 +17,45 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use std::cmp::Reverse;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 
 use anyhow::Context;
 use futures::future::try_join_all;
 use itertools::Itertools;
 use quickwit_config::{build_doc_mapper, IndexConfig};
+use quickwit_doc_mapper::DocMapper;
 use quickwit_metastore::{Metastore, SplitMetadata};
 use quickwit_proto::{
     FetchDocsRequest, FetchDocsResponse, Hit, LeafHit, LeafListTermsRequest, LeafListTermsResponse,
     LeafSearchRequest, LeafSearchResponse, ListTermsRequest, ListTermsResponse, PartialHit,
-    SearchRequest, SearchResponse, SplitIdAndFooterOffsets,
+    SearchRequest, SearchResponse, SortOrder, SplitIdAndFooterOffsets, SplitSearchError,
+    SplitSearchErrorCode,
 };
+use serde_json::Value as JsonValue;
 use tantivy::aggregation::agg_result::AggregationResults;
-use tantivy::aggregation::intermediate_agg_result::IntermediateAggregationResults;
 use tantivy::aggregation::AggregationLimits;
 use tantivy::collector::Collector;
 use tantivy::TantivyError;
 use tracing::{debug, error, info_span, instrument};
 
+use crate::aggregation_result::IntermediateAggregationResult;
+use crate::bucket_samples::{
+    assemble_bucket_samples, bucket_sample_partial_hits_to_fetch, BucketSamplesRequest,
+};
 use crate::cluster_client::ClusterClient;
-use crate::collector::{make_merge_collector, QuickwitAggregations};
-use crate::find_trace_ids_collector::Span;
+use crate::collector::{
+    make_merge_collector, parse_aggregation_request, parse_sort_by_field, resolve_max_hits_ceiling,
+    MissingValue, QuickwitAggregations, MAX_AGGREGATION_BUCKET_LIMIT, MAX_AGGREGATION_MEMORY_LIMIT,
+};
+use crate::filters::request_time_range;
 use crate::search_job_placer::Job;
 use crate::service::SearcherContext;
+use crate::terms_pagination::TermsAggregationPages;
+use crate::top_hits_aggregation::TopHitsAggregations;
 use crate::{
-    extract_split_and_footer_offsets, list_relevant_splits, SearchError, SearchJobPlacer,
-    SearchServiceClient,
+    address_only_hit, extract_split_and_footer_offsets, list_relevant_splits,
+    partial_hit_sorting_key, tie_break_order, SearchError, SearchJobPlacer, SearchServiceClient,
 };
 
 /// SearchJob to be assigned to search clients by the [`SearchJobPlacer`].
@@ -114,12 +124,120 @@ impl From<FetchDocsJob> for SplitIdAndFooterOffsets {
     }
 }
 
+/// Returns true if the aggregation request holds a `top_hits` aggregation, at any depth.
+fn contains_top_hits_aggregation(aggregation_json: &JsonValue) -> bool {
+    match aggregation_json {
+        JsonValue::Object(aggregation_map) => aggregation_map
+            .iter()
+            .any(|(key, value)| key == "top_hits" || contains_top_hits_aggregation(value)),
+        _ => false,
+    }
+}
+
 pub(crate) fn validate_request(search_request: &SearchRequest) -> crate::Result<()> {
     if let Some(agg) = search_request.aggregation_request.as_ref() {
-        let _aggs: QuickwitAggregations = serde_json::from_str(agg)
+        let _aggs: QuickwitAggregations = serde_json::from_str(agg).map_err(|err| {
+            // The aggregation engine does not support `top_hits`, which would otherwise be
+            // reported as an obscure mismatch of all the aggregation types.
+            let is_top_hits = serde_json::from_str::<JsonValue>(agg)
+                .map(|aggregation_json| contains_top_hits_aggregation(&aggregation_json))
+                .unwrap_or(false);
+            if is_top_hits {
+                SearchError::InvalidAggregationRequest(
+                    "`top_hits` aggregations are not supported, use `bucket_samples_request` to \
+                     sample the best documents of each bucket instead"
+                        .to_string(),
+                )
+            } else {
+                SearchError::InvalidAggregationRequest(err.to_string())
+            }
+        })?;
+    };
+
+    if let Some(top_hits_agg) = search_request.top_hits_aggregation_request.as_ref() {
+        let _top_hits_aggs: TopHitsAggregations = serde_json::from_str(top_hits_agg)
+            .map_err(|err| SearchError::InvalidAggregationRequest(err.to_string()))?;
+    };
+
+    if let Some(bucket_samples) = search_request.bucket_samples_request.as_ref() {
+        let bucket_samples: BucketSamplesRequest = serde_json::from_str(bucket_samples)
             .map_err(|err| SearchError::InvalidAggregationRequest(err.to_string()))?;
+        bucket_samples
+            .validate()
+            .map_err(SearchError::InvalidAggregationRequest)?;
     };
 
+    if search_request.hits_only_addresses
+        && (!search_request.snippet_fields.is_empty()
+            || search_request.return_matched_fields
+            || search_request.return_highlights
+            || search_request.return_sort_values
+            || search_request.bucket_samples_request.is_some())
+    {
+        return Err(SearchError::InvalidArgument(
+            "`hits_only_addresses` cannot be combined with snippet fields, matched fields, \
+             highlights, sort values or bucket samples"
+                .to_string(),
+        ));
+    }
+
+    if let Some(sort_missing) = search_request.sort_missing.as_ref() {
+        let _missing: MissingValue = sort_missing
+            .parse()
+            .map_err(|err| SearchError::InvalidArgument(format!("invalid sort_missing: {err}")))?;
+    };
+
+    // The fields are checked against the doc mapper, along with the query, but the syntax of the
+    // sort fields is only known to the collector.
+    if let Some(sort_by_field) = search_request.sort_by_field.as_ref() {
+        let sort_order_opt = search_request.sort_order.and_then(SortOrder::from_i32);
+        parse_sort_by_field(sort_by_field, sort_order_opt, MissingValue::default(), None)?;
+    }
+
+    if let Some(geo_distance) = search_request.sort_by_geo_distance.as_ref() {
+        if !(-90.0..=90.0).contains(&geo_distance.lat)
+            || !(-180.0..=180.0).contains(&geo_distance.lon)
+        {
+            return Err(SearchError::InvalidArgument(format!(
+                "invalid sort_by_geo_distance point: ({}, {})",
+                geo_distance.lat, geo_distance.lon
+            )));
+        }
+    }
+
+    if let Some(hash_partition) = search_request.hash_partition.as_ref() {
+        if hash_partition.bucket >= hash_partition.modulus {
+            return Err(SearchError::InvalidArgument(format!(
+                "invalid hash_partition: the bucket {} is not lower than the modulus {}",
+                hash_partition.bucket, hash_partition.modulus
+            )));
+        }
+    }
+
+    if let Some(score_boost) = search_request.score_boost.as_ref() {
+        if !score_boost.factor.is_finite() {
+            return Err(SearchError::InvalidArgument(format!(
+                "invalid score_boost factor: {}",
+                score_boost.factor
+            )));
+        }
+    }
+
+    if search_request.random_sample_seed.is_some()
+        && (search_request.sort_by_field.is_some() || search_request.sort_by_geo_distance.is_some())
+    {
+        return Err(SearchError::InvalidArgument(
+            "random_sample_seed cannot be combined with sort_by_field or sort_by_geo_distance"
+                .to_string(),
+        ));
+    }
+
+    if search_request.rank_fusion.is_some() && search_request.search_after.is_some() {
+        return Err(SearchError::InvalidArgument(
+            "rank_fusion cannot be combined with search_after".to_string(),
+        ));
+    }
+
     if search_request.start_offset > 10_000 {
         return Err(SearchError::InvalidArgument(format!(
             "max value for start_offset is 10_000, but got {}",
@@ -134,9 +252,42 @@ pub(crate) fn validate_request(search_request: &SearchRequest) -> crate::Result<
         )));
     }
 
+    request_time_range(search_request)?;
+
+    if let Some(memory_limit) = search_request.aggregation_memory_limit {
+        if memory_limit > MAX_AGGREGATION_MEMORY_LIMIT {
+            return Err(SearchError::InvalidArgument(format!(
+                "max value for aggregation_memory_limit is {MAX_AGGREGATION_MEMORY_LIMIT}, but \
+                 got {memory_limit}"
+            )));
+        }
+    }
+
+    if let Some(bucket_limit) = search_request.aggregation_bucket_limit {
+        if bucket_limit > MAX_AGGREGATION_BUCKET_LIMIT {
+            return Err(SearchError::InvalidArgument(format!(
+                "max value for aggregation_bucket_limit is {MAX_AGGREGATION_BUCKET_LIMIT}, but \
+                 got {bucket_limit}"
+            )));
+        }
+    }
+
     Ok(())
 }
 
+/// Validates the fields referenced by the aggregations of the request against the doc mapper,
+/// so that an invalid field is reported once rather than by every split.
+pub(crate) fn validate_aggregation_request(
+    search_request: &SearchRequest,
+    doc_mapper: &dyn DocMapper,
+) -> crate::Result<()> {
+    let (Some(aggregations), _terms_aggregation_pages) = parse_aggregation_request(search_request)?
+    else {
+        return Ok(());
+    };
+    aggregations.validate_fields(&doc_mapper.schema())
+}
+
 /// Performs a distributed search.
 /// 1. Sends leaf request over gRPC to multiple leaf nodes.
 /// 2. Merges the search results.
@@ -166,6 +317,8 @@ pub async fn root_search(
 
     // Validates the query by effectively building it against the current schema.
     doc_mapper.query(doc_mapper.schema(), search_request)?;
+    validate_aggregation_request(search_request, &*doc_mapper)?;
+    resolve_max_hits_ceiling(&searcher_context.searcher_config, search_request)?;
 
     let doc_mapper_str = serde_json::to_string(&doc_mapper).map_err(|err| {
         SearchError::InternalError(format!("Failed to serialize doc mapper: Cause {err}"))
@@ -206,7 +359,7 @@ pub async fn root_search(
 
     // Creates a collector which merges responses into one
     let merge_collector = make_merge_collector(search_request, &searcher_context)?;
-    let aggregations = merge_collector.aggregation.clone();
+    let (aggregations, terms_aggregation_pages) = parse_aggregation_request(search_request)?;
 
     // Merging is a cpu-bound task.
     // It should be executed by Tokio's blocking threads.
@@ -226,82 +379,126 @@ pub async fn root_search(
     })?;
     debug!(leaf_search_response = ?leaf_search_response, "Merged leaf search response.");
 
-    if !leaf_search_response.failed_splits.is_empty() {
+    // The splits whose aggregation result could not be decoded still contributed their hits: they
+    // are reported along with the response instead of failing the whole search.
+    let has_failed_hits = leaf_search_response
+        .failed_splits
+        .iter()
+        .any(|failed_split| failed_split.code() != SplitSearchErrorCode::Aggregation);
+    if has_failed_hits {
         error!(failed_splits = ?leaf_search_response.failed_splits, "Leaf search response contains at least one failed split.");
-        let errors: String = leaf_search_response
-            .failed_splits
-            .iter()
-            .map(|splits| format!("{splits}"))
-            .collect::<Vec<_>>()
-            .join(", ");
+        let errors = describe_failed_splits(
+            &leaf_search_response.failed_splits,
+            leaf_search_response.num_attempted_splits,
+        );
         return Err(SearchError::InternalError(errors));
     }
 
-    let client_fetch_docs_task: Vec<(SearchServiceClient, Vec<FetchDocsJob>)> =
-        assign_client_fetch_doc_tasks(
-            &leaf_search_response.partial_hits,
-            &split_offsets_map,
-            search_job_placer,
-        )?;
-
-    let fetch_docs_resp_futures =
-        client_fetch_docs_task
-            .into_iter()
-            .map(|(client, fetch_docs_jobs)| {
-                let partial_hits: Vec<PartialHit> = fetch_docs_jobs
-                    .iter()
-                    .flat_map(|fetch_doc_job| fetch_doc_job.partial_hits.iter().cloned())
-                    .collect();
-                let split_offsets: Vec<SplitIdAndFooterOffsets> = fetch_docs_jobs
-                    .into_iter()
-                    .map(|fetch_doc_job| fetch_doc_job.into())
-                    .collect();
-
-                let search_request_opt = if search_request.snippet_fields.is_empty() {
-                    None
-                } else {
-                    Some(search_request.clone())
-                };
-                let fetch_docs_req = FetchDocsRequest {
-                    partial_hits,
-                    index_id: search_request.index_id.to_string(),
-                    split_offsets,
-                    index_uri: index_uri.to_string(),
-                    search_request: search_request_opt,
-                    doc_mapper: doc_mapper_str.clone(),
-                };
-                cluster_client.fetch_docs(fetch_docs_req, client)
-            });
-
-    let fetch_docs_resps: Vec<FetchDocsResponse> = try_join_all(fetch_docs_resp_futures).await?;
+    let (hits, bucket_samples) = if search_request.hits_only_addresses {
+        // The documents are not fetched, the hits only carry their address. The merged partial
+        // hits are already sorted.
+        let hits = leaf_search_response
+            .partial_hits
+            .iter()
+            .map(address_only_hit)
+            .collect();
+        (hits, Vec::new())
+    } else {
+        // The samples of the buckets are fetched along with the hits.
+        let partial_hits_to_fetch: Vec<PartialHit> = leaf_search_response
+            .partial_hits
+            .iter()
+            .cloned()
+            .chain(bucket_sample_partial_hits_to_fetch(
+                &leaf_search_response.partial_hits,
+                &leaf_search_response.bucket_samples,
+            ))
+            .collect();
+        let client_fetch_docs_task: Vec<(SearchServiceClient, Vec<FetchDocsJob>)> =
+            assign_client_fetch_doc_tasks(
+                &partial_hits_to_fetch,
+                &split_offsets_map,
+                search_job_placer,
+            )?;
+
+        let fetch_docs_resp_futures =
+            client_fetch_docs_task
+                .into_iter()
+                .map(|(client, fetch_docs_jobs)| {
+                    let partial_hits: Vec<PartialHit> = fetch_docs_jobs
+                        .iter()
+                        .flat_map(|fetch_doc_job| fetch_doc_job.partial_hits.iter().cloned())
+                        .collect();
+                    let split_offsets: Vec<SplitIdAndFooterOffsets> = fetch_docs_jobs
+                        .into_iter()
+                        .map(|fetch_doc_job| fetch_doc_job.into())
+                        .collect();
+
+                    let search_request_opt = if search_request.snippet_fields.is_empty()
+                        && !search_request.return_matched_fields
+                        && !search_request.return_highlights
+                    {
+                        None
+                    } else {
+                        Some(search_request.clone())
+                    };
+                    let fetch_docs_req = FetchDocsRequest {
+                        partial_hits,
+                        index_id: search_request.index_id.to_string(),
+                        split_offsets,
+                        index_uri: index_uri.to_string(),
+                        search_request: search_request_opt,
+                        doc_mapper: doc_mapper_str.clone(),
+                    };
+                    cluster_client.fetch_docs(fetch_docs_req, client)
+                });
 
-    // Merge the fetched docs.
-    let leaf_hits = fetch_docs_resps
-        .into_iter()
-        .flat_map(|response| response.hits.into_iter());
+        let fetch_docs_resps: Vec<FetchDocsResponse> =
+            try_join_all(fetch_docs_resp_futures).await?;
 
-    let mut hits: Vec<Hit> = leaf_hits
-        .map(|leaf_hit: LeafHit| Hit {
-            json: leaf_hit.leaf_json,
-            partial_hit: leaf_hit.partial_hit,
-            snippet: leaf_hit.leaf_snippet_json,
-        })
-        .collect();
+        // Merge the fetched docs.
+        let leaf_hits = fetch_docs_resps
+            .into_iter()
+            .flat_map(|response| response.hits.into_iter());
+
+        let mut hits: Vec<Hit> = leaf_hits
+            .map(|leaf_hit: LeafHit| Hit {
+                json: leaf_hit.leaf_json,
+                partial_hit: leaf_hit.partial_hit,
+                snippet: leaf_hit.leaf_snippet_json,
+                matched_fields: leaf_hit.matched_fields,
+                match_offsets: leaf_hit.match_offsets,
+            })
+            .collect();
+        let bucket_samples = assemble_bucket_samples(
+            &mut hits,
+            &leaf_search_response.partial_hits,
+            leaf_search_response.bucket_samples,
+        );
 
-    hits.sort_unstable_by_key(|hit| {
-        Reverse(
-            hit.partial_hit
+        // Hits are sorted by their full sorting key so that ties are returned in the same
+        // order as the merge, which `search_after` pagination relies upon.
+        let tie_break_order = tie_break_order(search_request);
+        hits.sort_unstable_by(|left_hit, right_hit| {
+            let left_key = left_hit
+                .partial_hit
                 .as_ref()
-                .map(|hit| hit.sorting_field_value)
-                .unwrap_or(0),
-        )
-    });
+                .map(|partial_hit| partial_hit_sorting_key(partial_hit, tie_break_order));
+            let right_key = right_hit
+                .partial_hit
+                .as_ref()
+                .map(|partial_hit| partial_hit_sorting_key(partial_hit, tie_break_order));
+            left_key.cmp(&right_key)
+        });
+        (hits, bucket_samples)
+    };
 
     let elapsed = start_instant.elapsed();
 
     let aggregation = finalize_aggregation(
         leaf_search_response.intermediate_aggregation_result,
         aggregations,
+        terms_aggregation_pages.as_ref(),
     )?;
 
     Ok(SearchResponse {
@@ -309,33 +506,92 @@ pub async fn root_search(
         num_hits: leaf_search_response.num_hits,
         hits,
         elapsed_time_micros: elapsed.as_micros() as u64,
-        errors: Vec::new(),
+        errors: leaf_search_response
+            .failed_splits
+            .iter()
+            .map(|failed_split| failed_split.to_string())
+            .collect(),
+        top_hits_aggregation: leaf_search_response.top_hits_aggregation,
+        estimated_total_hits: leaf_search_response.estimated_total_hits,
+        bucket_samples,
+        num_hits_is_lower_bound: leaf_search_response.num_hits_is_lower_bound,
+        timed_out: leaf_search_response.timed_out,
+        sort_value_types: leaf_search_response.sort_value_types,
+        clamped_max_hits: leaf_search_response.clamped_max_hits,
+        aggregation_is_partial: leaf_search_response.aggregation_is_partial,
     })
 }
 
-pub fn finalize_aggregation(
+/// Turns the merged intermediate result of the aggregations into their JSON result, and cuts the
+/// pages of the paginated `terms` aggregations out of their buckets.
+pub(crate) fn finalize_aggregation(
     intermediate_aggregation_result: Option<Vec<u8>>,
     aggregations: Option<QuickwitAggregations>,
+    terms_aggregation_pages_opt: Option<&TermsAggregationPages>,
 ) -> crate::Result<Option<String>> {
     let aggregation = if let Some(intermediate_aggregation_result) = intermediate_aggregation_result
     {
-        match aggregations.expect(
+        let aggregations = aggregations.expect(
             "Aggregation should be present since we are processing an intermediate aggregation \
              result.",
-        ) {
-            QuickwitAggregations::FindTraceIdsAggregation(_) => {
+        );
+        let intermediate_aggregation_result = IntermediateAggregationResult::from_bytes(
+            &intermediate_aggregation_result,
+            aggregations.kind(),
+        )?;
+        match (aggregations, intermediate_aggregation_result) {
+            (
+                QuickwitAggregations::FindTraceIdsAggregation(_),
+                IntermediateAggregationResult::FindTraceIds(aggs),
+            ) => {
                 // The merge collector has already merged the intermediate results.
-                let aggs: Vec<Span> =
-                    postcard::from_bytes(intermediate_aggregation_result.as_slice())?;
                 Some(serde_json::to_string(&aggs)?)
             }
-            QuickwitAggregations::TantivyAggregations(aggregations) => {
-                let res: IntermediateAggregationResults =
-                    postcard::from_bytes(intermediate_aggregation_result.as_slice())?;
-                let res: AggregationResults =
+            (
+                QuickwitAggregations::DistinctCountAggregation(_),
+                IntermediateAggregationResult::DistinctCount(sketch),
+            ) => Some(serde_json::to_string(
+                &serde_json::json!({ "value": sketch.estimate() }),
+            )?),
+            (
+                QuickwitAggregations::TimestampRangeAggregation(_),
+                IntermediateAggregationResult::TimestampRange(timestamp_range_opt),
+            ) => {
+                // The timestamps are in microseconds, and are null if no document matched.
+                let (min_timestamp_opt, max_timestamp_opt) = timestamp_range_opt
+                    .map(|timestamp_range| {
+                        (
+                            timestamp_range.min_timestamp.into_timestamp_micros(),
+                            timestamp_range.max_timestamp.into_timestamp_micros(),
+                        )
+                    })
+                    .unzip();
+                Some(serde_json::to_string(&serde_json::json!({
+                    "min_timestamp": min_timestamp_opt,
+                    "max_timestamp": max_timestamp_opt,
+                }))?)
+            }
+            (
+                QuickwitAggregations::TantivyAggregations(aggregations),
+                IntermediateAggregationResult::Tantivy(res),
+            ) => {
+                let mut res: AggregationResults =
                     res.into_final_result(aggregations, &AggregationLimits::default())?;
+                if let Some(terms_aggregation_pages) = terms_aggregation_pages_opt {
+                    terms_aggregation_pages
+                        .paginate(&mut res)
+                        .map_err(SearchError::InvalidAggregationRequest)?;
+                }
                 Some(serde_json::to_string(&res)?)
             }
+            (aggregations, intermediate_aggregation_result) => {
+                return Err(SearchError::InternalError(format!(
+                    "expected an intermediate aggregation result of kind {:?}, got one of kind \
+                     {:?}",
+                    aggregations.kind(),
+                    intermediate_aggregation_result.kind()
+                )));
+            }
         }
     } else {
         None
@@ -428,11 +684,11 @@ pub async fn root_list_terms(
 
     if !failed_splits.is_empty() {
         error!(failed_splits = ?failed_splits, "Leaf search response contains at least one failed split.");
-        let errors: String = failed_splits
+        let num_attempted_splits = leaf_search_responses
             .iter()
-            .map(|splits| splits.to_string())
-            .collect::<Vec<_>>()
-            .join(", ");
+            .map(|leaf_search_response| leaf_search_response.num_attempted_splits)
+            .sum();
+        let errors = describe_failed_splits(failed_splits, num_attempted_splits);
         return Err(SearchError::InternalError(errors));
     }
 
@@ -462,6 +718,33 @@ pub async fn root_list_terms(
     })
 }
 
+/// Describes the failed splits of a search, e.g. `3 of 40 splits failed: storage timeout (2),
+/// missing fast field (1).`, followed by the error of each failed split.
+fn describe_failed_splits<'a>(
+    failed_splits: impl IntoIterator<Item = &'a SplitSearchError>,
+    num_attempted_splits: u64,
+) -> String {
+    let failed_splits: Vec<&SplitSearchError> = failed_splits.into_iter().collect();
+    let mut num_failed_splits_per_code: BTreeMap<SplitSearchErrorCode, usize> = BTreeMap::new();
+    for failed_split in &failed_splits {
+        *num_failed_splits_per_code
+            .entry(failed_split.code())
+            .or_default() += 1;
+    }
+    let causes = num_failed_splits_per_code
+        .iter()
+        .map(|(code, num_failed_splits)| format!("{code} ({num_failed_splits})"))
+        .join(", ");
+    let errors = failed_splits
+        .iter()
+        .map(|failed_split| failed_split.to_string())
+        .join(", ");
+    format!(
+        "{} of {num_attempted_splits} splits failed: {causes}. {errors}",
+        failed_splits.len()
+    )
+}
+
 fn assign_client_fetch_doc_tasks(
     partial_hits: &[PartialHit],
     split_offsets_map: &HashMap<String, SplitIdAndFooterOffsets>,
@@ -530,7 +813,6 @@ mod tests {
     use quickwit_grpc_clients::service_client_pool::ServiceClientPool;
     use quickwit_indexing::mock_split;
     use quickwit_metastore::{IndexMetadata, MockMetastore};
-    use quickwit_proto::SplitSearchError;
 
     use super::*;
     use crate::MockSearchService;
@@ -545,6 +827,13 @@ mod tests {
             split_id: split_id.to_string(),
             segment_ord: 1,
             doc_id,
+            tie_breaker_value: 0,
+            docvalue_fields: Default::default(),
+            secondary_sorting_field_values: Vec::new(),
+            score: None,
+            collapse_key: None,
+            sort_values: Vec::new(),
+            dedup_key: None,
         }
     }
 
@@ -563,6 +852,8 @@ mod tests {
                 .expect("Json serialization should not fail"),
                 partial_hit: Some(req),
                 leaf_snippet_json: None,
+                matched_fields: Vec::new(),
+                match_offsets: Vec::new(),
             })
             .collect()
     }
@@ -898,6 +1189,7 @@ mod tests {
                         error: "mock_error".to_string(),
                         split_id: "split2".to_string(),
                         retryable_error: true,
+                        code: SplitSearchErrorCode::Internal as i32,
                     }],
                     num_attempted_splits: 1,
                     ..Default::default()
@@ -974,6 +1266,7 @@ mod tests {
                         error: "mock_error".to_string(),
                         split_id: "split2".to_string(),
                         retryable_error: true,
+                        code: SplitSearchErrorCode::Internal as i32,
                     }],
                     num_attempted_splits: 1,
                     ..Default::default()
@@ -1032,6 +1325,7 @@ mod tests {
                         error: "mock_error".to_string(),
                         split_id: "split1".to_string(),
                         retryable_error: true,
+                        code: SplitSearchErrorCode::Internal as i32,
                     }],
                     num_attempted_splits: 1,
                     ..Default::default()
@@ -1109,6 +1403,7 @@ mod tests {
                             error: "mock_error".to_string(),
                             split_id: "split1".to_string(),
                             retryable_error: true,
+                            code: SplitSearchErrorCode::Internal as i32,
                         }],
                         num_attempted_splits: 1,
                         ..Default::default()
@@ -1187,6 +1482,7 @@ mod tests {
                         error: "mock_error".to_string(),
                         split_id: "split1".to_string(),
                         retryable_error: true,
+                        code: SplitSearchErrorCode::Storage as i32,
                     }],
                     num_attempted_splits: 1,
                     ..Default::default()
@@ -1212,7 +1508,10 @@ mod tests {
             &search_job_placer,
         )
         .await;
-        assert!(search_response.is_err());
+        assert!(search_response
+            .unwrap_err()
+            .to_string()
+            .contains("splits failed: storage error (1). (mock_error, split_id: split1)"));
         Ok(())
     }
 
@@ -1273,6 +1572,7 @@ mod tests {
                         error: "mock_error".to_string(),
                         split_id: "split1".to_string(),
                         retryable_error: true,
+                        code: SplitSearchErrorCode::Internal as i32,
                     }],
                     num_attempted_splits: 1,
                     ..Default::default()
@@ -1524,6 +1824,77 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_describe_failed_splits() {
+        let make_split_error = |split_id: &str, code: SplitSearchErrorCode| SplitSearchError {
+            error: "mock_error".to_string(),
+            split_id: split_id.to_string(),
+            retryable_error: true,
+            code: code as i32,
+        };
+        let failed_splits = vec![
+            make_split_error("split1", SplitSearchErrorCode::Timeout),
+            make_split_error("split2", SplitSearchErrorCode::MissingFastField),
+            make_split_error("split3", SplitSearchErrorCode::Timeout),
+        ];
+        assert_eq!(
+            describe_failed_splits(&failed_splits, 40),
+            "3 of 40 splits failed: storage timeout (2), missing fast field (1). (mock_error, \
+             split_id: split1), (mock_error, split_id: split2), (mock_error, split_id: split3)"
+        );
+    }
+
+    #[test]
+    fn test_validate_request_top_hits_aggregation() {
+        let agg_req = r#"
+            {
+                "colors": {
+                    "terms": {"field": "color"},
+                    "aggs": {
+                        "cheapest": {
+                            "top_hits": {"size": 1, "sort": [{"price": "asc"}]}
+                        }
+                    }
+                }
+            }"#;
+        let search_request = SearchRequest {
+            aggregation_request: Some(agg_req.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_request(&search_request).unwrap_err().to_string(),
+            "Invalid aggregation request: `top_hits` aggregations are not supported, use \
+             `bucket_samples_request` to sample the best documents of each bucket instead",
+        );
+    }
+
+    #[test]
+    fn test_validate_request_aggregation_limits() {
+        let search_request = SearchRequest {
+            aggregation_memory_limit: Some(1_000_000),
+            aggregation_bucket_limit: Some(100),
+            ..Default::default()
+        };
+        assert!(validate_request(&search_request).is_ok());
+        let search_request = SearchRequest {
+            aggregation_memory_limit: Some(MAX_AGGREGATION_MEMORY_LIMIT + 1),
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_request(&search_request).unwrap_err().to_string(),
+            "Invalid argument: max value for aggregation_memory_limit is 5000000000, but got \
+             5000000001",
+        );
+        let search_request = SearchRequest {
+            aggregation_bucket_limit: Some(MAX_AGGREGATION_BUCKET_LIMIT + 1),
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_request(&search_request).unwrap_err().to_string(),
+            "Invalid argument: max value for aggregation_bucket_limit is 1000000, but got 1000001",
+        );
+    }
+
     #[tokio::test]
     async fn test_root_search_invalid_request() -> anyhow::Result<()> {
         let search_request = quickwit_proto::SearchRequest {
@@ -20,9 +20,11 @@
 use std::convert::TryFrom;
 
 use quickwit_common::truncate_str;
-use quickwit_proto::SearchResponse;
+use quickwit_proto::{EstimatedTotalHits, SearchResponse, SortValueType};
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
+use tantivy::time::format_description::well_known::Rfc3339;
+use tantivy::DateTime;
 
 use crate::error::SearchError;
 
@@ -32,6 +34,10 @@ use crate::error::SearchError;
 pub struct SearchResponseRest {
     /// Overall number of documents matching the query.
     pub num_hits: u64,
+    /// Estimate of the number of documents matching the query, along with its 95% confidence
+    /// interval, if requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_total_hits: Option<EstimatedTotalHits>,
     #[schema(value_type = Vec<Object>)]
     /// List of hits returned.
     pub hits: Vec<JsonValue>,
@@ -47,14 +53,36 @@ pub struct SearchResponseRest {
     #[schema(value_type = Object)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub aggregations: Option<JsonValue>,
+    /// Values of the sort criteria of each hit, in the order of the hits, if requested with
+    /// `return_sort_values`. Datetimes are formatted as RFC3339 strings.
+    #[schema(value_type = Vec<Vec<Object>>)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort_values: Option<Vec<Vec<JsonValue>>>,
+    /// Types of the values of the sort criteria, in the order of the criteria, if requested with
+    /// `return_sort_values`.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sort_value_types: Vec<SortValueType>,
+    /// If set, the search stopped at its deadline: the hits and the aggregations only cover part
+    /// of the matching documents.
+    #[serde(default)]
+    pub timed_out: bool,
 }
 
 impl TryFrom<SearchResponse> for SearchResponseRest {
     type Error = SearchError;
 
     fn try_from(search_response: SearchResponse) -> Result<Self, Self::Error> {
+        let sort_value_types: Vec<SortValueType> = search_response
+            .sort_value_types
+            .iter()
+            .map(|&sort_value_type| {
+                SortValueType::from_i32(sort_value_type).unwrap_or(SortValueType::NoValue)
+            })
+            .collect();
         let mut documents = Vec::with_capacity(search_response.hits.len());
         let mut snippets = Vec::new();
+        let mut sort_values = Vec::new();
         for hit in search_response.hits {
             let document: JsonValue = serde_json::from_str(&hit.json).map_err(|err| {
                 SearchError::InternalError(format!(
@@ -74,6 +102,19 @@ impl TryFrom<SearchResponse> for SearchResponseRest {
                     })?;
                 snippets.push(snippet_opt);
             }
+
+            if !sort_value_types.is_empty() {
+                let hit_sort_values = hit
+                    .partial_hit
+                    .iter()
+                    .flat_map(|partial_hit| &partial_hit.sort_values)
+                    .zip(&sort_value_types)
+                    .map(|(sort_value_json, sort_value_type)| {
+                        parse_sort_value(sort_value_json, *sort_value_type)
+                    })
+                    .collect::<Result<Vec<JsonValue>, SearchError>>()?;
+                sort_values.push(hit_sort_values);
+            }
         }
 
         let snippet_opt = if !snippets.is_empty() {
@@ -82,6 +123,12 @@ impl TryFrom<SearchResponse> for SearchResponseRest {
             None
         };
 
+        let sort_values_opt = if !sort_value_types.is_empty() {
+            Some(sort_values)
+        } else {
+            None
+        };
+
         let aggregations_opt = if let Some(aggregation_json) = search_response.aggregation {
             let aggregation: JsonValue = serde_json::from_str(&aggregation_json)
                 .map_err(|err| SearchError::InternalError(err.to_string()))?;
@@ -92,11 +139,41 @@ impl TryFrom<SearchResponse> for SearchResponseRest {
 
         Ok(SearchResponseRest {
             num_hits: search_response.num_hits,
+            estimated_total_hits: search_response.estimated_total_hits,
             hits: documents,
             snippets: snippet_opt,
             elapsed_time_micros: search_response.elapsed_time_micros,
             errors: search_response.errors,
             aggregations: aggregations_opt,
+            sort_values: sort_values_opt,
+            sort_value_types,
+            timed_out: search_response.timed_out,
         })
     }
 }
+
+/// Parses the JSON serialized value of a sort criterion of a hit. Datetimes, serialized as a number
+/// of microseconds since the epoch, are formatted as RFC3339 strings.
+fn parse_sort_value(
+    sort_value_json: &str,
+    sort_value_type: SortValueType,
+) -> Result<JsonValue, SearchError> {
+    let sort_value: JsonValue = serde_json::from_str(sort_value_json).map_err(|err| {
+        SearchError::InternalError(format!(
+            "Failed to deserialize sort value `{sort_value_json}` from JSON: `{err}`."
+        ))
+    })?;
+    let (SortValueType::Datetime, Some(timestamp_micros)) = (sort_value_type, sort_value.as_i64())
+    else {
+        return Ok(sort_value);
+    };
+    let datetime_rfc3339 = DateTime::from_timestamp_micros(timestamp_micros)
+        .into_utc()
+        .format(&Rfc3339)
+        .map_err(|err| {
+            SearchError::InternalError(format!(
+                "Failed to format sort value `{timestamp_micros}` as a RFC3339 datetime: `{err}`."
+            ))
+        })?;
+    Ok(JsonValue::String(datetime_rfc3339))
+}
@@ -39,7 +39,10 @@ use tracing::*;
 
 use super::collector::{PartionnedFastFieldCollector, PartitionValues};
 use super::FastFieldCollector;
-use crate::filters::{create_timestamp_filter_builder, TimestampFilterBuilder};
+use crate::filters::{
+    create_timestamp_filter_builder, request_time_range, timestamp_field_precision,
+    TimestampFilterBuilder,
+};
 use crate::leaf::{open_index_with_caches, warmup};
 use crate::service::SearcherContext;
 use crate::{Result, SearchError};
@@ -150,11 +153,17 @@ async fn leaf_search_stream_single_split(
         .try_into()?;
     let searcher = reader.searcher();
 
+    let timestamp_precision = request_fields
+        .timestamp_field_name()
+        .map(|timestamp_field_name| timestamp_field_precision(&split_schema, timestamp_field_name))
+        .unwrap_or_default();
+    let (start_timestamp_opt, end_timestamp_opt) = request_time_range(&search_request)?;
     let timestamp_filter_builder_opt: Option<TimestampFilterBuilder> =
         create_timestamp_filter_builder(
             request_fields.timestamp_field_name(),
-            search_request.start_timestamp,
-            search_request.end_timestamp,
+            timestamp_precision,
+            start_timestamp_opt,
+            end_timestamp_opt,
         );
 
     let requires_scoring = search_request.sort_by_field.as_deref() == Some("_score");
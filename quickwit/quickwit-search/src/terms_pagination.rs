@@ -0,0 +1,232 @@
+// Copyright (C) 2023 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use serde_json::Value as JsonValue;
+use tantivy::aggregation::agg_req::{AggregationVariants, Aggregations};
+use tantivy::aggregation::agg_result::{AggregationResult, AggregationResults, BucketResult};
+use tantivy::aggregation::bucket::{Order, OrderTarget};
+use tantivy::aggregation::Key;
+
+use crate::collector::MAX_AGGREGATION_BUCKET_LIMIT;
+
+/// Number of buckets of a `terms` aggregation without `size`, as in tantivy.
+const DEFAULT_TERMS_SIZE: u32 = 10;
+
+/// Pages of the top-level `terms` aggregations of a request, see
+/// `SearchRequest::terms_aggregation_after`.
+///
+/// A leaf cannot tell which of its buckets follow the `after` key in the global order of the
+/// buckets, which depends on the doc counts of all the leaves. The paginated aggregations are
+/// therefore widened for the leaves to return all their buckets, and the pages are only cut once
+/// the buckets of all the leaves are merged and ordered.
+#[derive(Debug)]
+pub(crate) struct TermsAggregationPages {
+    page_per_aggregation: HashMap<String, TermsAggregationPage>,
+}
+
+#[derive(Debug)]
+struct TermsAggregationPage {
+    /// Key of the last bucket of the previous page, `None` for the first page.
+    after_opt: Option<Key>,
+    size: usize,
+    order_target: OrderTarget,
+    order: Order,
+}
+
+impl TermsAggregationPages {
+    /// Parses the `after` keys of the request, a JSON object mapping the names of top-level
+    /// `terms` aggregations to a string or numeric key, or to `null` for the first page, and
+    /// widens the paginated aggregations.
+    pub fn parse(
+        terms_aggregation_after: &str,
+        aggregations: &mut Aggregations,
+    ) -> Result<Self, String> {
+        let after_per_aggregation: HashMap<String, JsonValue> =
+            serde_json::from_str(terms_aggregation_after)
+                .map_err(|error| format!("invalid terms_aggregation_after: {error}"))?;
+        let mut page_per_aggregation = HashMap::with_capacity(after_per_aggregation.len());
+
+        for (name, after_json) in after_per_aggregation {
+            let after_opt = match &after_json {
+                JsonValue::Null => None,
+                JsonValue::String(key) => Some(Key::Str(key.clone())),
+                JsonValue::Number(key) if key.as_f64().is_some() => key.as_f64().map(Key::F64),
+                _ => {
+                    return Err(format!(
+                        "the `after` key of terms aggregation `{name}` must be a string, a number \
+                         or null, but got `{after_json}`"
+                    ));
+                }
+            };
+            let Some(aggregation) = aggregations.get_mut(&name) else {
+                return Err(format!(
+                    "terms_aggregation_after references aggregation `{name}`, which is not a \
+                     top-level aggregation"
+                ));
+            };
+            let AggregationVariants::Terms(terms) = &mut aggregation.agg else {
+                return Err(format!(
+                    "aggregation `{name}` is not a `terms` aggregation and cannot be paginated"
+                ));
+            };
+            let (order_target, order) = match &terms.order {
+                Some(custom_order) => (custom_order.target.clone(), custom_order.order),
+                None => (OrderTarget::Count, Order::Desc),
+            };
+            // Ties on a sub-aggregation could not be broken consistently from one page to the
+            // next.
+            if matches!(order_target, OrderTarget::SubAggregation(_)) {
+                return Err(format!(
+                    "terms aggregation `{name}` is ordered by a sub-aggregation and cannot be \
+                     paginated"
+                ));
+            }
+            let size = terms.size.unwrap_or(DEFAULT_TERMS_SIZE) as usize;
+            terms.size = Some(MAX_AGGREGATION_BUCKET_LIMIT);
+            terms.segment_size = Some(MAX_AGGREGATION_BUCKET_LIMIT);
+            page_per_aggregation.insert(
+                name,
+                TermsAggregationPage {
+                    after_opt,
+                    size,
+                    order_target,
+                    order,
+                },
+            );
+        }
+        Ok(TermsAggregationPages {
+            page_per_aggregation,
+        })
+    }
+
+    /// Cuts the page of each paginated aggregation out of its merged buckets. The buckets are
+    /// first ordered with ties broken by key, so that the pages do not overlap. The documents of
+    /// the buckets left out of the page are counted in `sum_other_doc_count`.
+    pub fn paginate(&self, aggregation_results: &mut AggregationResults) -> Result<(), String> {
+        for (name, page) in &self.page_per_aggregation {
+            let Some(AggregationResult::BucketResult(BucketResult::Terms {
+                buckets,
+                sum_other_doc_count,
+                ..
+            })) = aggregation_results.0.get_mut(name)
+            else {
+                continue;
+            };
+            buckets.sort_by(|left_bucket, right_bucket| {
+                let ordering = match page.order_target {
+                    OrderTarget::Count => left_bucket.doc_count.cmp(&right_bucket.doc_count),
+                    _ => cmp_keys(&left_bucket.key, &right_bucket.key),
+                };
+                let ordering = match page.order {
+                    Order::Asc => ordering,
+                    Order::Desc => ordering.reverse(),
+                };
+                ordering.then_with(|| cmp_keys(&left_bucket.key, &right_bucket.key))
+            });
+            let start = match &page.after_opt {
+                Some(after) => {
+                    let after_position = buckets
+                        .iter()
+                        .position(|bucket| cmp_keys(&bucket.key, after) == Ordering::Equal)
+                        .ok_or_else(|| {
+                            format!(
+                                "the `after` key {} of terms aggregation `{name}` matches no \
+                                 bucket",
+                                format_key(after)
+                            )
+                        })?;
+                    after_position + 1
+                }
+                None => 0,
+            };
+            let end = (start + page.size).min(buckets.len());
+            *sum_other_doc_count += buckets[..start]
+                .iter()
+                .chain(&buckets[end..])
+                .map(|bucket| bucket.doc_count)
+                .sum::<u64>();
+            buckets.truncate(end);
+            buckets.drain(..start);
+        }
+        Ok(())
+    }
+}
+
+/// Orders the keys of the buckets, numeric keys first.
+fn cmp_keys(left_key: &Key, right_key: &Key) -> Ordering {
+    match (left_key, right_key) {
+        (Key::Str(left_str), Key::Str(right_str)) => left_str.cmp(right_str),
+        (Key::F64(left_f64), Key::F64(right_f64)) => left_f64.total_cmp(right_f64),
+        (Key::F64(_), Key::Str(_)) => Ordering::Less,
+        (Key::Str(_), Key::F64(_)) => Ordering::Greater,
+    }
+}
+
+fn format_key(key: &Key) -> String {
+    match key {
+        Key::Str(key_str) => format!("`{key_str}`"),
+        Key::F64(key_f64) => key_f64.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tantivy::aggregation::agg_req::{AggregationVariants, Aggregations};
+
+    use super::TermsAggregationPages;
+    use crate::collector::MAX_AGGREGATION_BUCKET_LIMIT;
+
+    #[test]
+    fn test_terms_aggregation_pages_widen_aggregations() {
+        let mut aggregations: Aggregations = serde_json::from_value(json!({
+            "services": {"terms": {"field": "service", "size": 2}},
+            "max_latency": {"max": {"field": "latency"}}
+        }))
+        .unwrap();
+        TermsAggregationPages::parse(r#"{"services": "b"}"#, &mut aggregations).unwrap();
+        let AggregationVariants::Terms(terms) = &aggregations["services"].agg else {
+            panic!("expected a terms aggregation");
+        };
+        assert_eq!(terms.size, Some(MAX_AGGREGATION_BUCKET_LIMIT));
+        assert_eq!(terms.segment_size, Some(MAX_AGGREGATION_BUCKET_LIMIT));
+
+        let parse_error = |terms_aggregation_after: &str| -> String {
+            TermsAggregationPages::parse(terms_aggregation_after, &mut aggregations.clone())
+                .unwrap_err()
+        };
+        assert_eq!(
+            parse_error(r#"{"max_latency": "b"}"#),
+            "aggregation `max_latency` is not a `terms` aggregation and cannot be paginated"
+        );
+        assert_eq!(
+            parse_error(r#"{"hosts": "b"}"#),
+            "terms_aggregation_after references aggregation `hosts`, which is not a top-level \
+             aggregation"
+        );
+        assert_eq!(
+            parse_error(r#"{"services": ["b"]}"#),
+            "the `after` key of terms aggregation `services` must be a string, a number or null, \
+             but got `[\"b\"]`"
+        );
+    }
+}
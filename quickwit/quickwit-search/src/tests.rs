@@ -17,14 +17,17 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 
 use assert_json_diff::{assert_json_eq, assert_json_include};
 use quickwit_config::SearcherConfig;
 use quickwit_doc_mapper::DefaultDocMapper;
 use quickwit_indexing::TestSandbox;
 use quickwit_opentelemetry::otlp::TraceId;
-use quickwit_proto::{LeafListTermsResponse, SearchRequest, SortOrder};
+use quickwit_proto::{
+    CountHitsMode, FastFieldEquals, FastFieldRange, GeoDistanceSort, LeafListTermsResponse,
+    SearchRequest, SortOrder, SortValueType,
+};
 use serde_json::{json, Value as JsonValue};
 use tantivy::schema::Value as TantivyValue;
 use tantivy::time::OffsetDateTime;
@@ -314,7 +317,9 @@ async fn test_single_node_several_splits() -> anyhow::Result<()> {
     assert!(&single_node_result.hits[0].json.contains("Snoopy"));
     assert!(&single_node_result.hits[1].json.contains("breed"));
     assert!(is_sorted(single_node_result.hits.iter().flat_map(|hit| {
-        hit.partial_hit.as_ref().map(partial_hit_sorting_key)
+        hit.partial_hit
+            .as_ref()
+            .map(|partial_hit| partial_hit_sorting_key(partial_hit, SortOrder::Asc))
     })));
     assert!(single_node_result.elapsed_time_micros > 10);
     assert!(single_node_result.elapsed_time_micros < 1_000_000);
@@ -434,6 +439,117 @@ async fn test_single_node_filtering() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_single_node_filtering_back_to_back_windows() -> anyhow::Result<()> {
+    let index_id = "single-node-filtering-back-to-back-windows";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: ts
+                type: datetime
+                input_formats:
+                    - "unix_timestamp"
+                fast: true
+            timestamp_field: ts
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    let start_timestamp = 1_673_363_615;
+    let docs = (0..30)
+        .map(|i| json!({"body": "info", "ts": start_timestamp + i}))
+        .collect::<Vec<_>>();
+    test_sandbox.add_documents(docs).await?;
+
+    let count_hits = |start_timestamp: Option<i64>, end_timestamp: Option<i64>| {
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "info".to_string(),
+            start_timestamp,
+            end_timestamp,
+            max_hits: 0,
+            ..Default::default()
+        };
+        let metastore = test_sandbox.metastore();
+        let storage_uri_resolver = test_sandbox.storage_uri_resolver();
+        async move {
+            single_node_search(&search_request, &*metastore, storage_uri_resolver)
+                .await
+                .map(|search_response| search_response.num_hits)
+        }
+    };
+    let t1 = start_timestamp + 10;
+    let t2 = start_timestamp + 20;
+    // The documents stamped at `t1` and `t2` belong to the windows they start.
+    assert_eq!(count_hits(Some(start_timestamp), Some(t1)).await?, 10);
+    assert_eq!(count_hits(Some(t1), Some(t2)).await?, 10);
+    assert_eq!(count_hits(Some(t2), None).await?, 10);
+    assert_eq!(count_hits(Some(t1), Some(t1 + 1)).await?, 1);
+    assert_eq!(count_hits(Some(t1), Some(t1)).await?, 0);
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_filtering_rfc3339() -> anyhow::Result<()> {
+    let index_id = "single-node-filtering-rfc3339";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: ts
+                type: datetime
+                input_formats:
+                    - "rfc3339"
+                fast: true
+                precision: milliseconds
+            timestamp_field: ts
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    let docs = (1..=5)
+        .map(|i| {
+            let body = format!("info @ t:{i}");
+            let ts = format!("2023-01-10T15:13:35.{i}00Z");
+            json!({"body": body, "ts": ts})
+        })
+        .collect::<Vec<_>>();
+    test_sandbox.add_documents(docs).await?;
+
+    let mut search_request = SearchRequest {
+        index_id: index_id.to_string(),
+        query: "info".to_string(),
+        start_timestamp_rfc3339: Some("2023-01-10T15:13:35.200Z".to_string()),
+        end_timestamp_rfc3339: Some("2023-01-10T16:13:35.400+01:00".to_string()),
+        max_hits: 10,
+        sort_by_field: Some("ts".to_string()),
+        sort_order: Some(SortOrder::Asc as i32),
+        ..Default::default()
+    };
+    let single_node_response = single_node_search(
+        &search_request,
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await?;
+    assert_eq!(single_node_response.num_hits, 2);
+    assert!(single_node_response.hits[0].json.contains("t:2"));
+    assert!(single_node_response.hits[1].json.contains("t:3"));
+
+    search_request.end_timestamp_rfc3339 = Some("yesterday".to_string());
+    let single_node_error = single_node_search(
+        &search_request,
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(single_node_error, SearchError::InvalidArgument(_)));
+    assert!(single_node_error
+        .to_string()
+        .contains("failed to parse `end_timestamp_rfc3339` `yesterday`"));
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
 async fn single_node_search_sort_by_field(
     sort_by_field: &str,
     fieldnorms_enabled: bool,
@@ -485,73 +601,1296 @@ async fn single_node_search_sort_by_field(
     )
     .await?;
 
-    let mut docs = Vec::new();
-    let start_timestamp = 72057595;
-    for i in 0..30 {
-        let timestamp = start_timestamp + (i + 1) as i64;
-        let description = format!("city info-{timestamp}");
-        docs.push(json!({"description": description, "ts": timestamp, "temperature": i+32}));
-    }
+    let mut docs = Vec::new();
+    let start_timestamp = 72057595;
+    for i in 0..30 {
+        let timestamp = start_timestamp + (i + 1) as i64;
+        let description = format!("city info-{timestamp}");
+        docs.push(json!({"description": description, "ts": timestamp, "temperature": i+32}));
+    }
+    test_sandbox.add_documents(docs).await?;
+
+    let search_request = SearchRequest {
+        index_id: index_id.to_string(),
+        query: "city".to_string(),
+        search_fields: Vec::new(),
+        start_timestamp: None,
+        end_timestamp: None,
+        max_hits: 15,
+        start_offset: 0,
+        sort_by_field: Some(sort_by_field.to_string()),
+        sort_order: Some(SortOrder::Desc as i32),
+        ..Default::default()
+    };
+
+    match single_node_search(
+        &search_request,
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await
+    {
+        Ok(single_node_response) => {
+            assert_eq!(single_node_response.num_hits, 30);
+            assert_eq!(single_node_response.hits.len(), 15);
+            assert!(single_node_response.hits.windows(2).all(|hits| hits[0]
+                .partial_hit
+                .as_ref()
+                .unwrap()
+                .sorting_field_value
+                >= hits[1].partial_hit.as_ref().unwrap().sorting_field_value));
+            // Only the hits sorted by score carry their score.
+            assert!(single_node_response.hits.iter().all(|hit| {
+                hit.partial_hit.as_ref().unwrap().score.is_some() == (sort_by_field == "_score")
+            }));
+            test_sandbox.assert_quit().await;
+            Ok(())
+        }
+        Err(err) => {
+            test_sandbox.assert_quit().await;
+            Err(err).map_err(anyhow::Error::from)
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_single_node_sorting_with_query() -> anyhow::Result<()> {
+    single_node_search_sort_by_field("temperature", false).await?;
+    single_node_search_sort_by_field("_score", true).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_sort_by_score_should_fail() -> anyhow::Result<()> {
+    let search_response = single_node_search_sort_by_field("_score", false).await;
+    assert!(search_response.is_err());
+    assert_eq!(
+        search_response.err().map(|err| err.to_string()),
+        Some(
+            "Invalid query: Fieldnorms for field `description` is missing. Fieldnorms must be \
+             stored for the field to compute the BM25 score of the documents."
+                .to_string()
+        )
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_search_after_with_ties() -> anyhow::Result<()> {
+    let index_id = "single-node-search-after-with-ties";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: severity
+                type: i64
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    // Every document shares the same sorting value, and they are spread over three splits.
+    for split_ord in 0..3 {
+        let docs = (0..10)
+            .map(|doc_ord| json!({"body": format!("log {split_ord}-{doc_ord}"), "severity": 1}))
+            .collect();
+        test_sandbox.add_documents(docs).await?;
+    }
+    let mut search_request = SearchRequest {
+        index_id: index_id.to_string(),
+        query: "log".to_string(),
+        max_hits: 7,
+        sort_by_field: Some("severity".to_string()),
+        sort_order: Some(SortOrder::Desc as i32),
+        ..Default::default()
+    };
+    let mut collected_doc_addresses = Vec::new();
+    loop {
+        let single_node_response = single_node_search(
+            &search_request,
+            &*test_sandbox.metastore(),
+            test_sandbox.storage_uri_resolver(),
+        )
+        .await?;
+        assert_eq!(single_node_response.num_hits, 30);
+        let Some(last_hit) = single_node_response.hits.last() else {
+            break;
+        };
+        search_request.search_after = last_hit.partial_hit.clone();
+        collected_doc_addresses.extend(
+            single_node_response
+                .hits
+                .iter()
+                .map(|hit| GlobalDocAddress::from_partial_hit(hit.partial_hit.as_ref().unwrap())),
+        );
+    }
+    assert_eq!(collected_doc_addresses.len(), 30);
+    assert!(is_sorted(collected_doc_addresses.iter()));
+    let unique_doc_addresses: BTreeSet<&GlobalDocAddress> =
+        collected_doc_addresses.iter().collect();
+    assert_eq!(unique_doc_addresses.len(), 30);
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+async fn search_doc_uids_with_tie_breaker(
+    index_id: &str,
+    split_docs: Vec<Vec<JsonValue>>,
+) -> anyhow::Result<Vec<String>> {
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: doc_uid
+                type: text
+                tokenizer: raw
+                fast: true
+              - name: severity
+                type: i64
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    for docs in split_docs {
+        test_sandbox.add_documents(docs).await?;
+    }
+    let search_request = SearchRequest {
+        index_id: index_id.to_string(),
+        query: "log".to_string(),
+        max_hits: 10,
+        sort_by_field: Some("severity".to_string()),
+        sort_order: Some(SortOrder::Desc as i32),
+        tie_breaker_field: Some("doc_uid".to_string()),
+        ..Default::default()
+    };
+    let single_node_response = single_node_search(
+        &search_request,
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await?;
+    let doc_uids = single_node_response
+        .hits
+        .iter()
+        .map(|hit| {
+            let hit_json: JsonValue = serde_json::from_str(&hit.json).unwrap();
+            hit_json["doc_uid"].as_str().unwrap().to_string()
+        })
+        .collect();
+    test_sandbox.assert_quit().await;
+    Ok(doc_uids)
+}
+
+#[tokio::test]
+async fn test_single_node_tie_breaker_field_is_layout_independent() -> anyhow::Result<()> {
+    let docs: Vec<JsonValue> = (0..30)
+        .map(|doc_ord| {
+            json!({
+                "body": "log",
+                "doc_uid": format!("uid-{doc_ord}"),
+                "severity": doc_ord % 2,
+            })
+        })
+        .collect();
+    // Same logical data, indexed as three splits on one side, and as one split in reverse
+    // order on the other side, as a merge would do to the segment ords and doc ids.
+    let several_splits_doc_uids = search_doc_uids_with_tie_breaker(
+        "single-node-tie-breaker-several-splits",
+        docs.chunks(10).map(|chunk| chunk.to_vec()).collect(),
+    )
+    .await?;
+    let single_split_doc_uids = search_doc_uids_with_tie_breaker(
+        "single-node-tie-breaker-single-split",
+        vec![docs.iter().rev().cloned().collect()],
+    )
+    .await?;
+    assert_eq!(several_splits_doc_uids.len(), 10);
+    assert_eq!(several_splits_doc_uids, single_split_doc_uids);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_top_hits_aggregation() -> anyhow::Result<()> {
+    let index_id = "single-node-top-hits-aggregation";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: price
+                type: f64
+                fast: true
+              - name: quantity
+                type: i64
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    for split_ord in 0..2 {
+        let docs = (0..10)
+            .map(|doc_ord| {
+                let price = (split_ord * 10 + doc_ord) as f64;
+                json!({"body": "item", "price": price, "quantity": -doc_ord})
+            })
+            .collect();
+        test_sandbox.add_documents(docs).await?;
+    }
+    let search_request = SearchRequest {
+        index_id: index_id.to_string(),
+        query: "item".to_string(),
+        max_hits: 3,
+        start_offset: 1,
+        sort_by_field: Some("price".to_string()),
+        sort_order: Some(SortOrder::Desc as i32),
+        aggregation_request: Some(r#"{"avg_price": {"avg": {"field": "price"}}}"#.to_string()),
+        top_hits_aggregation_request: Some(
+            r#"{
+                "avg_price": {"avg": {"field": "price"}},
+                "min_quantity": {"min": {"field": "quantity"}}
+            }"#
+            .to_string(),
+        ),
+        ..Default::default()
+    };
+    let single_node_response = single_node_search(
+        &search_request,
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await?;
+    assert_eq!(single_node_response.num_hits, 20);
+    assert_eq!(single_node_response.hits.len(), 3);
+    // The regular aggregation covers every matching document...
+    let aggregation: JsonValue =
+        serde_json::from_str(single_node_response.aggregation.as_ref().unwrap())?;
+    assert_eq!(aggregation["avg_price"]["value"], json!(9.5));
+    // ... while the top hits aggregation only covers the returned hits, priced 18, 17 and 16.
+    let top_hits_aggregation: JsonValue =
+        serde_json::from_str(single_node_response.top_hits_aggregation.as_ref().unwrap())?;
+    assert_eq!(top_hits_aggregation["avg_price"]["value"], json!(17.0));
+    assert_eq!(top_hits_aggregation["min_quantity"]["value"], json!(-8.0));
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_min_score() -> anyhow::Result<()> {
+    let index_id = "single-node-min-score";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: description
+                type: text
+                fieldnorms: true
+        "#;
+    let test_sandbox =
+        TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["description"]).await?;
+    // The more often `city` appears, the higher the score.
+    let docs = (1..=6)
+        .map(|num_cities| json!({"description": vec!["city"; num_cities].join(" ")}))
+        .collect();
+    test_sandbox.add_documents(docs).await?;
+    let search = |min_score: Option<f32>| {
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "city".to_string(),
+            max_hits: 10,
+            sort_by_field: Some("_score".to_string()),
+            min_score,
+            ..Default::default()
+        };
+        let metastore = test_sandbox.metastore();
+        let storage_uri_resolver = test_sandbox.storage_uri_resolver();
+        async move { single_node_search(&search_request, &*metastore, storage_uri_resolver).await }
+    };
+    let scores = |search_response: &SearchResponse| -> Vec<f32> {
+        search_response
+            .hits
+            .iter()
+            .map(|hit| hit.partial_hit.as_ref().unwrap().score.unwrap())
+            .collect()
+    };
+    let search_response = search(None).await?;
+    assert_eq!(search_response.num_hits, 6);
+    let all_scores = scores(&search_response);
+    let min_score = all_scores[2];
+    let search_response = search(Some(min_score)).await?;
+    assert_eq!(search_response.num_hits, 3);
+    assert_eq!(scores(&search_response), all_scores[..3]);
+    let search_response = search(Some(all_scores[0] + 1.0)).await?;
+    assert_eq!(search_response.num_hits, 0);
+    assert!(search_response.hits.is_empty());
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_field_boosts() -> anyhow::Result<()> {
+    let index_id = "single-node-field-boosts";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: title
+                type: text
+                fieldnorms: true
+              - name: body
+                type: text
+                fieldnorms: true
+        "#;
+    let test_sandbox =
+        TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["title", "body"]).await?;
+    // Both documents match `rust` once, in fields with the same statistics.
+    let docs = vec![
+        json!({"title": "rust", "body": "ferris"}),
+        json!({"title": "ferris", "body": "rust"}),
+    ];
+    test_sandbox.add_documents(docs).await?;
+    let search = |field_boosts: &[(&str, f32)]| {
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "rust".to_string(),
+            max_hits: 10,
+            sort_by_field: Some("_score".to_string()),
+            field_boosts: field_boosts
+                .iter()
+                .map(|(field_name, boost)| (field_name.to_string(), *boost))
+                .collect(),
+            ..Default::default()
+        };
+        let metastore = test_sandbox.metastore();
+        let storage_uri_resolver = test_sandbox.storage_uri_resolver();
+        async move { single_node_search(&search_request, &*metastore, storage_uri_resolver).await }
+    };
+    let titles = |search_response: &SearchResponse| -> Vec<String> {
+        search_response
+            .hits
+            .iter()
+            .map(|hit| {
+                let doc: JsonValue = serde_json::from_str(&hit.json).unwrap();
+                doc["title"].as_str().unwrap().to_string()
+            })
+            .collect()
+    };
+    let scores = |search_response: &SearchResponse| -> Vec<f32> {
+        search_response
+            .hits
+            .iter()
+            .map(|hit| hit.partial_hit.as_ref().unwrap().score.unwrap())
+            .collect()
+    };
+    let search_response = search(&[]).await?;
+    let unboosted_scores = scores(&search_response);
+    assert_eq!(unboosted_scores.len(), 2);
+    assert_eq!(unboosted_scores[0], unboosted_scores[1]);
+
+    let search_response = search(&[("title", 2.0)]).await?;
+    assert_eq!(titles(&search_response), ["rust", "ferris"]);
+    let boosted_scores = scores(&search_response);
+    assert!(boosted_scores[0] > boosted_scores[1]);
+    assert_eq!(boosted_scores[1], unboosted_scores[1]);
+
+    let search_response = search(&[("body", 2.0)]).await?;
+    assert_eq!(titles(&search_response), ["ferris", "rust"]);
+
+    let error = search(&[("title", 0.0)]).await.unwrap_err();
+    assert!(matches!(error, SearchError::InvalidQuery(_)), "{error}");
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_sort_by_several_fields() -> anyhow::Result<()> {
+    let index_id = "single-node-sort-by-several-fields";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: priority
+                type: u64
+                fast: true
+              - name: severity
+                type: i64
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    // The two splits interleave their (priority, severity) pairs.
+    for split_ord in 0..2 {
+        let docs = (0..6)
+            .map(|doc_ord| {
+                json!({"body": "log", "priority": doc_ord % 3, "severity": doc_ord * 2 + split_ord})
+            })
+            .collect();
+        test_sandbox.add_documents(docs).await?;
+    }
+    let search_request = SearchRequest {
+        index_id: index_id.to_string(),
+        query: "log".to_string(),
+        max_hits: 5,
+        sort_by_field: Some("priority,-severity".to_string()),
+        sort_order: Some(SortOrder::Asc as i32),
+        ..Default::default()
+    };
+    let single_node_response = single_node_search(
+        &search_request,
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await?;
+    assert_eq!(single_node_response.num_hits, 12);
+    let priority_severity_pairs: Vec<(u64, i64)> = single_node_response
+        .hits
+        .iter()
+        .map(|hit| {
+            let hit_json: JsonValue = serde_json::from_str(&hit.json).unwrap();
+            (
+                hit_json["priority"].as_u64().unwrap(),
+                hit_json["severity"].as_i64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        priority_severity_pairs,
+        vec![(0, 7), (0, 6), (0, 1), (0, 0), (1, 9)]
+    );
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_sort_missing_value_placement() -> anyhow::Result<()> {
+    let index_id = "single-node-sort-missing-value-placement";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: priority
+                type: u64
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    let docs = vec![
+        json!({"body": "log", "priority": 1}),
+        json!({"body": "log"}),
+        json!({"body": "log", "priority": 3}),
+    ];
+    test_sandbox.add_documents(docs).await?;
+    let sorted_priorities = |sort_order: SortOrder, sort_missing: &str| {
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "log".to_string(),
+            max_hits: 10,
+            sort_by_field: Some("priority".to_string()),
+            sort_order: Some(sort_order as i32),
+            sort_missing: Some(sort_missing.to_string()),
+            ..Default::default()
+        };
+        let metastore = test_sandbox.metastore();
+        let storage_uri_resolver = test_sandbox.storage_uri_resolver();
+        async move {
+            let search_response =
+                single_node_search(&search_request, &*metastore, storage_uri_resolver).await?;
+            let priorities: Vec<Option<u64>> = search_response
+                .hits
+                .iter()
+                .map(|hit| {
+                    let hit_json: JsonValue = serde_json::from_str(&hit.json).unwrap();
+                    hit_json["priority"].as_u64()
+                })
+                .collect();
+            crate::Result::Ok(priorities)
+        }
+    };
+    for sort_order in [SortOrder::Asc, SortOrder::Desc] {
+        let priorities = sorted_priorities(sort_order, "_first").await?;
+        assert_eq!(priorities[0], None);
+        let priorities = sorted_priorities(sort_order, "_last").await?;
+        assert_eq!(priorities[2], None);
+    }
+    assert_eq!(
+        sorted_priorities(SortOrder::Asc, "2").await?,
+        vec![Some(1), None, Some(3)]
+    );
+    assert_eq!(
+        sorted_priorities(SortOrder::Desc, "2").await?,
+        vec![Some(3), None, Some(1)]
+    );
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_sort_missing_value_signed_and_float_fields() -> anyhow::Result<()> {
+    let index_id = "single-node-sort-missing-value-signed-and-float-fields";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: offset
+                type: i64
+                fast: true
+              - name: ratio
+                type: f64
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    let docs = vec![
+        json!({"body": "log", "offset": -20, "ratio": -2.5}),
+        json!({"body": "log"}),
+        json!({"body": "log", "offset": 5, "ratio": 0.5}),
+        json!({"body": "log", "offset": 30, "ratio": 3.5}),
+    ];
+    test_sandbox.add_documents(docs).await?;
+    let sorted_values = |sort_by_field: &str, sort_order: SortOrder, sort_missing: &str| {
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "log".to_string(),
+            max_hits: 10,
+            sort_by_field: Some(sort_by_field.to_string()),
+            sort_order: Some(sort_order as i32),
+            sort_missing: Some(sort_missing.to_string()),
+            ..Default::default()
+        };
+        let sort_by_field = sort_by_field.to_string();
+        let metastore = test_sandbox.metastore();
+        let storage_uri_resolver = test_sandbox.storage_uri_resolver();
+        async move {
+            let search_response =
+                single_node_search(&search_request, &*metastore, storage_uri_resolver).await?;
+            let values: Vec<Option<f64>> = search_response
+                .hits
+                .iter()
+                .map(|hit| {
+                    let hit_json: JsonValue = serde_json::from_str(&hit.json).unwrap();
+                    hit_json[&sort_by_field].as_f64()
+                })
+                .collect();
+            crate::Result::Ok(values)
+        }
+    };
+    assert_eq!(
+        sorted_values("offset", SortOrder::Asc, "10").await?,
+        vec![Some(-20.0), Some(5.0), None, Some(30.0)]
+    );
+    assert_eq!(
+        sorted_values("offset", SortOrder::Desc, "-10").await?,
+        vec![Some(30.0), Some(5.0), None, Some(-20.0)]
+    );
+    assert_eq!(
+        sorted_values("ratio", SortOrder::Asc, "1.5").await?,
+        vec![Some(-2.5), Some(0.5), None, Some(3.5)]
+    );
+    assert_eq!(
+        sorted_values("ratio", SortOrder::Desc, "-1").await?,
+        vec![Some(3.5), Some(0.5), None, Some(-2.5)]
+    );
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_estimate_total_hits() -> anyhow::Result<()> {
+    let index_id = "single-node-estimate-total-hits";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    let num_docs = 5_000;
+    let docs = (0..num_docs)
+        .map(|doc_ord| {
+            let mut body = "log".to_string();
+            if doc_ord % 2 == 0 {
+                body.push_str(" even");
+            }
+            if doc_ord % 3 == 0 {
+                body.push_str(" fizz");
+            }
+            json!({ "body": body })
+        })
+        .collect();
+    test_sandbox.add_documents(docs).await?;
+    let search = |query: &str| {
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: query.to_string(),
+            max_hits: 0,
+            estimate_total_hits: true,
+            ..Default::default()
+        };
+        let metastore = test_sandbox.metastore();
+        let storage_uri_resolver = test_sandbox.storage_uri_resolver();
+        async move { single_node_search(&search_request, &*metastore, storage_uri_resolver).await }
+    };
+    // Single term queries are estimated from the document frequency of the term.
+    let search_response = search("fizz").await?;
+    assert_eq!(search_response.num_hits, 1_667);
+    let estimated_total_hits = search_response.estimated_total_hits.unwrap();
+    assert_eq!(estimated_total_hits.value, 1_667);
+    assert_eq!(estimated_total_hits.lower_bound, 1_667);
+    assert_eq!(estimated_total_hits.upper_bound, 1_667);
+
+    // Other queries are estimated by sampling.
+    let search_response = search("even OR fizz").await?;
+    let num_hits = search_response.num_hits;
+    assert_eq!(num_hits, 3_333);
+    let estimated_total_hits = search_response.estimated_total_hits.unwrap();
+    assert!(estimated_total_hits.value.abs_diff(num_hits) <= num_hits / 20);
+    assert!(estimated_total_hits.lower_bound <= num_hits);
+    assert!(estimated_total_hits.upper_bound >= num_hits);
+    assert!(estimated_total_hits.upper_bound < num_docs);
+
+    // The estimate is only computed on demand.
+    let search_request = SearchRequest {
+        index_id: index_id.to_string(),
+        query: "fizz".to_string(),
+        max_hits: 0,
+        ..Default::default()
+    };
+    let search_response = single_node_search(
+        &search_request,
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await?;
+    assert!(search_response.estimated_total_hits.is_none());
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_bucket_samples() -> anyhow::Result<()> {
+    let index_id = "single-node-bucket-samples";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: severity
+                type: text
+                tokenizer: raw
+                fast: true
+              - name: latency
+                type: u64
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    let splits_docs = [
+        [("error", 10), ("error", 50), ("info", 20), ("warn", 5)],
+        [("error", 40), ("info", 70), ("info", 30), ("warn", 1)],
+    ];
+    for split_docs in splits_docs {
+        let docs = split_docs
+            .into_iter()
+            .map(|(severity, latency)| {
+                json!({"body": "log", "severity": severity, "latency": latency})
+            })
+            .collect();
+        test_sandbox.add_documents(docs).await?;
+    }
+    let search = |bucket_samples_request: &str| {
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "log".to_string(),
+            max_hits: 1,
+            sort_by_field: Some("latency".to_string()),
+            sort_order: Some(SortOrder::Desc as i32),
+            bucket_samples_request: Some(bucket_samples_request.to_string()),
+            ..Default::default()
+        };
+        let metastore = test_sandbox.metastore();
+        let storage_uri_resolver = test_sandbox.storage_uri_resolver();
+        async move { single_node_search(&search_request, &*metastore, storage_uri_resolver).await }
+    };
+    let latencies = |hits: &[Hit]| -> Vec<u64> {
+        hits.iter()
+            .map(|hit| {
+                let doc: JsonValue = serde_json::from_str(&hit.json).unwrap();
+                doc["latency"].as_u64().unwrap()
+            })
+            .collect()
+    };
+    let bucket_latencies = |search_response: &SearchResponse| -> Vec<(String, Vec<u64>)> {
+        search_response
+            .bucket_samples
+            .iter()
+            .map(|bucket_samples| (bucket_samples.key.clone(), latencies(&bucket_samples.hits)))
+            .collect()
+    };
+
+    let search_response = search(r#"{"field": "severity", "size": 2}"#).await?;
+    assert_eq!(latencies(&search_response.hits), vec![70]);
+    assert_eq!(
+        bucket_latencies(&search_response),
+        vec![
+            ("error".to_string(), vec![50, 40]),
+            ("info".to_string(), vec![70, 30]),
+            ("warn".to_string(), vec![5, 1]),
+        ]
+    );
+
+    let search_response = search(r#"{"field": "latency", "interval": 50, "size": 2}"#).await?;
+    assert_eq!(latencies(&search_response.hits), vec![70]);
+    assert_eq!(
+        bucket_latencies(&search_response),
+        vec![
+            ("0".to_string(), vec![40, 30]),
+            ("50".to_string(), vec![70, 50])
+        ]
+    );
+
+    let search_response = search(r#"{"field": "latency", "interval": -1, "size": 2}"#).await;
+    assert!(matches!(
+        search_response,
+        Err(SearchError::InvalidAggregationRequest(_))
+    ));
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_collapse_field() -> anyhow::Result<()> {
+    let index_id = "single-node-collapse-field";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: request_id
+                type: text
+                tokenizer: raw
+                fast: true
+              - name: latency
+                type: u64
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    // The same requests are logged in both splits.
+    let splits_docs = [
+        [("req1", 10), ("req2", 50), ("req1", 30), ("req3", 5)],
+        [("req1", 20), ("req2", 40), ("req3", 60), ("req3", 1)],
+    ];
+    for split_docs in splits_docs {
+        let docs = split_docs
+            .into_iter()
+            .map(|(request_id, latency)| {
+                json!({"body": "log", "request_id": request_id, "latency": latency})
+            })
+            .collect();
+        test_sandbox.add_documents(docs).await?;
+    }
+    let search = |max_hits: u64| {
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "log".to_string(),
+            max_hits,
+            sort_by_field: Some("latency".to_string()),
+            sort_order: Some(SortOrder::Desc as i32),
+            collapse_field: Some("request_id".to_string()),
+            ..Default::default()
+        };
+        let metastore = test_sandbox.metastore();
+        let storage_uri_resolver = test_sandbox.storage_uri_resolver();
+        async move { single_node_search(&search_request, &*metastore, storage_uri_resolver).await }
+    };
+    let collapsed_hits = |search_response: &SearchResponse| -> Vec<(String, u64)> {
+        search_response
+            .hits
+            .iter()
+            .map(|hit| {
+                let doc: JsonValue = serde_json::from_str(&hit.json).unwrap();
+                let collapse_key = hit.partial_hit.as_ref().unwrap().collapse_key.clone();
+                assert_eq!(collapse_key.as_deref(), doc["request_id"].as_str());
+                (
+                    doc["request_id"].as_str().unwrap().to_string(),
+                    doc["latency"].as_u64().unwrap(),
+                )
+            })
+            .collect()
+    };
+    let search_response = search(10).await?;
+    assert_eq!(search_response.num_hits, 8);
+    assert_eq!(
+        collapsed_hits(&search_response),
+        vec![
+            ("req3".to_string(), 60),
+            ("req2".to_string(), 50),
+            ("req1".to_string(), 30),
+        ]
+    );
+    let search_response = search(2).await?;
+    assert_eq!(
+        collapsed_hits(&search_response),
+        vec![("req3".to_string(), 60), ("req2".to_string(), 50)]
+    );
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_dedup_field() -> anyhow::Result<()> {
+    let index_id = "single-node-dedup-field";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: event_id
+                type: u64
+                fast: true
+              - name: latency
+                type: u64
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    // The event 2 was ingested again after a failed ingestion, ending up in both splits.
+    let splits_docs = [[(1, 10), (2, 30)], [(2, 30), (3, 20)]];
+    for split_docs in splits_docs {
+        let docs = split_docs
+            .into_iter()
+            .map(|(event_id, latency)| {
+                json!({"body": "log", "event_id": event_id, "latency": latency})
+            })
+            .collect();
+        test_sandbox.add_documents(docs).await?;
+    }
+    let search = |dedup_field: Option<&str>| {
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "log".to_string(),
+            max_hits: 10,
+            sort_by_field: Some("latency".to_string()),
+            sort_order: Some(SortOrder::Desc as i32),
+            dedup_field: dedup_field.map(ToString::to_string),
+            ..Default::default()
+        };
+        let metastore = test_sandbox.metastore();
+        let storage_uri_resolver = test_sandbox.storage_uri_resolver();
+        async move { single_node_search(&search_request, &*metastore, storage_uri_resolver).await }
+    };
+    let event_ids = |search_response: &SearchResponse| -> Vec<u64> {
+        search_response
+            .hits
+            .iter()
+            .map(|hit| {
+                let doc: JsonValue = serde_json::from_str(&hit.json).unwrap();
+                doc["event_id"].as_u64().unwrap()
+            })
+            .collect()
+    };
+    let search_response = search(None).await?;
+    assert_eq!(event_ids(&search_response), [2, 2, 3, 1]);
+
+    let search_response = search(Some("event_id")).await?;
+    assert_eq!(search_response.num_hits, 4);
+    assert_eq!(event_ids(&search_response), [2, 3, 1]);
+    let dedup_keys: Vec<Option<String>> = search_response
+        .hits
+        .iter()
+        .map(|hit| hit.partial_hit.as_ref().unwrap().dedup_key.clone())
+        .collect();
+    assert_eq!(
+        dedup_keys,
+        [
+            Some("2".to_string()),
+            Some("3".to_string()),
+            Some("1".to_string())
+        ]
+    );
+
+    let search_error = search(Some("body")).await.unwrap_err();
+    assert!(search_error
+        .to_string()
+        .contains("Dedup field must be a fast field"));
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_fast_field_equals_filter() -> anyhow::Result<()> {
+    let index_id = "single-node-fast-field-equals-filter";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: tenant_id
+                type: u64
+                fast: true
+              - name: user
+                type: text
+                tokenizer: raw
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    for split_ord in 0..2 {
+        let docs = (0..20)
+            .map(|doc_ord| {
+                json!({
+                    "body": "log",
+                    "tenant_id": split_ord * 100 + doc_ord * 2,
+                    "user": format!("user-{}", doc_ord % 4),
+                })
+            })
+            .collect();
+        test_sandbox.add_documents(docs).await?;
+    }
+    let search_with_filter = |field_name: &str, value: &str| {
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "log".to_string(),
+            max_hits: 10,
+            fast_field_equals: Some(FastFieldEquals {
+                field_name: field_name.to_string(),
+                value: value.to_string(),
+            }),
+            ..Default::default()
+        };
+        let metastore = test_sandbox.metastore();
+        let storage_uri_resolver = test_sandbox.storage_uri_resolver();
+        async move { single_node_search(&search_request, &*metastore, storage_uri_resolver).await }
+    };
+    assert_eq!(search_with_filter("tenant_id", "106").await?.num_hits, 1);
+    assert_eq!(search_with_filter("user", "user-1").await?.num_hits, 10);
+    // Values absent from every split, within the range of values of a split or not.
+    assert_eq!(search_with_filter("tenant_id", "105").await?.num_hits, 0);
+    assert_eq!(search_with_filter("tenant_id", "1000").await?.num_hits, 0);
+    assert_eq!(search_with_filter("user", "user-9").await?.num_hits, 0);
+    let invalid_value_response = search_with_filter("tenant_id", "not-a-number").await?;
+    assert_eq!(invalid_value_response.errors.len(), 2);
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_fast_field_range_filter() -> anyhow::Result<()> {
+    let index_id = "single-node-fast-field-range-filter";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: event_time
+                type: datetime
+                input_formats:
+                    - "unix_timestamp"
+                fast: true
+              - name: ingest_time
+                type: datetime
+                input_formats:
+                    - "unix_timestamp"
+                fast: true
+              - name: latency
+                type: i64
+                fast: true
+            timestamp_field: event_time
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    // Events are ingested 100 seconds after they happen.
+    let start_timestamp = 1_684_000_000;
+    let docs = (0..20)
+        .map(|i| {
+            json!({
+                "body": "event",
+                "event_time": start_timestamp + i,
+                "ingest_time": start_timestamp + i + 100,
+                "latency": i - 10,
+            })
+        })
+        .collect();
+    test_sandbox.add_documents(docs).await?;
+    let search = |start_timestamp_opt: Option<i64>, fast_field_range: FastFieldRange| {
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "event".to_string(),
+            max_hits: 20,
+            start_timestamp: start_timestamp_opt,
+            fast_field_range: Some(fast_field_range),
+            ..Default::default()
+        };
+        let metastore = test_sandbox.metastore();
+        let storage_uri_resolver = test_sandbox.storage_uri_resolver();
+        async move { single_node_search(&search_request, &*metastore, storage_uri_resolver).await }
+    };
+    let ingest_time_range = FastFieldRange {
+        field_name: "ingest_time".to_string(),
+        lower_bound: Some(start_timestamp + 105),
+        upper_bound: Some(start_timestamp + 115),
+    };
+    assert_eq!(search(None, ingest_time_range.clone()).await?.num_hits, 10);
+    // The range on the ingest time is combined with the time range on the event time.
+    assert_eq!(
+        search(Some(start_timestamp + 12), ingest_time_range)
+            .await?
+            .num_hits,
+        3
+    );
+    let latency_range = FastFieldRange {
+        field_name: "latency".to_string(),
+        lower_bound: Some(-2),
+        upper_bound: None,
+    };
+    assert_eq!(search(None, latency_range).await?.num_hits, 12);
+    let empty_range = FastFieldRange {
+        field_name: "latency".to_string(),
+        lower_bound: Some(1_000),
+        upper_bound: Some(2_000),
+    };
+    assert_eq!(search(None, empty_range).await?.num_hits, 0);
+    let unknown_field_range = FastFieldRange {
+        field_name: "unknown".to_string(),
+        lower_bound: Some(0),
+        upper_bound: None,
+    };
+    assert_eq!(search(None, unknown_field_range).await?.num_hits, 0);
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_return_matched_fields() -> anyhow::Result<()> {
+    let index_id = "single-node-return-matched-fields";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: id
+                type: u64
+                fast: true
+              - name: title
+                type: text
+              - name: body
+                type: text
+            mode: dynamic
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    let docs = vec![
+        json!({"id": 0, "title": "beagle", "body": "a hound dog"}),
+        json!({"id": 1, "title": "beagle", "body": "a small dog"}),
+        json!({"id": 2, "title": "poodle", "body": "not a hound", "owner": "beagle"}),
+        json!({"id": 3, "title": "poodle", "body": "a curly dog", "owner": "beagle"}),
+    ];
+    test_sandbox.add_documents(docs).await?;
+    let search_request = SearchRequest {
+        index_id: index_id.to_string(),
+        query: "title:beagle OR body:hound OR owner:beagle".to_string(),
+        max_hits: 10,
+        sort_by_field: Some("id".to_string()),
+        sort_order: Some(SortOrder::Asc as i32),
+        return_matched_fields: true,
+        ..Default::default()
+    };
+    let search_response = single_node_search(
+        &search_request,
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await?;
+    let matched_fields: Vec<Vec<&str>> = search_response
+        .hits
+        .iter()
+        .map(|hit| hit.matched_fields.iter().map(String::as_str).collect())
+        .collect();
+    assert_eq!(
+        matched_fields,
+        vec![
+            vec!["body", "title"],
+            vec!["title"],
+            vec!["body", "owner"],
+            vec!["owner"],
+        ]
+    );
+    // Matched fields are only computed on demand.
+    let search_response = single_node_search(
+        &SearchRequest {
+            return_matched_fields: false,
+            ..search_request
+        },
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await?;
+    assert_eq!(search_response.num_hits, 4);
+    assert!(search_response
+        .hits
+        .iter()
+        .all(|hit| hit.matched_fields.is_empty()));
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_return_highlights() -> anyhow::Result<()> {
+    let index_id = "single-node-return-highlights";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: id
+                type: u64
+                fast: true
+              - name: body
+                type: array<text>
+                record: position
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    let docs = vec![
+        json!({"id": 0, "body": ["the quick brown fox"]}),
+        json!({"id": 1, "body": ["brown", "a quick brown dog"]}),
+        json!({"id": 2, "body": ["brown quick fox"]}),
+    ];
+    test_sandbox.add_documents(docs.clone()).await?;
+    let search_request = SearchRequest {
+        index_id: index_id.to_string(),
+        query: r#"body:"quick brown""#.to_string(),
+        max_hits: 10,
+        sort_by_field: Some("id".to_string()),
+        sort_order: Some(SortOrder::Asc as i32),
+        return_highlights: true,
+        ..Default::default()
+    };
+    let search_response = single_node_search(
+        &search_request,
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await?;
+    assert_eq!(search_response.num_hits, 2);
+    let match_offsets: Vec<Vec<(u32, u32, u32, u32)>> = search_response
+        .hits
+        .iter()
+        .map(|hit| {
+            hit.match_offsets
+                .iter()
+                .map(|match_offset| {
+                    assert_eq!(match_offset.field_name, "body");
+                    (
+                        match_offset.value_ord,
+                        match_offset.position,
+                        match_offset.start_offset,
+                        match_offset.end_offset,
+                    )
+                })
+                .collect()
+        })
+        .collect();
+    // The positions of the second value follow those of the first one, after a gap.
+    assert_eq!(
+        match_offsets,
+        vec![
+            vec![(0, 1, 4, 9), (0, 2, 10, 15)],
+            vec![(0, 0, 0, 5), (1, 3, 2, 7), (1, 4, 8, 13)],
+        ]
+    );
+    for (hit, doc_id) in search_response.hits.iter().zip([0, 1]) {
+        for match_offset in &hit.match_offsets {
+            let value = docs[doc_id]["body"][match_offset.value_ord as usize]
+                .as_str()
+                .unwrap();
+            let token =
+                &value[match_offset.start_offset as usize..match_offset.end_offset as usize];
+            assert!(token == "quick" || token == "brown");
+        }
+    }
+    // Highlights are only computed on demand.
+    let search_response = single_node_search(
+        &SearchRequest {
+            return_highlights: false,
+            ..search_request
+        },
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await?;
+    assert_eq!(search_response.num_hits, 2);
+    assert!(search_response
+        .hits
+        .iter()
+        .all(|hit| hit.match_offsets.is_empty()));
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_hits_only_addresses() -> anyhow::Result<()> {
+    let index_id = "single-node-hits-only-addresses";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: id
+                type: u64
+                fast: true
+              - name: body
+                type: text
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    let docs = vec![
+        json!({"id": 0, "body": "a hound dog"}),
+        json!({"id": 1, "body": "a small dog"}),
+        json!({"id": 2, "body": "not a cat"}),
+        json!({"id": 3, "body": "a curly dog"}),
+    ];
     test_sandbox.add_documents(docs).await?;
-
     let search_request = SearchRequest {
         index_id: index_id.to_string(),
-        query: "city".to_string(),
-        search_fields: Vec::new(),
-        start_timestamp: None,
-        end_timestamp: None,
-        max_hits: 15,
-        start_offset: 0,
-        sort_by_field: Some(sort_by_field.to_string()),
+        query: "body:dog".to_string(),
+        max_hits: 10,
+        sort_by_field: Some("id".to_string()),
         sort_order: Some(SortOrder::Desc as i32),
+        hits_only_addresses: true,
         ..Default::default()
     };
-
-    match single_node_search(
+    let search_response = single_node_search(
         &search_request,
         &*test_sandbox.metastore(),
         test_sandbox.storage_uri_resolver(),
     )
-    .await
-    {
-        Ok(single_node_response) => {
-            assert_eq!(single_node_response.num_hits, 30);
-            assert_eq!(single_node_response.hits.len(), 15);
-            assert!(single_node_response.hits.windows(2).all(|hits| hits[0]
-                .partial_hit
-                .as_ref()
-                .unwrap()
-                .sorting_field_value
-                >= hits[1].partial_hit.as_ref().unwrap().sorting_field_value));
-            test_sandbox.assert_quit().await;
-            Ok(())
-        }
-        Err(err) => {
-            test_sandbox.assert_quit().await;
-            Err(err).map_err(anyhow::Error::from)
-        }
+    .await?;
+    assert_eq!(search_response.num_hits, 3);
+    assert_eq!(search_response.hits.len(), 3);
+    for hit in &search_response.hits {
+        assert!(hit.json.is_empty());
+        assert!(hit.snippet.is_none());
+        assert!(hit.matched_fields.is_empty());
+        assert!(hit.match_offsets.is_empty());
+        let partial_hit = hit.partial_hit.as_ref().unwrap();
+        assert!(!partial_hit.split_id.is_empty());
+        assert_eq!(
+            partial_hit,
+            &PartialHit {
+                split_id: partial_hit.split_id.clone(),
+                segment_ord: partial_hit.segment_ord,
+                doc_id: partial_hit.doc_id,
+                ..Default::default()
+            }
+        );
     }
-}
-
-#[tokio::test]
-async fn test_single_node_sorting_with_query() -> anyhow::Result<()> {
-    single_node_search_sort_by_field("temperature", false).await?;
-    single_node_search_sort_by_field("_score", true).await?;
-    Ok(())
-}
-
-#[tokio::test]
-async fn test_single_node_sort_by_score_should_fail() -> anyhow::Result<()> {
-    let search_response = single_node_search_sort_by_field("_score", false).await;
-    assert!(search_response.is_err());
+    // The addresses are those of the documents returned by a regular search, in the same order.
+    let search_response_with_docs = single_node_search(
+        &SearchRequest {
+            hits_only_addresses: false,
+            ..search_request.clone()
+        },
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await?;
+    let doc_addresses = |search_response: &SearchResponse| -> Vec<(String, u32, u32)> {
+        search_response
+            .hits
+            .iter()
+            .map(|hit| {
+                let partial_hit = hit.partial_hit.as_ref().unwrap();
+                (
+                    partial_hit.split_id.clone(),
+                    partial_hit.segment_ord,
+                    partial_hit.doc_id,
+                )
+            })
+            .collect()
+    };
     assert_eq!(
-        search_response.err().map(|err| err.to_string()),
-        Some(
-            "Invalid query: Fieldnorms for field `description` is missing. Fieldnorms must be \
-             stored for the field to compute the BM25 score of the documents."
-                .to_string()
-        )
+        doc_addresses(&search_response),
+        doc_addresses(&search_response_with_docs)
     );
+    // The documents are needed to build snippets.
+    let search_error = single_node_search(
+        &SearchRequest {
+            snippet_fields: vec!["body".to_string()],
+            ..search_request
+        },
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await
+    .unwrap_err();
+    assert!(search_error
+        .to_string()
+        .contains("`hits_only_addresses` cannot be combined"));
+    test_sandbox.assert_quit().await;
     Ok(())
 }
 
@@ -713,6 +2052,384 @@ async fn test_search_dynamic_util(test_sandbox: &TestSandbox, query: &str) -> Ve
         .collect::<Vec<u32>>()
 }
 
+#[tokio::test]
+async fn test_leaf_search_count_only() -> anyhow::Result<()> {
+    let index_id = "leaf-search-count-only";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: status_code
+                type: u64
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    for split_ord in 0..2 {
+        let docs = (0..50)
+            .map(|doc_ord| {
+                let body = if doc_ord % 5 == 0 { "error" } else { "info" };
+                json!({"body": body, "status_code": 200 + split_ord * 300})
+            })
+            .collect();
+        test_sandbox.add_documents(docs).await?;
+    }
+    let splits_offsets: Vec<SplitIdAndFooterOffsets> = test_sandbox
+        .metastore()
+        .list_all_splits(index_id)
+        .await?
+        .iter()
+        .map(|split| extract_split_and_footer_offsets(&split.split_metadata))
+        .collect();
+    let request = SearchRequest {
+        index_id: index_id.to_string(),
+        query: "error".to_string(),
+        max_hits: 0,
+        start_offset: 5,
+        aggregation_request: Some(
+            json!({"status_codes": {"terms": {"field": "status_code"}}}).to_string(),
+        ),
+        ..Default::default()
+    };
+    let searcher_context = Arc::new(SearcherContext::new(SearcherConfig::default()));
+    let leaf_search_response = leaf_search(
+        searcher_context,
+        &request,
+        test_sandbox.storage(),
+        &splits_offsets,
+        test_sandbox.doc_mapper(),
+    )
+    .await?;
+    assert!(leaf_search_response.partial_hits.is_empty());
+    assert_eq!(leaf_search_response.num_hits, 20);
+
+    // The aggregations are still computed.
+    let search_response = single_node_search(
+        &request,
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await?;
+    assert!(search_response.hits.is_empty());
+    assert_eq!(search_response.num_hits, 20);
+    let aggregation: JsonValue = serde_json::from_str(&search_response.aggregation.unwrap())?;
+    let doc_counts: Vec<u64> = aggregation["status_codes"]["buckets"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|bucket| bucket["doc_count"].as_u64().unwrap())
+        .collect();
+    assert_eq!(doc_counts, vec![10, 10]);
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_count_hits() -> anyhow::Result<()> {
+    let index_id = "single-node-count-hits";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: latency
+                type: u64
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    let docs = (0..100)
+        .map(|latency| json!({"body": "log", "latency": latency}))
+        .collect::<Vec<_>>();
+    test_sandbox.add_documents(docs).await?;
+    let search = |max_hits: u64, count_hits: CountHitsMode, count_hits_threshold: u64| {
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "log".to_string(),
+            max_hits,
+            sort_by_field: Some("latency".to_string()),
+            sort_order: Some(SortOrder::Desc as i32),
+            count_hits: count_hits as i32,
+            count_hits_threshold,
+            ..Default::default()
+        };
+        let metastore = test_sandbox.metastore();
+        let storage_uri_resolver = test_sandbox.storage_uri_resolver();
+        async move { single_node_search(&search_request, &*metastore, storage_uri_resolver).await }
+    };
+    let latencies = |search_response: &SearchResponse| -> Vec<u64> {
+        search_response
+            .hits
+            .iter()
+            .map(|hit| {
+                let doc: JsonValue = serde_json::from_str(&hit.json).unwrap();
+                doc["latency"].as_u64().unwrap()
+            })
+            .collect()
+    };
+
+    let search_response = search(3, CountHitsMode::CountAll, 0).await?;
+    assert_eq!(search_response.num_hits, 100);
+    assert!(!search_response.num_hits_is_lower_bound);
+
+    // Counting stops at the threshold, but the hits are still the top hits.
+    let search_response = search(3, CountHitsMode::Threshold, 10).await?;
+    assert!(search_response.num_hits >= 10);
+    assert!(search_response.num_hits < 100);
+    assert!(search_response.num_hits_is_lower_bound);
+    assert_eq!(latencies(&search_response), vec![99, 98, 97]);
+
+    let search_response = search(0, CountHitsMode::Threshold, 10).await?;
+    assert!(search_response.num_hits >= 10);
+    assert!(search_response.num_hits < 100);
+    assert!(search_response.num_hits_is_lower_bound);
+
+    // The count is exact when the threshold is not reached.
+    let search_response = search(0, CountHitsMode::Threshold, 1_000).await?;
+    assert_eq!(search_response.num_hits, 100);
+    assert!(!search_response.num_hits_is_lower_bound);
+
+    let search_response = search(3, CountHitsMode::Underestimate, 0).await?;
+    assert!(search_response.num_hits >= 3);
+    assert!(search_response.num_hits_is_lower_bound);
+    assert_eq!(latencies(&search_response), vec![99, 98, 97]);
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_soft_deadline() -> anyhow::Result<()> {
+    let index_id = "single-node-soft-deadline";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: latency
+                type: u64
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    let docs = (0..10)
+        .map(|latency| json!({"body": "log", "latency": latency}))
+        .collect::<Vec<_>>();
+    test_sandbox.add_documents(docs).await?;
+    let search = |soft_deadline_millis: u64| {
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "log".to_string(),
+            max_hits: 3,
+            sort_by_field: Some("latency".to_string()),
+            soft_deadline_millis: Some(soft_deadline_millis),
+            ..Default::default()
+        };
+        let metastore = test_sandbox.metastore();
+        let storage_uri_resolver = test_sandbox.storage_uri_resolver();
+        async move { single_node_search(&search_request, &*metastore, storage_uri_resolver).await }
+    };
+
+    let search_response = search(3_600_000).await?;
+    assert!(!search_response.timed_out);
+    assert_eq!(search_response.num_hits, 10);
+    assert_eq!(search_response.hits.len(), 3);
+
+    // The deadline passes before any split is searched: the response is empty, not an error.
+    let search_response = search(0).await?;
+    assert!(search_response.timed_out);
+    assert!(search_response.num_hits_is_lower_bound);
+    assert_eq!(search_response.num_hits, 0);
+    assert!(search_response.hits.is_empty());
+    assert!(search_response.errors.is_empty());
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_sort_by_geo_distance() -> anyhow::Result<()> {
+    let index_id = "single-node-sort-by-geo-distance";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: city
+                type: text
+              - name: lat
+                type: f64
+                fast: true
+              - name: lon
+                type: f64
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["city"]).await?;
+    let docs = vec![
+        json!({"city": "new york", "lat": 40.7128, "lon": -74.0060}),
+        json!({"city": "london", "lat": 51.5074, "lon": -0.1278}),
+        json!({"city": "nowhere"}),
+        json!({"city": "brussels", "lat": 50.8503, "lon": 4.3517}),
+        json!({"city": "sydney", "lat": -33.8688, "lon": 151.2093}),
+    ];
+    test_sandbox.add_documents(docs).await?;
+    let search = |sort_order: SortOrder| {
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "*".to_string(),
+            max_hits: 10,
+            sort_order: Some(sort_order as i32),
+            // Paris.
+            sort_by_geo_distance: Some(GeoDistanceSort {
+                lat_field: "lat".to_string(),
+                lon_field: "lon".to_string(),
+                lat: 48.8566,
+                lon: 2.3522,
+            }),
+            ..Default::default()
+        };
+        let metastore = test_sandbox.metastore();
+        let storage_uri_resolver = test_sandbox.storage_uri_resolver();
+        async move { single_node_search(&search_request, &*metastore, storage_uri_resolver).await }
+    };
+    let cities = |search_response: &SearchResponse| -> Vec<String> {
+        search_response
+            .hits
+            .iter()
+            .map(|hit| {
+                let doc: JsonValue = serde_json::from_str(&hit.json).unwrap();
+                doc["city"].as_str().unwrap().to_string()
+            })
+            .collect()
+    };
+    // Nearer documents come first, documents without coordinates last.
+    let search_response = search(SortOrder::Asc).await?;
+    assert_eq!(
+        cities(&search_response),
+        ["brussels", "london", "new york", "sydney", "nowhere"]
+    );
+    let search_response = search(SortOrder::Desc).await?;
+    assert_eq!(
+        cities(&search_response),
+        ["sydney", "new york", "london", "brussels", "nowhere"]
+    );
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_return_sort_values() -> anyhow::Result<()> {
+    let index_id = "single-node-return-sort-values";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: temperature
+                type: f64
+                fast: true
+              - name: offset
+                type: i64
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    let docs = vec![
+        json!({"body": "log", "temperature": 12.25, "offset": -10}),
+        json!({"body": "log", "offset": 1}),
+        json!({"body": "log", "temperature": -3.5, "offset": 5}),
+        json!({"body": "log", "temperature": -3.5, "offset": -2}),
+    ];
+    test_sandbox.add_documents(docs).await?;
+    let search = |return_sort_values: bool| {
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "log".to_string(),
+            max_hits: 10,
+            sort_by_field: Some("temperature,offset".to_string()),
+            sort_order: Some(SortOrder::Asc as i32),
+            return_sort_values,
+            ..Default::default()
+        };
+        let metastore = test_sandbox.metastore();
+        let storage_uri_resolver = test_sandbox.storage_uri_resolver();
+        async move { single_node_search(&search_request, &*metastore, storage_uri_resolver).await }
+    };
+    let sort_values = |search_response: &SearchResponse| -> Vec<Vec<String>> {
+        search_response
+            .hits
+            .iter()
+            .map(|hit| hit.partial_hit.as_ref().unwrap().sort_values.clone())
+            .collect()
+    };
+    // The values are the actual ones despite the ascending order.
+    let search_response = search(true).await?;
+    assert_eq!(
+        sort_values(&search_response),
+        [
+            ["-3.5", "-2"],
+            ["-3.5", "5"],
+            ["12.25", "-10"],
+            ["null", "1"]
+        ]
+    );
+    assert_eq!(
+        search_response.sort_value_types,
+        [SortValueType::F64 as i32, SortValueType::I64 as i32]
+    );
+    let search_response = search(false).await?;
+    assert!(sort_values(&search_response)
+        .iter()
+        .all(|sort_values| sort_values.is_empty()));
+    assert!(search_response.sort_value_types.is_empty());
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_random_sample() -> anyhow::Result<()> {
+    let index_id = "single-node-random-sample";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: body
+                type: text
+              - name: id
+                type: u64
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["body"]).await?;
+    for split_ord in 0..2 {
+        let docs = (0..50)
+            .map(|doc_ord| json!({"body": "log", "id": split_ord * 50 + doc_ord}))
+            .collect();
+        test_sandbox.add_documents(docs).await?;
+    }
+    let sample_ids = |random_sample_seed: u64| {
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "log".to_string(),
+            max_hits: 10,
+            random_sample_seed: Some(random_sample_seed),
+            ..Default::default()
+        };
+        let metastore = test_sandbox.metastore();
+        let storage_uri_resolver = test_sandbox.storage_uri_resolver();
+        async move {
+            let search_response =
+                single_node_search(&search_request, &*metastore, storage_uri_resolver).await?;
+            assert_eq!(search_response.num_hits, 100);
+            let ids: Vec<u64> = search_response
+                .hits
+                .iter()
+                .map(|hit| {
+                    let doc: JsonValue = serde_json::from_str(&hit.json).unwrap();
+                    doc["id"].as_u64().unwrap()
+                })
+                .collect();
+            crate::Result::Ok(ids)
+        }
+    };
+    let ids = sample_ids(42).await?;
+    assert_eq!(ids.len(), 10);
+    assert_eq!(ids.iter().collect::<HashSet<_>>().len(), 10);
+    // Both splits contribute to the sample.
+    assert!(ids.iter().any(|id| *id < 50));
+    assert!(ids.iter().any(|id| *id >= 50));
+    // The sample is reproducible with the same seed only.
+    assert_eq!(sample_ids(42).await?, ids);
+    assert_ne!(sample_ids(43).await?, ids);
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_search_dynamic_mode() -> anyhow::Result<()> {
     let doc_mapping_yaml = r#"
@@ -1046,6 +2763,382 @@ async fn test_single_node_aggregation() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_single_node_aggregation_several_splits() -> anyhow::Result<()> {
+    let index_id = "single-node-agg-several-splits";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: color
+                type: text
+                tokenizer: raw
+                fast: true
+              - name: price
+                type: f64
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["color"]).await?;
+    // Each split yields its own intermediate results, which are merged after a postcard
+    // round-trip.
+    test_sandbox
+        .add_documents(vec![
+            json!({"color": "blue", "price": 10.0}),
+            json!({"color": "white", "price": 100.0}),
+        ])
+        .await?;
+    test_sandbox
+        .add_documents(vec![
+            json!({"color": "blue", "price": 20.0}),
+            json!({"color": "green", "price": 5.0}),
+        ])
+        .await?;
+    let agg_req = json!({
+        "colors": {
+            "terms": {"field": "color", "order": {"_key": "asc"}},
+            "aggs": {
+                "price_stats": {"stats": {"field": "price"}}
+            }
+        }
+    });
+    let search_request = SearchRequest {
+        index_id: index_id.to_string(),
+        query: "*".to_string(),
+        max_hits: 0,
+        aggregation_request: Some(agg_req.to_string()),
+        ..Default::default()
+    };
+    let single_node_result = single_node_search(
+        &search_request,
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await?;
+    assert_eq!(single_node_result.num_hits, 4);
+    let agg_res_json: JsonValue = serde_json::from_str(&single_node_result.aggregation.unwrap())?;
+    let buckets = &agg_res_json["colors"]["buckets"];
+    assert_eq!(buckets[0]["key"], "blue");
+    assert_eq!(buckets[0]["doc_count"], 2);
+    assert_eq!(buckets[0]["price_stats"]["sum"], 30.0);
+    assert_eq!(buckets[1]["key"], "green");
+    assert_eq!(buckets[1]["doc_count"], 1);
+    assert_eq!(buckets[2]["key"], "white");
+    assert_eq!(buckets[2]["price_stats"]["max"], 100.0);
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_paginated_terms_aggregation() -> anyhow::Result<()> {
+    let index_id = "single-node-paginated-terms-agg";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: color
+                type: text
+                tokenizer: raw
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["color"]).await?;
+    // The colors are spread over several splits, with ties on the doc counts.
+    let colors_per_split = [
+        vec!["blue", "blue", "red", "green", "white"],
+        vec!["blue", "red", "yellow", "black"],
+        vec!["green", "purple", "orange", "red"],
+    ];
+    for colors in &colors_per_split {
+        let docs = colors
+            .iter()
+            .map(|color| json!({ "color": color }))
+            .collect();
+        test_sandbox.add_documents(docs).await?;
+    }
+    let search_terms_agg = |size: u32, after_opt: Option<JsonValue>| {
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "*".to_string(),
+            max_hits: 0,
+            aggregation_request: Some(
+                json!({"colors": {"terms": {"field": "color", "size": size}}}).to_string(),
+            ),
+            terms_aggregation_after: after_opt.map(|after| json!({ "colors": after }).to_string()),
+            ..Default::default()
+        };
+        let test_sandbox = &test_sandbox;
+        async move {
+            let search_response = single_node_search(
+                &search_request,
+                &*test_sandbox.metastore(),
+                test_sandbox.storage_uri_resolver(),
+            )
+            .await?;
+            let aggregation_json: JsonValue =
+                serde_json::from_str(&search_response.aggregation.unwrap())?;
+            let buckets: Vec<(String, u64)> = aggregation_json["colors"]["buckets"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|bucket| {
+                    (
+                        bucket["key"].as_str().unwrap().to_string(),
+                        bucket["doc_count"].as_u64().unwrap(),
+                    )
+                })
+                .collect();
+            let sum_other_doc_count = aggregation_json["colors"]["sum_other_doc_count"]
+                .as_u64()
+                .unwrap();
+            anyhow::Ok((buckets, sum_other_doc_count))
+        }
+    };
+    let (all_buckets, sum_other_doc_count) = search_terms_agg(100, None).await?;
+    assert_eq!(all_buckets.len(), 8);
+    assert_eq!(sum_other_doc_count, 0);
+
+    let mut paged_buckets: Vec<(String, u64)> = Vec::new();
+    let mut after = JsonValue::Null;
+    loop {
+        let (page_buckets, sum_other_doc_count) = search_terms_agg(3, Some(after)).await?;
+        let num_page_docs: u64 = page_buckets.iter().map(|(_, doc_count)| doc_count).sum();
+        // The documents of the other pages are counted as other documents.
+        assert_eq!(sum_other_doc_count + num_page_docs, 13);
+        let Some((last_key, _)) = page_buckets.last() else {
+            break;
+        };
+        after = json!(last_key);
+        paged_buckets.extend(page_buckets);
+    }
+    // The pages reassemble all the buckets, without gaps nor duplicates, ties on the doc counts
+    // being broken by key.
+    assert_eq!(
+        paged_buckets,
+        [
+            ("blue", 3),
+            ("red", 3),
+            ("green", 2),
+            ("black", 1),
+            ("orange", 1),
+            ("purple", 1),
+            ("white", 1),
+            ("yellow", 1),
+        ]
+        .map(|(key, doc_count)| (key.to_string(), doc_count))
+    );
+    assert_eq!(
+        paged_buckets.iter().collect::<HashSet<_>>(),
+        all_buckets.iter().collect::<HashSet<_>>()
+    );
+
+    let error = search_terms_agg(3, Some(json!("magenta")))
+        .await
+        .unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("the `after` key `magenta` of terms aggregation `colors` matches no bucket"));
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_distinct_count_aggregation() -> anyhow::Result<()> {
+    let index_id = "single-node-distinct-count";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: color
+                type: text
+                tokenizer: raw
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["color"]).await?;
+    // The sketches of the splits are merged after a postcard round-trip.
+    test_sandbox
+        .add_documents(vec![json!({"color": "blue"}), json!({"color": "white"})])
+        .await?;
+    test_sandbox
+        .add_documents(vec![json!({"color": "blue"}), json!({"color": "green"})])
+        .await?;
+    let search_request = SearchRequest {
+        index_id: index_id.to_string(),
+        query: "*".to_string(),
+        max_hits: 0,
+        aggregation_request: Some(json!({"distinct_count_field": "color"}).to_string()),
+        ..Default::default()
+    };
+    let single_node_result = single_node_search(
+        &search_request,
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await?;
+    assert_eq!(single_node_result.num_hits, 4);
+    let agg_res_json: JsonValue = serde_json::from_str(&single_node_result.aggregation.unwrap())?;
+    assert_eq!(agg_res_json, json!({"value": 3}));
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_aggregation_without_match() -> anyhow::Result<()> {
+    let index_id = "single-node-agg-without-match";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: ts
+                type: datetime
+                input_formats:
+                    - "unix_timestamp"
+                fast: true
+            timestamp_field: ts
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &[]).await?;
+    let start_timestamp = OffsetDateTime::now_utc().unix_timestamp();
+    test_sandbox
+        .add_documents(vec![json!({ "ts": start_timestamp })])
+        .await?;
+    let agg_req = json!({
+        "histo": {
+            "date_histogram": {"field": "ts", "fixed_interval": "1d"}
+        }
+    });
+    // The time range prunes the only split, so that no leaf response carries an aggregation.
+    let search_request = SearchRequest {
+        index_id: index_id.to_string(),
+        query: "*".to_string(),
+        start_timestamp: Some(start_timestamp + 10),
+        max_hits: 0,
+        aggregation_request: Some(agg_req.to_string()),
+        ..Default::default()
+    };
+    let single_node_result = single_node_search(
+        &search_request,
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await?;
+    assert_eq!(single_node_result.num_hits, 0);
+    let agg_res_json: JsonValue = serde_json::from_str(&single_node_result.aggregation.unwrap())?;
+    assert_eq!(agg_res_json["histo"]["buckets"], json!([]));
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_aggregation_high_cardinality_several_splits() -> anyhow::Result<()> {
+    let index_id = "single-node-agg-high-cardinality";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: term
+                type: text
+                tokenizer: raw
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["term"]).await?;
+    // Consecutive splits share half of their terms.
+    let num_splits = 5;
+    let num_terms_per_split = 1_000;
+    for split_ord in 0..num_splits {
+        let docs: Vec<JsonValue> = (0..num_terms_per_split)
+            .map(|term_ord| {
+                let term = format!("term-{}", split_ord * num_terms_per_split / 2 + term_ord);
+                json!({ "term": term })
+            })
+            .collect();
+        test_sandbox.add_documents(docs).await?;
+    }
+    let agg_req = json!({
+        "terms": {
+            "terms": {"field": "term", "size": 100_000}
+        }
+    });
+    let search_request = SearchRequest {
+        index_id: index_id.to_string(),
+        query: "*".to_string(),
+        max_hits: 0,
+        aggregation_request: Some(agg_req.to_string()),
+        ..Default::default()
+    };
+    let single_node_result = single_node_search(
+        &search_request,
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await?;
+    assert_eq!(
+        single_node_result.num_hits,
+        num_splits * num_terms_per_split
+    );
+    let agg_res_json: JsonValue = serde_json::from_str(&single_node_result.aggregation.unwrap())?;
+    let buckets = agg_res_json["terms"]["buckets"].as_array().unwrap();
+    assert_eq!(
+        buckets.len() as u64,
+        (num_splits + 1) * num_terms_per_split / 2
+    );
+    let total_doc_count: u64 = buckets
+        .iter()
+        .map(|bucket| bucket["doc_count"].as_u64().unwrap())
+        .sum();
+    assert_eq!(total_doc_count, num_splits * num_terms_per_split);
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_node_aggregation_bucket_limit_override() -> anyhow::Result<()> {
+    let index_id = "single-node-agg-bucket-limit";
+    let doc_mapping_yaml = r#"
+            field_mappings:
+              - name: color
+                type: text
+                tokenizer: raw
+                fast: true
+        "#;
+    let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &["color"]).await?;
+    test_sandbox
+        .add_documents(vec![
+            json!({"color": "blue"}),
+            json!({"color": "white"}),
+            json!({"color": "green"}),
+        ])
+        .await?;
+    let agg_req = json!({
+        "colors": {
+            "terms": {"field": "color"}
+        }
+    });
+    let mut search_request = SearchRequest {
+        index_id: index_id.to_string(),
+        query: "*".to_string(),
+        max_hits: 0,
+        aggregation_request: Some(agg_req.to_string()),
+        ..Default::default()
+    };
+    let single_node_result = single_node_search(
+        &search_request,
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await?;
+    assert!(single_node_result.errors.is_empty());
+    assert!(single_node_result.aggregation.is_some());
+
+    search_request.aggregation_bucket_limit = Some(1);
+    let single_node_result = single_node_search(
+        &search_request,
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await?;
+    assert_eq!(single_node_result.errors.len(), 1);
+
+    search_request.aggregation_bucket_limit = Some(2_000_000);
+    let single_node_error = single_node_search(
+        &search_request,
+        &*test_sandbox.metastore(),
+        test_sandbox.storage_uri_resolver(),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(single_node_error, SearchError::InvalidArgument(_)));
+    test_sandbox.assert_quit().await;
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_single_node_aggregation_missing_fast_field() {
     let index_id = "single-node-agg-2";
@@ -1460,6 +3553,10 @@ async fn test_single_node_find_trace_ids_collector() {
                 type: datetime
                 fast: true
                 precision: seconds
+              - name: service_name
+                type: text
+                tokenizer: raw
+                fast: true
         "#;
     let foo_trace_id = TraceId::new([1u8; 16]);
     let bar_trace_id = TraceId::new([2u8; 16]);
@@ -1479,6 +3576,21 @@ async fn test_single_node_find_trace_ids_collector() {
         json!({"trace_id": qux_trace_id, "span_timestamp_secs": "2025-01-10T15:13:35Z"}),
         json!({"trace_id": baz_trace_id, "span_timestamp_secs": "2022-01-10T15:13:35Z"}),
     ];
+    // The spans of `bar` and `baz` belong to the `backend` service.
+    let docs: Vec<JsonValue> = docs
+        .into_iter()
+        .map(|mut doc| {
+            let trace_id = &doc["trace_id"];
+            let service_name =
+                if *trace_id == json!(bar_trace_id) || *trace_id == json!(baz_trace_id) {
+                    "backend"
+                } else {
+                    "frontend"
+                };
+            doc["service_name"] = json!(service_name);
+            doc
+        })
+        .collect();
     let test_sandbox = TestSandbox::create(index_id, doc_mapping_yaml, "{}", &[])
         .await
         .unwrap();
@@ -1529,5 +3641,63 @@ async fn test_single_node_find_trace_ids_collector() {
             1673363620
         );
     }
+    {
+        let aggregations = r#"{
+            "num_traces": 3,
+            "trace_id_field_name": "trace_id",
+            "span_timestamp_field_name": "span_timestamp_secs",
+            "service_name_field": "service_name",
+            "service_name": "backend"
+        }"#
+        .to_string();
+
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "*".to_string(),
+            aggregation_request: Some(aggregations),
+            max_hits: 0,
+            ..Default::default()
+        };
+        let single_node_result = single_node_search(
+            &search_request,
+            &*test_sandbox.metastore(),
+            test_sandbox.storage_uri_resolver(),
+        )
+        .await
+        .unwrap();
+        let aggregation = single_node_result.aggregation.unwrap();
+        let trace_ids: Vec<Span> = serde_json::from_str(&aggregation).unwrap();
+        assert_eq!(trace_ids.len(), 2);
+        assert_eq!(trace_ids[0].trace_id, bar_trace_id);
+        assert_eq!(trace_ids[1].trace_id, baz_trace_id);
+    }
+    {
+        let aggregations = r#"{
+            "num_traces": 3,
+            "trace_id_field_name": "trace_id",
+            "span_timestamp_field_name": "span_timestamp_secs",
+            "service_name_field": "service_name",
+            "service_name": "unknown"
+        }"#
+        .to_string();
+
+        let search_request = SearchRequest {
+            index_id: index_id.to_string(),
+            query: "*".to_string(),
+            aggregation_request: Some(aggregations),
+            max_hits: 0,
+            ..Default::default()
+        };
+        let single_node_result = single_node_search(
+            &search_request,
+            &*test_sandbox.metastore(),
+            test_sandbox.storage_uri_resolver(),
+        )
+        .await
+        .unwrap();
+        let aggregation = single_node_result.aggregation.unwrap();
+        let trace_ids: Vec<Span> = serde_json::from_str(&aggregation).unwrap();
+        assert!(trace_ids.is_empty());
+    }
     test_sandbox.assert_quit().await;
 }
@@ -0,0 +1,268 @@
+// Copyright (C) 2023 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use tantivy::collector::{Collector, SegmentCollector};
+use tantivy::fastfield::Column;
+use tantivy::{DateTime, DocId, Score, SegmentReader, TantivyError};
+
+/// Returns the smallest and the largest timestamps among the matching documents, e.g. to pick the
+/// bounds of the buckets of a histogram without running two `stats` aggregations.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimestampRangeCollector {
+    /// Parameters of the aggregation.
+    pub timestamp_range: TimestampRangeParams,
+}
+
+/// Parameters of [`TimestampRangeCollector`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimestampRangeParams {
+    /// The name of the datetime fast field whose range is computed. Defaults to the timestamp
+    /// field of the doc mapper.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+impl TimestampRangeCollector {
+    /// The names of the fast fields accessed by this collector.
+    pub fn fast_field_names(&self) -> HashSet<String> {
+        self.timestamp_range.field.iter().cloned().collect()
+    }
+
+    /// Fills in the timestamp field of the doc mapper if the request does not name a field.
+    pub(crate) fn resolve_field(&mut self, timestamp_field_name_opt: Option<&str>) -> bool {
+        if self.timestamp_range.field.is_none() {
+            self.timestamp_range.field = timestamp_field_name_opt.map(ToString::to_string);
+        }
+        self.timestamp_range.field.is_some()
+    }
+}
+
+impl Collector for TimestampRangeCollector {
+    type Fruit = Option<TimestampRange>;
+    type Child = TimestampRangeSegmentCollector;
+
+    fn for_segment(
+        &self,
+        _segment_local_id: u32,
+        segment_reader: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        let field_name = self.timestamp_range.field.as_ref().ok_or_else(|| {
+            TantivyError::InternalError(
+                "the field of the timestamp range aggregation should be resolved".to_string(),
+            )
+        })?;
+        let timestamp_column_opt: Option<Column<DateTime>> = segment_reader
+            .fast_fields()
+            .column_opt::<DateTime>(field_name)?;
+        Ok(TimestampRangeSegmentCollector {
+            timestamp_column_opt,
+            timestamp_range_opt: None,
+        })
+    }
+
+    fn merge_fruits(
+        &self,
+        segment_fruits: Vec<<Self::Child as SegmentCollector>::Fruit>,
+    ) -> tantivy::Result<Self::Fruit> {
+        Ok(segment_fruits
+            .into_iter()
+            .flatten()
+            .reduce(TimestampRange::merge))
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+}
+
+pub struct TimestampRangeSegmentCollector {
+    /// `None` if no document of the segment has a value for the field.
+    timestamp_column_opt: Option<Column<DateTime>>,
+    timestamp_range_opt: Option<TimestampRange>,
+}
+
+impl SegmentCollector for TimestampRangeSegmentCollector {
+    type Fruit = Option<TimestampRange>;
+
+    fn collect(&mut self, doc: DocId, _score: Score) {
+        let Some(timestamp_column) = &self.timestamp_column_opt else {
+            return;
+        };
+        for timestamp in timestamp_column.values_for_doc(doc) {
+            let timestamp_range = TimestampRange {
+                min_timestamp: timestamp,
+                max_timestamp: timestamp,
+            };
+            self.timestamp_range_opt = Some(match self.timestamp_range_opt {
+                Some(running_timestamp_range) => running_timestamp_range.merge(timestamp_range),
+                None => timestamp_range,
+            });
+        }
+    }
+
+    fn harvest(self) -> Self::Fruit {
+        self.timestamp_range_opt
+    }
+}
+
+/// Smallest and largest timestamps of a set of documents, both inclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimestampRange {
+    /// Smallest timestamp, serialized as a number of microseconds since the epoch.
+    #[serde(with = "serde_datetime")]
+    pub min_timestamp: DateTime,
+    /// Largest timestamp, serialized as a number of microseconds since the epoch.
+    #[serde(with = "serde_datetime")]
+    pub max_timestamp: DateTime,
+}
+
+impl TimestampRange {
+    /// Returns the range covering both ranges.
+    pub(crate) fn merge(self, other: TimestampRange) -> TimestampRange {
+        TimestampRange {
+            min_timestamp: self.min_timestamp.min(other.min_timestamp),
+            max_timestamp: self.max_timestamp.max(other.max_timestamp),
+        }
+    }
+}
+
+mod serde_datetime {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use tantivy::DateTime;
+
+    pub(crate) fn serialize<S>(datetime: &DateTime, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer {
+        serializer.serialize_i64(datetime.into_timestamp_micros())
+    }
+
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<DateTime, D::Error>
+    where D: Deserializer<'de> {
+        let timestamp_micros: i64 = Deserialize::deserialize(deserializer)?;
+        Ok(DateTime::from_timestamp_micros(timestamp_micros))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quickwit_doc_mapper::{DefaultDocMapper, DocMapper};
+    use serde_json::json;
+    use tantivy::collector::Collector;
+    use tantivy::query::AllQuery;
+    use tantivy::{doc, Index};
+
+    use super::*;
+    use crate::QuickwitAggregations;
+
+    #[test]
+    fn test_timestamp_range_collector_serde() {
+        let aggregation: QuickwitAggregations =
+            serde_json::from_str(r#"{"timestamp_range": {}}"#).unwrap();
+        let QuickwitAggregations::TimestampRangeAggregation(mut collector) = aggregation else {
+            panic!("Expected TimestampRangeAggregation");
+        };
+        assert!(collector.timestamp_range.field.is_none());
+        assert!(!collector.resolve_field(None));
+        assert!(collector.resolve_field(Some("timestamp")));
+        assert_eq!(
+            collector.timestamp_range.field.as_deref(),
+            Some("timestamp")
+        );
+
+        let aggregation: QuickwitAggregations =
+            serde_json::from_str(r#"{"timestamp_range": {"field": "created_at"}}"#).unwrap();
+        let QuickwitAggregations::TimestampRangeAggregation(mut collector) = aggregation else {
+            panic!("Expected TimestampRangeAggregation");
+        };
+        assert!(collector.resolve_field(Some("timestamp")));
+        assert_eq!(
+            collector.timestamp_range.field.as_deref(),
+            Some("created_at")
+        );
+
+        // An aggregation named `timestamp_range` is still a Tantivy aggregation.
+        let aggregation: QuickwitAggregations =
+            serde_json::from_str(r#"{"timestamp_range": {"stats": {"field": "timestamp"}}}"#)
+                .unwrap();
+        assert!(matches!(
+            aggregation,
+            QuickwitAggregations::TantivyAggregations(_)
+        ));
+    }
+
+    #[test]
+    fn test_timestamp_range_collector() {
+        let doc_mapper: DefaultDocMapper = serde_json::from_value(json!({
+            "field_mappings": [
+                {"name": "timestamp", "type": "datetime", "fast": true},
+                {"name": "status_code", "type": "u64", "fast": true},
+            ],
+            "timestamp_field": "timestamp",
+        }))
+        .unwrap();
+        let schema = doc_mapper.schema();
+        let timestamp_field = schema.get_field("timestamp").unwrap();
+        let status_code_field = schema.get_field("status_code").unwrap();
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        for timestamp_secs in [20, 10, 30] {
+            index_writer
+                .add_document(doc!(
+                    timestamp_field => DateTime::from_timestamp_secs(timestamp_secs),
+                ))
+                .unwrap();
+        }
+        index_writer.commit().unwrap();
+        for timestamp_secs in [5, 15] {
+            index_writer
+                .add_document(doc!(
+                    timestamp_field => DateTime::from_timestamp_secs(timestamp_secs),
+                ))
+                .unwrap();
+        }
+        // A document without a timestamp does not count.
+        index_writer
+            .add_document(doc!(status_code_field => 200u64))
+            .unwrap();
+        index_writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        assert_eq!(searcher.segment_readers().len(), 2);
+
+        let timestamp_range = |field_name: &str| {
+            let collector = TimestampRangeCollector {
+                timestamp_range: TimestampRangeParams {
+                    field: Some(field_name.to_string()),
+                },
+            };
+            searcher.search(&AllQuery, &collector).unwrap()
+        };
+        assert_eq!(
+            timestamp_range("timestamp"),
+            Some(TimestampRange {
+                min_timestamp: DateTime::from_timestamp_secs(5),
+                max_timestamp: DateTime::from_timestamp_secs(30),
+            })
+        );
+        assert_eq!(timestamp_range("missing_field"), None);
+    }
+}
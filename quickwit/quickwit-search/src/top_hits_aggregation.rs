@@ -0,0 +1,215 @@
+// Copyright (C) 2023 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use quickwit_proto::PartialHit;
+use serde::{Deserialize, Serialize};
+use tantivy::columnar::{ColumnType, MonotonicallyMappableToU64};
+use tantivy::fastfield::Column;
+use tantivy::{DocId, SegmentReader};
+
+/// Metric aggregations computed over the fast field values of the returned hits only,
+/// as opposed to the regular aggregations which are computed over all the matching documents.
+///
+/// Their results can only be computed once the final top-K is known, so the hits carry the
+/// fast field values they need in their `docvalue_fields`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct TopHitsAggregations {
+    metrics: BTreeMap<String, TopHitsMetric>,
+}
+
+/// A metric computed over the values of a fast field.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum TopHitsMetric {
+    Avg(MetricField),
+    Min(MetricField),
+    Max(MetricField),
+    Sum(MetricField),
+    ValueCount(MetricField),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MetricField {
+    field: String,
+}
+
+impl TopHitsMetric {
+    fn field_name(&self) -> &str {
+        match self {
+            TopHitsMetric::Avg(metric_field)
+            | TopHitsMetric::Min(metric_field)
+            | TopHitsMetric::Max(metric_field)
+            | TopHitsMetric::Sum(metric_field)
+            | TopHitsMetric::ValueCount(metric_field) => &metric_field.field,
+        }
+    }
+
+    fn compute(&self, partial_hits: &[PartialHit]) -> MetricResult {
+        let values = partial_hits
+            .iter()
+            .flat_map(|partial_hit| partial_hit.docvalue_fields.get(self.field_name()))
+            .copied();
+        let value = match self {
+            TopHitsMetric::Avg(_) => {
+                let (count, sum) = values.fold((0usize, 0f64), |(count, sum), value| {
+                    (count + 1, sum + value)
+                });
+                if count == 0 {
+                    None
+                } else {
+                    Some(sum / count as f64)
+                }
+            }
+            TopHitsMetric::Min(_) => values.reduce(f64::min),
+            TopHitsMetric::Max(_) => values.reduce(f64::max),
+            TopHitsMetric::Sum(_) => Some(values.sum()),
+            TopHitsMetric::ValueCount(_) => Some(values.count() as f64),
+        };
+        MetricResult { value }
+    }
+}
+
+/// The result of a metric, serialized like its tantivy aggregation counterpart.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct MetricResult {
+    pub value: Option<f64>,
+}
+
+impl TopHitsAggregations {
+    /// Returns the fast fields the hits need to carry.
+    pub fn fast_field_names(&self) -> HashSet<String> {
+        self.metrics
+            .values()
+            .map(|metric| metric.field_name().to_string())
+            .collect()
+    }
+
+    /// Computes the metrics over the given hits, which must already be the final top-K.
+    pub fn compute(&self, partial_hits: &[PartialHit]) -> BTreeMap<String, MetricResult> {
+        self.metrics
+            .iter()
+            .map(|(name, metric)| (name.clone(), metric.compute(partial_hits)))
+            .collect()
+    }
+}
+
+/// Reads the fast field values carried by the hits of a given segment.
+pub(crate) struct DocValueFieldsReader {
+    columns: Vec<(String, Column<u64>, ColumnType)>,
+}
+
+impl DocValueFieldsReader {
+    pub fn open(
+        field_names: &HashSet<String>,
+        segment_reader: &SegmentReader,
+    ) -> tantivy::Result<Self> {
+        let mut columns = Vec::with_capacity(field_names.len());
+        for field_name in field_names {
+            if let Some((column, column_type)) =
+                segment_reader.fast_fields().u64_lenient(field_name)?
+            {
+                columns.push((field_name.clone(), column, column_type));
+            }
+        }
+        Ok(DocValueFieldsReader { columns })
+    }
+
+    /// Returns the first value of each numerical field for the given document.
+    pub fn read(&self, doc_id: DocId) -> HashMap<String, f64> {
+        self.columns
+            .iter()
+            .flat_map(|(field_name, column, column_type)| {
+                let value = column.first(doc_id)?;
+                let value_f64 = fast_field_value_to_f64(value, *column_type)?;
+                Some((field_name.clone(), value_f64))
+            })
+            .collect()
+    }
+}
+
+/// Converts a value read through `u64_lenient` back to its numerical value.
+///
+/// Dates are returned as their raw timestamp, in the precision of the fast field.
+fn fast_field_value_to_f64(value: u64, column_type: ColumnType) -> Option<f64> {
+    match column_type {
+        ColumnType::U64 | ColumnType::Bool => Some(value as f64),
+        ColumnType::I64 | ColumnType::DateTime => Some(i64::from_u64(value) as f64),
+        ColumnType::F64 => Some(f64::from_u64(value)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quickwit_proto::PartialHit;
+
+    use super::{MetricResult, TopHitsAggregations};
+
+    fn make_hit(doc_id: u32, price_opt: Option<f64>) -> PartialHit {
+        PartialHit {
+            doc_id,
+            docvalue_fields: price_opt
+                .map(|price| ("price".to_string(), price))
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_top_hits_aggregations_skip_missing_values() {
+        let top_hits_aggregations: TopHitsAggregations = serde_json::from_str(
+            r#"{
+                "avg_price": {"avg": {"field": "price"}},
+                "min_price": {"min": {"field": "price"}},
+                "max_price": {"max": {"field": "price"}},
+                "sum_price": {"sum": {"field": "price"}},
+                "num_prices": {"value_count": {"field": "price"}},
+                "max_missing": {"max": {"field": "missing"}}
+            }"#,
+        )
+        .unwrap();
+        let partial_hits = vec![
+            make_hit(0, Some(10.0)),
+            make_hit(1, None),
+            make_hit(2, Some(30.0)),
+        ];
+        let results = top_hits_aggregations.compute(&partial_hits);
+        let value = |name: &str| results.get(name).cloned().unwrap();
+        assert_eq!(value("avg_price"), MetricResult { value: Some(20.0) });
+        assert_eq!(value("min_price"), MetricResult { value: Some(10.0) });
+        assert_eq!(value("max_price"), MetricResult { value: Some(30.0) });
+        assert_eq!(value("sum_price"), MetricResult { value: Some(40.0) });
+        assert_eq!(value("num_prices"), MetricResult { value: Some(2.0) });
+        assert_eq!(value("max_missing"), MetricResult { value: None });
+    }
+
+    #[test]
+    fn test_top_hits_aggregations_reject_unknown_metric() {
+        let error = serde_json::from_str::<TopHitsAggregations>(
+            r#"{"p99_price": {"percentiles": {"field": "price"}}}"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("unknown variant `percentiles`"));
+    }
+}
@@ -0,0 +1,258 @@
+// Copyright (C) 2023 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::BinaryHeap;
+
+/// Above this number of hits, a [`BinaryHeap`] beats the [`SortedTopK`] insertion sort.
+pub(crate) const SORTED_TOP_K_MAX_HITS: usize = 16;
+
+/// Keeps the best items offered, the best items being the lowest ones.
+///
+/// The caller is in charge of the capacity: it pushes items until it holds as many as it
+/// needs, and then replaces the worst item by the better ones.
+pub trait TopK<T: Ord> {
+    /// Returns the number of items held.
+    fn len(&self) -> usize;
+
+    /// Returns true if no item is held.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the worst item held, if any.
+    fn worst(&self) -> Option<&T>;
+
+    /// Adds an item.
+    fn push(&mut self, item: T);
+
+    /// Replaces the worst item held, if any, by `item`.
+    fn replace_worst(&mut self, item: T);
+
+    /// Returns the items held, from the best to the worst.
+    fn into_sorted_vec(self) -> Vec<T>;
+}
+
+impl<T: Ord> TopK<T> for BinaryHeap<T> {
+    #[inline]
+    fn len(&self) -> usize {
+        BinaryHeap::len(self)
+    }
+
+    #[inline]
+    fn worst(&self) -> Option<&T> {
+        self.peek()
+    }
+
+    #[inline]
+    fn push(&mut self, item: T) {
+        BinaryHeap::push(self, item);
+    }
+
+    #[inline]
+    fn replace_worst(&mut self, item: T) {
+        if let Some(mut worst) = self.peek_mut() {
+            *worst = item;
+        }
+    }
+
+    fn into_sorted_vec(self) -> Vec<T> {
+        BinaryHeap::into_sorted_vec(self)
+    }
+}
+
+/// Keeps the items sorted from the best to the worst, inserting them like an insertion sort.
+/// The items being contiguous and a few, it is faster than a [`BinaryHeap`] for small top-Ks,
+/// see `SORTED_TOP_K_MAX_HITS`.
+pub struct SortedTopK<T> {
+    items: Vec<T>,
+}
+
+impl<T> SortedTopK<T> {
+    /// Creates a `SortedTopK` holding up to `capacity` items without reallocating.
+    pub fn with_capacity(capacity: usize) -> Self {
+        SortedTopK {
+            items: Vec::with_capacity(capacity),
+        }
+    }
+}
+
+impl<T: Ord> TopK<T> for SortedTopK<T> {
+    #[inline]
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    #[inline]
+    fn worst(&self) -> Option<&T> {
+        self.items.last()
+    }
+
+    #[inline]
+    fn push(&mut self, item: T) {
+        self.items.push(item);
+        let mut pos = self.items.len() - 1;
+        while pos > 0 && self.items[pos] < self.items[pos - 1] {
+            self.items.swap(pos, pos - 1);
+            pos -= 1;
+        }
+    }
+
+    #[inline]
+    fn replace_worst(&mut self, item: T) {
+        if self.items.pop().is_some() {
+            self.push(item);
+        }
+    }
+
+    fn into_sorted_vec(self) -> Vec<T> {
+        self.items
+    }
+}
+
+/// The top-K structure of a segment collector, picked by the number of hits to collect.
+pub(crate) enum AdaptiveTopK<T> {
+    Sorted(SortedTopK<T>),
+    Heap(BinaryHeap<T>),
+}
+
+impl<T: Ord> AdaptiveTopK<T> {
+    /// Creates the top-K structure best suited to collect `max_hits` items, with room for
+    /// `capacity` of them.
+    pub fn new(max_hits: usize, capacity: usize) -> Self {
+        if max_hits <= SORTED_TOP_K_MAX_HITS {
+            AdaptiveTopK::Sorted(SortedTopK::with_capacity(capacity))
+        } else {
+            AdaptiveTopK::Heap(BinaryHeap::with_capacity(capacity))
+        }
+    }
+
+    #[cfg(test)]
+    pub fn capacity(&self) -> usize {
+        match self {
+            AdaptiveTopK::Sorted(sorted_top_k) => sorted_top_k.items.capacity(),
+            AdaptiveTopK::Heap(heap) => heap.capacity(),
+        }
+    }
+}
+
+impl<T: Ord> TopK<T> for AdaptiveTopK<T> {
+    #[inline]
+    fn len(&self) -> usize {
+        match self {
+            AdaptiveTopK::Sorted(sorted_top_k) => sorted_top_k.len(),
+            AdaptiveTopK::Heap(heap) => TopK::len(heap),
+        }
+    }
+
+    #[inline]
+    fn worst(&self) -> Option<&T> {
+        match self {
+            AdaptiveTopK::Sorted(sorted_top_k) => sorted_top_k.worst(),
+            AdaptiveTopK::Heap(heap) => heap.worst(),
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, item: T) {
+        match self {
+            AdaptiveTopK::Sorted(sorted_top_k) => sorted_top_k.push(item),
+            AdaptiveTopK::Heap(heap) => TopK::push(heap, item),
+        }
+    }
+
+    #[inline]
+    fn replace_worst(&mut self, item: T) {
+        match self {
+            AdaptiveTopK::Sorted(sorted_top_k) => sorted_top_k.replace_worst(item),
+            AdaptiveTopK::Heap(heap) => heap.replace_worst(item),
+        }
+    }
+
+    fn into_sorted_vec(self) -> Vec<T> {
+        match self {
+            AdaptiveTopK::Sorted(sorted_top_k) => sorted_top_k.into_sorted_vec(),
+            AdaptiveTopK::Heap(heap) => TopK::into_sorted_vec(heap),
+        }
+    }
+}
+
+/// Offers `item` to `top_k`, which keeps at most `max_hits` items, and returns whether it
+/// entered it. In case of a tie with the worst item held, the latter is kept.
+#[inline]
+pub fn offer_to_top_k<T: Ord>(top_k: &mut impl TopK<T>, item: T, max_hits: usize) -> bool {
+    if top_k.len() < max_hits {
+        top_k.push(item);
+        true
+    } else if top_k.worst().map(|worst| item < *worst).unwrap_or(false) {
+        top_k.replace_worst(item);
+        true
+    } else {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn collect_top_k<T: Ord + Clone>(
+        mut top_k: impl TopK<T>,
+        items: &[T],
+        max_hits: usize,
+    ) -> Vec<T> {
+        for item in items {
+            offer_to_top_k(&mut top_k, item.clone(), max_hits);
+        }
+        top_k.into_sorted_vec()
+    }
+
+    #[test]
+    fn test_adaptive_top_k_picks_structure_by_max_hits() {
+        assert!(matches!(
+            AdaptiveTopK::<u64>::new(SORTED_TOP_K_MAX_HITS, 0),
+            AdaptiveTopK::Sorted(_)
+        ));
+        assert!(matches!(
+            AdaptiveTopK::<u64>::new(SORTED_TOP_K_MAX_HITS + 1, 0),
+            AdaptiveTopK::Heap(_)
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn test_proptest_sorted_top_k_matches_heap(
+            items in proptest::collection::vec(0u64..100, 0..200),
+            max_hits in 0usize..40,
+        ) {
+            let mut expected_top_k = items.clone();
+            expected_top_k.sort_unstable();
+            expected_top_k.truncate(max_hits);
+            prop_assert_eq!(
+                &collect_top_k(SortedTopK::with_capacity(max_hits), &items, max_hits),
+                &expected_top_k
+            );
+            prop_assert_eq!(
+                &collect_top_k(BinaryHeap::with_capacity(max_hits), &items, max_hits),
+                &expected_top_k
+            );
+        }
+    }
+}
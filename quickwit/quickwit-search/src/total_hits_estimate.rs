@@ -0,0 +1,216 @@
+// Copyright (C) 2023 Quickwit, Inc.
+//
+// Quickwit is offered under the AGPL v3.0 and as commercial software.
+// For commercial licensing, contact us at hello@quickwit.io.
+//
+// AGPL:
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use std::hash::Hasher;
+
+use fnv::FnvHasher;
+use quickwit_proto::EstimatedTotalHits;
+use tantivy::query::{EnableScoring, Query, Scorer, TermQuery};
+use tantivy::{DocId, DocSet, Searcher, SegmentReader, TERMINATED};
+
+/// Number of documents of a segment checked against the query when sampling.
+/// Segments with fewer documents are checked exhaustively.
+const NUM_SAMPLED_DOCS: u32 = 1_000;
+
+/// Z-score of the 95% confidence interval of the estimates.
+const CONFIDENCE_Z_SCORE: f64 = 1.96;
+
+/// Estimates the number of documents of a split matching a query without counting them.
+///
+/// Single term queries are estimated from the document frequency of their term, which is exact
+/// unless the split has deleted documents. Other queries are estimated by checking a sample of
+/// the documents of each segment.
+pub(crate) fn estimate_total_hits(
+    searcher: &Searcher,
+    query: &dyn Query,
+) -> tantivy::Result<EstimatedTotalHits> {
+    if let Some(term_query) = query.downcast_ref::<TermQuery>() {
+        return estimate_from_doc_freq(searcher, term_query);
+    }
+    let weight = query.weight(EnableScoring::disabled_from_searcher(searcher))?;
+    let mut estimate = Estimate::default();
+    for segment_reader in searcher.segment_readers() {
+        let mut scorer = weight.scorer(segment_reader, 1.0)?;
+        estimate.add(sample_segment(segment_reader, &mut *scorer));
+    }
+    Ok(estimate.into())
+}
+
+fn estimate_from_doc_freq(
+    searcher: &Searcher,
+    term_query: &TermQuery,
+) -> tantivy::Result<EstimatedTotalHits> {
+    let mut estimated_total_hits = EstimatedTotalHits::default();
+    for segment_reader in searcher.segment_readers() {
+        let doc_freq = segment_reader
+            .inverted_index(term_query.term().field())?
+            .doc_freq(term_query.term())? as u64;
+        // The document frequency includes the deleted documents, which are assumed to
+        // match as often as the others.
+        let alive_ratio = segment_reader.num_docs() as f64 / segment_reader.max_doc().max(1) as f64;
+        let num_deleted_docs = segment_reader.num_deleted_docs() as u64;
+        estimated_total_hits.value += (doc_freq as f64 * alive_ratio).round() as u64;
+        estimated_total_hits.lower_bound += doc_freq.saturating_sub(num_deleted_docs);
+        estimated_total_hits.upper_bound += doc_freq;
+    }
+    Ok(estimated_total_hits)
+}
+
+/// Sums the estimates of several leaf responses, skipping the responses without one.
+///
+/// Summing the bounds of the confidence intervals yields a conservative interval.
+pub(crate) fn merge_estimated_total_hits<'a>(
+    estimated_total_hits_iter: impl IntoIterator<Item = Option<&'a EstimatedTotalHits>>,
+) -> Option<EstimatedTotalHits> {
+    estimated_total_hits_iter.into_iter().flatten().fold(
+        None,
+        |merged_opt, estimated_total_hits| {
+            let mut merged: EstimatedTotalHits = merged_opt.unwrap_or_default();
+            merged.value += estimated_total_hits.value;
+            merged.lower_bound += estimated_total_hits.lower_bound;
+            merged.upper_bound += estimated_total_hits.upper_bound;
+            Some(merged)
+        },
+    )
+}
+
+/// Estimate of the number of matching documents, with the variance of the estimator.
+#[derive(Debug, Default)]
+struct Estimate {
+    value: f64,
+    variance: f64,
+    num_docs: u64,
+}
+
+impl Estimate {
+    /// Adds the estimate of another segment, assuming independent estimators.
+    fn add(&mut self, other: Estimate) {
+        self.value += other.value;
+        self.variance += other.variance;
+        self.num_docs += other.num_docs;
+    }
+}
+
+impl From<Estimate> for EstimatedTotalHits {
+    fn from(estimate: Estimate) -> Self {
+        let margin = CONFIDENCE_Z_SCORE * estimate.variance.sqrt();
+        let clamp = |value: f64| value.round().clamp(0.0, estimate.num_docs as f64) as u64;
+        EstimatedTotalHits {
+            value: clamp(estimate.value),
+            lower_bound: clamp(estimate.value - margin),
+            upper_bound: clamp(estimate.value + margin),
+        }
+    }
+}
+
+/// Checks a stratified sample of the documents of the segment against the query.
+///
+/// The segment is split into `NUM_SAMPLED_DOCS` strata of consecutive documents, and one
+/// pseudo-random document is picked in each of them. Picking the documents in increasing order
+/// lets us seek through the matching documents once.
+fn sample_segment(segment_reader: &SegmentReader, scorer: &mut dyn Scorer) -> Estimate {
+    let max_doc = segment_reader.max_doc();
+    let num_samples = max_doc.min(NUM_SAMPLED_DOCS);
+    if num_samples == 0 {
+        return Estimate::default();
+    }
+    let alive_bitset_opt = segment_reader.alive_bitset();
+    let mut num_matching_samples = 0u32;
+    let mut current_doc = scorer.doc();
+    for sample_ord in 0..num_samples {
+        let doc_id = sampled_doc_id(sample_ord, num_samples, max_doc);
+        if current_doc < doc_id {
+            current_doc = scorer.seek(doc_id);
+        }
+        if current_doc == TERMINATED {
+            break;
+        }
+        let is_alive = alive_bitset_opt
+            .map(|alive_bitset| alive_bitset.is_alive(doc_id))
+            .unwrap_or(true);
+        if current_doc == doc_id && is_alive {
+            num_matching_samples += 1;
+        }
+    }
+    let num_docs = max_doc as f64;
+    let num_samples = num_samples as f64;
+    let matching_ratio = num_matching_samples as f64 / num_samples;
+    // The finite population correction nullifies the variance of an exhaustive sample.
+    let finite_population_correction = if max_doc > 1 {
+        (num_docs - num_samples) / (num_docs - 1.0)
+    } else {
+        0.0
+    };
+    let ratio_variance =
+        matching_ratio * (1.0 - matching_ratio) / num_samples * finite_population_correction;
+    Estimate {
+        value: matching_ratio * num_docs,
+        variance: ratio_variance * num_docs * num_docs,
+        num_docs: segment_reader.num_docs() as u64,
+    }
+}
+
+/// Returns the document picked in the given stratum, in `[0, max_doc)`.
+fn sampled_doc_id(sample_ord: u32, num_samples: u32, max_doc: DocId) -> DocId {
+    let stratum_start = (sample_ord as u64 * max_doc as u64 / num_samples as u64) as DocId;
+    let stratum_end = ((sample_ord as u64 + 1) * max_doc as u64 / num_samples as u64) as DocId;
+    let mut hasher = FnvHasher::default();
+    hasher.write_u32(sample_ord);
+    stratum_start + (hasher.finish() % (stratum_end - stratum_start) as u64) as DocId
+}
+
+#[cfg(test)]
+mod tests {
+    use quickwit_proto::EstimatedTotalHits;
+
+    use super::{merge_estimated_total_hits, sampled_doc_id};
+
+    #[test]
+    fn test_merge_estimated_total_hits() {
+        assert_eq!(merge_estimated_total_hits([None, None]), None);
+        let estimated_total_hits = EstimatedTotalHits {
+            value: 10,
+            lower_bound: 8,
+            upper_bound: 13,
+        };
+        assert_eq!(
+            merge_estimated_total_hits([
+                Some(&estimated_total_hits),
+                None,
+                Some(&estimated_total_hits)
+            ]),
+            Some(EstimatedTotalHits {
+                value: 20,
+                lower_bound: 16,
+                upper_bound: 26,
+            })
+        );
+    }
+
+    #[test]
+    fn test_sampled_doc_ids_are_increasing_and_in_range() {
+        for (num_samples, max_doc) in [(1, 1), (10, 10), (10, 25), (1_000, 1_234_567)] {
+            let doc_ids: Vec<u32> = (0..num_samples)
+                .map(|sample_ord| sampled_doc_id(sample_ord, num_samples, max_doc))
+                .collect();
+            assert!(doc_ids.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(*doc_ids.last().unwrap() < max_doc);
+        }
+    }
+}
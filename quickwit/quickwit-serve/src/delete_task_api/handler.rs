@@ -24,7 +24,7 @@ use quickwit_janitor::error::JanitorError;
 use quickwit_metastore::{Metastore, MetastoreError};
 use quickwit_proto::metastore_api::{DeleteQuery, DeleteTask};
 use quickwit_proto::SearchRequest;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use warp::{Filter, Rejection};
 
 use crate::format::{extract_format_from_qs, make_response};
@@ -39,7 +39,7 @@ pub struct DeleteTaskApi;
 
 /// This struct represents the delete query passed to
 /// the rest API.
-#[derive(Deserialize, Debug, Eq, PartialEq, Default, utoipa::ToSchema)]
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Default, utoipa::ToSchema)]
 #[serde(deny_unknown_fields)]
 pub struct DeleteQueryRequest {
     /// Query text. The query language is that of tantivy.
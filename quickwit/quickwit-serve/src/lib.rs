@@ -82,6 +82,7 @@ use tower::ServiceBuilder;
 use tracing::{debug, error, warn};
 use warp::{Filter, Rejection};
 
+pub use crate::delete_task_api::DeleteQueryRequest;
 pub use crate::index_api::ListSplitsQueryParams;
 pub use crate::metrics::SERVE_METRICS;
 #[cfg(test)]
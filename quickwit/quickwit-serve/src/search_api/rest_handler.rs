@@ -23,8 +23,9 @@ use std::sync::Arc;
 use futures::stream::StreamExt;
 use hyper::header::HeaderValue;
 use hyper::HeaderMap;
+use quickwit_common::is_false;
 use quickwit_common::simple_list::{from_simple_list, to_simple_list};
-use quickwit_proto::{OutputFormat, ServiceError, SortOrder};
+use quickwit_proto::{EstimatedTotalHits, OutputFormat, ServiceError, SortOrder, SortValueType};
 use quickwit_search::{SearchError, SearchResponseRest, SearchService};
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value as JsonValue;
@@ -43,6 +44,8 @@ use crate::{with_arg, BodyFormat};
         SearchResponseRest,
         SortByField,
         SortOrder,
+        SortValueType,
+        EstimatedTotalHits,
         OutputFormat,
         BodyFormat,
     ),)
@@ -53,18 +56,19 @@ pub struct SearchApi;
 pub struct SortByField {
     /// Name of the field to sort by.
     pub field_name: String,
-    /// Order to sort by. A usual top-k search implies a descending order.
-    pub order: SortOrder,
+    /// Order to sort by, given by a `+` or `-` prefix. Without a prefix, the field is sorted in
+    /// its default order, e.g. descending for `_score`.
+    pub order: Option<SortOrder>,
 }
 
 impl From<String> for SortByField {
     fn from(string: String) -> Self {
         let (field_name, order) = if let Some(rest) = string.strip_prefix('+') {
-            (rest.trim().to_string(), SortOrder::Asc)
+            (rest.trim().to_string(), Some(SortOrder::Asc))
         } else if let Some(rest) = string.strip_prefix('-') {
-            (rest.trim().to_string(), SortOrder::Desc)
+            (rest.trim().to_string(), Some(SortOrder::Desc))
         } else {
-            (string.trim().to_string(), SortOrder::Asc)
+            (string.trim().to_string(), None)
         };
         SortByField { field_name, order }
     }
@@ -80,8 +84,9 @@ impl Serialize for SortByField {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where S: Serializer {
         let sort_str = match self.order {
-            SortOrder::Desc => "-",
-            SortOrder::Asc => "",
+            Some(SortOrder::Desc) => "-",
+            Some(SortOrder::Asc) => "+",
+            None => "",
         };
         serializer.serialize_str(&format!("{}{}", sort_str, self.field_name))
     }
@@ -148,6 +153,16 @@ pub struct SearchRequestQueryString {
     /// This timestamp is expressed in seconds.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub end_timestamp: Option<i64>,
+    /// If set, restrict search to documents with a `timestamp >= start_timestamp_rfc3339`.
+    /// This timestamp is an RFC3339 datetime such as `2023-01-10T15:13:35.123Z`, exclusive with
+    /// `start_timestamp`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_timestamp_rfc3339: Option<String>,
+    /// If set, restrict search to documents with a `timestamp < end_timestamp_rfc3339`.
+    /// This timestamp is an RFC3339 datetime such as `2023-01-10T15:13:35.123Z`, exclusive with
+    /// `end_timestamp`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_timestamp_rfc3339: Option<String>,
     /// Maximum number of hits to return (by default 20).
     #[serde(default = "default_max_hits")]
     pub max_hits: u64,
@@ -167,12 +182,17 @@ pub struct SearchRequestQueryString {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sort_by_field: Option<SortByField>,
+    /// If set, the response carries the values of the sort criteria of each hit, along with their
+    /// types.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_false")]
+    pub return_sort_values: bool,
 }
 
 fn get_proto_search_by(search_request: &SearchRequestQueryString) -> (Option<i32>, Option<String>) {
     if let Some(sort_by_field) = &search_request.sort_by_field {
         (
-            Some(sort_by_field.order as i32),
+            sort_by_field.order.map(|order| order as i32),
             Some(sort_by_field.field_name.clone()),
         )
     } else {
@@ -200,6 +220,10 @@ async fn search_endpoint(
             .map(|agg| serde_json::to_string(&agg).expect("could not serialize JsonValue")),
         sort_order,
         sort_by_field,
+        return_sort_values: search_request.return_sort_values,
+        start_timestamp_rfc3339: search_request.start_timestamp_rfc3339,
+        end_timestamp_rfc3339: search_request.end_timestamp_rfc3339,
+        ..Default::default()
     };
     let search_response = search_service.root_search(search_request).await?;
     let search_response_rest = SearchResponseRest::try_from(search_response)?;
@@ -447,11 +471,15 @@ mod tests {
     fn test_serialize_search_response() -> anyhow::Result<()> {
         let search_response = SearchResponseRest {
             num_hits: 55,
+            estimated_total_hits: None,
             hits: Vec::new(),
             snippets: None,
             elapsed_time_micros: 0u64,
             errors: Vec::new(),
             aggregations: None,
+            sort_values: None,
+            sort_value_types: Vec::new(),
+            timed_out: false,
         };
         let search_response_json: JsonValue = serde_json::to_value(&search_response)?;
         let expected_search_response_json: JsonValue = json!({
@@ -594,7 +622,7 @@ mod tests {
                 search_fields: None,
                 sort_by_field: Some(SortByField {
                     field_name: "field".to_string(),
-                    order: SortOrder::Asc
+                    order: None
                 }),
                 ..Default::default()
             }
@@ -602,7 +630,7 @@ mod tests {
 
         let rest_search_api_filter = search_get_filter();
         let (_, req) = warp::test::request()
-            .path("/quickwit-demo-index/search?query=*&format=json&sort_by_field=+field")
+            .path("/quickwit-demo-index/search?query=*&format=json&sort_by_field=%2Bfield")
             .filter(&rest_search_api_filter)
             .await
             .unwrap();
@@ -618,7 +646,7 @@ mod tests {
                 search_fields: None,
                 sort_by_field: Some(SortByField {
                     field_name: "field".to_string(),
-                    order: SortOrder::Asc
+                    order: Some(SortOrder::Asc)
                 }),
                 ..Default::default()
             }
@@ -642,7 +670,7 @@ mod tests {
                 search_fields: None,
                 sort_by_field: Some(SortByField {
                     field_name: "field".to_string(),
-                    order: SortOrder::Desc
+                    order: Some(SortOrder::Desc)
                 }),
                 ..Default::default()
             }
@@ -707,6 +735,179 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_rest_search_api_timestamp_rfc3339_parameters() -> anyhow::Result<()> {
+        let mut mock_search_service = MockSearchService::new();
+        mock_search_service
+            .expect_root_search()
+            .with(predicate::function(
+                |search_request: &quickwit_proto::SearchRequest| {
+                    search_request.start_timestamp_rfc3339.as_deref()
+                        == Some("2023-01-10T15:13:35.123Z")
+                        && search_request.end_timestamp_rfc3339.as_deref()
+                            == Some("2023-01-11T00:00:00Z")
+                        && search_request.start_timestamp.is_none()
+                },
+            ))
+            .returning(|_| Ok(Default::default()));
+        let rest_search_api_handler = search_handler(mock_search_service);
+        assert_eq!(
+            warp::test::request()
+                .path(
+                    "/quickwit-demo-index/search?query=*&start_timestamp_rfc3339=2023-01-10T15:13:\
+                     35.123Z&end_timestamp_rfc3339=2023-01-11T00:00:00Z"
+                )
+                .reply(&rest_search_api_handler)
+                .await
+                .status(),
+            200
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rest_search_api_return_sort_values() -> anyhow::Result<()> {
+        let mut mock_search_service = MockSearchService::new();
+        mock_search_service
+            .expect_root_search()
+            .with(predicate::function(
+                |search_request: &quickwit_proto::SearchRequest| search_request.return_sort_values,
+            ))
+            .returning(|_| {
+                Ok(quickwit_proto::SearchResponse {
+                    hits: vec![quickwit_proto::Hit {
+                        json: r#"{"title": "foo"}"#.to_string(),
+                        partial_hit: Some(quickwit_proto::PartialHit {
+                            sort_values: vec!["1672531200000000".to_string(), "1.5".to_string()],
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }],
+                    num_hits: 1,
+                    sort_value_types: vec![
+                        SortValueType::Datetime as i32,
+                        SortValueType::F64 as i32,
+                    ],
+                    ..Default::default()
+                })
+            });
+        let rest_search_api_handler = search_handler(mock_search_service);
+        let resp = warp::test::request()
+            .path(
+                "/quickwit-demo-index/search?query=*&sort_by_field=timestamp&\
+                 return_sort_values=true",
+            )
+            .reply(&rest_search_api_handler)
+            .await;
+        assert_eq!(resp.status(), 200);
+        let resp_json: JsonValue = serde_json::from_slice(resp.body())?;
+        let expected_response_json = serde_json::json!({
+            "num_hits": 1,
+            "hits": [{"title": "foo"}],
+            "sort_values": [["2023-01-01T00:00:00Z", 1.5]],
+            "sort_value_types": ["Datetime", "F64"],
+        });
+        assert_json_include!(actual: resp_json, expected: expected_response_json);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rest_search_api_estimated_total_hits() -> anyhow::Result<()> {
+        let mut mock_search_service = MockSearchService::new();
+        mock_search_service.expect_root_search().returning(|_| {
+            Ok(quickwit_proto::SearchResponse {
+                num_hits: 10,
+                estimated_total_hits: Some(EstimatedTotalHits {
+                    value: 12_000,
+                    lower_bound: 11_000,
+                    upper_bound: 13_000,
+                }),
+                num_hits_is_lower_bound: true,
+                ..Default::default()
+            })
+        });
+        let rest_search_api_handler = search_handler(mock_search_service);
+        let resp = warp::test::request()
+            .path("/quickwit-demo-index/search?query=*")
+            .reply(&rest_search_api_handler)
+            .await;
+        assert_eq!(resp.status(), 200);
+        let resp_json: JsonValue = serde_json::from_slice(resp.body())?;
+        let expected_response_json = serde_json::json!({
+            "num_hits": 10,
+            "estimated_total_hits": {
+                "value": 12_000,
+                "lower_bound": 11_000,
+                "upper_bound": 13_000,
+            },
+        });
+        assert_json_include!(actual: resp_json, expected: expected_response_json);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rest_search_api_timed_out() -> anyhow::Result<()> {
+        let mut mock_search_service = MockSearchService::new();
+        mock_search_service.expect_root_search().returning(|_| {
+            Ok(quickwit_proto::SearchResponse {
+                num_hits: 10,
+                timed_out: true,
+                ..Default::default()
+            })
+        });
+        let rest_search_api_handler = search_handler(mock_search_service);
+        let resp = warp::test::request()
+            .path("/quickwit-demo-index/search?query=*")
+            .reply(&rest_search_api_handler)
+            .await;
+        assert_eq!(resp.status(), 200);
+        let resp_json: JsonValue = serde_json::from_slice(resp.body())?;
+        let expected_response_json = serde_json::json!({
+            "num_hits": 10,
+            "timed_out": true,
+        });
+        assert_json_include!(actual: resp_json, expected: expected_response_json);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rest_search_api_sort_by_field_without_prefix_uses_default_order(
+    ) -> anyhow::Result<()> {
+        let mut mock_search_service = MockSearchService::new();
+        mock_search_service
+            .expect_root_search()
+            .with(predicate::function(
+                |search_request: &quickwit_proto::SearchRequest| {
+                    search_request.sort_by_field.as_deref() == Some("_score")
+                        && search_request.sort_order.is_none()
+                },
+            ))
+            .times(1)
+            .returning(|_| Ok(Default::default()));
+        mock_search_service
+            .expect_root_search()
+            .with(predicate::function(
+                |search_request: &quickwit_proto::SearchRequest| {
+                    search_request.sort_by_field.as_deref() == Some("_score")
+                        && search_request.sort_order == Some(SortOrder::Asc as i32)
+                },
+            ))
+            .times(1)
+            .returning(|_| Ok(Default::default()));
+        let rest_search_api_handler = search_handler(mock_search_service);
+        for sort_by_field in ["_score", "%2B_score"] {
+            let status = warp::test::request()
+                .path(&format!(
+                    "/quickwit-demo-index/search?query=*&sort_by_field={sort_by_field}"
+                ))
+                .reply(&rest_search_api_handler)
+                .await
+                .status();
+            assert_eq!(status, 200);
+        }
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_rest_search_api_start_offset_and_num_hits_parameter() -> anyhow::Result<()> {
         let mut mock_search_service = MockSearchService::new();
@@ -899,6 +1100,8 @@ mod tests {
                     json: r#"{"title": "foo", "body": "foo bar baz"}"#.to_string(),
                     partial_hit: None,
                     snippet: Some(r#"{"title": [], "body": ["foo <em>bar</em> baz"]}"#.to_string()),
+                    matched_fields: Vec::new(),
+                    match_offsets: Vec::new(),
                 }],
                 num_hits: 1,
                 elapsed_time_micros: 16,